@@ -0,0 +1,60 @@
+//! Errors of the library API.
+//!
+//! [`Client::run`](crate::Client::run) and its relatives return an [`Error`]
+//! telling apart why a node couldn't run, so an embedder like the simulation
+//! controller can react, e.g. retry on another port after [`Error::Bind`]
+//! instead of matching on messages.
+
+use std::io;
+
+use crate::server::listener::BindError;
+
+/// Why a client couldn't start or didn't run to the end.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The settings from the file named by `FRONTEND_CONFIG` or from the
+    /// `FRONTEND_*` variables couldn't be loaded.
+    #[error("Failed to load the configuration: {0}")]
+    Config(String),
+    /// The backend service couldn't be created.
+    #[error("Failed to start the backend: {0}")]
+    BackendInit(String),
+    /// The HTTP server couldn't bind its socket, e.g. because the port is taken.
+    #[error(transparent)]
+    Bind(BindError),
+    /// The backend exited while the server still ran, because the channels it
+    /// serves were disconnected.
+    #[error("The backend exited before the server stopped; its channels were disconnected")]
+    Disconnected,
+    /// The HTTP server failed, e.g. because the data directory is unusable.
+    #[error("The server failed: {0}")]
+    Server(io::Error),
+    /// The client didn't shut down cleanly.
+    #[error("Failed to shut down: {0}")]
+    Shutdown(String),
+}
+
+impl From<io::Error> for Error {
+    /// A [`BindError`] wrapped in an [`io::Error`] becomes [`Error::Bind`], any
+    /// other one [`Error::Server`].
+    fn from(error: io::Error) -> Self {
+        // Taking the inner error apart would lose the code of an OS error
+        if !error.get_ref().is_some_and(|inner| inner.is::<BindError>()) {
+            return Error::Server(error);
+        }
+        let kind = error.kind();
+        match error
+            .into_inner()
+            .map(|inner| inner.downcast::<BindError>())
+        {
+            Some(Ok(bind)) => Error::Bind(*bind),
+            // Not reached, as checked above
+            Some(Err(inner)) => Error::Server(io::Error::new(kind, inner)),
+            None => Error::Server(kind.into()),
+        }
+    }
+}
+
+/// Result of the library API.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
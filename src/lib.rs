@@ -1,96 +1,489 @@
+/// Public module `error` with the errors of the library API.
+pub mod error;
 /// Public module `server` containing related server-side functionality.
 pub mod server;
+/// Private module `supervisor` restarting the backend thread.
+mod supervisor;
 
-use anyhow::{Result, anyhow};
+use actix_web::dev::ServerHandle;
 use ap_client_backend_v2::backend::ListOfDiscoveredEdgeNodes;
 use ap_client_backend_v2::backend::UnreadMessagesFromServer;
 use ap_client_backend_v2::backend::{Command, Service};
-use crossbeam_channel::{Receiver, Sender, unbounded};
+use crossbeam_channel::{Receiver, Sender, bounded, unbounded};
+pub use error::{Error, Result};
 use messages::{node::NodeOptions, node_event::NodeEvent};
-use std::thread;
+use server::auth::AuthProvider;
+use server::probe::{BackendProbe, RestartHook};
+use server::{ListenCallback, ServerOptions, StartedCallback};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use supervisor::{Supervisor, Switch};
 
 /// `Client` is the main interface for interacting with the backend.
 /// It manages channels for sending commands, receiving updates,
 /// and handling backend events such as discovered nodes and unread messages.
 pub struct Client {
-    command_send: Sender<Command>,
-    command_receive: Receiver<Command>,
     flood_send: Sender<ListOfDiscoveredEdgeNodes>,
     flood_recv: Receiver<ListOfDiscoveredEdgeNodes>,
     unread_msg_send: Sender<UnreadMessagesFromServer>,
     unread_msg_recv: Receiver<UnreadMessagesFromServer>,
+    server_options: ServerOptions,
+    // Why the file named by `FRONTEND_CONFIG` couldn't be loaded; reported by `run`
+    config_error: Option<String>,
+    capacities: ChannelCapacities,
+    // Handles of the server and backend while running, for `stop`
+    running: Arc<Mutex<Running>>,
+}
+
+/// How many API commands may queue for the backend by default.
+pub const DEFAULT_COMMAND_CAPACITY: usize = 1024;
+
+/// Capacities of the channels between the HTTP server and the backend. `None`
+/// leaves a channel unbounded; only the command channel is bounded by default,
+/// to [`DEFAULT_COMMAND_CAPACITY`].
+#[derive(Debug, Clone, Copy)]
+pub struct ChannelCapacities {
+    /// API commands to the backend; once full, requests are refused with HTTP 429
+    /// until the backend catches up.
+    pub commands: Option<usize>,
+    /// Flood results from the backend; once full, the backend waits for the server.
+    pub flood_results: Option<usize>,
+    /// Unread messages from the backend; once full, the backend waits for the server.
+    pub unread_messages: Option<usize>,
+}
+
+impl Default for ChannelCapacities {
+    fn default() -> Self {
+        ChannelCapacities {
+            commands: Some(DEFAULT_COMMAND_CAPACITY),
+            flood_results: None,
+            unread_messages: None,
+        }
+    }
+}
+
+/// What [`Client::stop`] needs to tear down a running client.
+#[derive(Debug, Default)]
+struct Running {
+    // Set once the HTTP server runs
+    server: Option<ServerHandle>,
+    // Closed when `run` or `run_demo` returns
+    finished: Option<Receiver<()>>,
 }
 
 impl Default for Client {
+    /// A client with the settings of the environment, see [`Client::builder`].
     fn default() -> Self {
-        Self::new()
+        Self::builder().build()
     }
 }
 
 impl Client {
     #[must_use]
-    /// Creates a new `Client` instance.
-    ///
-    /// Initializes all required channels for backend communication,
-    /// including command, flood, and unread message streams.
+    /// Starts building a `Client`, see [`ClientBuilder`].
     ///
-    /// # Returns
-    /// A fully initialized `Client` ready for use.
-    pub fn new() -> Self {
-        // Create channels
-        //
-        // API commands tot the backend
-        let (command_send, command_receive) = unbounded::<Command>();
-        // To get flood responses from back to front
-        let (send_flood_res_channel, recv_flood_res_channel) =
-            unbounded::<ListOfDiscoveredEdgeNodes>();
-        // To get unread messages
-        let (send_serve_unread_msg, recv_server_unread_msg) =
-            unbounded::<UnreadMessagesFromServer>();
-
-        // TODO do I need to save node-event channel here so that
-        // it doesn't get dropped?
-        Client {
-            command_send,
-            command_receive,
-            flood_send: send_flood_res_channel,
-            flood_recv: recv_flood_res_channel,
-            unread_msg_send: send_serve_unread_msg,
-            unread_msg_recv: recv_server_unread_msg,
-        }
+    /// The HTTP server settings start out as read from the file named by the
+    /// `FRONTEND_CONFIG` environment variable, if set, and overridden by
+    /// `FRONTEND_*` variables, see [`server::config`]. If they can't be loaded,
+    /// running the client fails, unless the builder is given settings of its own.
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+
+    #[must_use]
+    /// Replaces the HTTP server settings (timeouts etc.) used by [`Client::run`] and
+    /// [`Client::run_demo`].
+    pub fn with_server_options(mut self, server_options: ServerOptions) -> Self {
+        self.server_options = server_options;
+        self.config_error = None;
+        self
+    }
+
+    #[must_use]
+    /// The HTTP server settings used by [`Client::run`] and [`Client::run_demo`].
+    pub fn server_options(&self) -> &ServerOptions {
+        &self.server_options
     }
 
     /// # Errors
     /// Starts the client's main execution loop.
     ///
     /// Spawns necessary threads and begins listening to events from the backend.
+    /// Installs a `RUST_LOG`-controlled tracing subscriber unless one is installed already.
+    ///
+    /// Returns once the server stopped on `SIGINT` or `SIGTERM`, or through
+    /// [`Client::stop`]. The channel the backend takes API commands from is then
+    /// closed, which tells it to exit, and its thread is joined, waiting up to
+    /// `ServerOptions::shutdown_timeout`.
+    ///
+    /// Fails with an [`Error`] telling whether the configuration, the backend, the
+    /// socket or the shutdown was the problem, or that the backend exited early.
     pub fn run(&self, options: &NodeOptions, channel: &Sender<NodeEvent>) -> Result<()> {
-        let mut client_backend = Service::new(
-            options.id,
-            channel.clone(),
-            options.command_recv.clone(),
-            options.packet_send.clone(),
-            options.packet_recv.clone(),
-            self.command_receive.clone(),
-            self.flood_send.clone(),
-            self.unread_msg_send.clone(),
-        )
-        .map_err(|e| anyhow!(e))?;
+        let run = self.start(options, channel)?;
+        actix_web::rt::System::new().block_on(run)
+    }
+
+    /// # Errors
+    /// Starts the client like [`Client::run`], but on the runtime of the caller,
+    /// returning right away, so several nodes can share an existing actix or tokio
+    /// runtime. Must be called from within that runtime.
+    ///
+    /// Returns an error if the configuration or the backend is unusable; errors of
+    /// the server are reported by the [`ClientHandle`].
+    pub fn spawn(
+        &self,
+        options: &NodeOptions,
+        channel: &Sender<NodeEvent>,
+    ) -> Result<ClientHandle> {
+        let run = self.start(options, channel)?;
+        Ok(ClientHandle {
+            task: actix_web::rt::spawn(run),
+            running: self.running.clone(),
+        })
+    }
+
+    /// Starts the backend thread and returns the server, which shuts the backend
+    /// down once it stopped.
+    fn start(
+        &self,
+        options: &NodeOptions,
+        channel: &Sender<NodeEvent>,
+    ) -> Result<impl Future<Output = Result<()>> + 'static> {
+        server::trace::init_subscriber();
+        self.check_config()?;
+        let (server_options, finished) = self.prepare_run();
+        let node_id = options.id;
+        // Every backend gets channels of its own, see `supervisor`. The server's
+        // threads may outlive it, so its commands are switched as well
+        let capacity = self.capacities.commands;
+        let (command_send, command_recv) = channel::<Command>(capacity);
+        let (stop_switches, switches_stopped) = bounded::<()>(0);
+        let commands = Switch::new(command_recv, switches_stopped.clone(), capacity);
+        let node_commands =
+            Switch::new(options.command_recv.clone(), switches_stopped.clone(), None);
+        let packets = Switch::new(options.packet_recv.clone(), switches_stopped, None);
+        let (event_send, packet_send) = (channel.clone(), options.packet_send.clone());
+        let (flood_send, unread_msg_send) = (self.flood_send.clone(), self.unread_msg_send.clone());
+        let make_backend = move || {
+            Service::new(
+                node_id,
+                event_send.clone(),
+                node_commands.replace(),
+                packet_send.clone(),
+                packets.replace(),
+                commands.replace(),
+                flood_send.clone(),
+                unread_msg_send.clone(),
+            )
+            .map_err(|e| Error::BackendInit(e.to_string()))
+        };
 
         // Move backend to different thread
-        thread::spawn(move || -> Result<()> {
-            client_backend.run();
-            Ok(())
-        });
+        let probe = Arc::new(BackendProbe::default());
+        let supervisor = Supervisor::start(node_id, make_backend, probe.clone())?;
+        let restarter = Arc::downgrade(&supervisor);
+        probe.set_restart(RestartHook(Box::new(move || {
+            let supervisor = restarter
+                .upgrade()
+                .ok_or_else(|| "The backend is shutting down".to_string())?;
+            supervisor.restart().map_err(|e| e.to_string())
+        })));
 
+        let shutdown_timeout = self.server_options.shutdown_timeout;
+        let running = self.running.clone();
         let server = server::start_server(
-            self.command_send.clone(),
-            options.id.into(),
-            options.id,
+            command_send,
+            self.server_options.listen_port(node_id),
+            node_id,
             self.flood_recv.clone(),
             self.unread_msg_recv.clone(),
+            probe,
+            server_options,
         );
-        actix_web::rt::System::new().block_on(server)?;
+        Ok(async move {
+            let result = server.await;
+            // Closing its channels tells the backend to exit
+            supervisor.prepare_stop();
+            drop(stop_switches);
+            // Waiting blocks, so keep it off the runtime's threads
+            let stopped =
+                actix_web::rt::task::spawn_blocking(move || supervisor.stop(shutdown_timeout))
+                    .await
+                    .unwrap_or_else(|e| {
+                        tracing::error!(node_id, "Failed to wait for the backend to exit");
+                        Err(Error::Shutdown(e.to_string()))
+                    });
+            lock(&running).server = None;
+            drop(finished);
+            // A failing server is what ended the run, so it comes first
+            result?;
+            stopped
+        })
+    }
+
+    /// # Errors
+    /// Runs the HTTP server as node `node_id` on a generated network instead of
+    /// the backend, see [`server::start_demo_server`].
+    pub fn run_demo(&self, node_id: u8) -> Result<()> {
+        server::trace::init_subscriber();
+        self.check_config()?;
+        let (server_options, finished) = self.prepare_run();
+        let server = server::start_demo_server(
+            self.server_options.listen_port(node_id),
+            node_id,
+            server_options,
+        );
+        let result = actix_web::rt::System::new().block_on(server);
+        lock(&self.running).server = None;
+        drop(finished);
+        result?;
         Ok(())
     }
+
+    /// Stops the client that [`Client::run`] or [`Client::run_demo`] runs on another
+    /// thread, like `SIGINT` would: the HTTP server finishes the requests in flight,
+    /// then the backend is told to exit and its thread is joined. Returns once `run`
+    /// is done; does nothing if the server isn't running (yet). On the runtime of
+    /// [`Client::spawn`], use [`ClientHandle::stop`] instead, which doesn't block it.
+    pub fn stop(&self) {
+        let (server, finished) = {
+            let mut running = lock(&self.running);
+            let Some(server) = running.server.take() else {
+                return;
+            };
+            (server, running.finished.take())
+        };
+        // The command is sent right away; waiting for `run` covers its completion
+        drop(server.stop(true));
+        if let Some(finished) = finished {
+            // Closed, not sent on, once `run` returns
+            let _ = finished.recv();
+        }
+    }
+
+    /// The server settings for a run, keeping the handle of the server for
+    /// [`Client::stop`], and the sender to drop when the run is over.
+    fn prepare_run(&self) -> (ServerOptions, Sender<()>) {
+        let (finished_send, finished_recv) = bounded::<()>(0);
+        lock(&self.running).finished = Some(finished_recv);
+        let running = self.running.clone();
+        let mut options = self.server_options.clone();
+        let on_started = options.on_started.take();
+        options.on_started = Some(StartedCallback(Arc::new(move |handle: ServerHandle| {
+            lock(&running).server = Some(handle.clone());
+            if let Some(StartedCallback(callback)) = &on_started {
+                callback(handle);
+            }
+        })));
+        (options, finished_send)
+    }
+
+    /// Fails if the configuration from the file named by `FRONTEND_CONFIG` or from
+    /// the environment couldn't be loaded.
+    fn check_config(&self) -> Result<()> {
+        match &self.config_error {
+            Some(e) => Err(Error::Config(e.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+fn lock(running: &Mutex<Running>) -> MutexGuard<'_, Running> {
+    running.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A channel holding up to `capacity` items, or any number without one.
+pub(crate) fn channel<T>(capacity: Option<usize>) -> (Sender<T>, Receiver<T>) {
+    capacity.map_or_else(unbounded, bounded)
+}
+
+/// A client started by [`Client::spawn`].
+#[derive(Debug)]
+pub struct ClientHandle {
+    task: actix_web::rt::task::JoinHandle<Result<()>>,
+    running: Arc<Mutex<Running>>,
+}
+
+impl ClientHandle {
+    /// # Errors
+    /// Stops the client like [`Client::stop`] and waits until the backend is
+    /// shut down, without blocking the runtime. Returns the error the server
+    /// failed with, if any.
+    pub async fn stop(self) -> Result<()> {
+        let server = lock(&self.running).server.take();
+        if let Some(server) = server {
+            server.stop(true).await;
+        }
+        self.join().await
+    }
+
+    /// # Errors
+    /// Waits until the client stopped, e.g. on `SIGINT`, returning the error the
+    /// server failed with, if any.
+    pub async fn join(self) -> Result<()> {
+        self.task
+            .await
+            .map_err(|e| Error::Shutdown(e.to_string()))?
+    }
+}
+
+/// Builds a [`Client`] with custom settings, so embedders can tune it without
+/// changing the crate.
+#[derive(Debug)]
+pub struct ClientBuilder {
+    server_options: ServerOptions,
+    // Why the settings from the environment couldn't be loaded
+    config_error: Option<String>,
+    capacities: ChannelCapacities,
+}
+
+impl Default for ClientBuilder {
+    /// Starts out with the settings of the environment, see [`Client::builder`].
+    fn default() -> Self {
+        let (server_options, config_error) = match server::config::load_from_env() {
+            Ok(options) => (options, None),
+            Err(e) => (ServerOptions::default(), Some(e.to_string())),
+        };
+        ClientBuilder {
+            server_options,
+            config_error,
+            capacities: ChannelCapacities::default(),
+        }
+    }
+}
+
+impl ClientBuilder {
+    #[must_use]
+    /// Replaces the HTTP server settings (timeouts etc.), including those of the
+    /// environment.
+    pub fn server_options(mut self, server_options: ServerOptions) -> Self {
+        let auth_provider = self.server_options.auth_provider.take();
+        self.server_options = server_options;
+        self.config_error = None;
+        // Keep a provider set before, unless the new settings bring their own
+        if self.server_options.auth_provider.is_none() {
+            self.server_options.auth_provider = auth_provider;
+        }
+        self
+    }
+
+    #[must_use]
+    /// Makes the HTTP server listen on `address`, e.g. `0.0.0.0` to be reachable
+    /// from other machines, instead of `127.0.0.1`.
+    pub fn bind_address(mut self, address: IpAddr) -> Self {
+        self.server_options.bind_address = address;
+        self
+    }
+
+    #[must_use]
+    /// Makes the HTTP server listen on `port` instead of 8000 plus the node ID;
+    /// with 0, the system picks a free port.
+    pub fn port(mut self, port: u16) -> Self {
+        self.server_options.port = Some(port);
+        self
+    }
+
+    #[must_use]
+    /// Sets the capacities of the channels between the HTTP server and the backend,
+    /// see [`ChannelCapacities`].
+    pub fn channel_capacities(mut self, capacities: ChannelCapacities) -> Self {
+        self.capacities = capacities;
+        self
+    }
+
+    #[must_use]
+    /// Stores the node's messages, identity and journal below `dir` instead of `data/`.
+    pub fn data_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.server_options.data_dir = dir.into();
+        self
+    }
+
+    #[must_use]
+    /// Makes `/register` wait up to `timeout` for the target server to confirm.
+    pub fn register_timeout(mut self, timeout: Duration) -> Self {
+        self.server_options.register_timeout = timeout;
+        self
+    }
+
+    #[must_use]
+    /// Makes content requests and probes of a server's type wait up to `timeout`
+    /// for the server's answer.
+    pub fn content_timeout(mut self, timeout: Duration) -> Self {
+        self.server_options.content_timeout = timeout;
+        self
+    }
+
+    #[must_use]
+    /// Lets a flood take up to `timeout`.
+    pub fn flood_timeout(mut self, timeout: Duration) -> Self {
+        self.server_options.flood_timeout = timeout;
+        self
+    }
+
+    #[must_use]
+    /// Makes stopping the client wait up to `timeout` for the requests in flight
+    /// and then for the backend to exit.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.server_options.shutdown_timeout = timeout;
+        self
+    }
+
+    #[must_use]
+    /// Serves the web UI from `dir` instead of `static/`.
+    pub fn static_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.server_options.static_dir = dir.into();
+        self
+    }
+
+    #[must_use]
+    /// Calls `callback` with the address the HTTP server listens on once it is
+    /// bound, e.g. to learn the port picked for [`ClientBuilder::port`] 0.
+    pub fn on_listening(mut self, callback: impl Fn(SocketAddr) + Send + Sync + 'static) -> Self {
+        self.server_options.on_listening = Some(ListenCallback(Arc::new(callback)));
+        self
+    }
+
+    #[must_use]
+    /// Makes the HTTP server listen on `address`, both its IP address and port.
+    pub fn listen(self, address: SocketAddr) -> Self {
+        self.bind_address(address.ip()).port(address.port())
+    }
+
+    /// Replaces the HTTP server settings with the ones in the TOML file at `path`,
+    /// overridden by `FRONTEND_*` environment variables, see [`server::config`].
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read or holds invalid settings.
+    pub fn config_file(self, path: &Path) -> std::io::Result<Self> {
+        Ok(self.server_options(server::config::load(path)?))
+    }
+
+    #[must_use]
+    /// Lets only users accepted by `provider` use the HTTP API, e.g.
+    /// [`server::auth::StaticUsers`], [`server::auth::FileUsers`] or an
+    /// embedder's own provider backed by LDAP.
+    pub fn auth_provider(mut self, provider: Arc<dyn AuthProvider>) -> Self {
+        self.server_options.auth_provider = Some(provider);
+        self
+    }
+
+    #[must_use]
+    /// Creates the [`Client`].
+    pub fn build(self) -> Client {
+        // API commands to the backend are created per run, see `Client::start`
+        let (flood_send, flood_recv) = channel(self.capacities.flood_results);
+        let (unread_msg_send, unread_msg_recv) = channel(self.capacities.unread_messages);
+        Client {
+            flood_send,
+            flood_recv,
+            unread_msg_send,
+            unread_msg_recv,
+            server_options: self.server_options,
+            config_error: self.config_error,
+            capacities: self.capacities,
+            running: Arc::default(),
+        }
+    }
 }
@@ -0,0 +1,75 @@
+//! Standalone entry point.
+//!
+//! Real nodes are started by the simulation controller through
+//! [`Client::run`]. Outside of it there is no drone network to join, so the
+//! binary runs the node on a generated network, see [`Client::run_demo`].
+
+use ap_client_frontend_v2::Client;
+use ap_client_frontend_v2::server::BIND_ADDRESS_ENV;
+use ap_client_frontend_v2::server::config;
+use clap::Parser;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Runs a client node with its web UI on a generated network.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Args {
+    /// ID of the node.
+    #[arg(long, visible_alias = "node-id", default_value_t = 1)]
+    id: u8,
+    /// TOML configuration file; defaults to the one named by `FRONTEND_CONFIG`.
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Address to listen on, e.g. 0.0.0.0 to be reachable from other machines.
+    #[arg(long, value_name = "ADDRESS")]
+    bind: Option<IpAddr>,
+    /// Port to listen on; 0 picks a free one.
+    #[arg(long)]
+    port: Option<u16>,
+    /// Directory to serve the web UI from.
+    #[arg(long, value_name = "DIR")]
+    static_dir: Option<PathBuf>,
+    /// Accepted for compatibility; the binary always runs on a generated network.
+    #[arg(long, hide = true)]
+    demo: bool,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    // The settings come from --config or FRONTEND_CONFIG, overridden by FRONTEND_* variables
+    // and then by the flags
+    let loaded = match &args.config {
+        Some(path) => config::load(path),
+        None => config::load_from_env(),
+    };
+    let mut builder = match loaded {
+        Ok(options) => Client::builder().server_options(options),
+        Err(e) => {
+            eprintln!("Failed to load the configuration: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    // With --port 0 the port is only known once bound
+    builder = builder.on_listening(|address| {
+        println!("Open http://{address} (set with --bind or {BIND_ADDRESS_ENV}, and --port)");
+    });
+    if let Some(address) = args.bind {
+        builder = builder.bind_address(address);
+    }
+    if let Some(port) = args.port {
+        builder = builder.port(port);
+    }
+    if let Some(dir) = args.static_dir {
+        builder = builder.static_dir(dir);
+    }
+    match builder.build().run_demo(args.id) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
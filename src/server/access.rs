@@ -0,0 +1,84 @@
+//! Access log of the HTTP server.
+//!
+//! With `ServerOptions::access_log` set, the [`handle`] middleware logs one
+//! structured event per request under the `access` target: request ID,
+//! method, path, status, duration and remote address. Server errors and requests that
+//! failed before producing a response are logged as warnings, so they leave a
+//! trace even when only warnings are printed.
+
+use actix_web::Error;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::web;
+use std::time::Instant;
+
+use super::request_id::RequestId;
+use super::settings::LiveOptions;
+
+/// Middleware logging every request once it is answered.
+///
+/// # Errors
+/// Passes on the errors of the wrapped service.
+pub async fn handle<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<B>, Error> {
+    let Some(options) = req
+        .app_data::<web::Data<LiveOptions>>()
+        .map(|options| options.current())
+        .filter(|options| options.access_log)
+    else {
+        return next.call(req).await;
+    };
+    // Behind a proxy the peer is the proxy; the client is in the forwarding headers
+    let remote_addr = if options.behind_tls_proxy {
+        req.connection_info()
+            .realip_remote_addr()
+            .map(str::to_string)
+    } else {
+        req.peer_addr().map(|addr| addr.to_string())
+    };
+    let remote_addr = remote_addr.unwrap_or_else(|| "-".to_string());
+    let request_id = req
+        .extensions()
+        .get::<RequestId>()
+        .map_or_else(|| "-".to_string(), ToString::to_string);
+    let method = req.method().to_string();
+    let path = req.path().to_string();
+    let started = Instant::now();
+
+    let result = next.call(req).await;
+    let duration_ms = started.elapsed().as_secs_f64() * 1000.0;
+    match &result {
+        Ok(response) if response.status().is_server_error() => tracing::warn!(
+            target: "access",
+            request_id,
+            method,
+            path,
+            status = response.status().as_u16(),
+            duration_ms,
+            remote_addr,
+        ),
+        Ok(response) => tracing::info!(
+            target: "access",
+            request_id,
+            method,
+            path,
+            status = response.status().as_u16(),
+            duration_ms,
+            remote_addr,
+        ),
+        Err(e) => tracing::warn!(
+            target: "access",
+            request_id,
+            method,
+            path,
+            status = e.as_response_error().status_code().as_u16(),
+            duration_ms,
+            remote_addr,
+            error = %e,
+        ),
+    }
+    result
+}
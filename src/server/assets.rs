@@ -0,0 +1,163 @@
+//! Snapshot of the web UI's static files.
+//!
+//! The files below `ServerOptions::static_dir` are read
+//! into memory once and served from there. HTML files are templates: their
+//! `{{node_id}}` and `{{version}}` placeholders are filled in while loading.
+//! `/admin/reload_assets` loads a fresh snapshot and swaps it in as a whole,
+//! so requests see either the old or the new files, never a mix, and the
+//! server keeps running while the UI is updated.
+
+use actix_web::web::Bytes;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock};
+
+use super::unix_millis;
+
+/// A static file ready to be served.
+#[derive(Debug, Clone)]
+pub struct Asset {
+    /// MIME type of the file.
+    pub content_type: String,
+    /// The file, with templates rendered.
+    pub body: Bytes,
+}
+
+/// Summary of a loaded snapshot.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct SnapshotInfo {
+    /// Number of the snapshot, counting reloads.
+    pub revision: u64,
+    /// Number of files.
+    pub files: usize,
+    /// Total size of the files in bytes.
+    pub bytes: usize,
+    /// Unix time in milliseconds when the snapshot was loaded.
+    pub loaded_at: u64,
+}
+
+#[derive(Debug)]
+struct Snapshot {
+    info: SnapshotInfo,
+    files: HashMap<String, Asset>,
+}
+
+/// The current snapshot of the static files.
+#[derive(Debug)]
+pub struct Assets {
+    dir: PathBuf,
+    node_id: u8,
+    current: RwLock<Arc<Snapshot>>,
+}
+
+impl Assets {
+    /// Loads the files below `dir`, rendering templates for node `node_id`.
+    /// If they can't be read, starts with no files so the API still works.
+    #[must_use]
+    pub fn load(dir: &Path, node_id: u8) -> Self {
+        let files = read_dir(dir, node_id).unwrap_or_else(|e| {
+            tracing::warn!("Failed to load static assets from {}: {e}", dir.display());
+            HashMap::new()
+        });
+        Assets {
+            dir: dir.to_path_buf(),
+            node_id,
+            current: RwLock::new(Arc::new(Snapshot::new(1, files))),
+        }
+    }
+
+    /// Reads the files again and replaces the snapshot with them.
+    ///
+    /// # Errors
+    /// Returns an error if the files can't be read; the old snapshot stays in place.
+    pub fn reload(&self) -> io::Result<SnapshotInfo> {
+        let files = read_dir(&self.dir, self.node_id)?;
+        let mut current = self.current.write().unwrap_or_else(PoisonError::into_inner);
+        let snapshot = Arc::new(Snapshot::new(current.info.revision + 1, files));
+        let info = snapshot.info;
+        *current = snapshot;
+        Ok(info)
+    }
+
+    /// The file at `path`, relative to the static directory.
+    #[must_use]
+    pub fn get(&self, path: &str) -> Option<Asset> {
+        self.snapshot().files.get(path).cloned()
+    }
+
+    /// Summary of the current snapshot.
+    #[must_use]
+    pub fn info(&self) -> SnapshotInfo {
+        self.snapshot().info
+    }
+
+    fn snapshot(&self) -> Arc<Snapshot> {
+        self.current
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl Snapshot {
+    fn new(revision: u64, files: HashMap<String, Asset>) -> Self {
+        Snapshot {
+            info: SnapshotInfo {
+                revision,
+                files: files.len(),
+                bytes: files.values().map(|asset| asset.body.len()).sum(),
+                loaded_at: unix_millis(),
+            },
+            files,
+        }
+    }
+}
+
+/// Reads every file below `dir`, keyed by its `/`-separated path relative to `dir`.
+fn read_dir(dir: &Path, node_id: u8) -> io::Result<HashMap<String, Asset>> {
+    let mut files = HashMap::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let Ok(relative) = path.strip_prefix(dir) else {
+                continue;
+            };
+            let key = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let extension = path
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            let mut body = fs::read(&path)?;
+            if extension == "html" {
+                body = render(&String::from_utf8_lossy(&body), node_id).into_bytes();
+            }
+            files.insert(
+                key,
+                Asset {
+                    content_type: actix_files::file_extension_to_mime(&extension).to_string(),
+                    body: Bytes::from(body),
+                },
+            );
+        }
+    }
+    Ok(files)
+}
+
+/// Fills in the placeholders of an HTML template.
+fn render(template: &str, node_id: u8) -> String {
+    template
+        .replace("{{node_id}}", &node_id.to_string())
+        .replace("{{version}}", env!("CARGO_PKG_VERSION"))
+}
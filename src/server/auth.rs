@@ -0,0 +1,430 @@
+//! Pluggable authentication.
+//!
+//! Who may use the API is decided by an [`AuthProvider`]: it turns
+//! credentials (a user name and password, or a token) into an
+//! [`AuthIdentity`] with roles, or rejects them. Two providers are built in,
+//! [`StaticUsers`] configured in code and [`FileUsers`] read from a JSON
+//! file; embedders can plug in their own (e.g. LDAP) through
+//! `ClientBuilder::auth_provider`. Without a provider, the API is open.
+//!
+//! The HTTP side stays here: `/login` exchanges a password for a session
+//! cookie, which lasts until it goes unused for `ServerOptions::session_idle_timeout`
+//! or reaches `ServerOptions::session_lifetime`. Every use checks the user
+//! against the current provider again, so users removed or changed on a reload
+//! lose their old access at once. The [`handle`] middleware accepts that cookie or an
+//! `Authorization: Bearer <token>` header on every route except the public
+//! ones. Routes below `/admin` additionally need the [`ADMIN_ROLE`], and
+//! users with the [`READ_ONLY_ROLE`] (e.g. the token of a dashboard) may only
+//! `GET`: they can watch the node but never send, register or change anything.
+//! The web UI is protected as well: a browser asking for a page without a
+//! session is sent to the [login page](super::login) instead of getting a 401.
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::http::header::{self, HeaderMap};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpResponse, web};
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::routed_path;
+use super::settings::LiveOptions;
+
+/// Name of the session cookie set by `/login`.
+pub const SESSION_COOKIE: &str = "session";
+/// Role needed for the routes below `/admin`.
+pub const ADMIN_ROLE: &str = "admin";
+/// Role restricting a user to `GET` requests, whatever other roles they have.
+pub const READ_ONLY_ROLE: &str = "read_only";
+/// How often the janitor ends expired sessions.
+const JANITOR_INTERVAL: Duration = Duration::from_secs(60);
+/// Routes usable without logging in, as prefixes of the request path.
+const PUBLIC_PREFIXES: &[&str] = &[
+    "/static/",
+    "/public/",
+    "/login",
+    "/logout",
+    "/api/changes",
+    "/health",
+    "/ready",
+];
+
+/// Who is making a request.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuthIdentity {
+    /// User name.
+    pub user: String,
+    /// Roles of the user, e.g. [`ADMIN_ROLE`] or [`READ_ONLY_ROLE`].
+    pub roles: Vec<String>,
+}
+
+impl AuthIdentity {
+    /// Whether the user has `role`.
+    #[must_use]
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+
+    /// Whether the user may only observe, see [`READ_ONLY_ROLE`].
+    #[must_use]
+    pub fn is_read_only(&self) -> bool {
+        self.has_role(READ_ONLY_ROLE)
+    }
+}
+
+/// Credentials presented by a client.
+#[derive(Debug, Clone, Copy)]
+pub enum Credentials<'a> {
+    /// A user name and password, as sent to `/login`.
+    Password {
+        /// User name.
+        user: &'a str,
+        /// Password.
+        password: &'a str,
+    },
+    /// A bearer token.
+    Token(&'a str),
+}
+
+/// Decides who may use the API.
+pub trait AuthProvider: Send + Sync + fmt::Debug {
+    /// Returns the identity the credentials belong to, or `None` if they are invalid.
+    fn authenticate(&self, credentials: &Credentials<'_>) -> Option<AuthIdentity>;
+
+    /// Returns the current identity of `user`, or `None` if the user is gone.
+    /// Sessions are checked against it on every request.
+    fn lookup(&self, user: &str) -> Option<AuthIdentity>;
+}
+
+/// A user known to [`StaticUsers`] or [`FileUsers`].
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UserEntry {
+    /// User name.
+    pub user: String,
+    /// Password for `/login`, if the user may log in with one.
+    pub password: Option<String>,
+    /// Token for `Authorization: Bearer`, if the user may use one.
+    pub token: Option<String>,
+    /// Roles of the user, e.g. [`ADMIN_ROLE`] or [`READ_ONLY_ROLE`].
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+impl UserEntry {
+    fn identity(&self) -> AuthIdentity {
+        AuthIdentity {
+            user: self.user.clone(),
+            roles: self.roles.clone(),
+        }
+    }
+}
+
+// The settings end up in logs and `/debug/env`; keep the secrets out of them
+impl fmt::Debug for UserEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserEntry")
+            .field("user", &self.user)
+            .field("password", &self.password.as_ref().map(|_| "***"))
+            .field("token", &self.token.as_ref().map(|_| "***"))
+            .field("roles", &self.roles)
+            .finish()
+    }
+}
+
+/// Finds the user the credentials belong to.
+fn check(users: &[UserEntry], credentials: &Credentials<'_>) -> Option<AuthIdentity> {
+    users
+        .iter()
+        .find(|entry| match credentials {
+            Credentials::Password { user, password } => {
+                entry.user == *user
+                    && entry
+                        .password
+                        .as_deref()
+                        .is_some_and(|expected| constant_time_eq(expected, password))
+            }
+            Credentials::Token(token) => entry
+                .token
+                .as_deref()
+                .is_some_and(|expected| constant_time_eq(expected, token)),
+        })
+        .map(UserEntry::identity)
+}
+
+/// Finds the current identity of `user`.
+fn find(users: &[UserEntry], user: &str) -> Option<AuthIdentity> {
+    users
+        .iter()
+        .find(|entry| entry.user == user)
+        .map(UserEntry::identity)
+}
+
+/// Compares secrets without revealing through timing how much of them matched.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Users configured in code.
+#[derive(Debug, Clone)]
+pub struct StaticUsers {
+    users: Vec<UserEntry>,
+}
+
+impl StaticUsers {
+    /// Accepts the credentials of `users`.
+    #[must_use]
+    pub fn new(users: Vec<UserEntry>) -> Self {
+        StaticUsers { users }
+    }
+}
+
+impl AuthProvider for StaticUsers {
+    fn authenticate(&self, credentials: &Credentials<'_>) -> Option<AuthIdentity> {
+        check(&self.users, credentials)
+    }
+
+    fn lookup(&self, user: &str) -> Option<AuthIdentity> {
+        find(&self.users, user)
+    }
+}
+
+/// Users read from a JSON file holding a list of [`UserEntry`]s.
+///
+/// The file is read on every authentication, so users can be added or
+/// removed while the node runs. If it can't be read, nobody gets in.
+#[derive(Debug, Clone)]
+pub struct FileUsers {
+    path: PathBuf,
+}
+
+impl FileUsers {
+    /// Accepts the credentials of the users listed in `path`.
+    #[must_use]
+    pub fn new(path: &Path) -> Self {
+        FileUsers {
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+impl FileUsers {
+    /// The users currently listed in the file; none if it can't be read.
+    fn users(&self) -> Vec<UserEntry> {
+        match fs::read(&self.path)
+            .map_err(|e| e.to_string())
+            .and_then(|json| serde_json::from_slice(&json).map_err(|e| e.to_string()))
+        {
+            Ok(users) => users,
+            Err(e) => {
+                tracing::error!("Failed to read users from {}: {e}", self.path.display());
+                vec![]
+            }
+        }
+    }
+}
+
+impl AuthProvider for FileUsers {
+    fn authenticate(&self, credentials: &Credentials<'_>) -> Option<AuthIdentity> {
+        check(&self.users(), credentials)
+    }
+
+    fn lookup(&self, user: &str) -> Option<AuthIdentity> {
+        find(&self.users(), user)
+    }
+}
+
+/// A session started via `/login`.
+#[derive(Debug)]
+struct Session {
+    user: String, // Looked up again on every use, so role changes apply at once
+    started_at: Instant,
+    last_used: Instant,
+}
+
+/// Sessions of the users logged in via `/login`, kept in memory.
+#[derive(Debug)]
+pub struct Sessions {
+    idle_timeout: Duration,
+    lifetime: Duration,
+    by_token: Mutex<HashMap<String, Session>>,
+}
+
+impl Sessions {
+    /// Keeps sessions until they go unused for `idle_timeout` or reach `lifetime`.
+    #[must_use]
+    pub fn new(idle_timeout: Duration, lifetime: Duration) -> Self {
+        Sessions {
+            idle_timeout,
+            lifetime,
+            by_token: Mutex::default(),
+        }
+    }
+
+    /// Starts a session for `identity` and returns its token.
+    pub fn start(&self, identity: &AuthIdentity) -> String {
+        let mut token = String::with_capacity(64);
+        for byte in rand::random::<[u8; 32]>() {
+            let _ = write!(token, "{byte:02x}");
+        }
+        let now = Instant::now();
+        let session = Session {
+            user: identity.user.clone(),
+            started_at: now,
+            last_used: now,
+        };
+        self.lock().insert(token.clone(), session);
+        token
+    }
+
+    /// Current identity of the session with `token` according to `provider`, if
+    /// the session exists and hasn't expired. Ends the session if it expired or
+    /// its user is gone.
+    #[must_use]
+    pub fn get(&self, token: &str, provider: &dyn AuthProvider) -> Option<AuthIdentity> {
+        let now = Instant::now();
+        let user = {
+            let mut sessions = self.lock();
+            let session = sessions.get_mut(token)?;
+            if self.expired(session, now) {
+                sessions.remove(token);
+                return None;
+            }
+            session.last_used = now;
+            session.user.clone()
+        };
+        // Asked without the lock, as a provider may have to read its users first
+        let identity = provider.lookup(&user);
+        if identity.is_none() {
+            self.end(token);
+        }
+        identity
+    }
+
+    /// Ends the session with `token`. Returns whether it existed.
+    pub fn end(&self, token: &str) -> bool {
+        self.lock().remove(token).is_some()
+    }
+
+    /// Ends the sessions that went unused for too long or reached their lifetime.
+    pub fn end_expired(&self) {
+        let now = Instant::now();
+        self.lock().retain(|_, session| !self.expired(session, now));
+    }
+
+    fn expired(&self, session: &Session, now: Instant) -> bool {
+        now.duration_since(session.last_used) >= self.idle_timeout
+            || now.duration_since(session.started_at) >= self.lifetime
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Session>> {
+        self.by_token.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Spawns the session janitor, which ends expired sessions every minute, so
+/// sessions nobody uses any more don't pile up. Stops once `shutdown` is
+/// disconnected.
+pub fn spawn_janitor(sessions: Arc<Sessions>, shutdown: Receiver<()>) {
+    thread::spawn(move || {
+        loop {
+            if let Err(RecvTimeoutError::Disconnected) = shutdown.recv_timeout(JANITOR_INTERVAL) {
+                return;
+            }
+            sessions.end_expired();
+        }
+    });
+}
+
+/// Whether `path` can be used without logging in.
+fn is_public(path: &str) -> bool {
+    PUBLIC_PREFIXES
+        .iter()
+        .any(|prefix| path.starts_with(prefix))
+}
+
+/// Whether a request with `headers` comes from a browser navigating to a page,
+/// as opposed to a script calling the API.
+pub(crate) fn wants_html(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"))
+}
+
+/// Middleware letting only authenticated requests through, if an
+/// [`AuthProvider`] is configured. The identity is added to the request's
+/// extensions for the handlers.
+///
+/// # Errors
+/// Passes on the errors of the wrapped service.
+pub async fn handle<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let provider = req
+        .app_data::<web::Data<LiveOptions>>()
+        .and_then(|options| options.current().auth_provider.clone());
+    let Some(provider) = provider else {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    };
+    if is_public(routed_path(&req)) {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    }
+
+    let from_session = req.cookie(SESSION_COOKIE).and_then(|cookie| {
+        req.app_data::<web::Data<Sessions>>()
+            .and_then(|sessions| sessions.get(cookie.value(), provider.as_ref()))
+    });
+    let identity = from_session.or_else(|| {
+        req.headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .and_then(|token| provider.authenticate(&Credentials::Token(token.trim())))
+    });
+
+    let Some(identity) = identity else {
+        if req.method() == Method::GET && wants_html(req.headers()) {
+            let response = HttpResponse::SeeOther()
+                .insert_header((header::LOCATION, "/login"))
+                .finish();
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+        let response = HttpResponse::Unauthorized()
+            .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+            .json("Log in via /login or send a bearer token");
+        return Ok(req.into_response(response).map_into_right_body());
+    };
+    if routed_path(&req).starts_with("/admin/") && !identity.has_role(ADMIN_ROLE) {
+        let response = HttpResponse::Forbidden().json("This route needs the admin role");
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+    // HEAD arrives here as GET, OPTIONS is answered before
+    if identity.is_read_only() && req.method() != Method::GET {
+        let response = HttpResponse::Forbidden().json("Read-only access may only use GET");
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+
+    req.extensions_mut().insert(identity);
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
@@ -0,0 +1,191 @@
+//! Away mode: automatic replies while nobody is watching the node.
+//!
+//! While away mode is on, every chat message is answered with the away
+//! message, at most once per sender per interval, through the server that
+//! forwarded it. The settings are stored as JSON in the node's data
+//! directory; when each conversation was last auto-replied to is only kept
+//! in memory.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use super::delivery::OutgoingMessage;
+use super::inbox::Envelope;
+use super::outbox::Outbox;
+use super::session::SessionIds;
+use super::{unix_millis, write_json_atomic};
+
+/// Reply sent when no away message is set.
+const DEFAULT_MESSAGE: &str = "I'm away right now and will answer later.";
+/// Default time between two auto-replies to the same sender, in seconds.
+const DEFAULT_INTERVAL_SECS: u64 = 60 * 60;
+
+/// Away mode as set by the user.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AwaySettings {
+    /// Whether incoming messages are auto-replied to.
+    pub enabled: bool,
+    /// The auto-reply; a default text is used if missing.
+    pub message: Option<String>,
+    /// Minimum time between two auto-replies to the same sender, in seconds.
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_interval_secs() -> u64 {
+    DEFAULT_INTERVAL_SECS
+}
+
+impl Default for AwaySettings {
+    fn default() -> Self {
+        AwaySettings {
+            enabled: false,
+            message: None,
+            interval_secs: DEFAULT_INTERVAL_SECS,
+        }
+    }
+}
+
+/// Away settings together with the conversations auto-replied to.
+#[derive(Debug, Clone, Serialize)]
+pub struct AwayStatus {
+    /// Current settings.
+    #[serde(flatten)]
+    pub settings: AwaySettings,
+    /// Unix time in milliseconds of the last auto-reply, per peer.
+    pub auto_replied: BTreeMap<u8, u64>,
+}
+
+#[derive(Debug)]
+struct AwayState {
+    settings: AwaySettings,
+    last_reply: BTreeMap<u8, u64>,
+}
+
+/// Away mode of the node, answering chat messages while it is on.
+#[derive(Debug)]
+pub struct AwayMode {
+    path: PathBuf,
+    state: Mutex<AwayState>,
+    node_id: u8,
+    session_ids: Arc<SessionIds>,
+    outbox: Arc<Outbox>,
+}
+
+impl AwayMode {
+    /// Loads the settings from `path`, starting with away mode off if the
+    /// file doesn't exist. Auto-replies are sent as node `node_id` through
+    /// the `outbox`, like messages sent via `/send`.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read or parsed.
+    pub fn load(
+        path: &Path,
+        node_id: u8,
+        session_ids: Arc<SessionIds>,
+        outbox: Arc<Outbox>,
+    ) -> io::Result<Self> {
+        let settings = match fs::read(path) {
+            Ok(json) => serde_json::from_slice(&json).map_err(io::Error::other)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => AwaySettings::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(AwayMode {
+            path: path.to_path_buf(),
+            state: Mutex::new(AwayState {
+                settings,
+                last_reply: BTreeMap::new(),
+            }),
+            node_id,
+            session_ids,
+            outbox,
+        })
+    }
+
+    /// Returns the settings and the conversations auto-replied to.
+    #[must_use]
+    pub fn status(&self) -> AwayStatus {
+        let state = self.lock();
+        AwayStatus {
+            settings: state.settings.clone(),
+            auto_replied: state.last_reply.clone(),
+        }
+    }
+
+    /// Replaces the settings and persists them. Turning away mode on starts
+    /// a fresh round of auto-replies.
+    ///
+    /// # Errors
+    /// Returns an error if the settings can't be written.
+    pub fn set(&self, settings: AwaySettings) -> io::Result<()> {
+        let mut state = self.lock();
+        write_json_atomic(&self.path, &settings)?;
+        if settings.enabled && !state.settings.enabled {
+            state.last_reply.clear();
+        }
+        state.settings = settings;
+        Ok(())
+    }
+
+    /// Auto-replies to the chat messages in `envelopes` whose sender is due
+    /// for one, if away mode is on.
+    pub fn respond(&self, envelopes: &[Envelope]) {
+        let outgoing = {
+            let mut state = self.lock();
+            if !state.settings.enabled {
+                return;
+            }
+            let now = unix_millis();
+            let interval_ms = state.settings.interval_secs.saturating_mul(1000);
+            let message = state
+                .settings
+                .message
+                .clone()
+                .unwrap_or_else(|| DEFAULT_MESSAGE.to_string());
+
+            let mut outgoing = vec![];
+            for envelope in envelopes
+                .iter()
+                .filter(|envelope| envelope.is_chat_message())
+            {
+                let (Some(server_id), Some(client_id)) = (envelope.source, envelope.sender())
+                else {
+                    continue;
+                };
+                if client_id == self.node_id
+                    || state
+                        .last_reply
+                        .get(&client_id)
+                        .is_some_and(|at| now.saturating_sub(*at) < interval_ms)
+                {
+                    continue;
+                }
+                state.last_reply.insert(client_id, now);
+                outgoing.push(OutgoingMessage {
+                    server_id,
+                    client_id,
+                    session_id: self.session_ids.next(),
+                    message: message.clone(),
+                });
+            }
+            outgoing
+        };
+
+        if outgoing.is_empty() {
+            return;
+        }
+        for (outgoing, result) in self.outbox.enqueue(outgoing) {
+            if let Err(e) = result {
+                tracing::warn!("Failed to auto-reply to {}: {e}", outgoing.client_id);
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, AwayState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,232 @@
+//! Content-addressed storage of files with reference counting.
+//!
+//! The same image is often fetched from several servers or under several IDs,
+//! and the same file uploaded to many peers. A [`BlobStore`] keeps each
+//! distinct content once, named by its SHA-256 hash, and maps any number of
+//! references (e.g. `<server_id>-<media_id>`) to it. A blob is deleted once
+//! its last reference is released. `/admin/storage` reports how much space
+//! that saves.
+//!
+//! Blobs are ordinary files in the store's directory, so they keep counting
+//! against its quota and may be evicted by it; a reference whose blob was
+//! evicted is dropped the next time it is looked up.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use super::write_json_atomic;
+
+/// A stored content.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Blob {
+    bytes: u64,
+    references: u64,
+}
+
+/// Which reference points to which blob.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    // Reference -> hash
+    references: BTreeMap<String, String>,
+    // Hash -> blob
+    blobs: BTreeMap<String, Blob>,
+}
+
+/// Space saved by storing every content once, served by `/admin/storage`.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct StorageSavings {
+    /// Number of distinct contents stored.
+    pub blobs: usize,
+    /// Number of references to them.
+    pub references: u64,
+    /// Bytes the blobs take.
+    pub stored_bytes: u64,
+    /// Bytes a copy per reference would take.
+    pub logical_bytes: u64,
+    /// Bytes not written thanks to deduplication.
+    pub saved_bytes: u64,
+}
+
+/// Files stored once per content.
+#[derive(Debug)]
+pub struct BlobStore {
+    dir: PathBuf,
+    index_path: Option<PathBuf>,
+    index: Mutex<Index>,
+}
+
+impl BlobStore {
+    /// Opens the store in `dir`, creating the directory if needed. The references
+    /// are kept in `index_path` across restarts if given, otherwise only in memory.
+    ///
+    /// # Errors
+    /// Returns an error if the directory can't be created or the index can't be read.
+    pub fn open(dir: &Path, index_path: Option<PathBuf>) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let index = match &index_path {
+            Some(path) if path.exists() => {
+                serde_json::from_slice(&fs::read(path)?).map_err(io::Error::other)?
+            }
+            _ => Index::default(),
+        };
+        Ok(BlobStore {
+            dir: dir.to_path_buf(),
+            index_path,
+            index: Mutex::new(index),
+        })
+    }
+
+    /// Path of the blob `reference` points to, if it is still stored.
+    #[must_use]
+    pub fn get(&self, reference: &str) -> Option<PathBuf> {
+        let mut index = self.lock();
+        let hash = index.references.get(reference)?.clone();
+        let path = self.dir.join(&hash);
+        if path.is_file() {
+            return Some(path);
+        }
+        // Evicted behind our back
+        unlink(&mut index, reference);
+        index.blobs.remove(&hash);
+        self.save(&index);
+        None
+    }
+
+    /// Stores `bytes` under `reference` and returns the path of the blob.
+    /// Writes nothing if the same content is stored already.
+    ///
+    /// # Errors
+    /// Returns an error if the blob can't be written.
+    pub fn put(&self, reference: &str, bytes: &[u8]) -> io::Result<PathBuf> {
+        let hash = hash(bytes);
+        self.link(reference, &hash, bytes.len() as u64, |path| {
+            // Written under a temporary name so a crash never leaves half a blob behind
+            let tmp = path.with_extension("tmp");
+            fs::write(&tmp, bytes).and_then(|()| fs::rename(&tmp, path))
+        })
+    }
+
+    /// Moves the file at `file` into the store under `reference` and returns the
+    /// path of the blob. If the same content is stored already, the file is removed.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read or moved.
+    pub fn put_file(&self, reference: &str, file: &Path) -> io::Result<PathBuf> {
+        let bytes = fs::read(file)?;
+        let path = self.link(reference, &hash(&bytes), bytes.len() as u64, |path| {
+            fs::rename(file, path)
+        })?;
+        if file.exists() {
+            fs::remove_file(file)?;
+        }
+        Ok(path)
+    }
+
+    /// Drops `reference`, deleting its blob if it was the last reference to it.
+    pub fn release(&self, reference: &str) {
+        let mut index = self.lock();
+        if let Some(hash) = unlink(&mut index, reference) {
+            let _ = fs::remove_file(self.dir.join(hash));
+        }
+        self.save(&index);
+    }
+
+    /// How much space deduplication saves.
+    #[must_use]
+    pub fn savings(&self) -> StorageSavings {
+        let index = self.lock();
+        let stored_bytes: u64 = index.blobs.values().map(|blob| blob.bytes).sum();
+        let logical_bytes: u64 = index
+            .blobs
+            .values()
+            .map(|blob| blob.bytes.saturating_mul(blob.references))
+            .sum();
+        StorageSavings {
+            blobs: index.blobs.len(),
+            references: index.blobs.values().map(|blob| blob.references).sum(),
+            stored_bytes,
+            logical_bytes,
+            saved_bytes: logical_bytes.saturating_sub(stored_bytes),
+        }
+    }
+
+    /// Points `reference` at blob `hash`, calling `write` to create the blob if
+    /// it isn't stored yet.
+    fn link(
+        &self,
+        reference: &str,
+        hash: &str,
+        bytes: u64,
+        write: impl FnOnce(&Path) -> io::Result<()>,
+    ) -> io::Result<PathBuf> {
+        let path = self.dir.join(hash);
+        let mut index = self.lock();
+        if index
+            .references
+            .get(reference)
+            .is_some_and(|linked| linked == hash)
+            && path.is_file()
+        {
+            return Ok(path);
+        }
+        if let Some(previous) = unlink(&mut index, reference).filter(|previous| previous != hash) {
+            let _ = fs::remove_file(self.dir.join(previous));
+        }
+        if !path.is_file()
+            && let Err(e) = write(&path)
+        {
+            self.save(&index);
+            return Err(e);
+        }
+        index
+            .references
+            .insert(reference.to_string(), hash.to_string());
+        index
+            .blobs
+            .entry(hash.to_string())
+            .or_insert(Blob {
+                bytes,
+                references: 0,
+            })
+            .references += 1;
+        self.save(&index);
+        Ok(path)
+    }
+
+    /// Writes the index, if it is kept. A failed write only loses the
+    /// references made since the last one.
+    fn save(&self, index: &Index) {
+        if let Some(path) = &self.index_path
+            && let Err(e) = write_json_atomic(path, index)
+        {
+            tracing::error!("Failed to write blob index {}: {e}", path.display());
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Index> {
+        self.index.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Removes `reference` from `index`. Returns the hash of its blob if that
+/// was the last reference to it, so the caller can delete the file.
+fn unlink(index: &mut Index, reference: &str) -> Option<String> {
+    let hash = index.references.remove(reference)?;
+    let blob = index.blobs.get_mut(&hash)?;
+    blob.references = blob.references.saturating_sub(1);
+    if blob.references > 0 {
+        return None;
+    }
+    index.blobs.remove(&hash);
+    Some(hash)
+}
+
+/// Hex SHA-256 of `bytes`.
+fn hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
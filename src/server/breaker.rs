@@ -0,0 +1,184 @@
+//! Circuit breaker for requests to the backend.
+//!
+//! A wedged backend makes every request wait out its whole timeout, which ties
+//! up workers and leaves the UI hanging. The [`Dispatcher`](super::dispatcher::Dispatcher)
+//! therefore reports to a [`CircuitBreaker`] whether the backend answered.
+//! After `ServerOptions::breaker_failures` timeouts or disconnects in a row the
+//! breaker opens, and requests fail right away, which the endpoints answer
+//! with HTTP 503 and a `Retry-After`. Once `ServerOptions::breaker_cooldown`
+//! has passed, a single request is let through: if the backend answers it, the
+//! breaker closes again, otherwise it opens for another cool-down. Its state
+//! is part of `/status`.
+
+use serde::Serialize;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use super::ServerOptions;
+use super::unix_millis;
+
+/// Whether requests are let through to the backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    /// Requests go through.
+    Closed,
+    /// Requests fail right away until the cool-down is over.
+    Open,
+    /// A single request tests whether the backend recovered.
+    HalfOpen,
+}
+
+/// What `/status` shows of the breaker.
+#[derive(Debug, Clone, Serialize)]
+pub struct BreakerStatus {
+    /// The current state.
+    pub state: BreakerState,
+    /// Requests that timed out or found the backend gone since the last answer.
+    pub consecutive_failures: u32,
+    /// How often the breaker opened.
+    pub trips: u64,
+    /// Unix time in milliseconds when the breaker last opened, if ever.
+    pub last_opened_at: Option<u64>,
+    /// Seconds until a request is let through again, while open.
+    pub retry_after_secs: Option<u64>,
+}
+
+#[derive(Debug)]
+struct Breaker {
+    consecutive_failures: u32,
+    trips: u64,
+    last_opened_at: Option<u64>,
+    // Until when requests fail right away
+    open_until: Option<Instant>,
+    // When the request testing the backend after a cool-down was let through
+    trial_since: Option<Instant>,
+}
+
+/// Stops sending requests to a backend that keeps failing, see the [module docs](self).
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failures: u32,
+    cooldown: Duration,
+    state: Mutex<Breaker>,
+}
+
+impl CircuitBreaker {
+    /// A closed breaker opening after `ServerOptions::breaker_failures` failures in a row
+    /// for `ServerOptions::breaker_cooldown`.
+    #[must_use]
+    pub fn new(options: &ServerOptions) -> Self {
+        CircuitBreaker {
+            failures: options.breaker_failures.max(1),
+            cooldown: options.breaker_cooldown,
+            state: Mutex::new(Breaker {
+                consecutive_failures: 0,
+                trips: 0,
+                last_opened_at: None,
+                open_until: None,
+                trial_since: None,
+            }),
+        }
+    }
+
+    /// Lets a request through, or returns how long to wait before trying again.
+    /// After the cool-down, only one request at a time is let through until one
+    /// of them is answered.
+    ///
+    /// # Errors
+    /// Returns the time left until requests are let through again while the breaker
+    /// is open or another request is testing the backend.
+    pub fn acquire(&self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut breaker = self.lock();
+        let Some(open_until) = breaker.open_until else {
+            return Ok(());
+        };
+        if now < open_until {
+            return Err(open_until - now);
+        }
+        // A trial whose requester never reported back doesn't block forever
+        match breaker.trial_since {
+            Some(since) if now.duration_since(since) < self.cooldown => {
+                Err(self.cooldown - now.duration_since(since))
+            }
+            _ => {
+                breaker.trial_since = Some(now);
+                Ok(())
+            }
+        }
+    }
+
+    /// Like [`CircuitBreaker::acquire`], but without letting a request through to test
+    /// the backend, for requests whose answer isn't reported.
+    ///
+    /// # Errors
+    /// Returns the time left while the breaker is open.
+    pub fn check(&self) -> Result<(), Duration> {
+        let now = Instant::now();
+        match self.lock().open_until {
+            Some(open_until) if now < open_until => Err(open_until - now),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records that the backend answered, closing the breaker.
+    pub fn record_success(&self) {
+        let mut breaker = self.lock();
+        if breaker.open_until.is_some() {
+            tracing::info!("The backend answered again; closing the circuit breaker");
+        }
+        breaker.consecutive_failures = 0;
+        breaker.open_until = None;
+        breaker.trial_since = None;
+    }
+
+    /// Records that a request timed out or found the backend gone, opening the
+    /// breaker after too many in a row or if the request was testing the backend.
+    pub fn record_failure(&self) {
+        let mut breaker = self.lock();
+        breaker.consecutive_failures = breaker.consecutive_failures.saturating_add(1);
+        let testing = breaker.trial_since.take().is_some();
+        if testing || breaker.consecutive_failures == self.failures {
+            if breaker.open_until.is_none() {
+                tracing::warn!(
+                    failures = breaker.consecutive_failures,
+                    "The backend keeps failing; opening the circuit breaker for {:?}",
+                    self.cooldown
+                );
+            }
+            breaker.trips += 1;
+            breaker.last_opened_at = Some(unix_millis());
+            breaker.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+
+    /// The state of the breaker as of now.
+    #[must_use]
+    pub fn status(&self) -> BreakerStatus {
+        let now = Instant::now();
+        let breaker = self.lock();
+        let (state, retry_after) = match breaker.open_until {
+            None => (BreakerState::Closed, None),
+            Some(open_until) if now < open_until => (BreakerState::Open, Some(open_until - now)),
+            Some(_) => (BreakerState::HalfOpen, None),
+        };
+        BreakerStatus {
+            state,
+            consecutive_failures: breaker.consecutive_failures,
+            trips: breaker.trips,
+            last_opened_at: breaker.last_opened_at,
+            retry_after_secs: retry_after.map(retry_after_secs),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Breaker> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// `left` in whole seconds, rounded up, for a `Retry-After` header.
+#[must_use]
+pub fn retry_after_secs(left: Duration) -> u64 {
+    left.as_secs() + u64::from(left.subsec_nanos() > 0)
+}
@@ -0,0 +1,64 @@
+//! Machine-readable changelog of the HTTP API.
+//!
+//! The API is versioned by a revision number that grows by one with every
+//! feature. Each feature is annotated next to its handlers with an
+//! [`ApiChange`] in `endpoints::API_CHANGES`, so a UI build can ask
+//! `/api/changes` whether the node supports a feature before using it:
+//! either by its name or by comparing revisions. Routes that are on their way
+//! out are listed as [`Deprecation`]s.
+
+use serde::Serialize;
+
+/// A feature added to the API.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ApiChange {
+    /// API revision that introduced the feature.
+    pub revision: u32,
+    /// Stable name to check for the feature.
+    pub feature: &'static str,
+    /// Routes added or changed by the feature.
+    pub routes: &'static [&'static str],
+    /// What the feature does.
+    pub summary: &'static str,
+}
+
+/// A route that is going to be removed.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Deprecation {
+    /// API revision that deprecated the route.
+    pub revision: u32,
+    /// The deprecated route.
+    pub route: &'static str,
+    /// Route to use instead, if any.
+    pub replacement: Option<&'static str>,
+}
+
+/// The changelog as served by `/api/changes`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiChangelog {
+    /// Current API revision.
+    pub revision: u32,
+    /// Every feature, oldest first.
+    pub changes: &'static [ApiChange],
+    /// Deprecated routes, oldest first.
+    pub deprecations: &'static [Deprecation],
+}
+
+impl ApiChangelog {
+    /// Builds the changelog from `changes` and `deprecations`; the current
+    /// revision is the newest one mentioned.
+    #[must_use]
+    pub fn new(changes: &'static [ApiChange], deprecations: &'static [Deprecation]) -> Self {
+        let revision = changes
+            .iter()
+            .map(|change| change.revision)
+            .chain(deprecations.iter().map(|deprecation| deprecation.revision))
+            .max()
+            .unwrap_or(0);
+        ApiChangelog {
+            revision,
+            changes,
+            deprecations,
+        }
+    }
+}
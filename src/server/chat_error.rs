@@ -0,0 +1,122 @@
+//! Errors reported by the chat protocol, mapped to HTTP statuses.
+//!
+//! A server that can't serve a request answers with an error message instead
+//! of the expected reply. [`ChatError::from_payload`] tells the common cases
+//! apart by the variant names of the reply and the text of an `Error` variant,
+//! so handlers answer with 404 when the addressed client is unknown, 409 when
+//! this node isn't registered with the server and 502 when the message could
+//! not be routed or the server failed otherwise, instead of passing the raw
+//! reply on under a generic status.
+
+use actix_web::HttpResponse;
+use actix_web::http::StatusCode;
+use serde::Serialize;
+use serde_json::Value;
+
+use super::inbox::unwrap_content;
+
+/// Variants that make a reply an error even outside of an `Error` variant.
+const ERROR_VARIANTS: [&str; 6] = [
+    "ClientNotFound",
+    "UnknownClient",
+    "NotRegistered",
+    "Unregistered",
+    "Nack",
+    "Unreachable",
+];
+/// Markers of a reply about an unknown client.
+const CLIENT_NOT_FOUND: [&str; 3] = ["clientnotfound", "client not found", "unknownclient"];
+/// Markers of a reply about a client that isn't registered.
+const NOT_REGISTERED: [&str; 3] = ["notregistered", "not registered", "unregistered"];
+/// Markers of a reply about a message that couldn't be routed.
+const ROUTING: [&str; 5] = ["route", "routing", "unreachable", "nack", "dropped"];
+
+/// What went wrong, as far as the reply tells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatErrorKind {
+    /// The server doesn't know the addressed client.
+    ClientNotFound,
+    /// This node isn't registered with the server.
+    NotRegistered,
+    /// The message couldn't be routed to its destination.
+    Routing,
+    /// Any other error reported by the server.
+    Other,
+}
+
+/// An error reply of a server, with the details the UI shows.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatError {
+    /// What went wrong.
+    pub kind: ChatErrorKind,
+    /// The error text or unit variant ending the reply, or else its variant names.
+    pub detail: String,
+    /// The reply as received from the backend.
+    pub reply: Value,
+}
+
+impl ChatError {
+    /// Classifies the message `payload`; `None` if it isn't an error reply.
+    #[must_use]
+    pub fn from_payload(payload: &Value) -> Option<Self> {
+        let (path, inner) = unwrap_content(payload);
+        if !is_error_reply(&path) {
+            return None;
+        }
+        let detail = match inner {
+            Some(Value::String(text)) => text.clone(),
+            _ => path.join("::"),
+        };
+        let haystack = format!("{} {detail}", path.join(" ")).to_lowercase();
+        let matches = |markers: &[&str]| markers.iter().any(|marker| haystack.contains(marker));
+
+        let kind = if matches(&CLIENT_NOT_FOUND) {
+            ChatErrorKind::ClientNotFound
+        } else if matches(&NOT_REGISTERED) {
+            ChatErrorKind::NotRegistered
+        } else if matches(&ROUTING) {
+            ChatErrorKind::Routing
+        } else {
+            ChatErrorKind::Other
+        };
+        Some(ChatError {
+            kind,
+            detail,
+            reply: payload.clone(),
+        })
+    }
+
+    /// HTTP status answering the error.
+    #[must_use]
+    pub fn status(&self) -> StatusCode {
+        match self.kind {
+            ChatErrorKind::ClientNotFound => StatusCode::NOT_FOUND,
+            ChatErrorKind::NotRegistered => StatusCode::CONFLICT,
+            ChatErrorKind::Routing | ChatErrorKind::Other => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    /// The error as an HTTP response with its details.
+    #[must_use]
+    pub fn response(&self) -> HttpResponse {
+        HttpResponse::build(self.status()).json(self)
+    }
+}
+
+/// Whether a reply with the content variant names `path` is an error: an
+/// `Error` variant or one of the variants reporting a failure directly.
+#[must_use]
+pub fn is_error_reply(path: &[&str]) -> bool {
+    path.first() == Some(&"Error") || path.iter().any(|name| ERROR_VARIANTS.contains(name))
+}
+
+/// Answers a server's unexpected `reply`: with the mapped status if it is an
+/// error reply, otherwise with HTTP 502 and the reply as is.
+#[must_use]
+pub fn error_response(reply: Value) -> HttpResponse {
+    match ChatError::from_payload(&reply) {
+        Some(error) => error.response(),
+        None => HttpResponse::BadGateway().json(reply),
+    }
+}
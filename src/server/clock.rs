@@ -0,0 +1,105 @@
+//! Estimation of how far the clocks of peer frontends are off from ours.
+//!
+//! A chat message whose content carries the author's `sent_at` time (Unix
+//! milliseconds) yields one sample: the peer sent it at `sent_at` by its
+//! clock, and it arrived at `received_at` by ours, one one-way trip later.
+//! The trip is taken as the baseline round trip time to the forwarding
+//! server, i.e. our half of it plus an equally long half on the peer's side.
+//! The estimate is the median of the latest samples, which rides out single
+//! slow deliveries. Messages without `sent_at` yield no sample.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use super::inbox::Envelope;
+use super::stats::NetworkStats;
+
+/// Number of latest samples the estimate is based on.
+const WINDOW: usize = 32;
+
+/// Clock offset estimate for a peer.
+#[derive(Debug, Clone, Serialize)]
+pub struct ClockEstimate {
+    /// The peer.
+    pub peer: u8,
+    /// How far the peer's clock is ahead of ours in milliseconds (negative if
+    /// behind); subtract it from the peer's times to get ours. `None` without samples.
+    pub offset_ms: Option<i64>,
+    /// Number of samples the estimate is based on.
+    pub samples: usize,
+    /// Difference between the largest and smallest sample in milliseconds,
+    /// a rough measure of the estimate's uncertainty.
+    pub spread_ms: Option<i64>,
+}
+
+/// Clock offset samples per peer.
+#[derive(Debug)]
+pub struct PeerClocks {
+    samples: Mutex<BTreeMap<u8, VecDeque<i64>>>,
+    network_stats: Arc<NetworkStats>,
+}
+
+impl PeerClocks {
+    /// Creates an estimator taking round trip times from `network_stats`.
+    #[must_use]
+    pub fn new(network_stats: Arc<NetworkStats>) -> Self {
+        PeerClocks {
+            samples: Mutex::new(BTreeMap::new()),
+            network_stats,
+        }
+    }
+
+    /// Takes a sample from a received chat message carrying its send time.
+    pub fn observe(&self, envelope: &Envelope) {
+        let (Some(peer), Some(sent_at)) = (envelope.sender(), envelope.sent_at()) else {
+            return;
+        };
+        let one_way_ms = envelope.source.map_or(0.0, |server| {
+            self.network_stats.with_nodes(|nodes| {
+                nodes
+                    .get(&server)
+                    .and_then(|stats| stats.baseline_latency_ms)
+                    .unwrap_or(0.0)
+            })
+        });
+        let offset = to_signed(sent_at)
+            .saturating_add(one_way_ms.round() as i64)
+            .saturating_sub(to_signed(envelope.received_at));
+
+        let mut samples = self.lock();
+        let peer_samples = samples.entry(peer).or_default();
+        if peer_samples.len() == WINDOW {
+            peer_samples.pop_front();
+        }
+        peer_samples.push_back(offset);
+    }
+
+    /// Returns the current estimate for `peer`.
+    #[must_use]
+    pub fn estimate(&self, peer: u8) -> ClockEstimate {
+        let mut sorted: Vec<i64> = self
+            .lock()
+            .get(&peer)
+            .map(|samples| samples.iter().copied().collect())
+            .unwrap_or_default();
+        sorted.sort_unstable();
+        ClockEstimate {
+            peer,
+            offset_ms: sorted.get(sorted.len() / 2).copied(),
+            samples: sorted.len(),
+            spread_ms: sorted
+                .first()
+                .zip(sorted.last())
+                .map(|(min, max)| max - min),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<u8, VecDeque<i64>>> {
+        self.samples.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn to_signed(millis: u64) -> i64 {
+    i64::try_from(millis).unwrap_or(i64::MAX)
+}
@@ -0,0 +1,490 @@
+//! Settings read from a TOML file.
+//!
+//! Every key is optional; a missing one keeps the default of
+//! [`ServerOptions`], an unknown one is an error so typos don't go unnoticed.
+//! Durations are given in seconds. [`load`] returns the resulting options,
+//! which feed [`start_server`](super::start_server) directly or a
+//! [`Client`](crate::Client) through `ClientBuilder::config_file`. `Client::builder`,
+//! as called by the simulation controller, reads the file named by
+//! [`CONFIG_ENV`], if set.
+//!
+//! Environment variables override single keys of the file, so a container can
+//! be configured without mounting one: the key in upper case with
+//! [`ENV_PREFIX`], prefixed by its section if it has one, e.g.
+//! `FRONTEND_BIND_ADDRESS`, `FRONTEND_METRICS=false` or
+//! `FRONTEND_TIMEOUTS_REGISTER_SECS=10`. Lists, like
+//! `FRONTEND_CORS_ALLOWED_ORIGINS`, are separated by commas. An empty variable
+//! counts as unset.
+//!
+//! ```toml
+//! bind_address = "0.0.0.0"
+//! port = 9001
+//! static_dir = "ui/dist"
+//! metrics = false
+//!
+//! [timeouts]
+//! register_secs = 5
+//! content_secs = 5
+//!
+//! [flood]
+//! cache_ttl_secs = 30
+//! refresh_interval_secs = 60
+//!
+//! [breaker]
+//! failures = 5
+//! cooldown_secs = 10
+//!
+//! [outbox]
+//! max_attempts = 5
+//! backoff_secs = 1
+//! max_backoff_secs = 60
+//! ack_timeout_secs = 30
+//!
+//! [auth]
+//! users_file = "users.json"
+//! session_idle_secs = 1800
+//! session_lifetime_secs = 43200
+//!
+//! [[route_limits]]
+//! route = "/send"
+//! max_in_flight = 8
+//! queue_secs = 1
+//! ```
+//!
+//! `route_limits`, if given, replace the default limits; a limit without
+//! `queue_secs` turns requests beyond it away at once. The timeouts, the flood
+//! cache TTL, the route limits and `log_filter` can be reloaded while the node
+//! runs, see [`settings`](super::settings).
+
+use serde::Deserialize;
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::ServerOptions;
+use super::auth::FileUsers;
+use super::cors::CorsOptions;
+use super::limits::{Overflow, RouteLimit};
+use super::tls::TlsOptions;
+
+/// Environment variable naming the configuration file of a [`Client`](crate::Client).
+pub const CONFIG_ENV: &str = "FRONTEND_CONFIG";
+/// Prefix of the environment variables overriding single keys.
+pub const ENV_PREFIX: &str = "FRONTEND_";
+
+/// Contents of the configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    bind_address: Option<IpAddr>,
+    port: Option<u16>,
+    unix_socket: Option<PathBuf>,
+    data_dir: Option<PathBuf>,
+    static_dir: Option<PathBuf>,
+    shutdown_report: Option<PathBuf>,
+    metrics: Option<bool>,
+    access_log: Option<bool>,
+    log_filter: Option<String>,
+    public_viewer: Option<bool>,
+    behind_tls_proxy: Option<bool>,
+    auto_register: Option<bool>,
+    read_receipts: Option<bool>,
+    json_limit: Option<usize>,
+    max_message_len: Option<usize>,
+    #[serde(default)]
+    timeouts: Timeouts,
+    #[serde(default)]
+    flood: Flood,
+    #[serde(default)]
+    breaker: Breaker,
+    #[serde(default)]
+    outbox: Outbox,
+    tls: Option<Tls>,
+    auth: Option<Auth>,
+    cors: Option<Cors>,
+    route_limits: Option<Vec<Limit>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Timeouts {
+    register_secs: Option<f64>,
+    content_secs: Option<f64>,
+    backend_ping_secs: Option<f64>,
+    shutdown_secs: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Flood {
+    timeout_secs: Option<f64>,
+    cache_ttl_secs: Option<f64>,
+    refresh_interval_secs: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Breaker {
+    failures: Option<u32>,
+    cooldown_secs: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Outbox {
+    max_attempts: Option<u32>,
+    backoff_secs: Option<f64>,
+    max_backoff_secs: Option<f64>,
+    ack_timeout_secs: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Tls {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Auth {
+    users_file: PathBuf,
+    session_idle_secs: Option<f64>,
+    session_lifetime_secs: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Cors {
+    allowed_origins: Vec<String>,
+    #[serde(default)]
+    allowed_methods: Vec<String>,
+    #[serde(default)]
+    allowed_headers: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Limit {
+    route: String,
+    max_in_flight: usize,
+    queue_secs: Option<f64>,
+}
+
+/// Reads the configuration file at `path` on top of the default options, then
+/// applies the overrides from the environment.
+///
+/// # Errors
+/// Returns an error if the file can't be read, isn't valid TOML, has unknown
+/// keys or holds a negative or infinite duration, or if an environment
+/// variable holds an invalid value.
+pub fn load(path: &Path) -> io::Result<ServerOptions> {
+    let text = fs::read_to_string(path)?;
+    let config: FileConfig = toml::from_str(&text)
+        .map_err(|e| invalid(format!("Invalid configuration in {}: {e}", path.display())))?;
+    let mut options = ServerOptions {
+        config_file: Some(path.to_path_buf()),
+        ..ServerOptions::default()
+    };
+    config.apply(&mut options)?;
+    apply_env(&mut options)?;
+    Ok(options)
+}
+
+/// Like [`load`], for the file named by [`CONFIG_ENV`]; without one, the
+/// default options with the overrides from the environment.
+///
+/// # Errors
+/// Same as [`load`].
+pub fn load_from_env() -> io::Result<ServerOptions> {
+    match std::env::var_os(CONFIG_ENV).filter(|path| !path.is_empty()) {
+        Some(path) => load(Path::new(&path)),
+        None => {
+            let mut options = ServerOptions::default();
+            apply_env(&mut options)?;
+            Ok(options)
+        }
+    }
+}
+
+impl FileConfig {
+    fn apply(self, options: &mut ServerOptions) -> io::Result<()> {
+        set(&mut options.bind_address, self.bind_address);
+        set(&mut options.port, self.port.map(Some));
+        set(&mut options.unix_socket, self.unix_socket.map(Some));
+        set(&mut options.data_dir, self.data_dir);
+        set(&mut options.static_dir, self.static_dir);
+        set(&mut options.shutdown_report, self.shutdown_report.map(Some));
+        set(&mut options.metrics, self.metrics);
+        set(&mut options.access_log, self.access_log);
+        set(&mut options.log_filter, self.log_filter.map(Some));
+        set(&mut options.public_viewer, self.public_viewer);
+        set(&mut options.behind_tls_proxy, self.behind_tls_proxy);
+        set(&mut options.auto_register, self.auto_register);
+        set(&mut options.read_receipts, self.read_receipts);
+        set(&mut options.json_limit, self.json_limit);
+        set(&mut options.max_message_len, self.max_message_len);
+
+        set(
+            &mut options.register_timeout,
+            seconds(self.timeouts.register_secs)?,
+        );
+        set(
+            &mut options.content_timeout,
+            seconds(self.timeouts.content_secs)?,
+        );
+        set(
+            &mut options.backend_ping_timeout,
+            seconds(self.timeouts.backend_ping_secs)?,
+        );
+        set(
+            &mut options.shutdown_timeout,
+            seconds(self.timeouts.shutdown_secs)?,
+        );
+        set(
+            &mut options.flood_timeout,
+            seconds(self.flood.timeout_secs)?,
+        );
+        set(
+            &mut options.flood_cache_ttl,
+            seconds(self.flood.cache_ttl_secs)?,
+        );
+        set(
+            &mut options.flood_refresh_interval,
+            seconds(self.flood.refresh_interval_secs)?.map(Some),
+        );
+        set(&mut options.breaker_failures, self.breaker.failures);
+        set(
+            &mut options.breaker_cooldown,
+            seconds(self.breaker.cooldown_secs)?,
+        );
+        set(&mut options.outbox_max_attempts, self.outbox.max_attempts);
+        set(
+            &mut options.outbox_backoff,
+            seconds(self.outbox.backoff_secs)?,
+        );
+        set(
+            &mut options.outbox_max_backoff,
+            seconds(self.outbox.max_backoff_secs)?,
+        );
+        set(
+            &mut options.outbox_ack_timeout,
+            seconds(self.outbox.ack_timeout_secs)?,
+        );
+
+        if let Some(tls) = self.tls {
+            options.tls = Some(TlsOptions {
+                cert_path: tls.cert_path,
+                key_path: tls.key_path,
+            });
+        }
+        if let Some(auth) = self.auth {
+            options.auth_provider = Some(Arc::new(FileUsers::new(&auth.users_file)));
+            set(
+                &mut options.session_idle_timeout,
+                seconds(auth.session_idle_secs)?,
+            );
+            set(
+                &mut options.session_lifetime,
+                seconds(auth.session_lifetime_secs)?,
+            );
+        }
+        if let Some(cors) = self.cors {
+            options.cors = CorsOptions {
+                allowed_origins: cors.allowed_origins,
+                allowed_methods: cors.allowed_methods,
+                allowed_headers: cors.allowed_headers,
+            };
+        }
+        if let Some(limits) = self.route_limits {
+            options.route_limits = limits
+                .into_iter()
+                .map(|limit| {
+                    let overflow = match seconds(limit.queue_secs)? {
+                        Some(wait) => Overflow::Queue(wait),
+                        None => Overflow::Reject,
+                    };
+                    Ok(RouteLimit::new(&limit.route, limit.max_in_flight, overflow))
+                })
+                .collect::<io::Result<_>>()?;
+        }
+        Ok(())
+    }
+}
+
+/// Overrides the keys set in the environment, see the [module docs](self).
+fn apply_env(options: &mut ServerOptions) -> io::Result<()> {
+    set(&mut options.bind_address, var("BIND_ADDRESS")?);
+    set(&mut options.port, var("PORT")?.map(Some));
+    set(&mut options.unix_socket, var("UNIX_SOCKET")?.map(Some));
+    set(&mut options.data_dir, var("DATA_DIR")?);
+    set(&mut options.static_dir, var("STATIC_DIR")?);
+    set(
+        &mut options.shutdown_report,
+        var("SHUTDOWN_REPORT")?.map(Some),
+    );
+    set(&mut options.metrics, var("METRICS")?);
+    set(&mut options.access_log, var("ACCESS_LOG")?);
+    set(&mut options.log_filter, var("LOG_FILTER")?.map(Some));
+    set(&mut options.public_viewer, var("PUBLIC_VIEWER")?);
+    set(&mut options.behind_tls_proxy, var("BEHIND_TLS_PROXY")?);
+    set(&mut options.auto_register, var("AUTO_REGISTER")?);
+    set(&mut options.read_receipts, var("READ_RECEIPTS")?);
+    set(&mut options.json_limit, var("JSON_LIMIT")?);
+    set(&mut options.max_message_len, var("MAX_MESSAGE_LEN")?);
+
+    set(
+        &mut options.register_timeout,
+        seconds(var("TIMEOUTS_REGISTER_SECS")?)?,
+    );
+    set(
+        &mut options.content_timeout,
+        seconds(var("TIMEOUTS_CONTENT_SECS")?)?,
+    );
+    set(
+        &mut options.backend_ping_timeout,
+        seconds(var("TIMEOUTS_BACKEND_PING_SECS")?)?,
+    );
+    set(
+        &mut options.shutdown_timeout,
+        seconds(var("TIMEOUTS_SHUTDOWN_SECS")?)?,
+    );
+    set(
+        &mut options.flood_timeout,
+        seconds(var("FLOOD_TIMEOUT_SECS")?)?,
+    );
+    set(
+        &mut options.flood_cache_ttl,
+        seconds(var("FLOOD_CACHE_TTL_SECS")?)?,
+    );
+    set(
+        &mut options.flood_refresh_interval,
+        seconds(var("FLOOD_REFRESH_INTERVAL_SECS")?)?.map(Some),
+    );
+    set(&mut options.breaker_failures, var("BREAKER_FAILURES")?);
+    set(
+        &mut options.breaker_cooldown,
+        seconds(var("BREAKER_COOLDOWN_SECS")?)?,
+    );
+    set(
+        &mut options.outbox_max_attempts,
+        var("OUTBOX_MAX_ATTEMPTS")?,
+    );
+    set(
+        &mut options.outbox_backoff,
+        seconds(var("OUTBOX_BACKOFF_SECS")?)?,
+    );
+    set(
+        &mut options.outbox_max_backoff,
+        seconds(var("OUTBOX_MAX_BACKOFF_SECS")?)?,
+    );
+    set(
+        &mut options.outbox_ack_timeout,
+        seconds(var("OUTBOX_ACK_TIMEOUT_SECS")?)?,
+    );
+
+    let cert_path = var("TLS_CERT_PATH")?;
+    let key_path = var("TLS_KEY_PATH")?;
+    if cert_path.is_some() || key_path.is_some() {
+        let current = options.tls.take();
+        let (current_cert, current_key) = match current {
+            Some(tls) => (Some(tls.cert_path), Some(tls.key_path)),
+            None => (None, None),
+        };
+        let (Some(cert_path), Some(key_path)) =
+            (cert_path.or(current_cert), key_path.or(current_key))
+        else {
+            return Err(invalid(format!(
+                "{ENV_PREFIX}TLS_CERT_PATH and {ENV_PREFIX}TLS_KEY_PATH must be set together"
+            )));
+        };
+        options.tls = Some(TlsOptions {
+            cert_path,
+            key_path,
+        });
+    }
+    if let Some(users_file) = var::<PathBuf>("AUTH_USERS_FILE")? {
+        options.auth_provider = Some(Arc::new(FileUsers::new(&users_file)));
+    }
+    set(
+        &mut options.session_idle_timeout,
+        seconds(var("AUTH_SESSION_IDLE_SECS")?)?,
+    );
+    set(
+        &mut options.session_lifetime,
+        seconds(var("AUTH_SESSION_LIFETIME_SECS")?)?,
+    );
+    set(
+        &mut options.cors.allowed_origins,
+        list("CORS_ALLOWED_ORIGINS")?,
+    );
+    set(
+        &mut options.cors.allowed_methods,
+        list("CORS_ALLOWED_METHODS")?,
+    );
+    set(
+        &mut options.cors.allowed_headers,
+        list("CORS_ALLOWED_HEADERS")?,
+    );
+    Ok(())
+}
+
+/// The value of the environment variable [`ENV_PREFIX`] `key`, if set.
+fn var<T>(key: &str) -> io::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let name = format!("{ENV_PREFIX}{key}");
+    let Some(value) = std::env::var_os(&name) else {
+        return Ok(None);
+    };
+    let value = value
+        .into_string()
+        .map_err(|_| invalid(format!("{name} isn't valid UTF-8")))?;
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value
+        .parse()
+        .map(Some)
+        .map_err(|e| invalid(format!("Invalid {name}={value}: {e}")))
+}
+
+/// The comma-separated list in the environment variable [`ENV_PREFIX`] `key`, if set.
+fn list(key: &str) -> io::Result<Option<Vec<String>>> {
+    Ok(var::<String>(key)?.map(|value| {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect()
+    }))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Overwrites `target` with `value`, if there is one.
+fn set<T>(target: &mut T, value: Option<T>) {
+    if let Some(value) = value {
+        *target = value;
+    }
+}
+
+/// Converts a number of seconds from the file into a duration.
+fn seconds(secs: Option<f64>) -> io::Result<Option<Duration>> {
+    secs.map(|secs| {
+        Duration::try_from_secs_f64(secs)
+            .map_err(|e| invalid(format!("Invalid duration of {secs} seconds: {e}")))
+    })
+    .transpose()
+}
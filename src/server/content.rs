@@ -0,0 +1,298 @@
+//! The content subset of the protocol: the files offered by content servers.
+//!
+//! Requests are built here and sent like any other message; the replies are
+//! picked out of the inbox by [`Inbox::request`](super::inbox::Inbox::request)
+//! and parsed from their JSON payload, like the chat replies.
+//!
+//! Text files can reference media as `[media:<id>]`. The media are fetched
+//! from a media server into the [`MediaCache`](super::media::MediaCache) and
+//! linked into the file by [`AssembledFile`] as `/media/{server_id}/{media_id}`
+//! URLs, so the browser can render the file in one go.
+//!
+//! Files are uploaded in chunks, one request per chunk, see [`upload_chunk`].
+
+use messages::{MediaRequest, Message, MessageType, RequestType, TextRequest};
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt::Write;
+
+use super::inbox::Envelope;
+
+/// Opening of a media reference in a text file, followed by the media ID and `]`.
+const MEDIA_REFERENCE: &str = "[media:";
+
+/// A file offered by a content server.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileEntry {
+    /// File ID to fetch the file with, if the server reports one.
+    pub id: Option<u64>,
+    /// File name, if the server reports one.
+    pub name: Option<String>,
+}
+
+/// Builds the request for the list of files of server `server_id`.
+#[must_use]
+pub fn list_files(node_id: u8, server_id: u8, session_id: u64) -> Message {
+    Message {
+        source: node_id,
+        destination: server_id,
+        session_id,
+        content: MessageType::Request(RequestType::TextRequest(TextRequest::TextList)),
+    }
+}
+
+/// Files listed in a `TextList` reply, or `None` for other messages.
+/// Servers list files by ID, by name or as objects carrying both.
+#[must_use]
+pub fn file_list(reply: &Envelope) -> Option<Vec<FileEntry>> {
+    let (kind, inner) = reply.unwrap_content();
+    if kind.last() != Some(&"TextList") {
+        return None;
+    }
+    Some(
+        inner?
+            .as_array()?
+            .iter()
+            .map(|entry| match entry {
+                Value::String(name) => FileEntry {
+                    id: None,
+                    name: Some(name.clone()),
+                },
+                entry => FileEntry {
+                    id: entry.as_u64().or_else(|| entry.get("id")?.as_u64()),
+                    name: entry
+                        .get("name")
+                        .and_then(Value::as_str)
+                        .map(str::to_string),
+                },
+            })
+            .collect(),
+    )
+}
+
+/// Builds the request for text file `file_id` of server `server_id`.
+#[must_use]
+pub fn fetch_file(node_id: u8, server_id: u8, session_id: u64, file_id: u64) -> Message {
+    Message {
+        source: node_id,
+        destination: server_id,
+        session_id,
+        content: MessageType::Request(RequestType::TextRequest(TextRequest::Text(file_id))),
+    }
+}
+
+/// Builds the request for media `media_id` of server `server_id`.
+#[must_use]
+pub fn fetch_media(node_id: u8, server_id: u8, session_id: u64, media_id: u64) -> Message {
+    Message {
+        source: node_id,
+        destination: server_id,
+        session_id,
+        content: MessageType::Request(RequestType::MediaRequest(MediaRequest::Media(media_id))),
+    }
+}
+
+/// Builds the request carrying chunk `index` of `total` of file `name` to server `server_id`.
+#[must_use]
+pub fn upload_chunk(
+    node_id: u8,
+    server_id: u8,
+    session_id: u64,
+    name: &str,
+    (index, total): (u64, u64),
+    data: Vec<u8>,
+) -> Message {
+    Message {
+        source: node_id,
+        destination: server_id,
+        session_id,
+        content: MessageType::Request(RequestType::MediaRequest(MediaRequest::Upload {
+            name: name.to_string(),
+            chunk: index,
+            total_chunks: total,
+            data,
+        })),
+    }
+}
+
+/// File ID in the reply to the last chunk of an upload, if the server reports one.
+#[must_use]
+pub fn uploaded_file_id(reply: &Envelope) -> Option<u64> {
+    let (_, inner) = reply.unwrap_content();
+    let inner = inner?;
+    inner.as_u64().or_else(|| inner.get("id")?.as_u64())
+}
+
+/// Text of a `Text` reply, or `None` for other messages.
+#[must_use]
+pub fn text(reply: &Envelope) -> Option<String> {
+    let (kind, inner) = reply.unwrap_content();
+    if kind.last() != Some(&"Text") {
+        return None;
+    }
+    inner?.as_str().map(str::to_string)
+}
+
+/// Bytes of a `Media` reply, or `None` for other messages.
+#[must_use]
+pub fn media(reply: &Envelope) -> Option<Vec<u8>> {
+    let (kind, inner) = reply.unwrap_content();
+    if kind.last() != Some(&"Media") {
+        return None;
+    }
+    inner?
+        .as_array()?
+        .iter()
+        .map(|byte| byte.as_u64().and_then(|byte| u8::try_from(byte).ok()))
+        .collect()
+}
+
+/// IDs of the media referenced by `text`, in order of first appearance.
+#[must_use]
+pub fn media_references(text: &str) -> Vec<u64> {
+    let mut ids = vec![];
+    for (_, id, _) in references(text) {
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+    ids
+}
+
+/// Every well-formed media reference in `text` as (start, media ID, end).
+fn references(text: &str) -> Vec<(usize, u64, usize)> {
+    let mut found = vec![];
+    let mut offset = 0;
+    while let Some(start) = text[offset..].find(MEDIA_REFERENCE).map(|i| offset + i) {
+        let id_start = start + MEDIA_REFERENCE.len();
+        let reference = text[id_start..].find(']').map(|len| {
+            (
+                id_start + len,
+                text[id_start..id_start + len].parse::<u64>(),
+            )
+        });
+        match reference {
+            Some((id_end, Ok(id))) => {
+                found.push((start, id, id_end + 1));
+                offset = id_end + 1;
+            }
+            _ => offset = id_start,
+        }
+    }
+    found
+}
+
+/// A referenced media, fetched or not.
+#[derive(Debug, Clone, Serialize)]
+pub struct MediaPart {
+    /// Media ID.
+    pub id: u64,
+    /// URL of the media, if it could be fetched.
+    pub url: Option<String>,
+    /// Why the media could not be fetched.
+    pub error: Option<String>,
+}
+
+impl MediaPart {
+    /// Media `id` fetched from server `server_id`, linked to `/media`.
+    #[must_use]
+    pub fn fetched(id: u64, server_id: u8) -> Self {
+        MediaPart {
+            id,
+            url: Some(format!("/media/{server_id}/{id}")),
+            error: None,
+        }
+    }
+
+    /// A media that could not be fetched.
+    #[must_use]
+    pub fn missing(id: u64, error: &str) -> Self {
+        MediaPart {
+            id,
+            url: None,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+/// A text file together with the media it references.
+#[derive(Debug, Clone, Serialize)]
+pub struct AssembledFile {
+    /// File ID.
+    pub file_id: u64,
+    /// Text of the file, references included.
+    pub text: String,
+    /// The referenced media, in order of first appearance.
+    pub media: Vec<MediaPart>,
+}
+
+impl AssembledFile {
+    /// Renders the file as an HTML fragment: the escaped text with every media
+    /// reference replaced by an image, or by a note if the media is missing.
+    #[must_use]
+    pub fn to_html(&self) -> String {
+        let mut html = String::from(r#"<div style="white-space: pre-wrap">"#);
+        let mut offset = 0;
+        for (start, id, end) in references(&self.text) {
+            html.push_str(&escape_html(&self.text[offset..start]));
+            match self
+                .media
+                .iter()
+                .find(|part| part.id == id)
+                .and_then(|part| part.url.as_deref())
+            {
+                Some(url) => {
+                    let _ = write!(html, r#"<img src="{url}" alt="media {id}">"#);
+                }
+                None => {
+                    let _ = write!(html, "<em>[media {id} unavailable]</em>");
+                }
+            }
+            offset = end;
+        }
+        html.push_str(&escape_html(&self.text[offset..]));
+        html.push_str("</div>");
+        html
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Guesses the MIME type of media from its first bytes.
+#[must_use]
+pub fn mime_type(bytes: &[u8]) -> &'static str {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+        "image/jpeg"
+    } else if bytes.starts_with(b"GIF8") {
+        "image/gif"
+    } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(&b"WEBP"[..]) {
+        "image/webp"
+    } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(&b"WAVE"[..]) {
+        "audio/wav"
+    } else if bytes.get(4..8) == Some(&b"ftyp"[..]) {
+        "video/mp4"
+    } else if bytes.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]) {
+        "video/webm"
+    } else if bytes.starts_with(b"OggS") {
+        "audio/ogg"
+    } else if bytes.starts_with(b"ID3") {
+        "audio/mpeg"
+    } else {
+        "application/octet-stream"
+    }
+}
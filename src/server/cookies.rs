@@ -0,0 +1,22 @@
+//! Cookies set by the frontend.
+
+use actix_web::cookie::{Cookie, SameSite};
+
+/// Builds an HTTP-only session cookie.
+///
+/// When the node is served over HTTPS (`secure`), by itself or through an
+/// HTTPS-terminating proxy in front of a plain HTTP listener, the cookie is
+/// marked `Secure` and restricted to same-site requests.
+#[must_use]
+pub fn session_cookie<'c>(name: &'c str, value: String, secure: bool) -> Cookie<'c> {
+    Cookie::build(name, value)
+        .path("/")
+        .http_only(true)
+        .secure(secure)
+        .same_site(if secure {
+            SameSite::Strict
+        } else {
+            SameSite::Lax
+        })
+        .finish()
+}
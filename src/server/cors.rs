@@ -0,0 +1,107 @@
+//! Cross-origin access to the API.
+//!
+//! Browsers only let a page call the API from another origin (a separately
+//! served SPA, a tool on another port) if the responses allow that origin.
+//! The [`methods`](super::methods) middleware already answers preflights with
+//! the methods and headers a route accepts; the [`handle`] middleware decides
+//! whether the origin may use them. For origins in `CorsOptions::allowed_origins`
+//! it adds `Access-Control-Allow-Origin`, narrows the preflight answer to the
+//! configured methods and headers and exposes the `X-Request-Id` header.
+//! Other origins get no CORS headers, so browsers keep refusing them.
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{Error, web};
+use serde::Serialize;
+
+use super::request_id::REQUEST_ID_HEADER;
+use super::settings::LiveOptions;
+
+/// Which cross-origin requests the API allows.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CorsOptions {
+    /// Origins allowed to call the API, e.g. `http://localhost:5173`;
+    /// `*` allows every origin. Empty disables cross-origin access.
+    pub allowed_origins: Vec<String>,
+    /// Methods allowed cross-origin; empty allows every method of the route.
+    pub allowed_methods: Vec<String>,
+    /// Request headers allowed cross-origin; empty allows the ones a preflight asks for.
+    pub allowed_headers: Vec<String>,
+}
+
+impl CorsOptions {
+    fn allows_origin(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+    }
+
+    fn allows_method(&self, method: &str) -> bool {
+        self.allowed_methods.is_empty()
+            || self
+                .allowed_methods
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(method))
+    }
+}
+
+/// Middleware adding the CORS headers for allowed origins, see the module docs.
+///
+/// # Errors
+/// Passes on the errors of the wrapped service.
+pub async fn handle<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<B>, Error> {
+    let options = req
+        .app_data::<web::Data<LiveOptions>>()
+        .map(|options| options.current());
+    let origin = req.headers().get(header::ORIGIN).cloned();
+    let preflight_method = req
+        .headers()
+        .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+        .filter(|_| req.method() == Method::OPTIONS)
+        .and_then(|method| method.to_str().ok())
+        .map(str::to_string);
+    let method = preflight_method
+        .clone()
+        .unwrap_or_else(|| req.method().to_string());
+
+    let mut response = next.call(req).await?;
+    let (Some(options), Some(origin)) = (options, origin) else {
+        return Ok(response);
+    };
+    let cors = &options.cors;
+    let allowed = origin
+        .to_str()
+        .is_ok_and(|origin| cors.allows_origin(origin))
+        && cors.allows_method(&method);
+    let headers = response.headers_mut();
+    headers.append(header::VARY, HeaderValue::from_static("Origin"));
+    if !allowed {
+        return Ok(response);
+    }
+
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    if preflight_method.is_some() {
+        if !cors.allowed_methods.is_empty()
+            && let Ok(methods) = HeaderValue::from_str(&cors.allowed_methods.join(", "))
+        {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, methods);
+        }
+        if !cors.allowed_headers.is_empty()
+            && let Ok(allowed_headers) = HeaderValue::from_str(&cors.allowed_headers.join(", "))
+        {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, allowed_headers);
+        }
+    } else {
+        headers.insert(
+            header::ACCESS_CONTROL_EXPOSE_HEADERS,
+            HeaderValue::from_static(REQUEST_ID_HEADER),
+        );
+    }
+    Ok(response)
+}
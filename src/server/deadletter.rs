@@ -0,0 +1,100 @@
+//! Dead-letter queue of messages that could not be delivered.
+//!
+//! When a send fails, because the backend couldn't be reached or didn't take
+//! the message in time, or because the server rejected it with an error, the
+//! [`DeliveryTracker`](super::delivery::DeliveryTracker) files the message
+//! here. The queue is stored as JSON in the node's data directory, so failed
+//! messages survive a restart until they are sent again via
+//! `/deadletter/{id}/retry`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use super::delivery::OutgoingMessage;
+use super::write_json_atomic;
+
+/// A message whose send failed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// Message id the send failed under.
+    pub id: u64,
+    /// The message.
+    pub message: OutgoingMessage,
+    /// Why the send failed.
+    pub reason: String,
+    /// The server's answer, if it rejected the message.
+    pub reply: Option<Value>,
+    /// Unix time in milliseconds when the send failed.
+    pub failed_at: u64,
+}
+
+/// The failed messages together with the file they are persisted in.
+#[derive(Debug)]
+pub struct DeadLetterQueue {
+    path: PathBuf,
+    letters: Mutex<Vec<DeadLetter>>,
+}
+
+impl DeadLetterQueue {
+    /// Loads the queue from `path`, starting empty if the file doesn't exist.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read or parsed.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let letters = match fs::read(path) {
+            Ok(json) => serde_json::from_slice(&json).map_err(io::Error::other)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e),
+        };
+        Ok(DeadLetterQueue {
+            path: path.to_path_buf(),
+            letters: Mutex::new(letters),
+        })
+    }
+
+    /// Returns every failed message, oldest failure first.
+    #[must_use]
+    pub fn list(&self) -> Vec<DeadLetter> {
+        self.lock().clone()
+    }
+
+    /// Files `letter` and persists the queue.
+    ///
+    /// # Errors
+    /// Returns an error if the queue can't be written.
+    pub fn add(&self, letter: DeadLetter) -> io::Result<()> {
+        let mut letters = self.lock();
+        letters.push(letter);
+        if let Err(e) = write_json_atomic(&self.path, &*letters) {
+            letters.pop();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Removes the message that failed under message id `id` and returns it,
+    /// or `None` if it isn't queued.
+    ///
+    /// # Errors
+    /// Returns an error if the queue can't be written.
+    pub fn take(&self, id: u64) -> io::Result<Option<DeadLetter>> {
+        let mut letters = self.lock();
+        let Some(index) = letters.iter().position(|letter| letter.id == id) else {
+            return Ok(None);
+        };
+        let letter = letters.remove(index);
+        if let Err(e) = write_json_atomic(&self.path, &*letters) {
+            letters.insert(index, letter);
+            return Err(e);
+        }
+        Ok(Some(letter))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<DeadLetter>> {
+        self.letters.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,89 @@
+//! Suppression of messages the backend hands out twice.
+//!
+//! When polls for unread messages overlap, a server can deliver the same
+//! message again, and the UI would show the same line twice. The
+//! [`Inbox`](super::inbox::Inbox) therefore runs every received chat message
+//! past the [`Deduplicator`] before storing it: one with the same source,
+//! session ID and content as one received within `ServerOptions::dedup_window`
+//! before it is dropped. Replies to requests are left alone, as a server
+//! answering two requests alike without echoing their session IDs isn't
+//! repeating itself.
+
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use super::inbox::Envelope;
+
+/// What makes two messages the same: source, session ID and a hash of the content.
+type Key = (Option<u8>, Option<u64>, u64);
+
+#[derive(Debug, Default)]
+struct Seen {
+    // Keys in the order they were received, with when
+    order: VecDeque<(u64, Key)>,
+    // How often each key occurs in `order`
+    counts: HashMap<Key, usize>,
+}
+
+/// The messages received within the window, see the [module docs](self).
+#[derive(Debug)]
+pub struct Deduplicator {
+    window: u64,
+    seen: Mutex<Seen>,
+}
+
+impl Deduplicator {
+    /// Remembers messages for `window`.
+    #[must_use]
+    pub fn new(window: Duration) -> Self {
+        Deduplicator {
+            window: u64::try_from(window.as_millis()).unwrap_or(u64::MAX),
+            seen: Mutex::new(Seen::default()),
+        }
+    }
+
+    /// Whether chat message `envelope` repeats one received within the window.
+    /// The envelope is remembered either way, so a message redelivered over
+    /// and over is dropped until it stops for a whole window. Other messages
+    /// are never duplicates.
+    pub fn is_duplicate(&self, envelope: &Envelope) -> bool {
+        if !envelope.is_chat_message() {
+            return false;
+        }
+        let key = key(envelope);
+        let mut seen = self.lock();
+        let cutoff = envelope.received_at.saturating_sub(self.window);
+        while let Some(&(at, expired)) = seen.order.front()
+            && at < cutoff
+        {
+            seen.order.pop_front();
+            if let Some(count) = seen.counts.get_mut(&expired) {
+                *count -= 1;
+                if *count == 0 {
+                    seen.counts.remove(&expired);
+                }
+            }
+        }
+        seen.order.push_back((envelope.received_at, key));
+        let count = seen.counts.entry(key).or_default();
+        *count += 1;
+        *count > 1
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Seen> {
+        self.seen.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn key(envelope: &Envelope) -> Key {
+    let mut hasher = DefaultHasher::new();
+    envelope
+        .payload
+        .get("content")
+        .map(Value::to_string)
+        .hash(&mut hasher);
+    (envelope.source, envelope.session_id, hasher.finish())
+}
@@ -0,0 +1,690 @@
+//! Tracking of whether messages sent via `/send` reached their server.
+//!
+//! Putting a message on the command channel only means the backend got it.
+//! Each send is therefore registered here under a message id and moves through
+//! the [`DeliveryState`]s: it is queued in the outbox, sent once handed to the
+//! backend, and acked once the destination server answers: a non-chat reply
+//! from that server acknowledges the sent message with the same session ID, or
+//! else the oldest one sent to it. The drone network has no delivery receipts,
+//! so an acked message counts as delivered once its recipient writes back, and
+//! as read once the recipient sends a [read receipt](super::receipts) for it.
+//!
+//! Pending messages make up the [`outbox`](super::outbox), whose worker hands
+//! them to the backend once they are due. A message is due right after it was
+//! tracked, or once it is released if it was held for lack of a route to its
+//! server, after a backoff if its send failed or the server answered with an
+//! error, and once the server failed to answer in time. When it used up its
+//! attempts, it fails and is filed in the [`DeadLetterQueue`], from where it
+//! can be sent again.
+//!
+//! Messages are numbered per conversation, i.e. per recipient client, in the
+//! order they were tracked, and go out in that order: a message to a client
+//! through a server isn't due while an earlier one to the same client and
+//! server is still queued, e.g. held or waiting for a retry. Concurrent
+//! `/send` requests are thus sent in the order they were accepted, and a
+//! message that was tried again doesn't fall behind the ones sent after it.
+//!
+//! If the tracker has a [`Journal`], every mutation is journaled first, so the
+//! outbox is restored after a crash: messages that were only enqueued are due
+//! right away, attempted ones once their answer is overdue.
+
+use messages::{ChatRequest, Message, MessageType, RequestType};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use super::deadletter::{DeadLetter, DeadLetterQueue};
+use super::inbox::Envelope;
+use super::journal::{Journal, JournalOp, JournalRecord};
+use super::outbox::RetryPolicy;
+use super::receipts;
+use super::unix_millis;
+
+/// A chat message accepted by `/send`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutgoingMessage {
+    /// Server the message is sent through.
+    pub server_id: u8,
+    /// Client the message is addressed to.
+    pub client_id: u8,
+    /// Session ID of the message.
+    pub session_id: u64,
+    /// Message content.
+    pub message: String,
+}
+
+impl OutgoingMessage {
+    /// Builds the `SendMessage` chat request sent by node `node_id`.
+    #[must_use]
+    pub fn to_message(&self, node_id: u8) -> Message {
+        Message {
+            source: node_id,
+            destination: self.server_id,
+            session_id: self.session_id,
+            content: MessageType::Request(RequestType::ChatRequest(ChatRequest::SendMessage {
+                from: node_id,
+                to: self.client_id,
+                message: self.message.clone(),
+            })),
+        }
+    }
+}
+
+/// Delivery state of a sent message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryState {
+    /// Waiting in the outbox: not handed to the backend yet, held until a route
+    /// is found, or waiting for a retry.
+    Queued,
+    /// Handed to the backend, no answer from the server yet.
+    Sent,
+    /// The server acknowledged the message.
+    #[serde(rename = "acked")]
+    Acknowledged,
+    /// The recipient wrote back after the server acknowledged the message.
+    Delivered,
+    /// The recipient sent a read receipt for the message.
+    Read,
+    /// The message could not be sent or the server rejected it, on every attempt.
+    Failed,
+}
+
+impl DeliveryState {
+    /// Whether the message is still in the outbox, waiting for the server's answer.
+    #[must_use]
+    pub fn is_pending(self) -> bool {
+        matches!(self, DeliveryState::Queued | DeliveryState::Sent)
+    }
+}
+
+/// Delivery status of a single sent message.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeliveryStatus {
+    /// Message id returned by `/send`.
+    pub id: u64,
+    /// Server the message was sent through.
+    pub server_id: u8,
+    /// Client the message is addressed to.
+    pub client_id: u8,
+    /// Session ID of the sent message.
+    pub session_id: u64,
+    /// Position of the message among those sent to `client_id`, counting from 1.
+    pub seq: u64,
+    /// Current state.
+    pub state: DeliveryState,
+    /// Unix time in milliseconds when the message was sent.
+    pub sent_at: u64,
+    /// Unix time in milliseconds of the last state change.
+    pub updated_at: u64,
+    /// The server's answer, once it arrived.
+    pub reply: Option<Value>,
+    /// How often the message was handed to the backend.
+    pub attempts: u32,
+    /// Unix time in milliseconds when the outbox sends the message (again), while pending.
+    pub next_attempt_at: Option<u64>,
+    /// Why the last attempt failed, if it did.
+    pub last_error: Option<String>,
+    /// Whether the message waits for a flood or registration to find a route to its server.
+    pub awaiting_route: bool,
+    /// Unix time in milliseconds when the server acknowledged the message.
+    pub acknowledged_at: Option<u64>,
+    /// Unix time in milliseconds when the recipient wrote back.
+    pub delivered_at: Option<u64>,
+    /// Unix time in milliseconds when the recipient's read receipt arrived.
+    pub read_at: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct Deliveries {
+    next_id: u64,
+    by_id: HashMap<u64, DeliveryStatus>,
+    // Message ids handed to the backend and awaiting an answer, per server, oldest first
+    pending: HashMap<u8, VecDeque<u64>>,
+    // The outbox: messages not settled yet
+    unsettled: HashMap<u64, OutgoingMessage>,
+    // Acknowledged message ids per recipient, until the recipient writes back
+    acked: HashMap<u8, Vec<u64>>,
+    // Sequence number of the last message tracked per recipient
+    last_seq: HashMap<u8, u64>,
+}
+
+impl Deliveries {
+    /// The sequence number of the next message to `client_id`.
+    fn next_seq(&self, client_id: u8) -> u64 {
+        self.last_seq.get(&client_id).map_or(1, |seq| seq + 1)
+    }
+
+    fn insert(&mut self, id: u64, message: &OutgoingMessage, seq: u64, at: u64) {
+        self.next_id = self.next_id.max(id);
+        let last_seq = self.last_seq.entry(message.client_id).or_default();
+        *last_seq = (*last_seq).max(seq);
+        self.by_id.insert(
+            id,
+            DeliveryStatus {
+                id,
+                server_id: message.server_id,
+                client_id: message.client_id,
+                session_id: message.session_id,
+                seq,
+                state: DeliveryState::Queued,
+                sent_at: at,
+                updated_at: at,
+                reply: None,
+                attempts: 0,
+                next_attempt_at: Some(at),
+                last_error: None,
+                awaiting_route: false,
+                acknowledged_at: None,
+                delivered_at: None,
+                read_at: None,
+            },
+        );
+        self.unsettled.insert(id, message.clone());
+    }
+
+    /// Holds back or releases an unsettled message; a released one is due at `at`.
+    fn set_held(&mut self, id: u64, held: bool, at: u64) {
+        if !self.unsettled.contains_key(&id) {
+            return;
+        }
+        if let Some(status) = self.by_id.get_mut(&id) {
+            status.state = DeliveryState::Queued;
+            status.awaiting_route = held;
+            status.updated_at = at;
+            status.next_attempt_at = (!held).then_some(at);
+        }
+    }
+
+    fn attempts(&self, id: u64) -> u32 {
+        self.by_id.get(&id).map_or(0, |status| status.attempts)
+    }
+
+    /// Records an attempt at `at` whose answer is due by `answer_by`.
+    fn attempt(&mut self, id: u64, at: u64, answer_by: u64) {
+        if !self.unsettled.contains_key(&id) {
+            return;
+        }
+        if let Some(status) = self.by_id.get_mut(&id) {
+            status.state = DeliveryState::Sent;
+            status.attempts += 1;
+            status.updated_at = at;
+            status.next_attempt_at = Some(answer_by);
+            let queue = self.pending.entry(status.server_id).or_default();
+            if !queue.contains(&id) {
+                queue.push_back(id);
+            }
+        }
+    }
+
+    /// Records a failed attempt; the message is due again at `retry_at`.
+    fn reschedule(&mut self, id: u64, reason: &str, reply: Option<Value>, at: u64, retry_at: u64) {
+        if let Some(status) = self.by_id.get_mut(&id) {
+            status.state = DeliveryState::Queued;
+            status.updated_at = at;
+            status.next_attempt_at = Some(retry_at);
+            status.last_error = Some(reason.to_string());
+            status.reply = reply;
+            if let Some(queue) = self.pending.get_mut(&status.server_id) {
+                queue.retain(|pending| *pending != id);
+            }
+        }
+    }
+
+    /// Returns the message, unless it was settled before.
+    fn settle(
+        &mut self,
+        id: u64,
+        state: DeliveryState,
+        reply: Option<Value>,
+        at: u64,
+    ) -> Option<OutgoingMessage> {
+        if let Some(status) = self.by_id.get_mut(&id) {
+            status.state = state;
+            status.updated_at = at;
+            status.reply = reply;
+            status.next_attempt_at = None;
+            if let Some(queue) = self.pending.get_mut(&status.server_id) {
+                queue.retain(|pending| *pending != id);
+            }
+            if state == DeliveryState::Acknowledged {
+                status.acknowledged_at = Some(at);
+                self.acked.entry(status.client_id).or_default().push(id);
+            }
+        }
+        self.unsettled.remove(&id)
+    }
+
+    /// Marks acknowledged message `id` as delivered at `at`.
+    fn deliver(&mut self, id: u64, at: u64) {
+        if let Some(status) = self.by_id.get_mut(&id)
+            && status.state == DeliveryState::Acknowledged
+        {
+            status.state = DeliveryState::Delivered;
+            status.updated_at = at;
+            status.delivered_at = Some(at);
+            if let Some(acked) = self.acked.get_mut(&status.client_id) {
+                acked.retain(|acked| *acked != id);
+            }
+        }
+    }
+
+    /// Marks acknowledged or delivered message `id` as read at `at`.
+    fn read(&mut self, id: u64, at: u64) {
+        if let Some(status) = self.by_id.get_mut(&id)
+            && matches!(
+                status.state,
+                DeliveryState::Acknowledged | DeliveryState::Delivered
+            )
+        {
+            status.state = DeliveryState::Read;
+            status.updated_at = at;
+            status.delivered_at.get_or_insert(at);
+            status.read_at = Some(at);
+            if let Some(acked) = self.acked.get_mut(&status.client_id) {
+                acked.retain(|acked| *acked != id);
+            }
+        }
+    }
+}
+
+/// Delivery status of every message sent through `/send`.
+#[derive(Debug, Default)]
+pub struct DeliveryTracker {
+    deliveries: Mutex<Deliveries>,
+    journal: Option<Journal>,
+    dead_letters: Option<Arc<DeadLetterQueue>>,
+    policy: RetryPolicy,
+}
+
+impl DeliveryTracker {
+    /// Restores the tracker from `journal` and keeps journaling into it,
+    /// scheduling attempts by `policy` and filing failed messages in `dead_letters`.
+    /// Messages that were pending are left for the outbox to send.
+    ///
+    /// # Errors
+    /// Returns an error if the journal can't be read.
+    pub fn recover(
+        journal: Journal,
+        dead_letters: Arc<DeadLetterQueue>,
+        policy: RetryPolicy,
+    ) -> io::Result<Self> {
+        let mut deliveries = Deliveries::default();
+        for record in journal.records()? {
+            match record.op {
+                JournalOp::Enqueue { id, message, seq } => {
+                    let seq = if seq == 0 {
+                        deliveries.next_seq(message.client_id)
+                    } else {
+                        seq
+                    };
+                    deliveries.insert(id, &message, seq, record.at);
+                }
+                JournalOp::Hold { id } => deliveries.set_held(id, true, record.at),
+                JournalOp::Release { id } => deliveries.set_held(id, false, record.at),
+                JournalOp::Attempt { id } => {
+                    deliveries.attempt(id, record.at, policy.answer_by(record.at));
+                }
+                JournalOp::Retry { id, reason, reply } => {
+                    let retry_at = policy.retry_at(record.at, deliveries.attempts(id));
+                    deliveries.reschedule(id, &reason, reply, record.at, retry_at);
+                }
+                JournalOp::Ack { id, reply } => {
+                    deliveries.settle(id, DeliveryState::Acknowledged, Some(reply), record.at);
+                }
+                JournalOp::Deliver { id } => deliveries.deliver(id, record.at),
+                JournalOp::Read { id } => deliveries.read(id, record.at),
+                JournalOp::Fail { id, reply } => {
+                    deliveries.settle(id, DeliveryState::Failed, reply, record.at);
+                }
+            }
+        }
+
+        Ok(DeliveryTracker {
+            deliveries: Mutex::new(deliveries),
+            journal: Some(journal),
+            dead_letters: Some(dead_letters),
+            policy,
+        })
+    }
+
+    /// Starts tracking `message` as the next one of its conversation and returns
+    /// its message id. The message is due to be sent right away, unless it is
+    /// `held` until [`DeliveryTracker::release`].
+    ///
+    /// # Errors
+    /// Returns an error if the message can't be journaled.
+    pub fn track(&self, message: &OutgoingMessage, held: bool) -> io::Result<u64> {
+        let mut deliveries = self.lock();
+        let id = deliveries.next_id + 1;
+        let seq = deliveries.next_seq(message.client_id);
+        self.journal(JournalOp::Enqueue {
+            id,
+            message: message.clone(),
+            seq,
+        })?;
+        let now = unix_millis();
+        deliveries.insert(id, message, seq, now);
+        // If the hold can't be journaled, the message is simply tried right away
+        if held && self.journal(JournalOp::Hold { id }).is_ok() {
+            deliveries.set_held(id, true, now);
+        }
+        Ok(id)
+    }
+
+    /// Makes the held messages to any of `servers` due right away. Returns how
+    /// many were released.
+    pub fn release(&self, servers: &[u8]) -> usize {
+        let mut guard = self.lock();
+        let deliveries = &mut *guard;
+        let held: Vec<u64> = deliveries
+            .unsettled
+            .iter()
+            .filter(|(id, message)| {
+                servers.contains(&message.server_id)
+                    && deliveries
+                        .by_id
+                        .get(id)
+                        .is_some_and(|status| status.awaiting_route)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        let now = unix_millis();
+        for id in &held {
+            self.journal_or_log(JournalOp::Release { id: *id });
+            deliveries.set_held(*id, false, now);
+        }
+        held.len()
+    }
+
+    /// Returns the messages due to be sent at `now` (Unix time in milliseconds)
+    /// with how often each was attempted, ordered by message id. Messages that
+    /// used up their attempts without an answer fail instead. A message queued
+    /// behind an earlier one to the same client and server that isn't due
+    /// waits for it.
+    #[must_use]
+    pub fn due(&self, now: u64) -> Vec<(u64, OutgoingMessage, u32)> {
+        let mut guard = self.lock();
+        let deliveries = &mut *guard;
+        let mut ids: Vec<u64> = deliveries.unsettled.keys().copied().collect();
+        ids.sort_unstable();
+
+        let mut due = vec![];
+        let mut exhausted = vec![];
+        // Destinations with a queued message that isn't due yet
+        let mut blocked = HashSet::new();
+        for id in ids {
+            let (Some(message), Some(status)) =
+                (deliveries.unsettled.get(&id), deliveries.by_id.get(&id))
+            else {
+                continue;
+            };
+            let destination = (message.server_id, message.client_id);
+            let is_due = status.next_attempt_at.is_some_and(|at| at <= now);
+            if blocked.contains(&destination) {
+                continue;
+            }
+            if !is_due {
+                if status.state == DeliveryState::Queued {
+                    blocked.insert(destination);
+                }
+                continue;
+            }
+            if status.attempts >= self.policy.max_attempts {
+                exhausted.push(id);
+            } else {
+                due.push((id, message.clone(), status.attempts));
+            }
+        }
+        for id in exhausted {
+            self.fail_locked(deliveries, id, "The server did not answer in time", None);
+        }
+        due
+    }
+
+    /// Returns when the next message in the outbox is due (Unix time in milliseconds).
+    #[must_use]
+    pub fn next_due(&self) -> Option<u64> {
+        let deliveries = self.lock();
+        deliveries
+            .unsettled
+            .keys()
+            .filter_map(|id| deliveries.by_id.get(id)?.next_attempt_at)
+            .min()
+    }
+
+    /// Records that message `id` is being handed to the backend.
+    ///
+    /// # Errors
+    /// Returns an error if the attempt can't be journaled; the message must
+    /// not be sent then.
+    pub fn mark_attempted(&self, id: u64) -> io::Result<()> {
+        let mut deliveries = self.lock();
+        self.journal(JournalOp::Attempt { id })?;
+        let now = unix_millis();
+        deliveries.attempt(id, now, self.policy.answer_by(now));
+        Ok(())
+    }
+
+    /// Records that handing message `id` to the backend failed for `reason`;
+    /// the message is sent again after a backoff, or fails if it used up its attempts.
+    pub fn retry(&self, id: u64, reason: &str) {
+        let mut deliveries = self.lock();
+        self.retry_or_fail(&mut deliveries, id, reason, None);
+    }
+
+    /// Marks message `id` as failed for `reason` without further attempts, and
+    /// files it as a dead letter.
+    pub fn fail(&self, id: u64, reason: &str) {
+        let mut deliveries = self.lock();
+        self.fail_locked(&mut deliveries, id, reason, None);
+    }
+
+    /// Inspects a message received from the backend. A reply settles the oldest
+    /// message sent to its source server, where an error reply counts as a failed
+    /// attempt; a chat message marks the messages acknowledged to its author as delivered.
+    pub fn observe(&self, envelope: &Envelope) {
+        if envelope.is_chat_message() {
+            if let Some(client_id) = envelope.sender() {
+                self.observe_chat(client_id);
+            }
+            return;
+        }
+        let Some(server_id) = envelope.source else {
+            return;
+        };
+
+        let mut guard = self.lock();
+        let deliveries = &mut *guard;
+        let Some(queue) = deliveries.pending.get(&server_id) else {
+            return;
+        };
+        let matching_session = envelope.session_id.and_then(|session_id| {
+            queue.iter().copied().find(|id| {
+                deliveries
+                    .by_id
+                    .get(id)
+                    .is_some_and(|status| status.session_id == session_id)
+            })
+        });
+        let Some(id) = matching_session.or_else(|| queue.front().copied()) else {
+            return;
+        };
+
+        let reply = envelope.payload.clone();
+        if envelope.is_error() {
+            self.retry_or_fail(
+                deliveries,
+                id,
+                "The server rejected the message",
+                Some(reply),
+            );
+        } else {
+            self.journal_or_log(JournalOp::Ack {
+                id,
+                reply: reply.clone(),
+            });
+            deliveries.settle(id, DeliveryState::Acknowledged, Some(reply), unix_millis());
+        }
+    }
+
+    /// Marks the acknowledged or delivered messages that read receipt
+    /// `envelope` reports as read, see [`receipts`](super::receipts).
+    pub fn observe_receipt(&self, envelope: &Envelope) {
+        let (Some(client_id), Some(sessions)) =
+            (envelope.sender(), receipts::read_sessions(envelope))
+        else {
+            return;
+        };
+        let mut deliveries = self.lock();
+        let read: Vec<u64> = deliveries
+            .by_id
+            .values()
+            .filter(|status| {
+                status.client_id == client_id
+                    && sessions.contains(&status.session_id)
+                    && matches!(
+                        status.state,
+                        DeliveryState::Acknowledged | DeliveryState::Delivered
+                    )
+            })
+            .map(|status| status.id)
+            .collect();
+        for id in read {
+            self.journal_or_log(JournalOp::Read { id });
+            deliveries.read(id, envelope.received_at);
+        }
+    }
+
+    /// Returns the delivery status of message `id`.
+    #[must_use]
+    pub fn status(&self, id: u64) -> Option<DeliveryStatus> {
+        self.lock().by_id.get(&id).cloned()
+    }
+
+    /// Returns the status of every message sent at or after `since`
+    /// (Unix time in milliseconds), ordered by message id.
+    #[must_use]
+    pub fn sent_since(&self, since: u64) -> Vec<DeliveryStatus> {
+        let mut statuses: Vec<_> = self
+            .lock()
+            .by_id
+            .values()
+            .filter(|status| status.sent_at >= since)
+            .cloned()
+            .collect();
+        statuses.sort_by_key(|status| status.id);
+        statuses
+    }
+
+    /// Returns the journal's records, or nothing if the tracker isn't journaled.
+    ///
+    /// # Errors
+    /// Returns an error if the journal can't be read.
+    pub fn journal_records(&self) -> io::Result<Vec<JournalRecord>> {
+        self.journal
+            .as_ref()
+            .map_or_else(|| Ok(vec![]), Journal::records)
+    }
+
+    /// Marks the messages acknowledged to `client_id` as delivered, since it wrote back.
+    fn observe_chat(&self, client_id: u8) {
+        let mut deliveries = self.lock();
+        let Some(acked) = deliveries.acked.remove(&client_id) else {
+            return;
+        };
+        let now = unix_millis();
+        for id in acked {
+            self.journal_or_log(JournalOp::Deliver { id });
+            deliveries.deliver(id, now);
+        }
+    }
+
+    /// Schedules another attempt at message `id` after a failed one, or fails it
+    /// if it used up its attempts.
+    fn retry_or_fail(
+        &self,
+        deliveries: &mut Deliveries,
+        id: u64,
+        reason: &str,
+        reply: Option<Value>,
+    ) {
+        if !deliveries.unsettled.contains_key(&id) {
+            return;
+        }
+        let attempts = deliveries.attempts(id);
+        if attempts >= self.policy.max_attempts {
+            self.fail_locked(deliveries, id, reason, reply);
+            return;
+        }
+        self.journal_or_log(JournalOp::Retry {
+            id,
+            reason: reason.to_string(),
+            reply: reply.clone(),
+        });
+        let now = unix_millis();
+        let retry_at = self.policy.retry_at(now, attempts);
+        deliveries.reschedule(id, reason, reply, now, retry_at);
+    }
+
+    fn fail_locked(
+        &self,
+        deliveries: &mut Deliveries,
+        id: u64,
+        reason: &str,
+        reply: Option<Value>,
+    ) {
+        if !deliveries.unsettled.contains_key(&id) {
+            return;
+        }
+        self.journal_or_log(JournalOp::Fail {
+            id,
+            reply: reply.clone(),
+        });
+        let message = deliveries.settle(id, DeliveryState::Failed, reply.clone(), unix_millis());
+        if let Some(status) = deliveries.by_id.get_mut(&id) {
+            status.last_error = Some(reason.to_string());
+        }
+        self.bury(id, message, reason, reply);
+    }
+
+    fn journal(&self, op: JournalOp) -> io::Result<()> {
+        match &self.journal {
+            Some(journal) => journal.append(op),
+            None => Ok(()),
+        }
+    }
+
+    /// Journals a settled state; the state change happens regardless, since
+    /// the message already left the node.
+    fn journal_or_log(&self, op: JournalOp) {
+        if let Err(e) = self.journal(op) {
+            tracing::error!("Failed to journal outbox update: {e}");
+        }
+    }
+
+    /// Files a failed message in the dead-letter queue, if it wasn't settled before.
+    fn bury(&self, id: u64, message: Option<OutgoingMessage>, reason: &str, reply: Option<Value>) {
+        let (Some(dead_letters), Some(message)) = (&self.dead_letters, message) else {
+            return;
+        };
+        let letter = DeadLetter {
+            id,
+            message,
+            reason: reason.to_string(),
+            reply,
+            failed_at: unix_millis(),
+        };
+        if let Err(e) = dead_letters.add(letter) {
+            tracing::error!("Failed to file message {id} as a dead letter: {e}");
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Deliveries> {
+        self.deliveries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
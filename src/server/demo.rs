@@ -0,0 +1,225 @@
+//! Synthetic network for running the frontend without any drone network.
+//!
+//! In demo mode no backend is started. [`Dispatcher::spawn_demo`] answers
+//! requests from a [`DemoNetwork`] instead: a few servers, drones and clients
+//! with generated IDs. Registering, listing clients and sending work as usual;
+//! the clients of the servers this node registered with chat with it now and
+//! then, and sometimes answer its messages. Replies are built as JSON in the
+//! shape the backend's messages serialize to, so everything downstream of the
+//! dispatcher works unchanged.
+//!
+//! [`Dispatcher::spawn_demo`]: super::dispatcher::Dispatcher::spawn_demo
+
+use ap_client_backend_v2::backend::Command;
+use messages::{ChatRequest, Message, MessageType, RequestType};
+use rand::Rng;
+use rand::seq::SliceRandom;
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, BTreeSet};
+use wg_2024::packet::NodeType;
+
+use super::flood::EdgeNode;
+use super::inbox::Envelope;
+use super::unix_millis;
+
+const SERVERS: usize = 3;
+const CLIENTS: usize = 8;
+const DRONES: usize = 6;
+/// Largest clock skew of a generated client, in milliseconds.
+const MAX_CLOCK_OFFSET_MS: i64 = 2000;
+/// Range of the pause between two unsolicited chat messages, in milliseconds.
+const CHAT_INTERVAL_MS: (u64, u64) = (2000, 8000);
+/// Range of the latency of server answers, in milliseconds.
+const ANSWER_LATENCY_MS: (u64, u64) = (50, 400);
+/// Range of how long a client takes to answer a message, in milliseconds.
+const REPLY_DELAY_MS: (u64, u64) = (1000, 4000);
+
+const PHRASES: &[&str] = &[
+    "Hi there!",
+    "Did the flood reach you?",
+    "The drones are dropping a lot of packets today.",
+    "Are you still registered with the server?",
+    "Let's meet at the next hop.",
+    "Got your message, thanks.",
+    "How is the network looking on your side?",
+];
+
+/// A generated network of servers, drones and clients.
+#[derive(Debug)]
+pub struct DemoNetwork {
+    node_id: u8,
+    clients_by_server: BTreeMap<u8, Vec<u8>>,
+    drones: Vec<u8>,
+    clock_offsets: BTreeMap<u8, i64>,
+    registered: BTreeSet<u8>,
+    // Messages to hand out once their time has come, as (due at, payload)
+    queued: Vec<(u64, Value)>,
+    next_chat_at: u64,
+}
+
+impl DemoNetwork {
+    /// Generates a network around node `node_id`.
+    #[must_use]
+    pub fn generate(node_id: u8) -> Self {
+        let mut rng = rand::thread_rng();
+        let mut ids: Vec<u8> = (1..=u8::MAX).filter(|id| *id != node_id).collect();
+        ids.shuffle(&mut rng);
+        let mut ids = ids.into_iter();
+
+        let servers: Vec<u8> = ids.by_ref().take(SERVERS).collect();
+        let mut clients_by_server: BTreeMap<u8, Vec<u8>> = servers
+            .iter()
+            .map(|server_id| (*server_id, vec![]))
+            .collect();
+        let mut clock_offsets = BTreeMap::new();
+        for (i, client_id) in ids.by_ref().take(CLIENTS).enumerate() {
+            if let Some(clients) = clients_by_server.get_mut(&servers[i % servers.len()]) {
+                clients.push(client_id);
+            }
+            clock_offsets.insert(
+                client_id,
+                rng.gen_range(-MAX_CLOCK_OFFSET_MS..=MAX_CLOCK_OFFSET_MS),
+            );
+        }
+        let drones = ids.take(DRONES).collect();
+
+        DemoNetwork {
+            node_id,
+            clients_by_server,
+            drones,
+            clock_offsets,
+            registered: BTreeSet::new(),
+            queued: vec![],
+            next_chat_at: unix_millis() + CHAT_INTERVAL_MS.0,
+        }
+    }
+
+    /// Every node of the network except this one.
+    #[must_use]
+    pub fn edge_nodes(&self) -> Vec<EdgeNode> {
+        let servers = self
+            .clients_by_server
+            .keys()
+            .map(|id| (*id, NodeType::Server));
+        let clients = self
+            .clients_by_server
+            .values()
+            .flatten()
+            .map(|id| (*id, NodeType::Client));
+        let drones = self.drones.iter().map(|id| (*id, NodeType::Drone));
+        servers.chain(clients).chain(drones).collect()
+    }
+
+    /// Handles a command meant for the backend. Only sent messages have an
+    /// effect; the network is static, so there is nothing to flood.
+    pub fn handle(&mut self, command: Command) {
+        if let Command::SendMessage(message) = command {
+            self.receive(&message);
+        }
+    }
+
+    /// Messages that arrived since the last call: answers to sent messages
+    /// and chat from clients of the registered servers.
+    pub fn unread_messages(&mut self) -> Vec<Envelope> {
+        let now = unix_millis();
+        self.generate_chat(now);
+
+        let (due, queued) = std::mem::take(&mut self.queued)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(at, _)| *at <= now);
+        self.queued = queued;
+        due.iter()
+            .map(|(_, payload)| Envelope::new(payload))
+            .collect()
+    }
+
+    fn receive(&mut self, message: &Message) {
+        let server_id = message.destination;
+        let Some(clients) = self.clients_by_server.get(&server_id) else {
+            // Not a server; the message gets lost like in a real network
+            return;
+        };
+        let MessageType::Request(RequestType::ChatRequest(request)) = &message.content else {
+            return;
+        };
+
+        let mut rng = rand::thread_rng();
+        let answer_at = unix_millis() + rng.gen_range(ANSWER_LATENCY_MS.0..=ANSWER_LATENCY_MS.1);
+        let answer = match request {
+            ChatRequest::Register => {
+                self.registered.insert(server_id);
+                json!("RegisterSuccessfully")
+            }
+            ChatRequest::ClientList => {
+                let mut listed = clients.clone();
+                if self.registered.contains(&server_id) {
+                    listed.push(self.node_id);
+                }
+                json!({ "ClientList": listed })
+            }
+            ChatRequest::SendMessage { to, message, .. } => {
+                if clients.contains(to) && rng.gen_bool(0.5) {
+                    let reply_at = answer_at + rng.gen_range(REPLY_DELAY_MS.0..=REPLY_DELAY_MS.1);
+                    let text = format!("Re: {message}");
+                    self.queue_chat(reply_at, server_id, *to, &text);
+                }
+                json!("MessageSent")
+            }
+            _ => return,
+        };
+        self.queued.push((
+            answer_at,
+            chat_response(server_id, self.node_id, message.session_id, answer),
+        ));
+    }
+
+    fn generate_chat(&mut self, now: u64) {
+        // Don't flood the UI with messages after a long pause
+        self.next_chat_at = self
+            .next_chat_at
+            .max(now.saturating_sub(CHAT_INTERVAL_MS.1));
+
+        let mut rng = rand::thread_rng();
+        while self.next_chat_at <= now {
+            let senders: Vec<(u8, u8)> = self
+                .registered
+                .iter()
+                .flat_map(|server_id| {
+                    self.clients_by_server
+                        .get(server_id)
+                        .into_iter()
+                        .flatten()
+                        .map(|client_id| (*server_id, *client_id))
+                })
+                .collect();
+            if let Some((server_id, client_id)) = senders.choose(&mut rng)
+                && let Some(text) = PHRASES.choose(&mut rng)
+            {
+                self.queue_chat(self.next_chat_at, *server_id, *client_id, text);
+            }
+            self.next_chat_at += rng.gen_range(CHAT_INTERVAL_MS.0..=CHAT_INTERVAL_MS.1);
+        }
+    }
+
+    fn queue_chat(&mut self, at: u64, server_id: u8, client_id: u8, text: &str) {
+        let offset = self.clock_offsets.get(&client_id).copied().unwrap_or(0);
+        let sent_at = at.saturating_add_signed(offset);
+        let content = json!({
+            "MessageFrom": { "from": client_id, "message": text, "sent_at": sent_at }
+        });
+        self.queued.push((
+            at,
+            chat_response(server_id, self.node_id, rand::random(), content),
+        ));
+    }
+}
+
+/// A chat response from `source` to `destination`, serialized like a backend message.
+fn chat_response(source: u8, destination: u8, session_id: u64, content: Value) -> Value {
+    json!({
+        "source": source,
+        "destination": destination,
+        "session_id": session_id,
+        "content": { "Response": { "ChatResponse": content } },
+    })
+}
@@ -0,0 +1,169 @@
+//! Startup diagnostics of the environment the server runs in.
+//!
+//! Collected once when the server starts, logged as a banner and served at
+//! `/debug/env`, so the usual reasons for "it starts but nothing works"
+//! (missing static files, a read-only data directory, unexpected settings)
+//! are visible at a glance.
+
+use serde::Serialize;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::ServerOptions;
+use super::unix_millis;
+
+/// Directory the web UI is served from unless `ServerOptions::static_dir` says otherwise.
+pub const STATIC_DIR: &str = "static";
+
+/// Where the web UI comes from.
+#[derive(Debug, Clone, Serialize)]
+pub struct StaticAssets {
+    /// `disk` or `embedded`.
+    pub source: &'static str,
+    /// Directory the assets are read from.
+    pub dir: PathBuf,
+    /// Whether `index.html` exists there.
+    pub index_found: bool,
+}
+
+/// Whether the data directory can be used.
+#[derive(Debug, Clone, Serialize)]
+pub struct DataDirStatus {
+    /// The node's data directory.
+    pub path: PathBuf,
+    /// Whether a file could be written there.
+    pub writable: bool,
+    /// Why writing failed.
+    pub error: Option<String>,
+}
+
+impl DataDirStatus {
+    /// Checks whether `path` is writable by creating and removing a file in it.
+    #[must_use]
+    pub fn probe(path: &Path) -> Self {
+        let probe = path.join(".write_probe");
+        let result = fs::write(&probe, b"").and_then(|()| fs::remove_file(&probe));
+        DataDirStatus {
+            path: path.to_path_buf(),
+            writable: result.is_ok(),
+            error: result.err().map(|e| e.to_string()),
+        }
+    }
+}
+
+/// A channel between the frontend and the backend.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelInfo {
+    /// What the channel carries.
+    pub name: &'static str,
+    /// Capacity of the channel; `None` if it is unbounded.
+    pub capacity: Option<usize>,
+}
+
+impl ChannelInfo {
+    /// Describes channel `name` with the given capacity.
+    #[must_use]
+    pub fn new(name: &'static str, capacity: Option<usize>) -> Self {
+        ChannelInfo { name, capacity }
+    }
+}
+
+/// Everything worth knowing about the environment when the server starts.
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostics {
+    /// The local node.
+    pub node_id: u8,
+    /// Version of the frontend.
+    pub version: &'static str,
+    /// Unix time in milliseconds when the server started.
+    pub started_at: u64,
+    /// Address the server listens on, or `unix:` and the path of its socket.
+    pub listen: String,
+    /// Where the web UI comes from.
+    pub static_assets: StaticAssets,
+    /// Whether the data directory can be used.
+    pub data_dir: DataDirStatus,
+    /// Channels to the backend.
+    pub channels: Vec<ChannelInfo>,
+    /// The settings in effect, defaults included.
+    pub config: ServerOptions,
+}
+
+impl Diagnostics {
+    /// Collects the diagnostics of node `node_id` listening on `listen`.
+    #[must_use]
+    pub fn collect(
+        node_id: u8,
+        listen: String,
+        data_dir: &Path,
+        channels: Vec<ChannelInfo>,
+        options: &ServerOptions,
+    ) -> Self {
+        let dir = options.static_dir.clone();
+        Diagnostics {
+            node_id,
+            version: env!("CARGO_PKG_VERSION"),
+            started_at: unix_millis(),
+            listen,
+            static_assets: StaticAssets {
+                source: "disk",
+                index_found: dir.join("index.html").is_file(),
+                dir,
+            },
+            data_dir: DataDirStatus::probe(data_dir),
+            channels,
+            config: options.clone(),
+        }
+    }
+
+    /// Multi-line banner for the log.
+    #[must_use]
+    pub fn banner(&self) -> String {
+        let scheme = if self.config.tls.is_some() {
+            "https"
+        } else {
+            "http"
+        };
+        let address = if self.listen.starts_with("unix:") {
+            self.listen.clone()
+        } else {
+            format!("{scheme}://{}", self.listen)
+        };
+        let mut banner = format!(
+            "Frontend {} of node {} listening on {address}",
+            self.version, self.node_id
+        );
+        let _ = write!(
+            banner,
+            "\n  static assets: {} {} ({})",
+            self.static_assets.source,
+            self.static_assets.dir.display(),
+            if self.static_assets.index_found {
+                "index.html found"
+            } else {
+                "index.html MISSING, serving the built-in page"
+            }
+        );
+        let _ = write!(
+            banner,
+            "\n  data directory: {} ({})",
+            self.data_dir.path.display(),
+            match &self.data_dir.error {
+                None => "writable".to_string(),
+                Some(e) => format!("NOT WRITABLE: {e}"),
+            }
+        );
+        let channels: Vec<String> = self
+            .channels
+            .iter()
+            .map(|channel| match channel.capacity {
+                Some(capacity) => format!("{} {capacity}", channel.name),
+                None => format!("{} unbounded", channel.name),
+            })
+            .collect();
+        let _ = write!(banner, "\n  channels: {}", channels.join(", "));
+        let _ = write!(banner, "\n  config: {:?}", self.config);
+        banner
+    }
+}
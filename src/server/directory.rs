@@ -0,0 +1,116 @@
+//! Servers this node registered with and the clients known on each of them.
+//!
+//! Servers are added once they confirm a `/register`; their clients are
+//! learned from the `ClientList` replies to `/clients` and to the requests of
+//! the [`presence`](super::presence) worker, each reply replacing the
+//! previously known list of that server. The directory also remembers when
+//! each peer was [last seen](super::last_seen) and tells when one is
+//! [typing](super::typing).
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use super::inbox::Envelope;
+use super::last_seen::LastSeen;
+use super::typing::TypingRelay;
+
+#[derive(Debug, Default)]
+struct KnownServer {
+    clients: BTreeSet<u8>,
+    // When the client list arrived, if ever
+    updated_at: Option<u64>,
+}
+
+/// Known servers and their clients.
+#[derive(Debug)]
+pub struct Directory {
+    servers: Mutex<BTreeMap<u8, KnownServer>>,
+    last_seen: LastSeen,
+    typing: TypingRelay,
+}
+
+impl Directory {
+    /// An empty directory keeping when peers were seen in `last_seen` and
+    /// raising events for typing peers through `typing`.
+    #[must_use]
+    pub fn new(last_seen: LastSeen, typing: TypingRelay) -> Self {
+        Directory {
+            servers: Mutex::new(BTreeMap::new()),
+            last_seen,
+            typing,
+        }
+    }
+
+    /// Records that server `server_id` accepted this node's registration.
+    pub fn add_server(&self, server_id: u8) {
+        self.lock().entry(server_id).or_default();
+    }
+
+    /// Inspects a message received from the backend and updates the client
+    /// list of its source server, if the message is a `ClientList` reply.
+    pub fn observe(&self, envelope: &Envelope) {
+        if let Some(server_id) = envelope.source
+            && let Some(clients) = envelope.client_list()
+        {
+            self.lock().insert(
+                server_id,
+                KnownServer {
+                    clients: clients.into_iter().collect(),
+                    updated_at: Some(envelope.received_at),
+                },
+            );
+        }
+    }
+
+    /// Records the peers appearing in a batch of received messages as seen,
+    /// see [`LastSeen::observe`].
+    pub fn observe_peers(&self, envelopes: &[Envelope]) {
+        self.last_seen.observe(envelopes);
+    }
+
+    /// Records the authors of the typing notices among a batch of received
+    /// messages as seen and raises events for them, see [`TypingRelay::observe`].
+    pub fn observe_typing(&self, notices: &[Envelope]) {
+        self.last_seen.observe(notices);
+        for notice in notices {
+            self.typing.observe(notice);
+        }
+    }
+
+    /// Unix time in milliseconds when `peer` was last seen, if ever.
+    #[must_use]
+    pub fn last_seen(&self, peer: u8) -> Option<u64> {
+        self.last_seen.get(peer)
+    }
+
+    /// Moves when peer `old` was last seen to peer `new`, for a renumbered contact.
+    pub fn merge_peer(&self, old: u8, new: u8) {
+        self.last_seen.merge_peer(old, new);
+    }
+
+    /// IDs of the known servers.
+    #[must_use]
+    pub fn servers(&self) -> Vec<u8> {
+        self.lock().keys().copied().collect()
+    }
+
+    /// Clients last reported by server `server_id`.
+    #[must_use]
+    pub fn clients(&self, server_id: u8) -> Vec<u8> {
+        self.lock()
+            .get(&server_id)
+            .map(|server| server.clients.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Unix time in milliseconds when server `server_id` last reported its
+    /// clients, or `None` if it never did.
+    #[must_use]
+    pub fn clients_updated_at(&self, server_id: u8) -> Option<u64> {
+        self.lock().get(&server_id)?.updated_at
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<u8, KnownServer>> {
+        self.servers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
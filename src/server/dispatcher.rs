@@ -0,0 +1,697 @@
+//! Routing of backend replies to the requests that asked for them.
+//!
+//! The backend answers `GetEdgeNodesFromFlood` and `GetUnreadMessagesFromServer`
+//! on shared channels. If several HTTP handlers read those channels directly,
+//! one handler can pick up the reply meant for another. The dispatcher thread
+//! is therefore the only reader, and every request gets a oneshot reply
+//! channel. The replies carry nothing to match them to their requests by, but
+//! the backend answers each kind of request in order and any answer of a kind
+//! serves every request of that kind alike, so a reply goes to the oldest
+//! request of its kind whose requester still waits. Requesters that gave up
+//! are skipped, so a reply is never lost on them while a live one times out.
+//! Each job also gets a correlation ID, which only ties its command spans
+//! together in the traces.
+//! A restarted backend never answers what the old one was asked, so on every
+//! restart the outstanding requests are dropped, which their requesters see as
+//! a disconnect, rather than lining up the new backend's replies behind them.
+//!
+//! Outgoing chat messages go through the dispatcher as well, in batches. The
+//! backend has no batch command, so a batch is forwarded as consecutive
+//! `SendMessage` commands; what batching saves is the per-message handoff.
+//! Jobs queued while the dispatcher was busy are taken along in one burst,
+//! so concurrent `/send`s coalesce the same way.
+//!
+//! Requests for unread messages have a queue of their own, which is served
+//! before any other job and between the jobs of a burst, so heavy flood or
+//! stats traffic never delays the ingestion of incoming messages.
+//!
+//! The backend has no no-op command, so the heartbeat of [`Dispatcher::ping`]
+//! is sent as a request for the discovered edge nodes, which the backend
+//! answers from memory. The dispatcher drops the nodes and only replies that
+//! the backend answered, measuring how long the round trip through both loops
+//! takes. How many requests are queued or waiting for the backend is kept in
+//! [`InFlight`] counts.
+//!
+//! The command channel to the backend is bounded, see `ChannelCapacities`. Once
+//! it is full, the backend is falling behind, and new requests fail right away
+//! with [`DispatchError::Busy`] instead of piling up in the dispatcher's queues;
+//! the endpoints answer those with HTTP 429. The dispatcher thread never blocks
+//! on it either: a command that doesn't fit any more fails its job with
+//! [`DispatchError::Busy`]. The job queues are bounded as well, and a job that
+//! doesn't fit fails the same way. Whether the backend answers is
+//! reported to a [`CircuitBreaker`]; while it is open, requests fail right
+//! away with [`DispatchError::Unavailable`], answered with HTTP 503.
+//!
+//! Commands, messages and failed requests are counted in the dispatcher's
+//! [`Metrics`]. Every chat message handed to the backend gets a span and every
+//! delivered message an event, carrying node and session IDs. Command spans are
+//! children of the span of the request that submitted them, so they carry its
+//! request ID even though the backend's commands have no room for it.
+//!
+//! In demo mode, the dispatcher answers from a [`DemoNetwork`] instead of
+//! the backend.
+
+use ap_client_backend_v2::backend::{Command, ListOfDiscoveredEdgeNodes, UnreadMessagesFromServer};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TrySendError, bounded, never, select};
+use messages::Message;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::breaker::{BreakerStatus, CircuitBreaker};
+use super::demo::DemoNetwork;
+use super::flood::EdgeNode;
+use super::inbox::{Envelope, PENDING_CAPACITY};
+use super::metrics::Metrics;
+
+/// Most jobs handled per wakeup of the dispatcher thread.
+const MAX_BURST: usize = 64;
+/// How long a sender waits for the dispatcher to forward its messages.
+const SEND_TIMEOUT: Duration = Duration::from_secs(1);
+/// Most jobs waiting in each queue of the dispatcher before new ones fail with
+/// [`DispatchError::Busy`].
+const JOB_QUEUE_CAPACITY: usize = 1024;
+
+/// Reasons a backend request can fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DispatchError {
+    /// The backend or the dispatcher is gone.
+    Disconnected,
+    /// No reply arrived in time.
+    Timeout,
+    /// The command channel to the backend is full.
+    Busy,
+    /// The backend kept failing; carries how long until it is tried again.
+    Unavailable(Duration),
+}
+
+impl fmt::Display for DispatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DispatchError::Disconnected => write!(f, "Backend is not reachable"),
+            DispatchError::Timeout => write!(f, "Backend did not answer in time"),
+            DispatchError::Busy => write!(f, "Backend is busy, try again later"),
+            DispatchError::Unavailable(left) => {
+                write!(f, "Backend keeps failing, trying again in {left:.0?}")
+            }
+        }
+    }
+}
+
+/// Where the answer to a request goes, or why it couldn't be asked.
+type Reply<T> = Sender<Result<T, DispatchError>>;
+
+enum Request {
+    EdgeNodes(Reply<Vec<EdgeNode>>),
+    // Replies once the backend answered, without the answer
+    Heartbeat(Reply<()>),
+    UnreadMessages(Reply<Vec<Envelope>>),
+    // Replies once every message reached the command channel
+    Send(Vec<Message>, Reply<()>),
+}
+
+struct Job {
+    // Ties the job's command spans together in traces; replies are routed by order
+    correlation_id: u64,
+    request: Request,
+    // Span of the request that submitted the job, carrying its request ID
+    span: tracing::Span,
+}
+
+/// Receiving ends of the job queues.
+struct JobChannels {
+    // Requests for unread messages, served before anything else
+    priority: Receiver<Job>,
+    jobs: Receiver<Job>,
+}
+
+/// Requests the dispatcher has not finished yet.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct InFlight {
+    /// Jobs waiting for the dispatcher to hand them to the backend.
+    pub queued: usize,
+    /// Requests for edge nodes waiting for the backend's answer.
+    pub edge_nodes: usize,
+    /// Requests for unread messages waiting for the backend's answer.
+    pub unread_messages: usize,
+    /// Heartbeats waiting for the backend's answer.
+    pub heartbeats: usize,
+}
+
+/// Numbers of requests waiting for the backend, as last counted by the dispatcher thread.
+#[derive(Debug, Default)]
+struct Awaiting {
+    edge_nodes: AtomicUsize,
+    unread_messages: AtomicUsize,
+    heartbeats: AtomicUsize,
+}
+
+/// Handle to the dispatcher thread. Cheap to clone; the thread exits once
+/// every handle is dropped.
+#[derive(Debug, Clone)]
+pub struct Dispatcher {
+    priority: Sender<Job>,
+    jobs: Sender<Job>,
+    next_correlation_id: Arc<AtomicU64>,
+    metrics: Arc<Metrics>,
+    awaiting: Arc<Awaiting>,
+    // The command channel, to refuse requests while it is full; none in demo mode
+    commands: Option<Sender<Command>>,
+    breaker: Arc<CircuitBreaker>,
+    // Disconnected once the dispatcher thread exited
+    exited: Receiver<()>,
+}
+
+impl Dispatcher {
+    /// Spawns the dispatcher thread, which takes ownership of the backend's
+    /// reply channels and reports whether the backend answers to `breaker`.
+    /// Each message on `restarts` announces that the backend was restarted.
+    #[must_use]
+    pub fn spawn(
+        command_send_channel: Sender<Command>,
+        flood_recv_channel: Receiver<ListOfDiscoveredEdgeNodes>,
+        unread_msg_recv_channel: Receiver<UnreadMessagesFromServer>,
+        restarts: Receiver<()>,
+        breaker: CircuitBreaker,
+    ) -> Self {
+        let (priority, priority_recv) = bounded::<Job>(JOB_QUEUE_CAPACITY);
+        let (jobs, job_recv) = bounded::<Job>(JOB_QUEUE_CAPACITY);
+        let metrics = Arc::new(Metrics::default());
+        watch(&metrics, "dispatcher jobs", job_recv.clone());
+        watch(&metrics, "dispatcher priority jobs", priority_recv.clone());
+        watch(&metrics, "flood results", flood_recv_channel.clone());
+        watch(&metrics, "unread messages", unread_msg_recv_channel.clone());
+        let commands = command_send_channel.clone();
+        metrics.watch_channel("commands", move || commands.len());
+        let commands = Some(command_send_channel.clone());
+        let counted = metrics.clone();
+        let awaiting = Arc::new(Awaiting::default());
+        let published = awaiting.clone();
+        let channels = JobChannels {
+            priority: priority_recv,
+            jobs: job_recv,
+        };
+        let (exiting, exited) = bounded::<()>(0);
+        thread::spawn(move || {
+            let _exiting = exiting;
+            run(
+                &command_send_channel,
+                &channels,
+                &flood_recv_channel,
+                &unread_msg_recv_channel,
+                restarts,
+                &counted,
+                &published,
+            );
+        });
+        Dispatcher {
+            priority,
+            jobs,
+            next_correlation_id: Arc::new(AtomicU64::new(0)),
+            metrics,
+            awaiting,
+            commands,
+            breaker: Arc::new(breaker),
+            exited,
+        }
+    }
+
+    /// Spawns a dispatcher thread answering every request from `network`.
+    /// Commands sent around the dispatcher have to arrive on `command_recv_channel`.
+    #[must_use]
+    pub fn spawn_demo(
+        command_recv_channel: Receiver<Command>,
+        mut network: DemoNetwork,
+        breaker: CircuitBreaker,
+    ) -> Self {
+        let (priority, priority_recv) = bounded::<Job>(JOB_QUEUE_CAPACITY);
+        let (jobs, job_recv) = bounded::<Job>(JOB_QUEUE_CAPACITY);
+        let metrics = Arc::new(Metrics::default());
+        watch(&metrics, "dispatcher jobs", job_recv.clone());
+        watch(&metrics, "dispatcher priority jobs", priority_recv.clone());
+        watch(&metrics, "commands", command_recv_channel.clone());
+        let counted = metrics.clone();
+        let channels = JobChannels {
+            priority: priority_recv,
+            jobs: job_recv,
+        };
+        let (exiting, exited) = bounded::<()>(0);
+        thread::spawn(move || {
+            let _exiting = exiting;
+            run_demo(&channels, &command_recv_channel, &mut network, &counted);
+        });
+        Dispatcher {
+            priority,
+            jobs,
+            next_correlation_id: Arc::new(AtomicU64::new(0)),
+            metrics,
+            // The demo network answers at once, so nothing ever waits
+            awaiting: Arc::default(),
+            commands: None,
+            breaker: Arc::new(breaker),
+            exited,
+        }
+    }
+
+    /// Drops this handle and waits up to `timeout` for the dispatcher thread to
+    /// exit, which it does once every other handle is dropped as well. Returns
+    /// whether it exited in time.
+    #[must_use]
+    pub fn join(self, timeout: Duration) -> bool {
+        let exited = self.exited.clone();
+        drop(self);
+        matches!(
+            exited.recv_timeout(timeout),
+            Err(RecvTimeoutError::Disconnected)
+        )
+    }
+
+    /// Counters of the node's activity, including those of the dispatcher.
+    #[must_use]
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    /// Requests not finished yet, queued or waiting for the backend.
+    #[must_use]
+    pub fn in_flight(&self) -> InFlight {
+        InFlight {
+            queued: self.jobs.len() + self.priority.len(),
+            edge_nodes: self.awaiting.edge_nodes.load(Ordering::Relaxed),
+            unread_messages: self.awaiting.unread_messages.load(Ordering::Relaxed),
+            heartbeats: self.awaiting.heartbeats.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Asks the backend for the edge nodes discovered by the last flood.
+    ///
+    /// # Errors
+    /// Returns a [`DispatchError`] if the backend can't be reached or doesn't
+    /// answer within `timeout`.
+    pub fn edge_nodes(&self, timeout: Duration) -> Result<Vec<EdgeNode>, DispatchError> {
+        let (reply_send, reply_recv) = bounded(1);
+        self.submit(Request::EdgeNodes(reply_send))?;
+        self.wait(&reply_recv, timeout)
+    }
+
+    /// Asks the backend for the messages received since the last call.
+    ///
+    /// # Errors
+    /// Returns a [`DispatchError`] if the backend can't be reached or doesn't
+    /// answer within `timeout`.
+    pub fn unread_messages(&self, timeout: Duration) -> Result<Vec<Envelope>, DispatchError> {
+        let (reply_send, reply_recv) = bounded(1);
+        self.submit(Request::UnreadMessages(reply_send))?;
+        self.wait(&reply_recv, timeout)
+    }
+
+    /// Sends a heartbeat through the dispatcher and the backend loop and
+    /// returns how long the round trip took.
+    ///
+    /// # Errors
+    /// Returns a [`DispatchError`] if the backend can't be reached or doesn't
+    /// answer within `timeout`.
+    pub fn ping(&self, timeout: Duration) -> Result<Duration, DispatchError> {
+        let started = Instant::now();
+        let (reply_send, reply_recv) = bounded(1);
+        self.submit(Request::Heartbeat(reply_send))?;
+        self.wait(&reply_recv, timeout)?;
+        Ok(started.elapsed())
+    }
+
+    /// Hands `messages` to the backend as `SendMessage` commands, in order.
+    ///
+    /// # Errors
+    /// Returns [`DispatchError::Busy`] if the command channel filled up before all
+    /// of the messages were handed over, or [`DispatchError::Disconnected`] if it closed.
+    pub fn send_messages(&self, messages: Vec<Message>) -> Result<(), DispatchError> {
+        let (reply_send, reply_recv) = bounded(1);
+        self.submit(Request::Send(messages, reply_send))?;
+        self.wait(&reply_recv, SEND_TIMEOUT)
+    }
+
+    /// Whether the command channel to the backend is full.
+    #[must_use]
+    pub fn is_busy(&self) -> bool {
+        self.commands.as_ref().is_some_and(Sender::is_full)
+    }
+
+    /// The state of the circuit breaker.
+    #[must_use]
+    pub fn breaker_status(&self) -> BreakerStatus {
+        self.breaker.status()
+    }
+
+    /// Fails like a request would while the circuit breaker is open, for commands
+    /// sent around the dispatcher.
+    ///
+    /// # Errors
+    /// Returns [`DispatchError::Unavailable`] while the breaker is open.
+    pub fn check_breaker(&self) -> Result<(), DispatchError> {
+        self.breaker.check().map_err(DispatchError::Unavailable)
+    }
+
+    fn submit(&self, request: Request) -> Result<(), DispatchError> {
+        if let Err(left) = self.breaker.acquire() {
+            self.metrics.record_backend_error();
+            return Err(DispatchError::Unavailable(left));
+        }
+        if self.is_busy() {
+            self.metrics.record_backend_error();
+            return Err(DispatchError::Busy);
+        }
+        let correlation_id = self.next_correlation_id.fetch_add(1, Ordering::Relaxed);
+        let queue = match request {
+            Request::UnreadMessages(_) => &self.priority,
+            _ => &self.jobs,
+        };
+        let job = Job {
+            correlation_id,
+            request,
+            span: tracing::Span::current(),
+        };
+        queue.try_send(job).map_err(|e| {
+            self.metrics.record_backend_error();
+            match e {
+                TrySendError::Full(_) => DispatchError::Busy,
+                TrySendError::Disconnected(_) => {
+                    self.breaker.record_failure();
+                    DispatchError::Disconnected
+                }
+            }
+        })
+    }
+
+    fn wait<T>(
+        &self,
+        reply_recv: &Receiver<Result<T, DispatchError>>,
+        timeout: Duration,
+    ) -> Result<T, DispatchError> {
+        let reply = reply_recv.recv_timeout(timeout).map_err(|e| {
+            self.metrics.record_backend_error();
+            self.breaker.record_failure();
+            match e {
+                RecvTimeoutError::Timeout => DispatchError::Timeout,
+                RecvTimeoutError::Disconnected => DispatchError::Disconnected,
+            }
+        })?;
+        match reply {
+            Ok(reply) => {
+                self.breaker.record_success();
+                Ok(reply)
+            }
+            // The backend was never asked, so this says nothing about whether it answers
+            Err(DispatchError::Busy) => {
+                self.metrics.record_backend_error();
+                Err(DispatchError::Busy)
+            }
+            Err(e) => {
+                self.metrics.record_backend_error();
+                self.breaker.record_failure();
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Reports the length of `channel` as a gauge in `metrics`.
+fn watch<T: Send + 'static>(metrics: &Metrics, name: &'static str, channel: Receiver<T>) {
+    metrics.watch_channel(name, move || channel.len());
+}
+
+/// Dispatcher loop: forwards requests to the backend and routes replies back.
+fn run(
+    command_send_channel: &Sender<Command>,
+    channels: &JobChannels,
+    flood_recv_channel: &Receiver<ListOfDiscoveredEdgeNodes>,
+    unread_msg_recv_channel: &Receiver<UnreadMessagesFromServer>,
+    mut restarts: Receiver<()>,
+    metrics: &Metrics,
+    awaiting: &Awaiting,
+) {
+    let mut pending = Pending::default();
+    // Messages whose requester gave up waiting, handed out with the next reply,
+    // at most `PENDING_CAPACITY` of them
+    let mut undelivered: Vec<Envelope> = vec![];
+    let forward_priority = |pending: &mut Pending| {
+        for job in channels.priority.try_iter() {
+            forward(job, command_send_channel, metrics, pending);
+        }
+    };
+
+    loop {
+        // Requests for unread messages go first, however many other jobs are queued
+        forward_priority(&mut pending);
+        pending.publish(awaiting);
+        select! {
+            recv(channels.priority) -> job => {
+                let Ok(job) = job else { return };
+                forward(job, command_send_channel, metrics, &mut pending);
+            }
+            recv(channels.jobs) -> job => {
+                // All handles dropped
+                let Ok(job) = job else { return };
+                // Take the rest of a burst along instead of waking up for each job
+                let burst = std::iter::once(job).chain(channels.jobs.try_iter().take(MAX_BURST - 1));
+                for job in burst {
+                    forward(job, command_send_channel, metrics, &mut pending);
+                    forward_priority(&mut pending);
+                }
+            }
+            recv(flood_recv_channel) -> nodes => {
+                let Ok(nodes) = nodes else { return };
+                let mut nodes: Vec<EdgeNode> =
+                    nodes.0.into_iter().map(|node| (node.0, node.1)).collect();
+                // Skip the requesters that timed out already
+                while let Some(reply) = pending.edge_nodes.pop_front() {
+                    match reply {
+                        EdgeNodesReply::Nodes(reply) => match reply.send(Ok(nodes)) {
+                            Ok(()) => break,
+                            Err(e) => nodes = e.into_inner().unwrap_or_default(),
+                        },
+                        EdgeNodesReply::Heartbeat(reply) => {
+                            if reply.send(Ok(())).is_ok() {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            recv(unread_msg_recv_channel) -> msgs => {
+                let Ok(msgs) = msgs else { return };
+                metrics.record_messages_received(msgs.0.len());
+                undelivered.extend(msgs.0.iter().map(Envelope::new).inspect(trace_delivery));
+                let excess = undelivered.len().saturating_sub(PENDING_CAPACITY);
+                if excess > 0 {
+                    undelivered.drain(..excess);
+                    tracing::error!("Dropped {excess} messages nobody fetched from the backend");
+                }
+                while let Some(reply) = pending.unread.pop_front() {
+                    match reply.send(Ok(std::mem::take(&mut undelivered))) {
+                        Ok(()) => break,
+                        Err(e) => undelivered = e.into_inner().unwrap_or_default(),
+                    }
+                }
+            }
+            recv(restarts) -> restart => {
+                if restart.is_err() {
+                    // Nobody can announce restarts any more
+                    restarts = never();
+                    continue;
+                }
+                tracing::warn!(
+                    edge_nodes = pending.edge_nodes.len(),
+                    unread = pending.unread.len(),
+                    "Dropped the requests the restarted backend won't answer"
+                );
+                pending = Pending::default();
+            }
+        }
+    }
+}
+
+/// Where the answer to a request for edge nodes goes.
+enum EdgeNodesReply {
+    Nodes(Reply<Vec<EdgeNode>>),
+    Heartbeat(Reply<()>),
+}
+
+impl EdgeNodesReply {
+    /// Tells the requester why the backend wasn't asked, if it still waits.
+    fn fail(self, e: DispatchError) {
+        match self {
+            EdgeNodesReply::Nodes(reply) => {
+                let _ = reply.send(Err(e));
+            }
+            EdgeNodesReply::Heartbeat(reply) => {
+                let _ = reply.send(Err(e));
+            }
+        }
+    }
+}
+
+/// Outstanding requests per reply channel, oldest first.
+#[derive(Default)]
+struct Pending {
+    edge_nodes: VecDeque<EdgeNodesReply>,
+    unread: VecDeque<Reply<Vec<Envelope>>>,
+}
+
+impl Pending {
+    /// Makes the numbers of outstanding requests visible to [`Dispatcher::in_flight`].
+    fn publish(&self, awaiting: &Awaiting) {
+        let heartbeats = self
+            .edge_nodes
+            .iter()
+            .filter(|reply| matches!(reply, EdgeNodesReply::Heartbeat(_)))
+            .count();
+        awaiting
+            .edge_nodes
+            .store(self.edge_nodes.len() - heartbeats, Ordering::Relaxed);
+        awaiting.heartbeats.store(heartbeats, Ordering::Relaxed);
+        awaiting
+            .unread_messages
+            .store(self.unread.len(), Ordering::Relaxed);
+    }
+}
+
+/// Hands `job` to the backend and remembers where the reply goes.
+/// A failed send drops the reply channel, which the requester sees as a disconnect.
+fn forward(
+    job: Job,
+    command_send_channel: &Sender<Command>,
+    metrics: &Metrics,
+    pending: &mut Pending,
+) {
+    let mut request_edge_nodes = |reply: EdgeNodesReply| match try_command(
+        command_send_channel,
+        Command::GetEdgeNodesFromFlood,
+    ) {
+        Ok(()) => {
+            metrics.record_command();
+            pending.edge_nodes.push_back(reply);
+        }
+        Err(e) => reply.fail(e),
+    };
+    match job.request {
+        Request::EdgeNodes(reply) => request_edge_nodes(EdgeNodesReply::Nodes(reply)),
+        Request::Heartbeat(reply) => request_edge_nodes(EdgeNodesReply::Heartbeat(reply)),
+        Request::UnreadMessages(reply) => {
+            match try_command(command_send_channel, Command::GetUnreadMessagesFromServer) {
+                Ok(()) => {
+                    metrics.record_command();
+                    pending.unread.push_back(reply);
+                }
+                Err(e) => {
+                    let _ = reply.send(Err(e));
+                }
+            }
+        }
+        Request::Send(messages, reply) => {
+            let sent = messages.into_iter().try_for_each(|message| {
+                let _span = command_span(&job.span, job.correlation_id, &message).entered();
+                let sent = try_command(command_send_channel, Command::SendMessage(message));
+                match sent {
+                    Ok(()) => {
+                        metrics.record_command();
+                        metrics.record_messages_sent(1);
+                        tracing::debug!("Handed to the backend");
+                    }
+                    Err(e) => tracing::warn!("Failed to hand the message to the backend: {e}"),
+                }
+                sent
+            });
+            let _ = reply.send(sent);
+        }
+    }
+}
+
+/// Hands `command` to the backend without blocking the dispatcher thread.
+fn try_command(
+    command_send_channel: &Sender<Command>,
+    command: Command,
+) -> Result<(), DispatchError> {
+    command_send_channel.try_send(command).map_err(|e| match e {
+        TrySendError::Full(_) => DispatchError::Busy,
+        TrySendError::Disconnected(_) => DispatchError::Disconnected,
+    })
+}
+
+/// Span of handing `message` to the backend as part of job `correlation_id`,
+/// a child of the span of the request that submitted the job.
+fn command_span(parent: &tracing::Span, correlation_id: u64, message: &Message) -> tracing::Span {
+    tracing::debug_span!(
+        parent: parent,
+        "send_command",
+        correlation_id,
+        node_id = message.source,
+        session_id = message.session_id,
+        destination = message.destination,
+    )
+}
+
+/// Logs a message the backend delivered.
+fn trace_delivery(envelope: &Envelope) {
+    tracing::debug!(
+        node_id = envelope.source,
+        session_id = envelope.session_id,
+        kind = ?envelope.kind(),
+        "Backend response",
+    );
+}
+
+/// Demo dispatcher loop: answers requests and commands from the demo network.
+fn run_demo(
+    channels: &JobChannels,
+    command_recv_channel: &Receiver<Command>,
+    network: &mut DemoNetwork,
+    metrics: &Metrics,
+) {
+    loop {
+        // Requests for unread messages go first, like in the real loop
+        let job = match channels.priority.try_recv() {
+            Ok(job) => job,
+            Err(_) => select! {
+                recv(channels.priority) -> job => {
+                    let Ok(job) = job else { return };
+                    job
+                }
+                recv(channels.jobs) -> job => {
+                    let Ok(job) = job else { return };
+                    job
+                }
+                recv(command_recv_channel) -> command => {
+                    let Ok(command) = command else { return };
+                    network.handle(command);
+                    continue;
+                }
+            },
+        };
+        match job.request {
+            Request::EdgeNodes(reply) => {
+                let _ = reply.send(Ok(network.edge_nodes()));
+            }
+            Request::Heartbeat(reply) => {
+                let _ = reply.send(Ok(()));
+            }
+            Request::UnreadMessages(reply) => {
+                let messages = network.unread_messages();
+                metrics.record_messages_received(messages.len());
+                messages.iter().for_each(trace_delivery);
+                let _ = reply.send(Ok(messages));
+            }
+            Request::Send(messages, reply) => {
+                metrics.record_messages_sent(messages.len());
+                for message in messages {
+                    let _span = command_span(&job.span, job.correlation_id, &message).entered();
+                    network.handle(Command::SendMessage(message));
+                }
+                let _ = reply.send(Ok(()));
+            }
+        }
+    }
+}
@@ -1,194 +1,3224 @@
 //! Actix-web HTTP handlers for client frontend.
 //!
 //! This module exposes HTTP endpoints to:
-//! - Serve the frontend HTML (`index`).
-//! - Initiate and query network discovery via flooding (`/flood`).
-//! - Register clients with servers (`/register`).
-//! - Send chat messages to clients through servers (`/send`).
+//! - Serve the frontend HTML (`index`) and static files (`/static/...`) from a snapshot,
+//!   and swap in a fresh snapshot (`/admin/reload_assets`). Without the HTML, `index`
+//!   serves a built-in status and chat page.
+//! - Report the space saved by storing attachments once per content (`/admin/storage`).
+//! - Initiate and query network discovery via flooding (`/flood`), also in the background
+//!   (`/flood/start`, `/flood/result/{id}`).
+//! - List the discovered servers with the type each of them reported (`/servers`).
+//! - Describe the known network for drawing it (`/topology`).
+//! - Register clients with servers and await their confirmation (`/register`), and list
+//!   the registrations, including those made automatically after a flood (`/registrations`).
+//! - Send chat messages to clients through servers and follow their delivery (`/send`).
+//! - Send a chat message to every known client (`/send/broadcast`).
+//! - Reconstruct everything known about the message sent in a session
+//!   (`/status/{session_id}/timeline`).
+//! - List messages whose send failed and send them again (`/deadletter`,
+//!   `/deadletter/{id}/retry`).
+//! - Follow every sent message through its delivery states, for rendering ticks
+//!   (`/outbox`, `/outbox/{id}`).
 //! - Request list of connected clients from a server (`/clients`).
-//! - Retrieve unread messages from the backend (`/messages`).
+//! - Tell a peer that the user is typing (`/typing`); typing peers show in `/events`.
+//! - Tell which contacts are registered with a server, refreshed in the background
+//!   (`/presence/{server_id}`).
+//! - List the files of a content server and fetch them with the media they reference
+//!   (`/content/{server_id}/files`, `/content/{server_id}/files/{file_id}`).
+//! - Upload files to content servers in chunks and follow their progress
+//!   (`/content/{server_id}/upload`, `/content/uploads/{id}`).
+//! - Stream media of media servers, cached on disk (`/media/{server_id}/{media_id}`).
+//! - Retrieve unread messages from the backend and mark them as read (`/messages`, `/messages/ack`).
+//! - Optionally, send read receipts for acknowledged messages, and show which sent messages
+//!   were read (`/outbox`).
+//! - Wait for new messages with long polling (`/messages/longpoll`).
+//! - Count unread messages per peer (`/messages/count`).
+//! - Surface important messages and manage the rules marking them (`/inbox/priority`).
+//! - Browse previously received messages (`/messages/history`).
+//! - Move messages and conversations to the trash and restore them (`/trash`).
+//! - Show and change the node's persistent identity (`/identity`).
+//! - Merge a contact that got a new node ID into its new ID (`/contacts/{old}/merge/{new}`).
+//! - Describe the node to the web UI (`/config.json`).
+//! - Auto-reply to incoming messages while away (`/settings/away`).
+//! - List conversations with the key status of the peer and require a recorded key per peer
+//!   (`/conversations`, `/conversations/{peer}/key-requirement`, `/contacts/{peer}/key`).
+//! - Report per-conversation statistics (`/conversations/{peer}/stats`).
+//! - Serve the messages of a conversation with their metadata (`/conversations/{peer}/messages`).
+//! - Estimate the clock offsets of peers (`/peers/{id}/clock`).
+//! - Report node health and anomalies (`/stats/drones`, `/events`).
+//! - Show the topology and aggregate statistics without login or message content,
+//!   for projecting a node during demos (`/public/topology`, `/public/stats`).
+//! - Rebuild derived data from the stored messages (`/admin/reindex`).
+//! - Inspect the outbox journal (`/admin/journal`).
+//! - Reload the runtime-tunable settings without a restart (`/admin/config/reload`).
+//! - Replace a wedged backend without restarting the process (`/admin/backend/restart`).
+//! - Report how responsive the backend is and whether it had to be restarted (`/stats/backend`).
+//! - Report how much disk space the node's data takes, per quota (`/stats/disk`).
+//! - Serve metric time series for charts (`/stats/timeseries`) and as CSV (`/stats/export.csv`).
+//! - Export the chat history as JSON or CSV for archiving (`/export`).
+//! - Keep an address book of labelled contacts and their preferred servers
+//!   (`/contacts`, `/contacts/{peer}`).
+//! - Expose activity counters and channel depths to Prometheus (`/metrics`).
+//! - Describe which API features the node supports (`/api/changes`).
+//! - Describe the environment the node started in (`/debug/env`).
+//! - Tell deployment scripts that the server is up (`/health`) and orchestration
+//!   whether the backend is responsive (`/ready`).
+//! - Summarize the liveness of the backend, its last heartbeat, the uptime, the
+//!   requests in flight and the circuit breaker (`/status`).
+//! - Log in and out when an auth provider is configured, also through a login form
+//!   protecting the web UI (`/login`, `/logout`).
+//!
+//! Every feature is annotated in [`API_CHANGES`]; add an entry with the next
+//! revision when adding or changing routes.
 //!
 //! Each endpoint interacts with the client backend via command channels,
 //! forwarding commands and awaiting responses through crossbeam channels.
 //! Responses are converted into appropriate HTTP status codes and JSON payloads.
 
 use actix_files::NamedFile;
-use actix_web::{HttpRequest, HttpResponse, Responder, get, post, web};
-use ap_client_backend_v2::backend::{Command, ListOfDiscoveredEdgeNodes, UnreadMessagesFromServer};
-use crossbeam_channel::{Receiver, Sender, select, tick};
+use actix_multipart::Multipart;
+use actix_web::error::BlockingError;
+use actix_web::http::header;
+use actix_web::{HttpRequest, HttpResponse, Responder, delete, get, mime, post, put, web};
+use ap_client_backend_v2::backend::Command;
+use crossbeam_channel::{Sender, TrySendError};
+use futures_util::StreamExt;
 use messages::{ChatRequest, Message, MessageType, RequestType};
-use serde::Deserialize;
-use std::time::Duration;
-use wg_2024::packet::NodeType;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::ServerOptions;
+use super::assets::{Asset, Assets};
+use super::auth::{self, Credentials, SESSION_COOKIE, Sessions};
+use super::away::{AwayMode, AwaySettings};
+use super::blobs::StorageSavings;
+use super::breaker::{self, BreakerStatus};
+use super::changes::{ApiChange, ApiChangelog, Deprecation};
+use super::chat_error::{self, ChatError};
+use super::clock::PeerClocks;
+use super::content::{self, AssembledFile, MediaPart};
+use super::cookies;
+use super::deadletter::DeadLetterQueue;
+use super::delivery::{DeliveryState, DeliveryStatus, DeliveryTracker, OutgoingMessage};
+use super::diagnostics::Diagnostics;
+use super::directory::Directory;
+use super::dispatcher::{DispatchError, Dispatcher, InFlight};
+use super::events::{Event, EventKind, EventLog};
+use super::export;
+use super::fallback;
+use super::flood::{self, FloodCache, FloodError, FloodJob, FloodJobs};
+use super::history::{Direction, History};
+use super::identity::{Contact, IdentityStore, KeyStatus};
+use super::inbox::{Envelope, Inbox};
+use super::login;
+use super::media::{FetchError, MediaCache};
+use super::methods::ROUTES;
+use super::metrics::Metrics;
+use super::outbox::{EnqueueResult, Outbox};
+use super::presence::Presence;
+use super::priority::{PriorityRules, RuleCriteria};
+use super::probe::BackendProbe;
+use super::quota::DiskQuotas;
+use super::receipts::ReadReceipts;
+use super::registration::{RegisterOutcome, Registrar};
+use super::servers::ServerDirectory;
+use super::session::SessionIds;
+use super::settings::LiveOptions;
+use super::stats::NetworkStats;
+use super::storage::{MessageFilter, MessageStore, StoredMessage};
+use super::timeline;
+use super::timeseries::{Metric, TimeSeries};
+use super::topology::Topology;
+use super::typing;
+use super::unix_millis;
+use super::upload::{StageError, StagedFile, UploadState, Uploads};
+use super::validation::{ValidationErrors, check_message_len};
+
+/// Features of the API, by the revision that introduced them. Served by `/api/changes`.
+pub const API_CHANGES: &[ApiChange] = &[
+    ApiChange {
+        revision: 1,
+        feature: "chat",
+        routes: &["/", "/flood", "/register", "/send", "/clients", "/messages"],
+        summary: "Discover servers, register with them and chat through them",
+    },
+    ApiChange {
+        revision: 2,
+        feature: "register_confirmation",
+        routes: &["/register"],
+        summary: "Registration waits for the server to confirm",
+    },
+    ApiChange {
+        revision: 3,
+        feature: "conversation_stats",
+        routes: &["/conversations/{peer}/stats"],
+        summary: "Per-conversation statistics",
+    },
+    ApiChange {
+        revision: 4,
+        feature: "anomalies",
+        routes: &["/stats/drones", "/events"],
+        summary: "Node health statistics with latency and drop anomalies",
+    },
+    ApiChange {
+        revision: 5,
+        feature: "delivery_status",
+        routes: &["/send", "/send/{id}/status"],
+        summary: "Sent messages get an ID to follow their delivery",
+    },
+    ApiChange {
+        revision: 6,
+        feature: "timeseries",
+        routes: &["/stats/timeseries"],
+        summary: "Downsampled metric history for charts",
+    },
+    ApiChange {
+        revision: 7,
+        feature: "csv_export",
+        routes: &["/stats/export.csv"],
+        summary: "Metrics and delivery records as CSV",
+    },
+    ApiChange {
+        revision: 8,
+        feature: "reindex",
+        routes: &["/admin/reindex"],
+        summary: "Rebuild the history and the unread messages from the stored messages",
+    },
+    ApiChange {
+        revision: 9,
+        feature: "trash",
+        routes: &[
+            "/messages/{id}",
+            "/conversations/{peer}",
+            "/trash",
+            "/trash/{id}/restore",
+        ],
+        summary: "Delete messages and conversations into a restorable trash",
+    },
+    ApiChange {
+        revision: 10,
+        feature: "history",
+        routes: &["/messages/history"],
+        summary: "Paginated message history",
+    },
+    ApiChange {
+        revision: 11,
+        feature: "outbox_journal",
+        routes: &["/admin/journal"],
+        summary: "Inspect the crash-safe outbox journal",
+    },
+    ApiChange {
+        revision: 12,
+        feature: "message_filters",
+        routes: &["/messages", "/messages/history"],
+        summary: "Filter messages by sender and time range",
+    },
+    ApiChange {
+        revision: 13,
+        feature: "identity",
+        routes: &["/identity"],
+        summary: "Persistent node identity with alias and contacts",
+    },
+    ApiChange {
+        revision: 14,
+        feature: "long_poll",
+        routes: &["/messages/longpoll"],
+        summary: "Wait for new messages",
+    },
+    ApiChange {
+        revision: 15,
+        feature: "client_config",
+        routes: &["/config.json"],
+        summary: "Node URLs for the UI, also behind an HTTPS proxy",
+    },
+    ApiChange {
+        revision: 16,
+        feature: "unread_count",
+        routes: &["/messages/count"],
+        summary: "Unread messages per peer",
+    },
+    ApiChange {
+        revision: 17,
+        feature: "explicit_ack",
+        routes: &["/messages", "/messages/ack"],
+        summary: "Fetch messages with `peek=true` and mark them as read separately",
+    },
+    ApiChange {
+        revision: 18,
+        feature: "options_head",
+        routes: &[],
+        summary: "Every route answers OPTIONS and HEAD",
+    },
+    ApiChange {
+        revision: 19,
+        feature: "broadcast",
+        routes: &["/send/broadcast"],
+        summary: "Send a message to every known client",
+    },
+    ApiChange {
+        revision: 20,
+        feature: "concurrency_limits",
+        routes: &[],
+        summary: "Routes may answer 429 when too many requests are in flight",
+    },
+    ApiChange {
+        revision: 21,
+        feature: "topology",
+        routes: &["/topology"],
+        summary: "The known network for drawing it",
+    },
+    ApiChange {
+        revision: 22,
+        feature: "priority_inbox",
+        routes: &[
+            "/inbox/priority",
+            "/inbox/priority/rules",
+            "/inbox/priority/rules/{id}",
+        ],
+        summary: "Rules marking incoming messages as important",
+    },
+    ApiChange {
+        revision: 23,
+        feature: "away_mode",
+        routes: &["/settings/away"],
+        summary: "Automatic replies while away",
+    },
+    ApiChange {
+        revision: 24,
+        feature: "peer_clock",
+        routes: &["/peers/{id}/clock"],
+        summary: "Estimated clock offsets of peers",
+    },
+    ApiChange {
+        revision: 25,
+        feature: "flood_jobs",
+        routes: &["/flood/start", "/flood/result/{id}"],
+        summary: "Flood in the background and fetch the result later",
+    },
+    ApiChange {
+        revision: 26,
+        feature: "contact_merge",
+        routes: &["/contacts/{old}/merge/{new}"],
+        summary: "Merge a renumbered contact into its new ID",
+    },
+    ApiChange {
+        revision: 27,
+        feature: "flood_cache",
+        routes: &["/flood"],
+        summary: "Recent flood results are reused unless `refresh=true` is given",
+    },
+    ApiChange {
+        revision: 28,
+        feature: "public_viewer",
+        routes: &["/public/topology", "/public/stats"],
+        summary: "Optional read-only viewer without login",
+    },
+    ApiChange {
+        revision: 29,
+        feature: "registrations",
+        routes: &["/registrations"],
+        summary: "Registrations, including automatic ones after a flood",
+    },
+    ApiChange {
+        revision: 30,
+        feature: "api_changes",
+        routes: &["/api/changes"],
+        summary: "This changelog",
+    },
+    ApiChange {
+        revision: 31,
+        feature: "content_files",
+        routes: &["/content/{server_id}/files"],
+        summary: "List the files of a content server",
+    },
+    ApiChange {
+        revision: 32,
+        feature: "debug_env",
+        routes: &["/debug/env"],
+        summary: "Environment diagnostics collected at startup",
+    },
+    ApiChange {
+        revision: 33,
+        feature: "content_fetch",
+        routes: &["/content/{server_id}/files/{file_id}"],
+        summary: "Fetch a text file with its media embedded",
+    },
+    ApiChange {
+        revision: 34,
+        feature: "asset_reload",
+        routes: &["/static/{path}", "/admin/reload_assets"],
+        summary: "Static files served from a snapshot that can be reloaded at runtime",
+    },
+    ApiChange {
+        revision: 35,
+        feature: "media_streaming",
+        routes: &[
+            "/media/{server_id}/{media_id}",
+            "/content/{server_id}/files/{file_id}",
+        ],
+        summary: "Stream cached media with Range support; fetched files link to it",
+    },
+    ApiChange {
+        revision: 36,
+        feature: "auth",
+        routes: &["/login", "/logout"],
+        summary: "Optional login with a session cookie or bearer token; /admin needs the admin role",
+    },
+    ApiChange {
+        revision: 37,
+        feature: "disk_quotas",
+        routes: &["/stats/disk", "/media/{server_id}/{media_id}"],
+        summary: "Disk usage per quota; media that don't fit are refused with 507",
+    },
+    ApiChange {
+        revision: 38,
+        feature: "content_upload",
+        routes: &["/content/{server_id}/upload", "/content/uploads/{id}"],
+        summary: "Upload files to content servers in chunks with per-chunk progress",
+    },
+    ApiChange {
+        revision: 39,
+        feature: "backend_probe",
+        routes: &["/stats/backend", "/stats/timeseries"],
+        summary: "Backend round trip time from periodic pings, also as the backend_latency metric",
+    },
+    ApiChange {
+        revision: 40,
+        feature: "health",
+        routes: &["/health"],
+        summary: "Liveness check for deployment scripts",
+    },
+    ApiChange {
+        revision: 41,
+        feature: "conversation_keys",
+        routes: &[
+            "/conversations",
+            "/conversations/{peer}/key-requirement",
+            "/contacts/{peer}/key",
+            "/send",
+            "/send/broadcast",
+        ],
+        summary: "Key status per conversation; messages to peers without a key can be refused",
+    },
+    ApiChange {
+        revision: 42,
+        feature: "readiness",
+        routes: &["/ready"],
+        summary: "Readiness check pinging the backend; 503 while it is wedged",
+    },
+    ApiChange {
+        revision: 43,
+        feature: "delivery_timeline",
+        routes: &["/status/{session_id}/timeline"],
+        summary: "Lifecycle of a sent message from the outbox journal, replies and events",
+    },
+    ApiChange {
+        revision: 44,
+        feature: "prometheus_metrics",
+        routes: &["/metrics"],
+        summary: "Commands, messages, floods, HTTP requests and backend errors in Prometheus format",
+    },
+    ApiChange {
+        revision: 45,
+        feature: "channel_depth",
+        routes: &["/metrics"],
+        summary: "Lengths of the channels to and from the backend as gauges",
+    },
+    ApiChange {
+        revision: 46,
+        feature: "fallback_page",
+        routes: &["/"],
+        summary: "Built-in status and chat page when the web UI is missing",
+    },
+    ApiChange {
+        revision: 47,
+        feature: "server_types",
+        routes: &["/servers"],
+        summary: "Discovered servers with their type, probed concurrently and cached",
+    },
+    ApiChange {
+        revision: 48,
+        feature: "message_metadata",
+        routes: &["/conversations/{peer}/messages", "/messages/history"],
+        summary: "Free-form metadata attached to stored messages, read-only",
+    },
+    ApiChange {
+        revision: 49,
+        feature: "deduplication",
+        routes: &["/admin/storage"],
+        summary: "Attachments and transferred files stored once per content",
+    },
+    ApiChange {
+        revision: 50,
+        feature: "chat_errors",
+        routes: &[
+            "/register",
+            "/send/{id}/status",
+            "/content/{server_id}/files",
+            "/content/{server_id}/files/{file_id}",
+            "/media/{server_id}/{media_id}",
+        ],
+        summary: "Error replies of servers answered with 404, 409 or 502 and their details",
+    },
+    ApiChange {
+        revision: 51,
+        feature: "validation",
+        routes: &["/send", "/register", "/clients"],
+        summary: "Invalid payloads rejected with HTTP 400 and the errors of each field",
+    },
+    ApiChange {
+        revision: 52,
+        feature: "payload_limits",
+        routes: &["/send", "/send/broadcast"],
+        summary: "Oversized JSON bodies and messages rejected with HTTP 413",
+    },
+    ApiChange {
+        revision: 53,
+        feature: "cors",
+        routes: &[],
+        summary: "Configured origins may call the API cross-origin",
+    },
+    ApiChange {
+        revision: 54,
+        feature: "tls",
+        routes: &[],
+        summary: "The node can serve the API over HTTPS itself",
+    },
+    ApiChange {
+        revision: 55,
+        feature: "login_page",
+        routes: &["/", "/login", "/logout"],
+        summary: "Login form protecting the web UI; /login and /logout also take form posts",
+    },
+    ApiChange {
+        revision: 56,
+        feature: "read_only_role",
+        routes: &[],
+        summary: "Users with the read_only role get 403 on everything but GET",
+    },
+    ApiChange {
+        revision: 57,
+        feature: "idempotency_keys",
+        routes: &["/send", "/register"],
+        summary: "Retries with the same Idempotency-Key header get the original response",
+    },
+    ApiChange {
+        revision: 58,
+        feature: "config_reload",
+        routes: &["/admin/config/reload"],
+        summary: "Reload timeouts, flood TTL, route limits and log filter from the configuration",
+    },
+    ApiChange {
+        revision: 59,
+        feature: "backend_restarts",
+        routes: &["/stats/backend"],
+        summary: "How often the backend was restarted after a panic, and why last",
+    },
+    ApiChange {
+        revision: 60,
+        feature: "backend_restart",
+        routes: &["/admin/backend/restart", "/stats/backend"],
+        summary: "Replace the backend with a new one on request",
+    },
+    ApiChange {
+        revision: 61,
+        feature: "node_status",
+        routes: &["/status"],
+        summary: "Backend liveness from heartbeats, uptime and requests in flight",
+    },
+    ApiChange {
+        revision: 62,
+        feature: "backend_backpressure",
+        routes: &[
+            "/flood",
+            "/servers",
+            "/topology",
+            "/register",
+            "/clients",
+            "/content/{server_id}/files",
+            "/content/{server_id}/files/{file_id}",
+            "/media/{server_id}/{media_id}",
+        ],
+        summary: "429 with Retry-After while the command channel to the backend is full",
+    },
+    ApiChange {
+        revision: 63,
+        feature: "circuit_breaker",
+        routes: &[
+            "/status",
+            "/flood",
+            "/servers",
+            "/topology",
+            "/register",
+            "/clients",
+            "/content/{server_id}/files",
+            "/content/{server_id}/files/{file_id}",
+            "/media/{server_id}/{media_id}",
+        ],
+        summary: "503 with Retry-After while the backend keeps failing; breaker state in /status",
+    },
+    ApiChange {
+        revision: 64,
+        feature: "dead_letters",
+        routes: &["/deadletter", "/deadletter/{id}/retry"],
+        summary: "Failed sends are kept in a persisted queue and can be sent again",
+    },
+    ApiChange {
+        revision: 65,
+        feature: "outbox_retries",
+        routes: &[
+            "/send",
+            "/send/broadcast",
+            "/send/{id}/status",
+            "/deadletter/{id}/retry",
+            "/outbox",
+        ],
+        summary: "Sends are queued (HTTP 202) and retried with backoff; attempts in the status",
+    },
+    ApiChange {
+        revision: 66,
+        feature: "offline_queueing",
+        routes: &[
+            "/send",
+            "/send/{id}/status",
+            "/deadletter/{id}/retry",
+            "/outbox",
+        ],
+        summary: "Messages to servers without a route are held until a flood or registration",
+    },
+    ApiChange {
+        revision: 67,
+        feature: "delivery_states",
+        routes: &[
+            "/send/{id}/status",
+            "/status/{session_id}/timeline",
+            "/outbox",
+            "/outbox/{id}",
+        ],
+        summary: "States queued, sent, acked, delivered and failed replace pending and acknowledged",
+    },
+    ApiChange {
+        revision: 68,
+        feature: "message_ordering",
+        routes: &["/send", "/send/{id}/status", "/outbox", "/outbox/{id}"],
+        summary: "Sent messages are numbered per conversation and sent in that order",
+    },
+    ApiChange {
+        revision: 69,
+        feature: "receive_timestamps",
+        routes: &["/messages", "/messages/longpoll"],
+        summary: "Fetched messages carry the time the frontend received them",
+    },
+    ApiChange {
+        revision: 70,
+        feature: "chat_export",
+        routes: &["/export"],
+        summary: "Export the newest chat history as JSON or CSV, by peer and time range",
+    },
+    ApiChange {
+        revision: 71,
+        feature: "contacts",
+        routes: &["/contacts", "/contacts/{peer}", "/identity"],
+        summary: "Address book of labelled contacts with preferred servers",
+    },
+    ApiChange {
+        revision: 72,
+        feature: "nicknames",
+        routes: &[
+            "/contacts/{peer}",
+            "/messages",
+            "/messages/longpoll",
+            "/clients",
+            "/events",
+        ],
+        summary: "Messages, client lists and events show nicknames next to node IDs",
+    },
+    ApiChange {
+        revision: 73,
+        feature: "presence",
+        routes: &["/presence/{server_id}"],
+        summary: "Which contacts are registered with a server, refreshed in the background",
+    },
+    ApiChange {
+        revision: 74,
+        feature: "last_seen",
+        routes: &["/contacts", "/contacts/{peer}"],
+        summary: "Contacts carry when the peer last wrote or appeared in a client list",
+    },
+    ApiChange {
+        revision: 75,
+        feature: "typing",
+        routes: &["/typing", "/events"],
+        summary: "Typing notifications sent to peers, and typing peers reported as events",
+    },
+    ApiChange {
+        revision: 76,
+        feature: "read_receipts",
+        routes: &["/messages/ack", "/outbox", "/outbox/{id}"],
+        summary: "Read receipts sent for acknowledged messages; sent messages get a read state",
+    },
+];
+
+/// Routes that are going to be removed. Served by `/api/changes`.
+pub const API_DEPRECATIONS: &[Deprecation] = &[];
 
-/// Serves the main HTML file for the web frontend.
+/// Why a message to a peer requiring a key was refused.
+const KEY_MISSING: &str = "The conversation requires a key of the peer, but none is recorded";
+/// How long a single wait for backend messages lasts while a handler
+/// is looking for a specific reply.
+const REPLY_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// Default and maximum time `/messages/longpoll` holds a request, in seconds.
+const DEFAULT_LONG_POLL_SECS: u64 = 30;
+const MAX_LONG_POLL_SECS: u64 = 120;
+
+/// Serves the main HTML file for the web frontend from the asset snapshot.
 /// Called when a GET request is made to `/`
-///
-/// # Errors
-/// Returns an error if `index.html` cannot be opened.
-pub async fn index(_req: HttpRequest) -> actix_web::Result<NamedFile> {
-    Ok(NamedFile::open("static/index.html")?)
+/// If there is no `index.html`, serves a built-in page with the node's status,
+/// a minimal chat and the list of API routes instead.
+pub async fn index(
+    assets: web::Data<Assets>,
+    node_id: web::Data<u8>,
+    probe: web::Data<BackendProbe>,
+) -> impl Responder {
+    match assets.get("index.html") {
+        Some(asset) => asset_response(Some(asset)),
+        None => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(fallback::page(**node_id, probe.responsive(), ROUTES)),
+    }
+}
+
+#[get("/static/{path:.*}")]
+/// Serves the other static files of the web frontend from the asset snapshot.
+/// Returns HTTP 404 for unknown files.
+pub async fn static_file(path: web::Path<String>, assets: web::Data<Assets>) -> impl Responder {
+    asset_response(assets.get(&path))
+}
+
+/// Runs `f` on the blocking thread pool like [`web::block`], inside the span of the
+/// current request, so the commands it sends to the backend are traced back to it.
+async fn block<F, R>(f: F) -> Result<R, BlockingError>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let span = tracing::Span::current();
+    web::block(move || span.in_scope(f)).await
+}
+
+/// Renders a static file: HTTP 200 with the file, or 404 if there is none.
+fn asset_response(asset: Option<Asset>) -> HttpResponse {
+    match asset {
+        Some(asset) => HttpResponse::Ok()
+            .content_type(asset.content_type)
+            .body(asset.body),
+        None => HttpResponse::NotFound().json("No such file"),
+    }
+}
+
+#[derive(Serialize)]
+struct StorageReport {
+    media: StorageSavings,   // Cached media
+    staging: StorageSavings, // Files being uploaded
+}
+
+#[get("/admin/storage")]
+/// Reports how much disk space storing attachments and transferred files once per
+/// content saves: the distinct contents, the references to them and the bytes
+/// stored compared to a copy per reference.
+pub async fn storage_savings(
+    media_cache: web::Data<MediaCache>,
+    uploads: web::Data<Uploads>,
+) -> impl Responder {
+    HttpResponse::Ok().json(StorageReport {
+        media: media_cache.savings(),
+        staging: uploads.savings(),
+    })
+}
+
+#[post("/admin/reload_assets")]
+/// Reads the static files from disk again and swaps them in at once, rendering the
+/// HTML templates anew, so UI changes go live without restarting the node.
+/// Returns a summary of the new snapshot; if the files can't be read, the old ones
+/// stay in place and HTTP 500 is returned.
+pub async fn reload_assets(assets: web::Data<Assets>) -> impl Responder {
+    match block(move || assets.reload()).await {
+        Ok(Ok(info)) => HttpResponse::Ok().json(info),
+        Ok(Err(_)) => HttpResponse::InternalServerError().json("Failed to read the static files"),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to wait for the reload"),
+    }
+}
+
+#[post("/admin/config/reload")]
+/// Reads the configuration again and applies the settings that can change while the
+/// node runs, like sending the process `SIGHUP`; see [`super::settings`].
+/// Returns the applied settings; if the configuration can't be loaded, the current
+/// settings stay in place and HTTP 500 is returned with the error.
+pub async fn reload_config(options: web::Data<LiveOptions>) -> impl Responder {
+    match block(move || options.reload()).await {
+        Ok(Ok(tunables)) => HttpResponse::Ok().json(tunables),
+        Ok(Err(e)) => HttpResponse::InternalServerError().json(e.to_string()),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to wait for the reload"),
+    }
+}
+
+#[derive(Deserialize)]
+struct FloodQuery {
+    #[serde(default)]
+    refresh: bool, // Flood even if a recent result is cached
 }
 
 #[get("/flood")]
 /// Initiates a network flood to discover edge nodes, then retrieves the list of discovered nodes.
+/// - Answers from the cache if the last flood is younger than `ServerOptions::flood_cache_ttl`,
+///   unless `refresh=true` is given.
 /// - Sends `InitializeFlood` command.
-/// - Waits 2 seconds.
-/// - Sends `GetEdgeNodesFromFlood` command.
+/// - Polls `GetEdgeNodesFromFlood` until the discovered nodes stop changing.
 /// - Filters the results to only return IDs of nodes of type `Server`.
-/// Returns HTTP 500 on any backend communication failure.
+/// Returns HTTP 429 while the backend is busy, HTTP 503 while it keeps failing, and
+/// HTTP 500 on any other backend communication failure.
 pub async fn flood_network(
+    query: web::Query<FloodQuery>,
+    command_send_channel: web::Data<Sender<Command>>,
+    dispatcher: web::Data<Dispatcher>,
+    options: web::Data<LiveOptions>,
+    flood_cache: web::Data<FloodCache>,
+) -> impl Responder {
+    let options = options.current();
+    let refresh = query.refresh;
+    let nodes = block(move || {
+        flood::discover_cached(
+            &command_send_channel,
+            &dispatcher,
+            &options,
+            &flood_cache,
+            refresh,
+        )
+    })
+    .await;
+
+    match nodes {
+        // Keep only nodes of type Server
+        Ok(Ok(nodes)) => HttpResponse::Ok().json(flood::servers(&nodes)),
+        Ok(Err(FloodError::Busy)) => backend_busy(),
+        Ok(Err(FloodError::Unavailable(left))) => backend_unavailable(left),
+        Ok(Err(e)) => HttpResponse::InternalServerError().json(e.to_string()),
+        Err(_) => {
+            HttpResponse::InternalServerError().json("Failed to receive answer from the backend")
+        }
+    }
+}
+
+#[get("/servers")]
+/// Returns the servers found by the last flood together with the type each of them
+/// reported (chat, text or media), flooding first like `/flood` if the result is stale
+/// or `refresh=true` is given.
+/// Servers are probed for their type concurrently, and only if their cached type expired;
+/// a server that didn't answer is listed with the error instead of a type.
+/// Returns HTTP 429 while the backend is busy, HTTP 503 while it keeps failing, and
+/// HTTP 500 on any other backend communication failure.
+pub async fn discovered_servers(
+    query: web::Query<FloodQuery>,
     command_send_channel: web::Data<Sender<Command>>,
-    flood_res_channel: web::Data<Receiver<ListOfDiscoveredEdgeNodes>>,
+    dispatcher: web::Data<Dispatcher>,
+    options: web::Data<LiveOptions>,
+    flood_cache: web::Data<FloodCache>,
+    server_directory: web::Data<ServerDirectory>,
 ) -> impl Responder {
-    // Trigger flood initialization
-    if command_send_channel.send(Command::InitializeFlood).is_err() {
-        return HttpResponse::InternalServerError()
-            .json("Failed to send request to the backend to flood");
+    let options = options.current();
+    let refresh = query.refresh;
+    let servers = block(move || {
+        let nodes = flood::discover_cached(
+            &command_send_channel,
+            &dispatcher,
+            &options,
+            &flood_cache,
+            refresh,
+        )?;
+        Ok::<_, FloodError>(server_directory.lookup(&flood::servers(&nodes)))
+    })
+    .await;
+
+    match servers {
+        Ok(Ok(servers)) => HttpResponse::Ok().json(servers),
+        Ok(Err(FloodError::Busy)) => backend_busy(),
+        Ok(Err(FloodError::Unavailable(left))) => backend_unavailable(left),
+        Ok(Err(e)) => HttpResponse::InternalServerError().json(e.to_string()),
+        Err(_) => {
+            HttpResponse::InternalServerError().json("Failed to receive answer from the backend")
+        }
     }
+}
 
-    // Give backend time to perform flood discovery
-    std::thread::sleep(Duration::from_secs(2));
+#[derive(Serialize)]
+struct FloodStarted {
+    id: u64, // Job ID to fetch the result with
+}
 
-    // Request the discovered edge nodes
-    if command_send_channel
-        .send(Command::GetEdgeNodesFromFlood)
-        .is_err()
-    {
-        return HttpResponse::InternalServerError()
-            .json("Failed to send request to get nodes from the backend");
+#[post("/flood/start")]
+/// Starts a flood in the background and returns its job ID right away, so the UI isn't
+/// blocked during discovery. If a flood is already running, its job ID is returned.
+pub async fn start_flood(
+    command_send_channel: web::Data<Sender<Command>>,
+    dispatcher: web::Data<Dispatcher>,
+    options: web::Data<LiveOptions>,
+    flood_jobs: web::Data<FloodJobs>,
+    flood_cache: web::Data<FloodCache>,
+) -> impl Responder {
+    let options = options.current();
+    let id = flood_jobs.start(
+        command_send_channel.get_ref().clone(),
+        dispatcher.get_ref().clone(),
+        (*options).clone(),
+        flood_cache.into_inner(),
+    );
+    HttpResponse::Accepted().json(FloodStarted { id })
+}
+
+#[get("/flood/result/{id}")]
+/// Returns the state of a flood started via `/flood/start`.
+/// - Returns HTTP 202 (Accepted) while it is running.
+/// - Returns HTTP 200 with the IDs of the discovered servers once it is done.
+/// - Returns HTTP 500 if it failed and HTTP 404 for unknown job IDs.
+pub async fn flood_result(id: web::Path<u64>, flood_jobs: web::Data<FloodJobs>) -> impl Responder {
+    match flood_jobs.get(id.into_inner()) {
+        Some(job @ FloodJob::Running) => HttpResponse::Accepted().json(job),
+        Some(job @ FloodJob::Done { .. }) => HttpResponse::Ok().json(job),
+        Some(job @ FloodJob::Failed { .. }) => HttpResponse::InternalServerError().json(job),
+        None => HttpResponse::NotFound().json("Unknown flood job id"),
     }
+}
 
-    // Receive node list from backend
-    let nodes = flood_res_channel.recv();
-    match nodes {
-        Ok(nodes) => {
-            let mut ids = vec![];
-            // Keep only nodes of type Server
-            for node in nodes.0 {
-                if let NodeType::Server = node.1 {
-                    ids.push(node.0);
-                }
-            }
-            HttpResponse::Ok().json(ids)
+#[get("/topology")]
+/// Returns the known network for drawing it: the nodes found by the last flood (without
+/// starting a new one) plus the servers this node registered with and their clients.
+/// The nodes come from the flood cache while it is fresh, otherwise from the backend.
+/// The backend does not report drones or the links between nodes, so only the
+/// registrations and client lists appear as edges.
+/// Returns HTTP 429 while the backend is busy, HTTP 503 while it keeps failing, and
+/// HTTP 500 on any other backend communication failure.
+pub async fn network_topology(
+    node_id: web::Data<u8>,
+    dispatcher: web::Data<Dispatcher>,
+    directory: web::Data<Directory>,
+    options: web::Data<LiveOptions>,
+    flood_cache: web::Data<FloodCache>,
+) -> impl Responder {
+    let options = options.current();
+    let nodes = match flood_cache.get(flood::max_age(&options)) {
+        Some(nodes) => Ok(Ok(nodes)),
+        None => {
+            let timeout = options.flood_timeout;
+            block(move || dispatcher.edge_nodes(timeout)).await
         }
+    };
+
+    match nodes {
+        Ok(Ok(nodes)) => HttpResponse::Ok().json(Topology::build(**node_id, &nodes, &directory)),
+        Ok(Err(DispatchError::Busy)) => backend_busy(),
+        Ok(Err(DispatchError::Unavailable(left))) => backend_unavailable(left),
+        Ok(Err(e)) => HttpResponse::InternalServerError().json(e.to_string()),
         Err(_) => {
             HttpResponse::InternalServerError().json("Failed to receive answer from the backend")
         }
     }
 }
 
+/// Servers a payload may address: the ones found by a flood and the ones registered with.
+fn known_servers(directory: &Directory, flood_cache: &FloodCache) -> BTreeSet<u8> {
+    let mut known = flood_cache.discovered_servers();
+    known.extend(directory.servers());
+    known
+}
+
+/// Whether a message can be sent through `server_id` right away: it is in the current
+/// topology or this node registered with it.
+fn has_route(server_id: u8, directory: &Directory, flood_cache: &FloodCache) -> bool {
+    directory.servers().contains(&server_id) || flood_cache.last_servers().contains(&server_id)
+}
+
+/// Puts `outgoing` in the outbox, held until a route to its server is found if there is none.
+fn enqueue_routed(
+    outbox: &Outbox,
+    outgoing: OutgoingMessage,
+    directory: &Directory,
+    flood_cache: &FloodCache,
+) -> Vec<(OutgoingMessage, EnqueueResult)> {
+    if has_route(outgoing.server_id, directory, flood_cache) {
+        outbox.enqueue(vec![outgoing])
+    } else {
+        outbox.hold(vec![outgoing])
+    }
+}
+
 #[derive(Deserialize)]
 struct RegisterRequest {
-    id: u8, // Target node ID to register with
+    id: i64, // Target node ID to register with
 }
 
 #[post("/register")]
-/// Sends a registration request to another node.
-/// Constructs a `Register` chat request from the current node (`client_id`) to the target `id`.
+/// Sends a registration request to another node and waits for its answer.
+/// Constructs a `Register` chat request from the current node to the target `id`.
+/// - Returns HTTP 200 with the server's reply once it confirms.
+/// - Returns HTTP 404, 409 or 502 with the error details if the server answers with an
+///   error, see [`ChatError`].
+/// - Returns HTTP 504 if no answer arrives within `ServerOptions::register_timeout`.
+/// - Returns HTTP 429 while the backend is busy, and HTTP 503 while it keeps failing.
+/// - Returns HTTP 400 with the invalid fields if `id` is not a node ID, is this node or was
+///   never discovered, see [`ValidationErrors`].
 pub async fn register(
     payload: web::Json<RegisterRequest>,
-    client_id: web::Data<u8>,
-    command_send_channel: web::Data<Sender<Command>>,
+    node_id: web::Data<u8>,
+    registrar: web::Data<Registrar>,
+    directory: web::Data<Directory>,
+    flood_cache: web::Data<FloodCache>,
 ) -> impl Responder {
-    let msg = Message {
-        source: **client_id,
-        destination: payload.id,
-        session_id: 0,
-        content: MessageType::Request(RequestType::ChatRequest(ChatRequest::Register)),
+    let mut errors = ValidationErrors::default();
+    let known = known_servers(&directory, &flood_cache);
+    let Some(server_id) = errors.server_id("id", payload.id, **node_id, &known) else {
+        return errors.response();
     };
+    let outcome = block(move || registrar.register(server_id, false)).await;
 
-    match command_send_channel.send(Command::SendMessage(msg)) {
-        Ok(()) => HttpResponse::Ok(),
-        Err(_) => HttpResponse::InternalServerError(),
+    match outcome {
+        Ok(RegisterOutcome::Confirmed(reply)) => HttpResponse::Ok().json(reply),
+        Ok(RegisterOutcome::Rejected(reply)) => chat_error::error_response(reply),
+        Ok(RegisterOutcome::TimedOut) => {
+            HttpResponse::GatewayTimeout().json("Server did not confirm the registration")
+        }
+        Ok(RegisterOutcome::Failed) => {
+            HttpResponse::InternalServerError().json("Failed to send request to the backend")
+        }
+        Ok(RegisterOutcome::Busy) => backend_busy(),
+        Ok(RegisterOutcome::Unavailable(left)) => backend_unavailable(left),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to wait for the registration"),
     }
 }
 
+#[get("/registrations")]
+/// Returns the latest registration with every server, by server id: whether it is
+/// pending, confirmed or failed, and whether it was started automatically after a
+/// flood (see `ServerOptions::auto_register`).
+pub async fn registrations(registrar: web::Data<Registrar>) -> impl Responder {
+    HttpResponse::Ok().json(registrar.registrations())
+}
+
 #[derive(Deserialize)]
 struct SendRequest {
-    server_id: u8,   // ID of the server to send message through
-    client_id: u8,   // Target client ID to send message to
+    server_id: i64,  // ID of the server to send message through
+    client_id: i64,  // Target client ID to send message to
     message: String, // Message content
 }
 
+#[derive(Serialize)]
+struct SendResponse {
+    id: u64, // Message ID to query the delivery status with
+}
+
 #[post("/send")]
 /// Sends a chat message from this node to a target client through a server.
-/// Builds a `SendMessage` chat request and forwards it to the backend.
+/// Puts a `SendMessage` chat request in the outbox, which forwards it to the backend and
+/// retries it until the server acknowledges it, see [`outbox`](super::outbox).
+/// Returns HTTP 202 with a message ID whose delivery can be followed via `/send/{id}/status`.
+/// If the server is neither in the current topology nor registered with, the message is
+/// held (`awaiting_route` in its status) until a flood finds the server or it confirms a
+/// registration, and is sent then.
+/// Messages to the same client are numbered (`seq` in their status) and sent in the order
+/// they were accepted, even if the UI sends them in quick succession.
+/// Returns HTTP 400 with the invalid fields if the message is empty, or an ID is not a node ID
+/// or is this node, see [`ValidationErrors`].
+/// Returns HTTP 409 if the conversation requires a key of the peer but none is recorded.
+/// Returns HTTP 413 if the message is longer than `ServerOptions::max_message_len`.
 pub async fn send_message(
     payload: web::Json<SendRequest>,
     node_id: web::Data<u8>,
-    command_send_channel: web::Data<Sender<Command>>,
+    options: web::Data<LiveOptions>,
+    outbox: web::Data<Outbox>,
+    session_ids: web::Data<SessionIds>,
+    identity: web::Data<IdentityStore>,
+    (directory, flood_cache): (web::Data<Directory>, web::Data<FloodCache>),
 ) -> impl Responder {
-    let msg = Message {
-        source: *node_id.get_ref(),
-        destination: payload.server_id,
-        session_id: 0,
-        content: MessageType::Request(RequestType::ChatRequest(ChatRequest::SendMessage {
-            from: *node_id.get_ref(),
-            to: payload.client_id,
-            message: payload.message.clone(),
-        })),
+    let options = options.current();
+    let mut errors = ValidationErrors::default();
+    let server_id = errors.node_id("server_id", payload.server_id, **node_id);
+    let client_id = errors.node_id("client_id", payload.client_id, **node_id);
+    errors.non_empty("message", &payload.message);
+    let (server_id, client_id) = match (server_id, client_id) {
+        (Some(server_id), Some(client_id)) if errors.is_empty() => (server_id, client_id),
+        _ => return errors.response(),
+    };
+    if let Err(response) = check_message_len(&payload.message, options.max_message_len) {
+        return response;
+    }
+
+    if identity.lacks_required_key(client_id) {
+        return HttpResponse::Conflict().json(KEY_MISSING);
+    }
+    let outgoing = OutgoingMessage {
+        server_id,
+        client_id,
+        session_id: session_ids.next(),
+        message: payload.message.clone(),
+    };
+
+    let results = block(move || enqueue_routed(&outbox, outgoing, &directory, &flood_cache)).await;
+
+    match results
+        .ok()
+        .and_then(|mut results| results.pop())
+        .map(|(_, result)| result)
+    {
+        Some(Ok(id)) => HttpResponse::Accepted().json(SendResponse { id }),
+        Some(Err(e)) => HttpResponse::InternalServerError().json(e),
+        None => HttpResponse::InternalServerError().json("Failed to send the message"),
+    }
+}
+
+#[derive(Deserialize)]
+struct BroadcastRequest {
+    server_id: Option<u8>, // Server whose clients to reach; every registered server if missing
+    message: String,       // Message content
+}
+
+#[derive(Serialize)]
+struct BroadcastResult {
+    server_id: u8,
+    client_id: u8,
+    id: Option<u64>,             // Message ID, if the message was accepted
+    error: Option<&'static str>, // Why the message could not be sent
+}
+
+#[post("/send/broadcast")]
+/// Sends a chat message to every client known on a server, or on every registered server.
+/// Clients are known from the latest `/clients` reply of each server; this node is skipped.
+/// The messages are put in the outbox, which hands them to the backend as one batch.
+/// - Returns HTTP 200 with the result per recipient; each delivery can be followed
+///   via `/send/{id}/status`.
+/// - Returns HTTP 404 if no clients are known.
+/// - Returns HTTP 413 if the message is longer than `ServerOptions::max_message_len`.
+///
+/// Clients whose conversation requires a key of the peer while none is recorded are skipped
+/// and listed with an error.
+pub async fn broadcast_message(
+    payload: web::Json<BroadcastRequest>,
+    node_id: web::Data<u8>,
+    options: web::Data<LiveOptions>,
+    outbox: web::Data<Outbox>,
+    session_ids: web::Data<SessionIds>,
+    directory: web::Data<Directory>,
+    identity: web::Data<IdentityStore>,
+) -> impl Responder {
+    let options = options.current();
+    if let Err(response) = check_message_len(&payload.message, options.max_message_len) {
+        return response;
+    }
+    let servers = payload
+        .server_id
+        .map_or_else(|| directory.servers(), |server_id| vec![server_id]);
+
+    let mut outgoing = vec![];
+    let mut refused = vec![];
+    for server_id in servers {
+        for client_id in directory.clients(server_id) {
+            if client_id != **node_id && identity.lacks_required_key(client_id) {
+                refused.push(BroadcastResult {
+                    server_id,
+                    client_id,
+                    id: None,
+                    error: Some(KEY_MISSING),
+                });
+            } else if client_id != **node_id {
+                outgoing.push(OutgoingMessage {
+                    server_id,
+                    client_id,
+                    session_id: session_ids.next(),
+                    message: payload.message.clone(),
+                });
+            }
+        }
+    }
+    if outgoing.is_empty() && refused.is_empty() {
+        return HttpResponse::NotFound().json("No known clients; list them via /clients first");
+    }
+
+    let results = block(move || outbox.enqueue(outgoing)).await;
+
+    match results {
+        Ok(results) => {
+            let results: Vec<_> = results
+                .into_iter()
+                .map(|(outgoing, result)| {
+                    let (id, error) = match result {
+                        Ok(id) => (Some(id), None),
+                        Err(e) => (None, Some(e)),
+                    };
+                    BroadcastResult {
+                        server_id: outgoing.server_id,
+                        client_id: outgoing.client_id,
+                        id,
+                        error,
+                    }
+                })
+                .chain(refused)
+                .collect();
+            HttpResponse::Ok().json(results)
+        }
+        Err(_) => HttpResponse::InternalServerError().json("Failed to send the messages"),
+    }
+}
+
+#[derive(Serialize)]
+struct FailedDelivery {
+    error: ChatError,         // Why the server rejected the message
+    delivery: DeliveryStatus, // The delivery status as usual
+}
+
+#[get("/send/{id}/status")]
+/// Returns the delivery status of a message sent via `/send`, including how often the outbox
+/// handed it to the backend and when it tries again.
+/// While the message is still pending, briefly polls the backend for the server's answer.
+/// - Returns HTTP 404, 409 or 502 with the error details next to the status if the server
+///   rejected the message, see [`ChatError`]. A failure without an answer is HTTP 200.
+/// - Returns HTTP 404 for unknown message IDs.
+pub async fn send_status(
+    id: web::Path<u64>,
+    dispatcher: web::Data<Dispatcher>,
+    inbox: web::Data<Inbox>,
+    deliveries: web::Data<DeliveryTracker>,
+) -> impl Responder {
+    let id = id.into_inner();
+    if deliveries
+        .status(id)
+        .is_some_and(|status| status.state.is_pending())
+    {
+        // Nothing new to report if this fails; the status is served as it is
+        refill(&inbox, &dispatcher, REPLY_POLL_INTERVAL).await;
+    }
+
+    match deliveries.status(id) {
+        Some(status) if status.state == DeliveryState::Failed => {
+            match status.reply.as_ref().and_then(ChatError::from_payload) {
+                Some(error) => HttpResponse::build(error.status()).json(FailedDelivery {
+                    error,
+                    delivery: status,
+                }),
+                None => HttpResponse::Ok().json(status),
+            }
+        }
+        Some(status) => HttpResponse::Ok().json(status),
+        None => HttpResponse::NotFound().json("Unknown message id"),
+    }
+}
+
+#[get("/status/{session_id}/timeline")]
+/// Returns the lifecycle of the message sent in session `session_id`, oldest step first:
+/// accepted, held until a route was found, handed to the backend (again, when retried),
+/// acknowledged, delivered or failed, replies stored under the session ID and anomalies of
+/// the server or client raised while the message was underway.
+/// Returns HTTP 404 if no message was sent in the session.
+pub async fn delivery_timeline(
+    session_id: web::Path<u64>,
+    deliveries: web::Data<DeliveryTracker>,
+    store: web::Data<MessageStore>,
+    events: web::Data<EventLog>,
+) -> impl Responder {
+    let session_id = session_id.into_inner();
+    let timeline = block(move || -> Result<_, String> {
+        let records = deliveries.journal_records().map_err(|e| e.to_string())?;
+        let replies = store.in_session(session_id).map_err(|e| e.to_string())?;
+        Ok(timeline::reconstruct(
+            session_id,
+            &records,
+            &replies,
+            &events.since(0),
+            unix_millis(),
+        ))
+    })
+    .await;
+
+    match timeline {
+        Ok(Ok(Some(timeline))) => HttpResponse::Ok().json(timeline),
+        Ok(Ok(None)) => HttpResponse::NotFound().json("No message was sent in this session"),
+        Ok(Err(_)) => HttpResponse::InternalServerError().json("Failed to read the outbox journal"),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to wait for the timeline"),
+    }
+}
+
+#[get("/deadletter")]
+/// Returns the messages whose send failed, oldest failure first: the backend couldn't be
+/// reached or didn't take them in time, or the server rejected them. Each is listed under
+/// the message ID it failed with, together with the reason and the server's answer.
+pub async fn dead_letters(dead_letters: web::Data<DeadLetterQueue>) -> impl Responder {
+    HttpResponse::Ok().json(dead_letters.list())
+}
+
+#[post("/deadletter/{id}/retry")]
+/// Puts the message that failed under message ID `id` back in the outbox and takes it off
+/// the queue. Returns HTTP 202 with a new message ID whose delivery can be followed via
+/// `/send/{id}/status`; if the message fails again, it is queued under that ID. Like with
+/// `/send`, the message is held until a route to its server is found if there is none.
+/// - Returns HTTP 404 if no message failed under `id`.
+/// - Returns HTTP 409 if the conversation requires a key of the peer but none is recorded.
+pub async fn retry_dead_letter(
+    id: web::Path<u64>,
+    outbox: web::Data<Outbox>,
+    dead_letters: web::Data<DeadLetterQueue>,
+    identity: web::Data<IdentityStore>,
+    directory: web::Data<Directory>,
+    flood_cache: web::Data<FloodCache>,
+) -> impl Responder {
+    let id = id.into_inner();
+    let Some(letter) = dead_letters
+        .list()
+        .into_iter()
+        .find(|letter| letter.id == id)
+    else {
+        return HttpResponse::NotFound().json("No message failed under this id");
+    };
+    if identity.lacks_required_key(letter.message.client_id) {
+        return HttpResponse::Conflict().json(KEY_MISSING);
+    }
+    let letter = match dead_letters.take(id) {
+        Ok(Some(letter)) => letter,
+        Ok(None) => return HttpResponse::NotFound().json("No message failed under this id"),
+        Err(_) => {
+            return HttpResponse::InternalServerError().json("Failed to update the dead letters");
+        }
     };
 
-    match command_send_channel.send(Command::SendMessage(msg)) {
-        Ok(()) => HttpResponse::Ok(),
-        Err(_) => HttpResponse::InternalServerError(),
+    let queue = dead_letters.clone();
+    let results = block(move || {
+        let mut results = enqueue_routed(&outbox, letter.message.clone(), &directory, &flood_cache);
+        // The message was never tracked; keep it queued
+        if let Some((_, Err(_))) = results.last()
+            && let Err(e) = queue.add(letter)
+        {
+            tracing::error!("Failed to put message {id} back on the dead letters: {e}");
+        }
+        results.pop()
+    })
+    .await;
+
+    match results.ok().flatten().map(|(_, result)| result) {
+        Some(Ok(id)) => HttpResponse::Accepted().json(SendResponse { id }),
+        Some(Err(e)) => HttpResponse::InternalServerError().json(e),
+        None => HttpResponse::InternalServerError().json("Failed to send the message"),
+    }
+}
+
+#[derive(Deserialize)]
+struct OutboxQuery {
+    #[serde(default)]
+    since: u64, // Only messages sent at or after this Unix time in milliseconds
+    state: Option<DeliveryState>, // Only messages in this state
+}
+
+#[get("/outbox")]
+/// Returns the delivery status of the sent messages, ordered by message ID. Each moves
+/// through the states `queued`, `sent`, `acked`, `delivered` and `read` or `failed`, see
+/// [`DeliveryState`]; the status also tells how often the message was handed to the backend,
+/// when it is sent next and why the last attempt failed, if it did.
+/// - Optional `since` (Unix time in milliseconds) and `state` query parameters restrict the
+///   result; `state=queued` lists what the outbox is still waiting to send.
+pub async fn outbox_progress(
+    query: web::Query<OutboxQuery>,
+    deliveries: web::Data<DeliveryTracker>,
+) -> impl Responder {
+    let statuses: Vec<_> = deliveries
+        .sent_since(query.since)
+        .into_iter()
+        .filter(|status| query.state.is_none_or(|state| status.state == state))
+        .collect();
+    HttpResponse::Ok().json(statuses)
+}
+
+#[get("/outbox/{id}")]
+/// Returns the delivery status of sent message `id`, like `/outbox` does for every message.
+/// Returns HTTP 404 for unknown message IDs.
+pub async fn outbox_message(
+    id: web::Path<u64>,
+    deliveries: web::Data<DeliveryTracker>,
+) -> impl Responder {
+    match deliveries.status(id.into_inner()) {
+        Some(status) => HttpResponse::Ok().json(status),
+        None => HttpResponse::NotFound().json("Unknown message id"),
     }
 }
 
 #[post("/clients")]
 /// Requests a list of connected clients from a server.
-/// Sends a `ClientList` chat request to the target server.
+/// Sends a `ClientList` chat request to the target server, whose answer arrives via
+/// `/messages`, and returns the clients last known to be connected to it with their
+/// nicknames.
+/// Returns HTTP 429 while the backend is busy, and HTTP 503 while it keeps failing.
+/// Returns HTTP 400 with the invalid fields if `server_id` is not a node ID, is this node or
+/// was never discovered, see [`ValidationErrors`].
 pub async fn clients(
     payload: web::Json<SendRequest>,
-    node_id: web::Data<u8>,
+    (node_id, session_ids): (web::Data<u8>, web::Data<SessionIds>),
     command_send_channel: web::Data<Sender<Command>>,
+    dispatcher: web::Data<Dispatcher>,
+    metrics: web::Data<Metrics>,
+    (directory, flood_cache): (web::Data<Directory>, web::Data<FloodCache>),
+    identity: web::Data<IdentityStore>,
 ) -> impl Responder {
+    let mut errors = ValidationErrors::default();
+    let known = known_servers(&directory, &flood_cache);
+    let Some(server_id) = errors.server_id("server_id", payload.server_id, **node_id, &known)
+    else {
+        return errors.response();
+    };
     let msg = Message {
         source: *node_id.get_ref(),
-        destination: payload.server_id,
-        session_id: 0,
+        destination: server_id,
+        session_id: session_ids.next(),
         content: MessageType::Request(RequestType::ChatRequest(ChatRequest::ClientList)),
     };
 
-    match command_send_channel.send(Command::SendMessage(msg)) {
-        Ok(()) => HttpResponse::Ok(),
-        Err(_) => HttpResponse::InternalServerError(),
+    if let Err(DispatchError::Unavailable(left)) = dispatcher.check_breaker() {
+        return backend_unavailable(left);
+    }
+    match command_send_channel.try_send(Command::SendMessage(msg)) {
+        Ok(()) => {
+            metrics.record_command();
+            let nicknames = identity.nicknames();
+            let clients: Vec<_> = directory
+                .clients(server_id)
+                .into_iter()
+                .map(|id| NamedNode::new(id, &nicknames))
+                .collect();
+            HttpResponse::Ok().json(clients)
+        }
+        Err(TrySendError::Full(_)) => {
+            metrics.record_backend_error();
+            backend_busy()
+        }
+        Err(TrySendError::Disconnected(_)) => {
+            metrics.record_backend_error();
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TypingRequest {
+    server_id: i64, // ID of the server the peer is registered with
+    client_id: i64, // The peer
+}
+
+#[post("/typing")]
+/// Tells client `client_id` that the user is typing, with a typing notice sent through server
+/// `server_id`, see [`typing`](super::typing). The UI calls this every few seconds while the
+/// user types; the notice bypasses the outbox, as a lost one doesn't matter. Notices received
+/// from peers show as `typing` events in `/events`.
+/// - Returns HTTP 204 once the notice is handed to the backend.
+/// - Returns HTTP 429 while the backend is busy, and HTTP 503 while it keeps failing.
+/// - Returns HTTP 400 with the invalid fields if `server_id` is not a node ID, is this node or
+///   was never discovered, or `client_id` is not a node ID or is this node, see
+///   [`ValidationErrors`].
+pub async fn typing_notice(
+    payload: web::Json<TypingRequest>,
+    node_id: web::Data<u8>,
+    command_send_channel: web::Data<Sender<Command>>,
+    dispatcher: web::Data<Dispatcher>,
+    session_ids: web::Data<SessionIds>,
+    metrics: web::Data<Metrics>,
+    (directory, flood_cache): (web::Data<Directory>, web::Data<FloodCache>),
+) -> impl Responder {
+    let mut errors = ValidationErrors::default();
+    let known = known_servers(&directory, &flood_cache);
+    let server_id = errors.server_id("server_id", payload.server_id, **node_id, &known);
+    let client_id = errors.node_id("client_id", payload.client_id, **node_id);
+    let (Some(server_id), Some(client_id)) = (server_id, client_id) else {
+        return errors.response();
+    };
+    let msg = typing::notice(**node_id, server_id, client_id, session_ids.next());
+
+    if let Err(DispatchError::Unavailable(left)) = dispatcher.check_breaker() {
+        return backend_unavailable(left);
+    }
+    match command_send_channel.try_send(Command::SendMessage(msg)) {
+        Ok(()) => {
+            metrics.record_command();
+            HttpResponse::NoContent().finish()
+        }
+        Err(TrySendError::Full(_)) => {
+            metrics.record_backend_error();
+            backend_busy()
+        }
+        Err(TrySendError::Disconnected(_)) => {
+            metrics.record_backend_error();
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ContactPresence {
+    id: u8,
+    nickname: String,
+    online: bool, // Whether the contact is registered with the server
+}
+
+#[derive(Serialize)]
+struct PresenceView {
+    server_id: u8,
+    contacts: Vec<ContactPresence>,
+    updated_at: Option<u64>, // When the server last reported its clients, if ever
+    refresh_interval_secs: u64,
+}
+
+#[get("/presence/{server_id}")]
+/// Reports which contacts are registered with server `server_id`, according to the client
+/// list it last reported, and when that was. From the first request on, the list is
+/// refreshed every `ServerOptions::presence_interval` in the background, see
+/// [`presence`](super::presence); until the first list arrives, `updated_at` is null.
+/// Returns HTTP 400 with the invalid fields if `server_id` is this node or was never
+/// discovered, see [`ValidationErrors`].
+pub async fn contact_presence(
+    server_id: web::Path<u8>,
+    node_id: web::Data<u8>,
+    presence: web::Data<Presence>,
+    directory: web::Data<Directory>,
+    flood_cache: web::Data<FloodCache>,
+    identity: web::Data<IdentityStore>,
+) -> impl Responder {
+    let mut errors = ValidationErrors::default();
+    let known = known_servers(&directory, &flood_cache);
+    let Some(server_id) = errors.server_id(
+        "server_id",
+        i64::from(server_id.into_inner()),
+        **node_id,
+        &known,
+    ) else {
+        return errors.response();
+    };
+    presence.watch(server_id);
+
+    let online = directory.clients(server_id);
+    let contacts = identity
+        .get()
+        .contacts
+        .into_iter()
+        .map(|(id, contact)| ContactPresence {
+            id,
+            nickname: contact.label,
+            online: online.contains(&id),
+        })
+        .collect();
+    HttpResponse::Ok().json(PresenceView {
+        server_id,
+        contacts,
+        updated_at: directory.clients_updated_at(server_id),
+        refresh_interval_secs: presence.interval().as_secs(),
+    })
+}
+
+/// Sends a content request and waits up to `ServerOptions::content_timeout` for the
+/// answer, recording it in the statistics of the server.
+fn content_request(
+    request: Message,
+    dispatcher: &Dispatcher,
+    inbox: &Inbox,
+    options: &ServerOptions,
+    network_stats: &NetworkStats,
+) -> Result<Envelope, DispatchError> {
+    let server_id = request.destination;
+    let started = Instant::now();
+    let reply = inbox.request(dispatcher, request, options.content_timeout);
+    match &reply {
+        Ok(_) => network_stats.record_answer(server_id, started.elapsed()),
+        Err(DispatchError::Timeout) => network_stats.record_drop(server_id),
+        Err(DispatchError::Disconnected | DispatchError::Busy | DispatchError::Unavailable(_)) => {}
+    }
+    reply
+}
+
+/// Renders a failed content request: HTTP 504 on timeout, HTTP 429 while the backend
+/// is busy, HTTP 503 while it keeps failing, otherwise HTTP 500.
+fn content_error(e: DispatchError, what: &str) -> HttpResponse {
+    match e {
+        DispatchError::Timeout => {
+            HttpResponse::GatewayTimeout().json(format!("Server did not answer the {what} request"))
+        }
+        DispatchError::Busy => backend_busy(),
+        DispatchError::Unavailable(left) => backend_unavailable(left),
+        DispatchError::Disconnected => {
+            HttpResponse::InternalServerError().json("Failed to send request to the backend")
+        }
+    }
+}
+
+/// HTTP 429 for a request refused because the command channel to the backend is full.
+fn backend_busy() -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .insert_header((header::RETRY_AFTER, 1))
+        .json("The backend is busy, try again later")
+}
+
+/// HTTP 503 for a request refused by the circuit breaker, retried after the `left`
+/// cool-down.
+fn backend_unavailable(left: Duration) -> HttpResponse {
+    HttpResponse::ServiceUnavailable()
+        .insert_header((header::RETRY_AFTER, breaker::retry_after_secs(left)))
+        .json("The backend keeps failing, try again later")
+}
+
+#[get("/content/{server_id}/files")]
+/// Asks content server `server_id` for the files it offers and returns their IDs and names.
+/// - Returns HTTP 404, 409 or 502 with the error details if the server answers with an
+///   error, see [`ChatError`], and HTTP 502 if it answers with something else than a list.
+/// - Returns HTTP 504 if no answer arrives within `ServerOptions::content_timeout`.
+pub async fn content_files(
+    server_id: web::Path<u8>,
+    node_id: web::Data<u8>,
+    session_ids: web::Data<SessionIds>,
+    dispatcher: web::Data<Dispatcher>,
+    inbox: web::Data<Inbox>,
+    options: web::Data<LiveOptions>,
+    network_stats: web::Data<NetworkStats>,
+) -> impl Responder {
+    let options = options.current();
+    let request = content::list_files(**node_id, server_id.into_inner(), session_ids.next());
+    let reply =
+        block(move || content_request(request, &dispatcher, &inbox, &options, &network_stats))
+            .await;
+
+    match reply {
+        Ok(Ok(reply)) if reply.is_error() => chat_error::error_response(reply.payload),
+        Ok(Ok(reply)) => match content::file_list(&reply) {
+            Some(files) => HttpResponse::Ok().json(files),
+            None => HttpResponse::BadGateway().json(reply.payload),
+        },
+        Ok(Err(e)) => content_error(e, "file list"),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to wait for the file list"),
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum ContentFormat {
+    #[default]
+    Html,
+    Json,
+}
+
+#[derive(Deserialize)]
+struct ContentFileQuery {
+    media_server: Option<u8>, // Server to fetch referenced media from; the file's server if missing
+    #[serde(default)]
+    format: ContentFormat, // `html` or `json`
+}
+
+#[get("/content/{server_id}/files/{file_id}")]
+/// Fetches text file `file_id` from content server `server_id` together with the media it
+/// references as `[media:<id>]`, which are fetched from `media_server` into the media cache.
+/// - Returns HTTP 200 with the file as HTML fragment, the media embedded as images, or with
+///   `format=json` as its text plus the `/media` URLs of the media.
+///   Media that could not be fetched are left out and noted.
+/// - Returns HTTP 404, 409 or 502 with the error details if the server answers with an
+///   error, see [`ChatError`], and HTTP 502 if it answers with something else than a text.
+/// - Returns HTTP 504 if the file doesn't arrive within `ServerOptions::content_timeout`.
+pub async fn content_file(
+    path: web::Path<(u8, u64)>,
+    query: web::Query<ContentFileQuery>,
+    (node_id, session_ids): (web::Data<u8>, web::Data<SessionIds>),
+    (dispatcher, inbox): (web::Data<Dispatcher>, web::Data<Inbox>),
+    options: web::Data<LiveOptions>,
+    network_stats: web::Data<NetworkStats>,
+    media_cache: web::Data<MediaCache>,
+) -> impl Responder {
+    let options = options.current();
+    let (server_id, file_id) = path.into_inner();
+    let media_server = query.media_server.unwrap_or(server_id);
+    let format = query.format;
+
+    let assembled = block(
+        move || -> Result<Result<AssembledFile, Value>, DispatchError> {
+            let request = content::fetch_file(**node_id, server_id, session_ids.next(), file_id);
+            let reply = content_request(request, &dispatcher, &inbox, &options, &network_stats)?;
+            let Some(text) = content::text(&reply).filter(|_| !reply.is_error()) else {
+                return Ok(Err(reply.payload));
+            };
+
+            let media = content::media_references(&text)
+                .into_iter()
+                .map(|media_id| {
+                    let fetched = media_cache.get_or_fetch(media_server, media_id, || {
+                        let request = content::fetch_media(
+                            **node_id,
+                            media_server,
+                            session_ids.next(),
+                            media_id,
+                        );
+                        content_request(request, &dispatcher, &inbox, &options, &network_stats)
+                    });
+                    match fetched {
+                        Ok(_) => MediaPart::fetched(media_id, media_server),
+                        Err(e) => MediaPart::missing(media_id, &e.to_string()),
+                    }
+                })
+                .collect();
+            Ok(Ok(AssembledFile {
+                file_id,
+                text,
+                media,
+            }))
+        },
+    )
+    .await;
+
+    match assembled {
+        Ok(Ok(Ok(file))) if format == ContentFormat::Json => HttpResponse::Ok().json(file),
+        Ok(Ok(Ok(file))) => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(file.to_html()),
+        Ok(Ok(Err(payload))) => chat_error::error_response(payload),
+        Ok(Err(e)) => content_error(e, "file"),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to wait for the file"),
+    }
+}
+
+#[derive(Serialize)]
+struct UploadStarted {
+    id: u64, // Upload ID to follow the progress with
+}
+
+#[post("/content/{server_id}/upload")]
+/// Accepts a file as `multipart/form-data` (the first part with a file name), stages it
+/// in the data directory and starts sending it to content server `server_id` in chunks
+/// of `ServerOptions::upload_chunk_size` bytes. Returns HTTP 202 with the upload ID
+/// right away; the progress is served by `/content/uploads/{id}`.
+/// - Returns HTTP 400 if the form holds no file or can't be read.
+/// - Returns HTTP 507 if the file doesn't fit into the staging quota.
+pub async fn upload_file(
+    server_id: web::Path<u8>,
+    mut form: Multipart,
+    uploads: web::Data<Uploads>,
+    quotas: web::Data<DiskQuotas>,
+) -> HttpResponse {
+    let mut file = None;
+    while let Some(field) = form.next().await {
+        let Ok(field) = field else {
+            return HttpResponse::BadRequest().json("Failed to read the form");
+        };
+        let name = field
+            .content_disposition()
+            .and_then(|disposition| disposition.get_filename())
+            .map(str::to_string);
+        if let Some(name) = name {
+            file = Some((name, field));
+            break;
+        }
+    }
+    let Some((name, mut field)) = file else {
+        return HttpResponse::BadRequest().json("The form holds no file");
+    };
+
+    let quotas = quotas.into_inner();
+    let mut staged = match block(move || StagedFile::create(quotas)).await {
+        Ok(Ok(staged)) => staged,
+        _ => return HttpResponse::InternalServerError().json("Failed to stage the file"),
+    };
+    while let Some(piece) = field.next().await {
+        let Ok(piece) = piece else {
+            return HttpResponse::BadRequest().json("Failed to read the file");
+        };
+        let appended = block(move || staged.append(&piece).map(|()| staged)).await;
+        staged = match appended {
+            Ok(Ok(staged)) => staged,
+            Ok(Err(StageError::Quota(e))) => return HttpResponse::InsufficientStorage().json(e),
+            _ => return HttpResponse::InternalServerError().json("Failed to stage the file"),
+        };
+    }
+
+    let id = uploads
+        .into_inner()
+        .start(server_id.into_inner(), name, staged.finish());
+    HttpResponse::Accepted().json(UploadStarted { id })
+}
+
+#[get("/content/uploads/{id}")]
+/// Returns the progress of an upload started via `/content/{server_id}/upload`,
+/// with the state of every chunk.
+/// - Returns HTTP 202 (Accepted) while chunks are being sent.
+/// - Returns HTTP 200 with the server's file ID, if it reported one, once every chunk
+///   was acknowledged.
+/// - Returns HTTP 502 if a chunk failed and HTTP 404 for unknown upload IDs.
+pub async fn upload_progress(id: web::Path<u64>, uploads: web::Data<Uploads>) -> impl Responder {
+    match uploads.get(id.into_inner()) {
+        Some(progress) => match progress.state {
+            UploadState::Running => HttpResponse::Accepted().json(progress),
+            UploadState::Done { .. } => HttpResponse::Ok().json(progress),
+            UploadState::Failed { .. } => HttpResponse::BadGateway().json(progress),
+        },
+        None => HttpResponse::NotFound().json("Unknown upload id"),
+    }
+}
+
+#[get("/media/{server_id}/{media_id}")]
+/// Streams media `media_id` of media server `server_id`, honoring `Range` requests.
+/// The media is fetched once and then served from the on-disk media cache.
+/// - Returns HTTP 404, 409 or 502 with the error details if the server answers with an
+///   error, see [`ChatError`], and HTTP 502 if it answers with something else than media.
+/// - Returns HTTP 504 if the media doesn't arrive within `ServerOptions::content_timeout`.
+/// - Returns HTTP 507 if the media doesn't fit into the media quota.
+pub async fn stream_media(
+    req: HttpRequest,
+    path: web::Path<(u8, u64)>,
+    (node_id, session_ids): (web::Data<u8>, web::Data<SessionIds>),
+    (dispatcher, inbox): (web::Data<Dispatcher>, web::Data<Inbox>),
+    options: web::Data<LiveOptions>,
+    network_stats: web::Data<NetworkStats>,
+    media_cache: web::Data<MediaCache>,
+) -> HttpResponse {
+    let options = options.current();
+    let (server_id, media_id) = path.into_inner();
+    let fetched = block(move || {
+        media_cache.get_or_fetch(server_id, media_id, || {
+            let request = content::fetch_media(**node_id, server_id, session_ids.next(), media_id);
+            content_request(request, &dispatcher, &inbox, &options, &network_stats)
+        })
+    })
+    .await;
+
+    let path = match fetched {
+        Ok(Ok(path)) => path,
+        Ok(Err(FetchError::Backend(e))) => return content_error(e, "media"),
+        Ok(Err(FetchError::Rejected(payload))) => return chat_error::error_response(payload),
+        Ok(Err(FetchError::Storage(_))) => {
+            return HttpResponse::InternalServerError().json("Failed to cache the media");
+        }
+        Ok(Err(FetchError::Quota(e))) => return HttpResponse::InsufficientStorage().json(e),
+        Err(_) => return HttpResponse::InternalServerError().json("Failed to wait for the media"),
+    };
+    let content_type = MediaCache::content_type(&path);
+    match NamedFile::open(&path) {
+        Ok(file) => file
+            .set_content_type(
+                content_type
+                    .parse()
+                    .unwrap_or(mime::APPLICATION_OCTET_STREAM),
+            )
+            .into_response(&req),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to read the cached media"),
+    }
+}
+
+#[derive(Deserialize)]
+struct MessagesQuery {
+    #[serde(default)]
+    peek: bool, // Leave the messages unread; acknowledge them via `/messages/ack`
+}
+
+/// A node ID together with the nickname given to it in the address book.
+#[derive(Serialize)]
+struct NamedNode {
+    id: u8,
+    nickname: Option<String>,
+}
+
+impl NamedNode {
+    fn new(id: u8, nicknames: &BTreeMap<u8, String>) -> Self {
+        NamedNode {
+            id,
+            nickname: nicknames.get(&id).cloned(),
+        }
+    }
+}
+
+/// The JSON of a received message as handed to the UI: the payload stamped with its
+/// `received_at`, its `sender` and, for a `ClientList` reply, the listed `clients`,
+/// each with its nickname.
+fn message_json(envelope: &Envelope, nicknames: &BTreeMap<u8, String>) -> Value {
+    let mut payload = envelope.payload.clone();
+    if let Some(fields) = payload.as_object_mut() {
+        fields.insert("received_at".to_string(), envelope.received_at.into());
+        if let Some(sender) = envelope.sender() {
+            let sender = NamedNode::new(sender, nicknames);
+            fields.insert(
+                "sender".to_string(),
+                serde_json::to_value(sender).unwrap_or(Value::Null),
+            );
+        }
+        if let Some(clients) = envelope.client_list() {
+            let clients: Vec<_> = clients
+                .into_iter()
+                .map(|id| NamedNode::new(id, nicknames))
+                .collect();
+            fields.insert(
+                "clients".to_string(),
+                serde_json::to_value(clients).unwrap_or(Value::Null),
+            );
+        }
     }
+    payload
+}
+
+#[derive(Serialize)]
+struct PeekedMessage {
+    id: Option<u64>,  // ID to acknowledge the message with
+    received_at: u64, // Unix time in milliseconds when the frontend received the message
+    payload: Value,
 }
 
 #[get("/messages")]
 /// Retrieves unread messages from the backend.
-/// - Sends `GetUnreadMessagesFromServer` command.
-/// - Waits up to 3 seconds for a response using a channel select.
-/// - Returns messages if available, otherwise HTTP 204 (No Content).
+/// - Requests unread messages through the dispatcher.
+/// - Waits up to 3 seconds for a response.
+/// - Returns the fetched messages together with any already buffered in the inbox,
+///   otherwise HTTP 204 (No Content). Each message carries `received_at`, the Unix time
+///   in milliseconds when the frontend received it, as the drone protocol has no timestamps,
+///   and its `sender` with the nickname given to it via `/contacts/{peer}`; a `ClientList`
+///   reply also the listed `clients` with their nicknames.
+/// - Optional `from`, `since` and `until` query parameters restrict the result to one
+///   sender and a time range; other messages stay unread.
+/// - Returned messages are marked as read, unless `peek=true` is given: then they are
+///   returned with their IDs and stay unread until acknowledged via `/messages/ack`.
+/// - No read receipts are sent, so read-only clients like dashboards don't tell peers their
+///   messages were read; only `/messages/ack` does, see [`receipts`](super::receipts).
 pub async fn get_messages(
-    cmd_channel: web::Data<Sender<Command>>,
-    unread_msg_channel: web::Data<Receiver<UnreadMessagesFromServer>>,
+    query: web::Query<MessagesQuery>,
+    filter: web::Query<MessageFilter>,
+    dispatcher: web::Data<Dispatcher>,
+    inbox: web::Data<Inbox>,
+    identity: web::Data<IdentityStore>,
 ) -> impl Responder {
-    let res = cmd_channel.send(Command::GetUnreadMessagesFromServer);
+    if !refill(&inbox, &dispatcher, Duration::from_secs(3)).await {
+        return HttpResponse::InternalServerError().json("Failed to send request to the backend");
+    }
 
-    match res {
-        Ok(()) => {
-            let timeout_tick = tick(Duration::from_secs(3));
-
-            // Wait for either messages or timeout
-            select! {
-                recv(unread_msg_channel) -> msg => match msg {
-                    Ok(msgs) => {
-                        if msgs.0.is_empty(){
-                            HttpResponse::NoContent().json("No new messages")
-                        } else {
-                            HttpResponse::Ok().json(msgs.0)
-                        }
-                    },
-                    _ => { HttpResponse::Ok().json("No new messages") },
-                },
-                recv(timeout_tick) -> _ => {
-                    HttpResponse::NoContent().json("No new messages")
-                }
-            }
-        }
-        Err(_) => HttpResponse::InternalServerError().json("Failed to send request to the backend"),
+    let nicknames = identity.nicknames();
+    if query.peek {
+        let msgs: Vec<_> = inbox
+            .peek_matching(|envelope| filter.matches(envelope))
+            .into_iter()
+            .map(|envelope| PeekedMessage {
+                id: envelope.id,
+                received_at: envelope.received_at,
+                payload: message_json(&envelope, &nicknames),
+            })
+            .collect();
+        return if msgs.is_empty() {
+            HttpResponse::NoContent().json("No new messages")
+        } else {
+            HttpResponse::Ok().json(msgs)
+        };
+    }
+
+    let filter = filter.into_inner();
+    let Ok(msgs) = block(move || inbox.take_read(|envelope| filter.matches(envelope))).await else {
+        return HttpResponse::InternalServerError().json("Failed to wait for the inbox");
+    };
+    messages_response(msgs, &nicknames)
+}
+
+#[derive(Deserialize)]
+struct AckRequest {
+    ids: Vec<u64>, // IDs of the messages to mark as read
+}
+
+#[post("/messages/ack")]
+/// Marks messages fetched with `/messages?peek=true` as read, removing them from the unread set,
+/// and, with `ServerOptions::read_receipts`, sends their authors a read receipt.
+/// This is the only route sending read receipts.
+/// Returns the IDs that were acknowledged; IDs that are unknown or already read are skipped.
+pub async fn ack_messages(
+    payload: web::Json<AckRequest>,
+    inbox: web::Data<Inbox>,
+    receipts: web::Data<ReadReceipts>,
+) -> impl Responder {
+    let ids = payload.into_inner().ids;
+    let Ok(acked) = block(move || inbox.ack(&ids)).await else {
+        return HttpResponse::InternalServerError().json("Failed to wait for the inbox");
+    };
+    receipts.send(&acked);
+    let ids: Vec<_> = acked.iter().filter_map(|envelope| envelope.id).collect();
+    HttpResponse::Ok().json(ids)
+}
+
+/// Fetches new messages into the inbox like [`Inbox::refill`], on the blocking
+/// thread pool. Returns `false` if the backend can't be reached.
+async fn refill(
+    inbox: &web::Data<Inbox>,
+    dispatcher: &web::Data<Dispatcher>,
+    timeout: Duration,
+) -> bool {
+    let (inbox, dispatcher) = (inbox.clone(), dispatcher.clone());
+    block(move || inbox.refill(&dispatcher, timeout))
+        .await
+        .unwrap_or(false)
+}
+
+/// Renders taken inbox messages, see [`message_json`]: HTTP 200 with the messages,
+/// or 204 if there are none.
+fn messages_response(envelopes: Vec<Envelope>, nicknames: &BTreeMap<u8, String>) -> HttpResponse {
+    let msgs: Vec<_> = envelopes
+        .iter()
+        .map(|envelope| message_json(envelope, nicknames))
+        .collect();
+    if msgs.is_empty() {
+        HttpResponse::NoContent().json("No new messages")
+    } else {
+        HttpResponse::Ok().json(msgs)
+    }
+}
+
+#[derive(Serialize)]
+struct UnreadCounts {
+    total: usize,
+    per_peer: BTreeMap<u8, usize>, // Unread messages by sender
+}
+
+#[get("/messages/count")]
+/// Returns the number of unread messages per sender without handing them out,
+/// so the UI can render badges cheaply. Messages stay unread.
+pub async fn unread_count(
+    dispatcher: web::Data<Dispatcher>,
+    inbox: web::Data<Inbox>,
+) -> impl Responder {
+    if !refill(&inbox, &dispatcher, REPLY_POLL_INTERVAL).await {
+        return HttpResponse::InternalServerError().json("Failed to send request to the backend");
+    }
+
+    let per_peer = inbox.unread_counts();
+    HttpResponse::Ok().json(UnreadCounts {
+        total: per_peer.values().sum(),
+        per_peer,
+    })
+}
+
+#[get("/inbox/priority")]
+/// Returns the unread messages marked important by the priority rules, with their IDs,
+/// rendered like `/messages?peek=true` does. Messages stay unread.
+pub async fn priority_inbox(
+    dispatcher: web::Data<Dispatcher>,
+    inbox: web::Data<Inbox>,
+    rules: web::Data<PriorityRules>,
+    identity: web::Data<IdentityStore>,
+) -> impl Responder {
+    if !refill(&inbox, &dispatcher, REPLY_POLL_INTERVAL).await {
+        return HttpResponse::InternalServerError().json("Failed to send request to the backend");
+    }
+
+    let nicknames = identity.nicknames();
+    let msgs: Vec<_> = inbox
+        .peek_matching(|envelope| rules.is_important(envelope))
+        .into_iter()
+        .map(|envelope| PeekedMessage {
+            id: envelope.id,
+            received_at: envelope.received_at,
+            payload: message_json(&envelope, &nicknames),
+        })
+        .collect();
+    HttpResponse::Ok().json(msgs)
+}
+
+#[get("/inbox/priority/rules")]
+/// Lists the rules that mark messages as important.
+pub async fn list_priority_rules(rules: web::Data<PriorityRules>) -> impl Responder {
+    HttpResponse::Ok().json(rules.list())
+}
+
+#[post("/inbox/priority/rules")]
+/// Adds a rule marking messages from `from`, containing `keyword`, or both as important.
+/// Returns the stored rule, or HTTP 400 if neither is given.
+pub async fn add_priority_rule(
+    payload: web::Json<RuleCriteria>,
+    rules: web::Data<PriorityRules>,
+) -> impl Responder {
+    let criteria = payload.into_inner();
+    if criteria.from.is_none() && criteria.keyword.as_deref().is_none_or(str::is_empty) {
+        return HttpResponse::BadRequest().json("A rule needs a sender or a keyword");
+    }
+
+    match rules.add(criteria) {
+        Ok(rule) => HttpResponse::Ok().json(rule),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to save the rule"),
+    }
+}
+
+#[delete("/inbox/priority/rules/{id}")]
+/// Deletes a priority rule. Returns HTTP 404 for unknown rule IDs.
+pub async fn delete_priority_rule(
+    id: web::Path<u64>,
+    rules: web::Data<PriorityRules>,
+) -> impl Responder {
+    match rules.remove(id.into_inner()) {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().json("Unknown rule id"),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to save the rules"),
+    }
+}
+
+#[derive(Deserialize)]
+struct LongPollQuery {
+    timeout: Option<u64>, // Seconds to wait for new messages, defaults to 30
+}
+
+#[get("/messages/longpoll")]
+/// Like `/messages`, but holds the request open until a message (passing the optional
+/// `from`/`since`/`until` filter) arrives or `timeout` seconds have passed.
+/// Returns HTTP 204 (No Content) on timeout. Sends no read receipts, like `/messages`.
+pub async fn long_poll_messages(
+    query: web::Query<LongPollQuery>,
+    filter: web::Query<MessageFilter>,
+    dispatcher: web::Data<Dispatcher>,
+    inbox: web::Data<Inbox>,
+    identity: web::Data<IdentityStore>,
+) -> impl Responder {
+    let timeout = query
+        .timeout
+        .unwrap_or(DEFAULT_LONG_POLL_SECS)
+        .min(MAX_LONG_POLL_SECS);
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+    let filter = filter.into_inner();
+
+    let msgs = block(move || {
+        loop {
+            if !inbox.refill(&dispatcher, REPLY_POLL_INTERVAL) {
+                return None;
+            }
+            let msgs = inbox.take_read(|envelope| filter.matches(envelope));
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if !msgs.is_empty() || remaining.is_zero() {
+                return Some(msgs);
+            }
+            thread::sleep(remaining.min(REPLY_POLL_INTERVAL));
+        }
+    })
+    .await;
+
+    match msgs {
+        Ok(Some(msgs)) => messages_response(msgs, &identity.nicknames()),
+        Ok(None) => {
+            HttpResponse::InternalServerError().json("Failed to send request to the backend")
+        }
+        Err(_) => HttpResponse::InternalServerError().json("Failed to wait for messages"),
+    }
+}
+
+#[derive(Serialize)]
+struct ConversationView {
+    peer: u8,
+    label: Option<String>, // Name of the contact, if the peer is one
+    messages: usize,       // Messages in both directions
+    key_status: KeyStatus, // Messages are sent as plaintext whatever the status
+    require_key: bool,     // Whether messages are refused while no key of the peer is recorded
+}
+
+impl ConversationView {
+    fn new(peer: u8, contact: Option<&Contact>, messages: usize) -> Self {
+        ConversationView {
+            peer,
+            label: contact.map(|contact| contact.label.clone()),
+            messages,
+            key_status: contact.map_or(KeyStatus::Missing, Contact::key_status),
+            require_key: contact.is_some_and(|contact| contact.require_key),
+        }
+    }
+}
+
+#[get("/conversations")]
+/// Lists the conversations, i.e. every peer messages were exchanged with and every
+/// contact, with the status of the peer's key (`missing`, `recorded` or `verified`).
+/// Messages are sent as plaintext whatever the status.
+pub async fn conversations(
+    history: web::Data<History>,
+    identity: web::Data<IdentityStore>,
+) -> impl Responder {
+    let mut messages: BTreeMap<u8, usize> = BTreeMap::new();
+    for entry in history.entries() {
+        *messages.entry(entry.peer).or_default() += 1;
+    }
+    let contacts = identity.get().contacts;
+    for peer in contacts.keys() {
+        messages.entry(*peer).or_default();
+    }
+    let conversations: Vec<_> = messages
+        .into_iter()
+        .map(|(peer, messages)| ConversationView::new(peer, contacts.get(&peer), messages))
+        .collect();
+    HttpResponse::Ok().json(conversations)
+}
+
+#[derive(Deserialize)]
+struct KeyRequirement {
+    required: bool, // Whether to refuse messages to the peer while no key of it is recorded
+}
+
+#[get("/conversations/{peer}/messages")]
+/// Serves the stored messages of the conversation with `peer`, newest first, together
+/// with the metadata attached to them. Paged with `limit` and `offset` like
+/// `/messages/history`; the metadata can't be changed through the API.
+pub async fn conversation_messages(
+    peer: web::Path<u8>,
+    query: web::Query<HistoryQuery>,
+    store: web::Data<MessageStore>,
+) -> impl Responder {
+    let filter = MessageFilter {
+        from: Some(peer.into_inner()),
+        ..MessageFilter::default()
+    };
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_HISTORY_LIMIT)
+        .min(MAX_HISTORY_LIMIT);
+    let offset = query.offset;
+    let page = block(move || -> rusqlite::Result<_> {
+        Ok(HistoryPage {
+            total: store.count(&filter)?,
+            limit,
+            offset,
+            messages: store.page(&filter, limit, offset)?,
+        })
+    })
+    .await;
+
+    match page {
+        Ok(Ok(page)) => HttpResponse::Ok().json(page),
+        Ok(Err(_)) => HttpResponse::InternalServerError().json("Failed to read stored messages"),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to wait for stored messages"),
+    }
+}
+
+#[put("/conversations/{peer}/key-requirement")]
+/// Requires a recorded key of `peer` for the conversation with it, or stops requiring it.
+/// While required, messages to the peer are refused unless its key is recorded; they are
+/// still sent as plaintext.
+/// Returns HTTP 409 when requiring a key before one of the peer was recorded.
+pub async fn set_key_requirement(
+    peer: web::Path<u8>,
+    payload: web::Json<KeyRequirement>,
+    identity: web::Data<IdentityStore>,
+    history: web::Data<History>,
+) -> impl Responder {
+    let peer = peer.into_inner();
+    let required = payload.required;
+    let updated = identity.update(|identity| {
+        let contact = identity.contacts.get_mut(&peer)?;
+        if required && contact.key_status() == KeyStatus::Missing {
+            return None;
+        }
+        contact.require_key = required;
+        Some(contact.clone())
+    });
+
+    match updated {
+        Ok(Some(contact)) => {
+            let messages = history
+                .entries()
+                .iter()
+                .filter(|entry| entry.peer == peer)
+                .count();
+            HttpResponse::Ok().json(ConversationView::new(peer, Some(&contact), messages))
+        }
+        Ok(None) if !required => HttpResponse::NoContent().finish(),
+        Ok(None) => HttpResponse::Conflict().json("Record a key of the peer first"),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to save the identity"),
+    }
+}
+
+#[derive(Serialize)]
+struct ContactView {
+    #[serde(flatten)]
+    contact: Contact,
+    last_seen: Option<u64>, // When the peer last wrote or was listed by a server, if ever
+}
+
+impl ContactView {
+    fn new(peer: u8, contact: Contact, directory: &Directory) -> Self {
+        ContactView {
+            contact,
+            last_seen: directory.last_seen(peer),
+        }
+    }
+}
+
+#[get("/contacts")]
+/// Returns the address book: every contact by node ID, with its label, preferred servers
+/// and when it was last seen, i.e. wrote to this node or appeared in a server's client list.
+pub async fn list_contacts(
+    identity: web::Data<IdentityStore>,
+    directory: web::Data<Directory>,
+) -> impl Responder {
+    let contacts: BTreeMap<_, _> = identity
+        .get()
+        .contacts
+        .into_iter()
+        .map(|(peer, contact)| (peer, ContactView::new(peer, contact, &directory)))
+        .collect();
+    HttpResponse::Ok().json(contacts)
+}
+
+#[get("/contacts/{peer}")]
+/// Returns the contact `peer` like `/contacts`, or HTTP 404 if it isn't in the address book.
+pub async fn get_contact(
+    peer: web::Path<u8>,
+    identity: web::Data<IdentityStore>,
+    directory: web::Data<Directory>,
+) -> impl Responder {
+    let peer = peer.into_inner();
+    match identity.get().contacts.remove(&peer) {
+        Some(contact) => HttpResponse::Ok().json(ContactView::new(peer, contact, &directory)),
+        None => HttpResponse::NotFound().json("Unknown contact"),
+    }
+}
+
+#[derive(Deserialize)]
+struct ContactUpdate {
+    #[serde(alias = "nickname")]
+    label: String, // Name the UI shows for the peer
+    #[serde(default)]
+    preferred_servers: Vec<i64>, // Servers to reach the peer through, most preferred first
+}
+
+#[put("/contacts/{peer}")]
+/// Adds `peer` to the address book or changes its label and preferred servers, keeping
+/// any exchanged key. The label, also accepted as `nickname`, is shown next to the node ID
+/// in messages, client lists and events. Returns the contact, with HTTP 201 if it was added.
+/// Returns HTTP 400 with the invalid fields if the label is empty, or `peer` or a server
+/// is not a node ID or is this node, see [`ValidationErrors`].
+pub async fn put_contact(
+    peer: web::Path<u8>,
+    payload: web::Json<ContactUpdate>,
+    node_id: web::Data<u8>,
+    identity: web::Data<IdentityStore>,
+    directory: web::Data<Directory>,
+) -> impl Responder {
+    let ContactUpdate {
+        label,
+        preferred_servers,
+    } = payload.into_inner();
+    let mut errors = ValidationErrors::default();
+    let peer = errors.node_id("peer", i64::from(peer.into_inner()), **node_id);
+    errors.non_empty("label", &label);
+    let preferred_servers: Vec<u8> = preferred_servers
+        .into_iter()
+        .filter_map(|server| errors.node_id("preferred_servers", server, **node_id))
+        .collect();
+    let peer = match peer {
+        Some(peer) if errors.is_empty() => peer,
+        _ => return errors.response(),
+    };
+
+    let updated = identity.update(|identity| {
+        let added = !identity.contacts.contains_key(&peer);
+        let contact = identity
+            .contacts
+            .entry(peer)
+            .or_insert_with(|| Contact::new(label.clone()));
+        contact.label = label;
+        contact.preferred_servers = preferred_servers;
+        (added, contact.clone())
+    });
+    match updated {
+        Ok((true, contact)) => {
+            HttpResponse::Created().json(ContactView::new(peer, contact, &directory))
+        }
+        Ok((false, contact)) => {
+            HttpResponse::Ok().json(ContactView::new(peer, contact, &directory))
+        }
+        Err(_) => HttpResponse::InternalServerError().json("Failed to save the identity"),
+    }
+}
+
+#[delete("/contacts/{peer}")]
+/// Removes `peer` from the address book, together with any key exchanged with it.
+/// Stored messages of the conversation are kept. Returns HTTP 404 if it isn't a contact.
+pub async fn delete_contact(
+    peer: web::Path<u8>,
+    identity: web::Data<IdentityStore>,
+) -> impl Responder {
+    let peer = peer.into_inner();
+    match identity.update(|identity| identity.contacts.remove(&peer).is_some()) {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().json("Unknown contact"),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to save the identity"),
+    }
+}
+
+#[derive(Deserialize)]
+struct KeyUpdate {
+    public_key: Option<String>, // Hex encoded key of the peer, `null` to forget it
+    #[serde(default)]
+    verified: bool, // Whether the user verified the key
+}
+
+#[put("/contacts/{peer}/key")]
+/// Records the public key exchanged with `peer`, making the peer a contact if it
+/// isn't one yet, or forgets it, and returns the resulting key status. The key isn't used
+/// to encrypt messages. Forgetting the key of a peer that requires one stops messages to
+/// it until a key is recorded again.
+/// Returns HTTP 400 if the key isn't hex encoded.
+pub async fn set_contact_key(
+    peer: web::Path<u8>,
+    payload: web::Json<KeyUpdate>,
+    identity: web::Data<IdentityStore>,
+) -> impl Responder {
+    let peer = peer.into_inner();
+    let KeyUpdate {
+        public_key,
+        verified,
+    } = payload.into_inner();
+    if public_key
+        .as_deref()
+        .is_some_and(|key| key.is_empty() || !key.chars().all(|c| c.is_ascii_hexdigit()))
+    {
+        return HttpResponse::BadRequest().json("The key must be hex encoded");
+    }
+
+    let updated = identity.update(|identity| {
+        let contact = identity
+            .contacts
+            .entry(peer)
+            .or_insert_with(|| Contact::new(format!("Client {peer}")));
+        contact.key_verified = verified && public_key.is_some();
+        contact.public_key = public_key;
+        contact.key_status()
+    });
+    match updated {
+        Ok(status) => HttpResponse::Ok().json(status),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to save the identity"),
+    }
+}
+
+#[get("/conversations/{peer}/stats")]
+/// Returns statistics of the conversation with client `peer`:
+/// message counts and bytes in both directions, average reply latency
+/// and the share of sent messages that failed.
+pub async fn conversation_stats(
+    peer: web::Path<u8>,
+    history: web::Data<History>,
+) -> impl Responder {
+    HttpResponse::Ok().json(history.conversation_stats(peer.into_inner()))
+}
+
+#[get("/peers/{id}/clock")]
+/// Returns how far the clock of peer `id` is estimated to be off from ours, so the UI
+/// can show the peer's "sent at" times on our clock. Samples come from chat messages
+/// that state their send time; without any, `offset_ms` is null.
+pub async fn peer_clock(id: web::Path<u8>, clocks: web::Data<PeerClocks>) -> impl Responder {
+    HttpResponse::Ok().json(clocks.estimate(id.into_inner()))
+}
+
+#[get("/stats/drones")]
+/// Returns health statistics of every node this client sent requests to,
+/// keyed by node id: answered and dropped requests, latency averages and
+/// any detected anomalies (latency regression, drop spike).
+pub async fn drone_stats(network_stats: web::Data<NetworkStats>) -> impl Responder {
+    network_stats.with_nodes(|nodes| HttpResponse::Ok().json(nodes))
+}
+
+#[derive(Serialize)]
+struct PublicStats {
+    node_id: u8,
+    conversations: usize,
+    messages_sent: usize,
+    messages_received: usize,
+    requests_answered: u64,
+    requests_dropped: u64,
+}
+
+#[get("/stats")]
+/// Returns aggregate statistics without any message content or peer details.
+/// Served below `/public` when `ServerOptions::public_viewer` is set.
+pub async fn public_stats(
+    node_id: web::Data<u8>,
+    history: web::Data<History>,
+    network_stats: web::Data<NetworkStats>,
+) -> impl Responder {
+    let entries = history.entries();
+    let peers: BTreeSet<u8> = entries.iter().map(|entry| entry.peer).collect();
+    let (requests_answered, requests_dropped) = network_stats.with_nodes(|nodes| {
+        nodes.values().fold((0, 0), |(answered, dropped), stats| {
+            (answered + stats.answered, dropped + stats.dropped)
+        })
+    });
+    HttpResponse::Ok().json(PublicStats {
+        node_id: **node_id,
+        conversations: peers.len(),
+        messages_sent: entries
+            .iter()
+            .filter(|entry| entry.direction == Direction::Outgoing)
+            .count(),
+        messages_received: entries
+            .iter()
+            .filter(|entry| entry.direction == Direction::Incoming)
+            .count(),
+        requests_answered,
+        requests_dropped,
+    })
+}
+
+#[derive(Deserialize)]
+struct EventsQuery {
+    #[serde(default)]
+    since: u64, // Return only events newer than this id
+}
+
+#[derive(Serialize)]
+struct NamedEvent {
+    #[serde(flatten)]
+    event: Event,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nickname: Option<String>, // Nickname of the node the event concerns
+}
+
+#[get("/events")]
+/// Returns logged events (e.g. detected anomalies or typing peers) newer than `since`. Events
+/// about a node carry the nickname given to it via `/contacts/{peer}`, if any.
+pub async fn get_events(
+    query: web::Query<EventsQuery>,
+    events: web::Data<EventLog>,
+    identity: web::Data<IdentityStore>,
+) -> impl Responder {
+    let nicknames = identity.nicknames();
+    let events: Vec<_> = events
+        .since(query.since)
+        .into_iter()
+        .map(|event| {
+            let nickname = match &event.kind {
+                EventKind::Anomaly { node, .. } | EventKind::Typing { peer: node } => {
+                    nicknames.get(node).cloned()
+                }
+                EventKind::QuotaEviction { .. } => None,
+            };
+            NamedEvent { event, nickname }
+        })
+        .collect();
+    HttpResponse::Ok().json(events)
+}
+
+#[derive(Deserialize)]
+struct TimeSeriesQuery {
+    metric: Metric,     // One of `latency`, `drops`, `throughput`
+    range: Option<u64>, // Only return the last `range` seconds
+}
+
+#[get("/metrics")]
+/// Returns the counters of commands sent to the backend, chat messages sent and received,
+/// floods, HTTP requests per route with their latency and failed backend requests,
+/// and the number of items waiting in every channel to and from the backend,
+/// in Prometheus text format.
+/// Returns HTTP 404 if `ServerOptions::metrics` is off.
+pub async fn prometheus_metrics(
+    metrics: web::Data<Metrics>,
+    options: web::Data<LiveOptions>,
+) -> impl Responder {
+    let options = options.current();
+    if !options.metrics {
+        return HttpResponse::NotFound().json("Metrics are disabled");
+    }
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics.render())
+}
+
+#[get("/stats/backend")]
+/// Returns the round trip time of the last ping to the backend, when it last answered,
+/// whether it counts as responsive and how often it was restarted after a panic.
+/// Never waits on the backend itself.
+pub async fn backend_status(probe: web::Data<BackendProbe>) -> impl Responder {
+    HttpResponse::Ok().json(probe.status())
+}
+
+#[post("/admin/backend/restart")]
+/// Replaces the backend with a new one made from the same node options, for recovering
+/// from a wedged backend without restarting the process. A stuck thread can't be
+/// stopped, so the old backend is cut off from its channels and left to exit on its
+/// own; what was queued for it goes to the new one.
+/// - Returns HTTP 200 with the backend status, counting the restart.
+/// - Returns HTTP 500 if the new backend couldn't be made; the old one stays.
+/// - Returns HTTP 501 if the backend can't be restarted, as in demo mode.
+pub async fn restart_backend(probe: web::Data<BackendProbe>) -> impl Responder {
+    let restarting = probe.clone();
+    match block(move || restarting.restart()).await {
+        Ok(Some(Ok(()))) => HttpResponse::Ok().json(probe.status()),
+        Ok(Some(Err(e))) => HttpResponse::InternalServerError().json(e),
+        Ok(None) => HttpResponse::NotImplemented().json("This backend can't be restarted"),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to wait for the restart"),
+    }
+}
+
+#[get("/stats/disk")]
+/// Returns the disk usage of the message database, the media cache and the
+/// transfer staging area together with their quotas, the bytes evicted to stay
+/// within them and the writes refused for lack of space.
+pub async fn disk_usage(quotas: web::Data<DiskQuotas>) -> impl Responder {
+    match block(move || quotas.report()).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to measure the disk usage"),
+    }
+}
+
+#[get("/stats/timeseries")]
+/// Returns the downsampled time series of a metric, optionally limited to a recent range.
+/// Each point aggregates the samples of one bucket (count, sum, min, max).
+pub async fn stats_timeseries(
+    query: web::Query<TimeSeriesQuery>,
+    timeseries: web::Data<TimeSeries>,
+) -> impl Responder {
+    let range_ms = query.range.map(|secs| secs.saturating_mul(1000));
+    HttpResponse::Ok().json(timeseries.query(query.metric, range_ms))
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    metrics: Option<String>, // Comma separated metrics, `deliveries` for delivery records; all if absent
+    range: Option<u64>,      // Only export the last `range` seconds
+}
+
+#[get("/stats/export.csv")]
+/// Exports the stored time series and per-message delivery records as CSV.
+/// Returns HTTP 400 if `metrics` names an unknown metric.
+pub async fn stats_export_csv(
+    query: web::Query<ExportQuery>,
+    timeseries: web::Data<TimeSeries>,
+    deliveries: web::Data<DeliveryTracker>,
+) -> impl Responder {
+    let mut metrics = vec![];
+    let mut include_deliveries = true;
+    if let Some(names) = &query.metrics {
+        include_deliveries = false;
+        for name in names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            if name == "deliveries" {
+                include_deliveries = true;
+                continue;
+            }
+            match name.parse() {
+                Ok(metric) => metrics.push(metric),
+                Err(e) => return HttpResponse::BadRequest().json(e),
+            }
+        }
+    } else {
+        metrics.extend(Metric::ALL);
+    }
+
+    let range_ms = query.range.map(|secs| secs.saturating_mul(1000));
+    let csv = export::stats_csv(
+        &timeseries,
+        &metrics,
+        include_deliveries.then_some(deliveries.get_ref()),
+        range_ms,
+    );
+    HttpResponse::Ok().content_type("text/csv").body(csv)
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum ChatExportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Deserialize)]
+struct ChatExportQuery {
+    #[serde(default)]
+    format: ChatExportFormat,
+    peer: Option<u8>,   // Only the conversation with this client
+    since: Option<u64>, // Only messages sent or received at or after this Unix time in milliseconds
+    until: Option<u64>, // Only messages sent or received at or before this Unix time in milliseconds
+    limit: Option<u32>, // Most messages to export, the newest ones
+}
+
+/// Most messages `/export` returns, and the default of its `limit`.
+const MAX_EXPORT_LIMIT: u32 = 10_000;
+
+#[get("/export")]
+/// Exports the chat history, received messages from local storage and sent ones from the
+/// outbox journal with their delivery state, oldest first, for archiving or grading.
+/// `format=csv` renders it as CSV instead of JSON; `peer`, `since` and `until` restrict it
+/// to one conversation and a time range. At most `limit` messages are exported, the newest
+/// ones; older history is exported by passing an `until` before the oldest exported time.
+pub async fn export_chat(
+    query: web::Query<ChatExportQuery>,
+    store: web::Data<MessageStore>,
+    deliveries: web::Data<DeliveryTracker>,
+) -> impl Responder {
+    let filter = MessageFilter {
+        from: query.peer,
+        since: query.since,
+        until: query.until,
+    };
+    let limit = query
+        .limit
+        .unwrap_or(MAX_EXPORT_LIMIT)
+        .min(MAX_EXPORT_LIMIT);
+    let chat = block(move || -> Result<_, &'static str> {
+        let received = store
+            .page(&filter, limit, 0)
+            .map_err(|_| "Failed to read stored messages")?;
+        let records = deliveries
+            .journal_records()
+            .map_err(|_| "Failed to read the outbox journal")?;
+        let mut chat = export::chat_history(&received, &records, &deliveries, &filter);
+        let keep = usize::try_from(limit).unwrap_or(usize::MAX);
+        chat.drain(..chat.len().saturating_sub(keep));
+        Ok(chat)
+    })
+    .await;
+
+    match (chat, query.format) {
+        (Ok(Ok(chat)), ChatExportFormat::Json) => HttpResponse::Ok().json(chat),
+        (Ok(Ok(chat)), ChatExportFormat::Csv) => HttpResponse::Ok()
+            .content_type("text/csv")
+            .body(export::chat_csv(&chat)),
+        (Ok(Err(e)), _) => HttpResponse::InternalServerError().json(e),
+        (Err(_), _) => HttpResponse::InternalServerError().json("Failed to wait for the export"),
+    }
+}
+
+#[derive(Serialize)]
+struct ReindexReport {
+    messages: usize,      // Stored messages replayed
+    conversations: usize, // Conversations after the rebuild
+    unread: usize,        // Unread chat messages after the rebuild
+}
+
+#[post("/admin/reindex")]
+/// Rebuilds the conversation history and the unread messages from the raw
+/// stored messages and their read flags, e.g. after the derived data got out of sync.
+pub async fn reindex(
+    store: web::Data<MessageStore>,
+    history: web::Data<History>,
+    inbox: web::Data<Inbox>,
+) -> impl Responder {
+    let report = block(move || -> rusqlite::Result<_> {
+        let messages = store.all()?;
+        Ok(ReindexReport {
+            messages: messages.len(),
+            conversations: history.rebuild_incoming(&messages),
+            unread: inbox.rebuild_unread(&messages),
+        })
+    })
+    .await;
+
+    match report {
+        Ok(Ok(report)) => HttpResponse::Ok().json(report),
+        Ok(Err(_)) => HttpResponse::InternalServerError().json("Failed to read stored messages"),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to wait for the rebuild"),
+    }
+}
+
+/// Default page size of `/messages/history`.
+const DEFAULT_HISTORY_LIMIT: u32 = 50;
+/// Largest page size of `/messages/history`.
+const MAX_HISTORY_LIMIT: u32 = 500;
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    limit: Option<u32>, // Page size, defaults to 50, at most 500
+    #[serde(default)]
+    offset: u32, // Number of newest messages to skip
+}
+
+#[derive(Serialize)]
+struct HistoryPage {
+    total: u64, // Number of stored messages passing the filter
+    limit: u32,
+    offset: u32,
+    messages: Vec<StoredMessage>, // Newest first
+}
+
+#[get("/messages/history")]
+/// Serves previously received messages from local storage, newest first.
+/// Unlike `/messages`, this doesn't consume anything and can be paged through with `limit` and `offset`.
+/// Accepts the same `from`, `since` and `until` filters as `/messages`.
+pub async fn message_history(
+    query: web::Query<HistoryQuery>,
+    filter: web::Query<MessageFilter>,
+    store: web::Data<MessageStore>,
+) -> impl Responder {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_HISTORY_LIMIT)
+        .min(MAX_HISTORY_LIMIT);
+    let offset = query.offset;
+    let filter = filter.into_inner();
+    let page = block(move || -> rusqlite::Result<_> {
+        Ok(HistoryPage {
+            total: store.count(&filter)?,
+            limit,
+            offset,
+            messages: store.page(&filter, limit, offset)?,
+        })
+    })
+    .await;
+
+    match page {
+        Ok(Ok(page)) => HttpResponse::Ok().json(page),
+        Ok(Err(_)) => HttpResponse::InternalServerError().json("Failed to read stored messages"),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to wait for stored messages"),
+    }
+}
+
+/// Rebuilds the conversation history after messages were trashed or restored.
+fn refresh_history(store: &MessageStore, history: &History) -> rusqlite::Result<()> {
+    history.rebuild_incoming(&store.all()?);
+    Ok(())
+}
+
+#[delete("/messages/{id}")]
+/// Moves a stored message to the trash.
+/// Returns HTTP 404 if there is no such message outside the trash.
+pub async fn delete_message(
+    id: web::Path<u64>,
+    store: web::Data<MessageStore>,
+    history: web::Data<History>,
+) -> impl Responder {
+    let id = id.into_inner();
+    let trashed = block(move || -> Result<_, &'static str> {
+        let trashed = store
+            .trash(id)
+            .map_err(|_| "Failed to update stored messages")?;
+        if trashed {
+            refresh_history(&store, &history).map_err(|_| "Failed to rebuild the history")?;
+        }
+        Ok(trashed)
+    })
+    .await;
+
+    match trashed {
+        Ok(Ok(true)) => HttpResponse::NoContent().finish(),
+        Ok(Ok(false)) => HttpResponse::NotFound().json("Unknown message id"),
+        Ok(Err(e)) => HttpResponse::InternalServerError().json(e),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to wait for the trash"),
+    }
+}
+
+#[delete("/conversations/{peer}")]
+/// Moves every message received from client `peer` to the trash.
+/// Returns the number of trashed messages.
+pub async fn delete_conversation(
+    peer: web::Path<u8>,
+    store: web::Data<MessageStore>,
+    history: web::Data<History>,
+) -> impl Responder {
+    let peer = peer.into_inner();
+    let trashed = block(move || -> Result<_, &'static str> {
+        let trashed = store
+            .trash_conversation(peer)
+            .map_err(|_| "Failed to update stored messages")?;
+        refresh_history(&store, &history).map_err(|_| "Failed to rebuild the history")?;
+        Ok(trashed)
+    })
+    .await;
+
+    match trashed {
+        Ok(Ok(trashed)) => HttpResponse::Ok().json(trashed),
+        Ok(Err(e)) => HttpResponse::InternalServerError().json(e),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to wait for the trash"),
+    }
+}
+
+#[get("/trash")]
+/// Lists the messages in the trash, most recently deleted first.
+/// They are purged permanently once `ServerOptions::trash_retention` has passed.
+pub async fn trash(store: web::Data<MessageStore>) -> impl Responder {
+    match block(move || store.trashed()).await {
+        Ok(Ok(messages)) => HttpResponse::Ok().json(messages),
+        Ok(Err(_)) => HttpResponse::InternalServerError().json("Failed to read stored messages"),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to wait for the trash"),
+    }
+}
+
+#[post("/trash/{id}/restore")]
+/// Takes a message out of the trash.
+/// Returns HTTP 404 if the message isn't in the trash.
+pub async fn restore_from_trash(
+    id: web::Path<u64>,
+    store: web::Data<MessageStore>,
+    history: web::Data<History>,
+) -> impl Responder {
+    let id = id.into_inner();
+    let restored = block(move || -> Result<_, &'static str> {
+        let restored = store
+            .restore(id)
+            .map_err(|_| "Failed to update stored messages")?;
+        if restored {
+            refresh_history(&store, &history).map_err(|_| "Failed to rebuild the history")?;
+        }
+        Ok(restored)
+    })
+    .await;
+
+    match restored {
+        Ok(Ok(true)) => HttpResponse::NoContent().finish(),
+        Ok(Ok(false)) => HttpResponse::NotFound().json("Message is not in the trash"),
+        Ok(Err(e)) => HttpResponse::InternalServerError().json(e),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to wait for the trash"),
+    }
+}
+
+#[get("/admin/journal")]
+/// Returns the records of the outbox journal, oldest first.
+pub async fn outbox_journal(deliveries: web::Data<DeliveryTracker>) -> impl Responder {
+    match block(move || deliveries.journal_records()).await {
+        Ok(Ok(records)) => HttpResponse::Ok().json(records),
+        Ok(Err(_)) => HttpResponse::InternalServerError().json("Failed to read the journal"),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to wait for the journal"),
+    }
+}
+
+#[derive(Serialize)]
+struct MergeResult {
+    messages: usize, // Stored messages moved to the new conversation
+}
+
+#[post("/contacts/{old}/merge/{new}")]
+/// Merges everything known about peer `old` into peer `new`, for contacts that got a new
+/// node ID when the topology was regenerated: stored messages and conversation history,
+/// the contact entry (unless `new` already has one, which then takes over the exchanged key
+/// if it has none), when it was last seen and priority rules.
+/// Returns HTTP 400 if both IDs are the same and 409 if both contacts have different keys;
+/// nothing is changed then.
+pub async fn merge_contact(
+    path: web::Path<(u8, u8)>,
+    store: web::Data<MessageStore>,
+    history: web::Data<History>,
+    identity: web::Data<IdentityStore>,
+    rules: web::Data<PriorityRules>,
+    directory: web::Data<Directory>,
+) -> impl Responder {
+    let (old, new) = path.into_inner();
+    if old == new {
+        return HttpResponse::BadRequest().json("Cannot merge a contact into itself");
+    }
+    let contacts = identity.get().contacts;
+    if let (Some(from), Some(into)) = (contacts.get(&old), contacts.get(&new))
+        && into.conflicts_with(from)
+    {
+        return HttpResponse::Conflict().json("Both contacts have different public keys");
+    }
+
+    // Everything is checked; what has to be saved comes before the in-memory changes
+    let merged = block(move || -> Result<_, &'static str> {
+        identity
+            .update(|identity| {
+                if let Some(contact) = identity.contacts.remove(&old) {
+                    identity
+                        .contacts
+                        .entry(new)
+                        .and_modify(|existing| existing.adopt_key(&contact))
+                        .or_insert(contact);
+                }
+            })
+            .map_err(|_| "Failed to save the identity")?;
+        rules
+            .merge_peer(old, new)
+            .map_err(|_| "Failed to save the priority rules")?;
+        let messages = store
+            .reassign_peer(old, new)
+            .map_err(|_| "Failed to update stored messages")?;
+        history.merge_peer(old, new);
+        directory.merge_peer(old, new);
+        refresh_history(&store, &history).map_err(|_| "Failed to rebuild the history")?;
+        Ok(messages)
+    })
+    .await;
+
+    match merged {
+        Ok(Ok(messages)) => HttpResponse::Ok().json(MergeResult { messages }),
+        Ok(Err(e)) => HttpResponse::InternalServerError().json(e),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to wait for the merge"),
+    }
+}
+
+#[derive(Serialize)]
+struct IdentityView {
+    node_id: u8,
+    alias: Option<String>,
+    key_id: String, // Identifies the key without revealing it
+    created_at: u64,
+    contacts: BTreeMap<u8, Contact>,
+}
+
+#[get("/identity")]
+/// Returns the node's persistent identity, without the secret key.
+pub async fn get_identity(identity: web::Data<IdentityStore>) -> impl Responder {
+    let identity = identity.get();
+    HttpResponse::Ok().json(IdentityView {
+        node_id: identity.node_id,
+        key_id: identity.key_id(),
+        alias: identity.alias,
+        created_at: identity.created_at,
+        contacts: identity.contacts,
+    })
+}
+
+#[derive(Deserialize)]
+struct IdentityUpdate {
+    alias: Option<String>, // New alias, `null` to remove it
+}
+
+#[put("/identity")]
+/// Changes the node's alias and persists it.
+pub async fn update_identity(
+    payload: web::Json<IdentityUpdate>,
+    identity: web::Data<IdentityStore>,
+) -> impl Responder {
+    let alias = payload.into_inner().alias;
+    match identity.update(|identity| identity.alias = alias) {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to save the identity"),
+    }
+}
+
+#[get("/settings/away")]
+/// Returns the away mode settings and when each conversation was last auto-replied to.
+pub async fn get_away(away: web::Data<AwayMode>) -> impl Responder {
+    HttpResponse::Ok().json(away.status())
+}
+
+#[put("/settings/away")]
+/// Turns away mode on or off. While on, each sender gets the optional `message`
+/// (or a default text) as automatic reply, at most once per `interval_secs`.
+pub async fn set_away(
+    payload: web::Json<AwaySettings>,
+    away: web::Data<AwayMode>,
+) -> impl Responder {
+    match away.set(payload.into_inner()) {
+        Ok(()) => HttpResponse::Ok().json(away.status()),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to save the away settings"),
+    }
+}
+
+#[derive(Serialize)]
+struct ClientConfig {
+    node_id: u8,
+    base_url: String,   // Where the UI reaches this node's API
+    invite_url: String, // Link other users can open to start a chat with this node
+}
+
+#[get("/config.json")]
+/// Returns the configuration the web UI needs, including the URLs of this node.
+/// The scheme follows the `Forwarded`/`X-Forwarded-Proto` headers and is always
+/// `https` when `ServerOptions::behind_tls_proxy` is set.
+pub async fn client_config(
+    req: HttpRequest,
+    node_id: web::Data<u8>,
+    options: web::Data<LiveOptions>,
+) -> impl Responder {
+    let options = options.current();
+    let conn = req.connection_info();
+    let scheme = if options.behind_tls_proxy {
+        "https"
+    } else {
+        conn.scheme()
+    };
+    let base_url = format!("{scheme}://{}", conn.host());
+
+    HttpResponse::Ok().json(ClientConfig {
+        node_id: **node_id,
+        invite_url: format!("{base_url}/?peer={}", **node_id),
+        base_url,
+    })
+}
+
+#[get("/api/changes")]
+/// Returns the current API revision, the features added in each revision and
+/// the deprecated routes, so a UI can check whether a feature is supported.
+pub async fn api_changes() -> impl Responder {
+    HttpResponse::Ok().json(ApiChangelog::new(API_CHANGES, API_DEPRECATIONS))
+}
+
+#[get("/debug/env")]
+/// Returns the diagnostics collected when the server started: the settings in effect,
+/// where the static assets come from, whether the data directory is writable and
+/// the capacities of the channels to the backend.
+pub async fn debug_env(diagnostics: web::Data<Diagnostics>) -> impl Responder {
+    HttpResponse::Ok().json(diagnostics.get_ref())
+}
+
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+    node_id: u8,
+    version: &'static str,
+    backend_responsive: bool, // Whether the backend answered its recent pings
+}
+
+#[get("/health")]
+/// Returns HTTP 200 with a small JSON body as long as the HTTP server is up, for
+/// deployment scripts and the simulation harness. Whether the backend is responsive
+/// is reported from the last pings, without waiting on it.
+pub async fn health(node_id: web::Data<u8>, probe: web::Data<BackendProbe>) -> impl Responder {
+    HttpResponse::Ok().json(Health {
+        status: "ok",
+        node_id: **node_id,
+        version: env!("CARGO_PKG_VERSION"),
+        backend_responsive: probe.responsive(),
+    })
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    ready: bool,
+    latency_ms: Option<u64>, // Round trip time of the ping, if it was answered
+    error: Option<String>,   // Why the ping failed
+}
+
+#[get("/ready")]
+/// Pings the backend through the command channel and returns HTTP 200 if it answers
+/// within `ServerOptions::backend_ping_timeout`, or HTTP 503 if the backend thread is
+/// gone or wedged, so orchestration can restart the node. The result also counts as a
+/// ping of the backend probe.
+pub async fn ready(
+    dispatcher: web::Data<Dispatcher>,
+    options: web::Data<LiveOptions>,
+    probe: web::Data<BackendProbe>,
+) -> impl Responder {
+    let options = options.current();
+    let timeout = options.backend_ping_timeout;
+    let Ok(result) = block(move || dispatcher.ping(timeout)).await else {
+        return HttpResponse::InternalServerError().json("Failed to wait for the backend");
+    };
+    probe.record(result);
+
+    match result {
+        Ok(latency) => HttpResponse::Ok().json(Readiness {
+            ready: true,
+            latency_ms: Some(u64::try_from(latency.as_millis()).unwrap_or(u64::MAX)),
+            error: None,
+        }),
+        Err(e) => HttpResponse::ServiceUnavailable().json(Readiness {
+            ready: false,
+            latency_ms: None,
+            error: Some(e.to_string()),
+        }),
+    }
+}
+
+#[derive(Serialize)]
+struct NodeStatus {
+    node_id: u8,
+    version: &'static str,
+    uptime_secs: u64,
+    backend_alive: bool, // Whether the backend answered its recent heartbeats
+    last_heartbeat_at: Option<u64>, // Unix time in milliseconds of the last answered one
+    heartbeat_latency_ms: Option<u64>,
+    in_flight: InFlight,
+    circuit_breaker: BreakerStatus,
+}
+
+#[get("/status")]
+/// Returns the liveness of the backend as seen by the periodic heartbeats, when it
+/// last answered one, how long the server has been up and how many requests are
+/// queued for or waiting on the backend, and the state of the circuit breaker. Like
+/// `/health`, it doesn't wait on the backend.
+pub async fn node_status(
+    diagnostics: web::Data<Diagnostics>,
+    dispatcher: web::Data<Dispatcher>,
+    probe: web::Data<BackendProbe>,
+) -> impl Responder {
+    let backend = probe.status();
+    HttpResponse::Ok().json(NodeStatus {
+        node_id: diagnostics.node_id,
+        version: diagnostics.version,
+        uptime_secs: unix_millis().saturating_sub(diagnostics.started_at) / 1000,
+        backend_alive: backend.responsive,
+        last_heartbeat_at: backend.last_ok_at,
+        heartbeat_latency_ms: backend.latency_ms,
+        in_flight: dispatcher.in_flight(),
+        circuit_breaker: dispatcher.breaker_status(),
+    })
+}
+
+#[derive(Deserialize)]
+struct LoginRequest {
+    user: String,
+    password: String,
+}
+
+#[get("/login")]
+/// Serves the login form of the web UI.
+/// Returns HTTP 404 if no auth provider is configured.
+pub async fn login_page(node_id: web::Data<u8>, options: web::Data<LiveOptions>) -> impl Responder {
+    let options = options.current();
+    if options.auth_provider.is_none() {
+        return HttpResponse::NotFound().json("Authentication is not enabled");
+    }
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(login::page(**node_id, None))
+}
+
+#[post("/login")]
+/// Checks the user's password with the configured auth provider and starts a
+/// session, returned as an HTTP-only cookie along with the user's identity.
+/// Accepts the credentials as JSON or, from the login form, form-encoded; the
+/// form is answered with a redirect to `/` instead, or with the form again.
+/// Returns HTTP 401 for wrong credentials and 404 if no provider is configured.
+pub async fn login(
+    body: web::Either<web::Json<LoginRequest>, web::Form<LoginRequest>>,
+    node_id: web::Data<u8>,
+    options: web::Data<LiveOptions>,
+    sessions: web::Data<Sessions>,
+) -> impl Responder {
+    let options = options.current();
+    let Some(provider) = options.auth_provider.clone() else {
+        return HttpResponse::NotFound().json("Authentication is not enabled");
+    };
+    let (body, from_form) = match body {
+        web::Either::Left(json) => (json.into_inner(), false),
+        web::Either::Right(form) => (form.into_inner(), true),
+    };
+    let authenticated = block(move || {
+        provider.authenticate(&Credentials::Password {
+            user: &body.user,
+            password: &body.password,
+        })
+    })
+    .await;
+    match authenticated {
+        Ok(Some(identity)) => {
+            let token = sessions.start(&identity);
+            let cookie = cookies::session_cookie(SESSION_COOKIE, token, options.serves_https());
+            if from_form {
+                return HttpResponse::SeeOther()
+                    .cookie(cookie)
+                    .insert_header((header::LOCATION, "/"))
+                    .finish();
+            }
+            HttpResponse::Ok().cookie(cookie).json(identity)
+        }
+        Ok(None) if from_form => HttpResponse::Unauthorized()
+            .content_type("text/html; charset=utf-8")
+            .body(login::page(**node_id, Some("Wrong user name or password"))),
+        Ok(None) => HttpResponse::Unauthorized().json("Wrong user name or password"),
+        Err(_) => HttpResponse::InternalServerError().json("Failed to check the credentials"),
+    }
+}
+
+#[post("/logout")]
+/// Ends the session of the request's cookie and removes the cookie.
+/// A browser submitting a form is redirected to the login page.
+pub async fn logout(
+    req: HttpRequest,
+    options: web::Data<LiveOptions>,
+    sessions: web::Data<Sessions>,
+) -> impl Responder {
+    let options = options.current();
+    if let Some(cookie) = req.cookie(SESSION_COOKIE) {
+        sessions.end(cookie.value());
+    }
+    let mut removal =
+        cookies::session_cookie(SESSION_COOKIE, String::new(), options.serves_https());
+    removal.make_removal();
+    if auth::wants_html(req.headers()) {
+        return HttpResponse::SeeOther()
+            .cookie(removal)
+            .insert_header((header::LOCATION, "/login"))
+            .finish();
     }
+    HttpResponse::Ok().cookie(removal).json("Logged out")
 }
@@ -0,0 +1,96 @@
+//! Log of notable things that happened on this node.
+//!
+//! Subsystems push [`EventKind`]s here; the UI polls `/events?since=<id>`
+//! to pick up everything newer than the last event it has seen.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use super::quota::StorageArea;
+use super::stats::Anomaly;
+use super::unix_millis;
+
+/// How many events are kept before the oldest ones are dropped.
+const EVENT_LOG_CAPACITY: usize = 1000;
+
+/// What happened.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventKind {
+    /// A node started behaving abnormally.
+    Anomaly {
+        /// The affected node.
+        node: u8,
+        /// What looks wrong.
+        anomaly: Anomaly,
+    },
+    /// Old data was removed to keep an area of the data directory within its quota.
+    QuotaEviction {
+        /// The area.
+        area: StorageArea,
+        /// Bytes removed.
+        freed_bytes: u64,
+    },
+    /// A peer is typing a message to this node, see [`typing`](super::typing).
+    Typing {
+        /// The peer.
+        peer: u8,
+    },
+}
+
+/// A logged event.
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    /// Increasing event number.
+    pub id: u64,
+    /// Unix time in milliseconds when the event was raised.
+    pub at: u64,
+    /// What happened.
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Default)]
+struct Log {
+    next_id: u64,
+    events: VecDeque<Event>,
+}
+
+/// Bounded, in-memory event log.
+#[derive(Debug, Default)]
+pub struct EventLog {
+    log: Mutex<Log>,
+}
+
+impl EventLog {
+    /// Appends an event to the log.
+    pub fn push(&self, kind: EventKind) {
+        let mut log = self.lock();
+        log.next_id += 1;
+        let event = Event {
+            id: log.next_id,
+            at: unix_millis(),
+            kind,
+        };
+        if log.events.len() == EVENT_LOG_CAPACITY {
+            log.events.pop_front();
+        }
+        log.events.push_back(event);
+    }
+
+    /// Returns every event with an id greater than `id`.
+    #[must_use]
+    pub fn since(&self, id: u64) -> Vec<Event> {
+        self.lock()
+            .events
+            .iter()
+            .filter(|event| event.id > id)
+            .cloned()
+            .collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Log> {
+        self.log.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,209 @@
+//! Export of recorded data in analysis-ready formats.
+
+use serde::Serialize;
+use std::fmt::Write;
+
+use super::delivery::{DeliveryState, DeliveryTracker};
+use super::history::Direction;
+use super::journal::{JournalOp, JournalRecord};
+use super::storage::{MessageFilter, StoredMessage};
+use super::timeseries::{Metric, TimeSeries};
+use super::unix_millis;
+
+/// Header of the statistics CSV.
+///
+/// Time series buckets fill the aggregate columns; delivery records use
+/// `at` for the send time, `width` for the time until the last state change
+/// and fill the message columns.
+const STATS_CSV_HEADER: &str =
+    "metric,at,width,count,sum,min,max,message_id,server_id,client_id,state";
+
+/// Header of the chat history CSV.
+const CHAT_CSV_HEADER: &str = "at,peer,direction,server_id,session_id,state,message";
+
+/// Builds a CSV of the given metrics' time series and, if `deliveries` is
+/// set, of the per-message delivery records, limited to the last `range_ms`
+/// milliseconds if given.
+#[must_use]
+pub fn stats_csv(
+    timeseries: &TimeSeries,
+    metrics: &[Metric],
+    deliveries: Option<&DeliveryTracker>,
+    range_ms: Option<u64>,
+) -> String {
+    let mut csv = String::from(STATS_CSV_HEADER);
+    csv.push('\n');
+
+    for metric in metrics {
+        for point in timeseries.query(*metric, range_ms) {
+            // Writing to a String can't fail
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{},{},{},,,,",
+                metric.name(),
+                point.at,
+                point.width,
+                point.count,
+                point.sum,
+                point.min,
+                point.max
+            );
+        }
+    }
+
+    if let Some(deliveries) = deliveries {
+        let since = range_ms.map_or(0, |range| unix_millis().saturating_sub(range));
+        for status in deliveries.sent_since(since) {
+            let state = state_name(status.state);
+            let _ = writeln!(
+                csv,
+                "delivery,{},{},1,,,,{},{},{},{}",
+                status.sent_at,
+                status.updated_at.saturating_sub(status.sent_at),
+                status.id,
+                status.server_id,
+                status.client_id,
+                state
+            );
+        }
+    }
+
+    csv
+}
+
+/// A chat message of the exported history.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatRecord {
+    /// Unix time in milliseconds when the message was accepted for sending or received.
+    pub at: u64,
+    /// The other client of the conversation.
+    pub peer: u8,
+    /// Whether the message was sent or received.
+    pub direction: Direction,
+    /// Server the message went through.
+    pub server_id: Option<u8>,
+    /// Session ID of the message.
+    pub session_id: Option<u64>,
+    /// Delivery state of a sent message.
+    pub state: Option<DeliveryState>,
+    /// Text of the message, or its body as JSON if it isn't text.
+    pub message: String,
+}
+
+/// Builds the chat history passing `filter` from the `received` messages and
+/// the messages enqueued in the outbox journal `records`, oldest first. The
+/// delivery state of sent messages is looked up in `deliveries`.
+#[must_use]
+pub fn chat_history(
+    received: &[StoredMessage],
+    records: &[JournalRecord],
+    deliveries: &DeliveryTracker,
+    filter: &MessageFilter,
+) -> Vec<ChatRecord> {
+    let in_range = |at: u64| {
+        filter.since.is_none_or(|since| at >= since) && filter.until.is_none_or(|until| at <= until)
+    };
+
+    let mut chat: Vec<ChatRecord> = received
+        .iter()
+        .filter(|stored| stored.envelope.is_chat_message() && in_range(stored.envelope.received_at))
+        .filter_map(|stored| {
+            let envelope = &stored.envelope;
+            let peer = stored.peer.or_else(|| envelope.sender())?;
+            let message = envelope.body_text().map_or_else(
+                || {
+                    envelope
+                        .unwrap_content()
+                        .1
+                        .and_then(|inner| inner.get("message"))
+                        .map(ToString::to_string)
+                        .unwrap_or_default()
+                },
+                str::to_string,
+            );
+            Some(ChatRecord {
+                at: envelope.received_at,
+                peer,
+                direction: Direction::Incoming,
+                server_id: envelope.source,
+                session_id: envelope.session_id,
+                state: None,
+                message,
+            })
+        })
+        .filter(|record| filter.from.is_none_or(|peer| record.peer == peer))
+        .collect();
+
+    for record in records {
+        let JournalOp::Enqueue { id, message, .. } = &record.op else {
+            continue;
+        };
+        if !in_range(record.at) || filter.from.is_some_and(|peer| message.client_id != peer) {
+            continue;
+        }
+        chat.push(ChatRecord {
+            at: record.at,
+            peer: message.client_id,
+            direction: Direction::Outgoing,
+            server_id: Some(message.server_id),
+            session_id: Some(message.session_id),
+            state: deliveries.status(*id).map(|status| status.state),
+            message: message.message.clone(),
+        });
+    }
+
+    chat.sort_by_key(|record| record.at);
+    chat
+}
+
+/// Renders the chat history as CSV.
+#[must_use]
+pub fn chat_csv(chat: &[ChatRecord]) -> String {
+    let mut csv = String::from(CHAT_CSV_HEADER);
+    csv.push('\n');
+    for record in chat {
+        let direction = match record.direction {
+            Direction::Outgoing => "outgoing",
+            Direction::Incoming => "incoming",
+        };
+        let _ = writeln!(
+            csv,
+            "{},{},{},{},{},{},{}",
+            record.at,
+            record.peer,
+            direction,
+            record
+                .server_id
+                .map(|id| id.to_string())
+                .unwrap_or_default(),
+            record
+                .session_id
+                .map(|id| id.to_string())
+                .unwrap_or_default(),
+            record.state.map(state_name).unwrap_or_default(),
+            csv_field(&record.message)
+        );
+    }
+    csv
+}
+
+/// Name of `state` as used in the API.
+fn state_name(state: DeliveryState) -> &'static str {
+    match state {
+        DeliveryState::Queued => "queued",
+        DeliveryState::Sent => "sent",
+        DeliveryState::Acknowledged => "acked",
+        DeliveryState::Delivered => "delivered",
+        DeliveryState::Read => "read",
+        DeliveryState::Failed => "failed",
+    }
+}
+
+/// Quotes `text` for a CSV field if it contains a separator, quote or line break.
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
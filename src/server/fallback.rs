@@ -0,0 +1,83 @@
+//! Built-in page served at `/` when the web UI is missing.
+//!
+//! Without `static/index.html` (e.g. right after `cargo run` from a fresh
+//! checkout) the node would only answer `/` with a 404. Instead, the page
+//! generated here shows the node's status, a minimal chat to send and read
+//! messages, and every route of the API, so the node can be used and
+//! explored without the UI.
+
+use std::fmt::Write;
+
+/// Renders the page for node `node_id`, listing `routes` as `(path, method)`.
+#[must_use]
+pub fn page(node_id: u8, backend_responsive: bool, routes: &[(&str, &str)]) -> String {
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Node {node_id}</title>\n</head>\n<body>\n\
+         <h1>Node {node_id}</h1>\n\
+         <p>The web UI was not found in <code>static/</code>; this is the built-in fallback page.</p>\n\
+         <h2>Status</h2>\n<ul>\n\
+         <li>Version: {}</li>\n\
+         <li>Backend: {}</li>\n</ul>\n",
+        env!("CARGO_PKG_VERSION"),
+        if backend_responsive {
+            "responsive"
+        } else {
+            "not responding"
+        },
+    );
+    html.push_str(CHAT);
+    html.push_str("<h2>API</h2>\n<table>\n");
+    for (path, method) in routes {
+        let _ = writeln!(
+            html,
+            "<tr><td>{method}</td><td><code>{}</code></td></tr>",
+            escape(path)
+        );
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+/// Form sending a message through `/send` and a list of the messages from `/messages`.
+const CHAT: &str = r#"<h2>Chat</h2>
+<form id="send">
+  Server <input name="server_id" type="number" min="0" max="255" required>
+  Client <input name="client_id" type="number" min="0" max="255" required>
+  <input name="message" placeholder="Message" required>
+  <button>Send</button> <span id="result"></span>
+</form>
+<button id="refresh">Fetch messages</button>
+<pre id="messages"></pre>
+<script>
+document.getElementById("send").onsubmit = async (event) => {
+  event.preventDefault();
+  const form = new FormData(event.target);
+  const response = await fetch("/send", {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({
+      server_id: Number(form.get("server_id")),
+      client_id: Number(form.get("client_id")),
+      message: form.get("message"),
+    }),
+  });
+  document.getElementById("result").textContent =
+    response.status + " " + (await response.text());
+};
+document.getElementById("refresh").onclick = async () => {
+  const response = await fetch("/messages");
+  document.getElementById("messages").textContent =
+    JSON.stringify(await response.json(), null, 2);
+};
+</script>
+"#;
+
+/// Escapes the characters of `text` that are special in HTML.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
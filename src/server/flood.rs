@@ -0,0 +1,345 @@
+//! Network discovery through flooding.
+//!
+//! The backend does not signal when a flood is complete; it only returns the
+//! edge nodes discovered so far. Discovery therefore polls the result until it
+//! stops changing, which adapts to the size and latency of the network instead
+//! of waiting for a fixed amount of time.
+//!
+//! Since that can take a while, a flood can also run in the background as a
+//! [`FloodJobs`] job whose result is fetched later, and the last result is
+//! kept in a [`FloodCache`] so repeated discoveries don't flood the drone
+//! network every time. Optionally, [`spawn_refresher`] keeps the cache fresh
+//! by flooding periodically, and the cache hands every result to a
+//! [`Registrar`] to register with newly discovered servers and to the
+//! [`Outbox`] to send the messages held for the servers found.
+
+use ap_client_backend_v2::backend::Command;
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TrySendError};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use wg_2024::packet::NodeType;
+
+use super::ServerOptions;
+use super::dispatcher::{DispatchError, Dispatcher};
+use super::outbox::Outbox;
+use super::registration::Registrar;
+use super::settings::LiveOptions;
+
+/// Number of identical consecutive results after which a flood counts as complete.
+const STABLE_POLLS: usize = 3;
+/// Number of background floods whose results are kept.
+const MAX_JOBS: usize = 32;
+
+/// A discovered edge node: its id and type.
+pub type EdgeNode = (u8, NodeType);
+
+/// Reasons a discovery can fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloodError {
+    /// A command could not be handed to the backend.
+    Send,
+    /// The backend did not answer.
+    Receive,
+    /// The command channel to the backend is full.
+    Busy,
+    /// The backend kept failing; carries how long until it is tried again.
+    Unavailable(Duration),
+}
+
+impl fmt::Display for FloodError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FloodError::Send => write!(f, "Failed to send request to the backend to flood"),
+            FloodError::Receive => write!(f, "Failed to receive answer from the backend"),
+            FloodError::Busy => write!(f, "Backend is busy, try again later"),
+            FloodError::Unavailable(left) => {
+                write!(f, "Backend keeps failing, trying again in {left:.0?}")
+            }
+        }
+    }
+}
+
+impl From<DispatchError> for FloodError {
+    fn from(e: DispatchError) -> Self {
+        match e {
+            DispatchError::Busy => FloodError::Busy,
+            DispatchError::Unavailable(left) => FloodError::Unavailable(left),
+            DispatchError::Disconnected | DispatchError::Timeout => FloodError::Receive,
+        }
+    }
+}
+
+/// Floods the network and returns the discovered edge nodes.
+///
+/// After `InitializeFlood` the discovered nodes are requested every
+/// `flood_poll_interval` until the result has been stable for a few polls
+/// or `flood_timeout` has passed.
+///
+/// # Errors
+/// Returns a [`FloodError`] if the backend can't be reached.
+pub fn discover(
+    command_send_channel: &Sender<Command>,
+    dispatcher: &Dispatcher,
+    options: &ServerOptions,
+) -> Result<Vec<EdgeNode>, FloodError> {
+    dispatcher.check_breaker()?;
+    command_send_channel
+        .try_send(Command::InitializeFlood)
+        .map_err(|e| match e {
+            TrySendError::Full(_) => FloodError::Busy,
+            TrySendError::Disconnected(_) => FloodError::Send,
+        })?;
+    dispatcher.metrics().record_command();
+    dispatcher.metrics().record_flood();
+    tracing::debug!("InitializeFlood handed to the backend");
+
+    let deadline = Instant::now() + options.flood_timeout;
+    let mut nodes = vec![];
+    let mut previous = BTreeSet::new();
+    let mut stable_polls = 0;
+
+    while stable_polls < STABLE_POLLS && Instant::now() < deadline {
+        thread::sleep(options.flood_poll_interval);
+
+        nodes = dispatcher.edge_nodes(options.flood_timeout)?;
+
+        let ids: BTreeSet<u8> = nodes.iter().map(|node| node.0).collect();
+        if !ids.is_empty() && ids == previous {
+            stable_polls += 1;
+        } else {
+            stable_polls = 1;
+            previous = ids;
+        }
+    }
+
+    Ok(nodes)
+}
+
+/// The result of the last flood.
+#[derive(Debug, Default)]
+pub struct FloodCache {
+    last: Mutex<Option<(Instant, Vec<EdgeNode>)>>,
+    discovered: Mutex<BTreeSet<u8>>,
+    registrar: Option<Arc<Registrar>>,
+    outbox: Option<Arc<Outbox>>,
+}
+
+impl FloodCache {
+    /// Creates an empty cache that lets `registrar`, if any, register with
+    /// the servers of every stored result, and `outbox` send the messages held for them.
+    #[must_use]
+    pub fn new(registrar: Option<Arc<Registrar>>, outbox: Arc<Outbox>) -> Self {
+        FloodCache {
+            last: Mutex::new(None),
+            discovered: Mutex::new(BTreeSet::new()),
+            registrar,
+            outbox: Some(outbox),
+        }
+    }
+
+    /// Returns the last result if it is younger than `ttl`.
+    #[must_use]
+    pub fn get(&self, ttl: Duration) -> Option<Vec<EdgeNode>> {
+        self.lock()
+            .as_ref()
+            .filter(|(at, _)| at.elapsed() < ttl)
+            .map(|(_, nodes)| nodes.clone())
+    }
+
+    /// Replaces the last result with `nodes`.
+    pub fn store(&self, nodes: &[EdgeNode]) {
+        *self.lock() = Some((Instant::now(), nodes.to_vec()));
+        self.discovered
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .extend(servers(nodes));
+        if let Some(registrar) = &self.registrar {
+            registrar.register_discovered(&servers(nodes));
+        }
+        if let Some(outbox) = &self.outbox {
+            outbox.release(&servers(nodes));
+        }
+    }
+
+    /// IDs of the servers in the last result, however old it is.
+    #[must_use]
+    pub fn last_servers(&self) -> BTreeSet<u8> {
+        self.lock()
+            .as_ref()
+            .map(|(_, nodes)| servers(nodes).into_iter().collect())
+            .unwrap_or_default()
+    }
+
+    /// IDs of every server found by any flood so far, even if it since vanished.
+    #[must_use]
+    pub fn discovered_servers(&self) -> BTreeSet<u8> {
+        self.discovered
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<(Instant, Vec<EdgeNode>)>> {
+        self.last.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Like [`discover`], but returns the cached result if it is younger than
+/// `ServerOptions::flood_cache_ttl`, unless `refresh` is set. A fresh
+/// result is cached.
+///
+/// # Errors
+/// Returns a [`FloodError`] if the backend can't be reached.
+pub fn discover_cached(
+    command_send_channel: &Sender<Command>,
+    dispatcher: &Dispatcher,
+    options: &ServerOptions,
+    cache: &FloodCache,
+    refresh: bool,
+) -> Result<Vec<EdgeNode>, FloodError> {
+    if !refresh && let Some(nodes) = cache.get(max_age(options)) {
+        return Ok(nodes);
+    }
+    let nodes = discover(command_send_channel, dispatcher, options)?;
+    cache.store(&nodes);
+    Ok(nodes)
+}
+
+/// How old a cached result may be. With the refresher running, the cache is
+/// kept until the next refresh had time to finish.
+#[must_use]
+pub fn max_age(options: &ServerOptions) -> Duration {
+    options
+        .flood_refresh_interval
+        .map_or(options.flood_cache_ttl, |interval| {
+            options
+                .flood_cache_ttl
+                .max(interval + options.flood_timeout)
+        })
+}
+
+/// Spawns the refresher, which floods every `ServerOptions::flood_refresh_interval`
+/// with the current settings and stores the result in `cache`, until `shutdown` is
+/// disconnected. Does nothing if no interval is set.
+pub fn spawn_refresher(
+    command_send_channel: Sender<Command>,
+    dispatcher: Dispatcher,
+    options: Arc<LiveOptions>,
+    cache: Arc<FloodCache>,
+    shutdown: Receiver<()>,
+) {
+    let Some(interval) = options.current().flood_refresh_interval else {
+        return;
+    };
+    thread::spawn(move || {
+        loop {
+            let current = options.current();
+            if let Err(e) =
+                discover_cached(&command_send_channel, &dispatcher, &current, &cache, true)
+            {
+                tracing::warn!("Periodic flood failed: {e}");
+            }
+            if let Err(RecvTimeoutError::Disconnected) = shutdown.recv_timeout(interval) {
+                return;
+            }
+        }
+    });
+}
+
+/// IDs of the servers among `nodes`.
+#[must_use]
+pub fn servers(nodes: &[EdgeNode]) -> Vec<u8> {
+    nodes
+        .iter()
+        .filter(|node| matches!(node.1, NodeType::Server))
+        .map(|node| node.0)
+        .collect()
+}
+
+/// State of a background flood.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub enum FloodJob {
+    /// Still discovering.
+    Running,
+    /// Finished with the IDs of the discovered servers.
+    Done {
+        /// Discovered servers.
+        servers: Vec<u8>,
+    },
+    /// The backend could not be reached.
+    Failed {
+        /// What went wrong.
+        error: String,
+    },
+}
+
+#[derive(Debug, Default)]
+struct Jobs {
+    next_id: u64,
+    by_id: BTreeMap<u64, FloodJob>,
+}
+
+/// Floods running in the background and the results of the latest ones.
+#[derive(Debug, Default)]
+pub struct FloodJobs {
+    jobs: Mutex<Jobs>,
+}
+
+impl FloodJobs {
+    /// Starts a flood in the background and returns its job ID. While a
+    /// flood is running, no second one is started; its job ID is returned instead.
+    /// The result is stored in `cache`.
+    pub fn start(
+        self: &Arc<Self>,
+        command_send_channel: Sender<Command>,
+        dispatcher: Dispatcher,
+        options: ServerOptions,
+        cache: Arc<FloodCache>,
+    ) -> u64 {
+        let mut jobs = self.lock();
+        if let Some((id, _)) = jobs
+            .by_id
+            .iter()
+            .find(|(_, job)| matches!(job, FloodJob::Running))
+        {
+            return *id;
+        }
+
+        jobs.next_id += 1;
+        let id = jobs.next_id;
+        jobs.by_id.insert(id, FloodJob::Running);
+        while jobs.by_id.len() > MAX_JOBS {
+            jobs.by_id.pop_first();
+        }
+
+        let this = self.clone();
+        thread::spawn(move || {
+            let job =
+                match discover_cached(&command_send_channel, &dispatcher, &options, &cache, true) {
+                    Ok(nodes) => FloodJob::Done {
+                        servers: servers(&nodes),
+                    },
+                    Err(e) => FloodJob::Failed {
+                        error: e.to_string(),
+                    },
+                };
+            this.lock().by_id.insert(id, job);
+        });
+        id
+    }
+
+    /// Returns the state of job `id`, unless it is unknown or too old.
+    #[must_use]
+    pub fn get(&self, id: u64) -> Option<FloodJob> {
+        self.lock().by_id.get(&id).cloned()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Jobs> {
+        self.jobs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,193 @@
+//! Record of chat traffic exchanged with other clients.
+//!
+//! Every chat message sent through `/send` and every chat message received
+//! from the backend is appended here, keyed by the peer client. The history
+//! is the source for per-conversation statistics.
+
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use super::storage::StoredMessage;
+use super::timeseries::{Metric, TimeSeries};
+use super::unix_millis;
+
+/// Whether a message was sent or received by this node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Sent by this node.
+    Outgoing,
+    /// Received from a peer.
+    Incoming,
+}
+
+/// A single chat message in the history.
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEntry {
+    /// The other client of the conversation.
+    pub peer: u8,
+    /// Whether the message was sent or received.
+    pub direction: Direction,
+    /// Unix time in milliseconds when the message was sent or received.
+    pub at: u64,
+    /// Size of the message body in bytes.
+    pub bytes: usize,
+    /// Whether sending the message failed.
+    pub failed: bool,
+}
+
+/// Aggregated statistics of a conversation with one peer.
+#[derive(Debug, Default, Serialize)]
+pub struct ConversationStats {
+    /// The other client of the conversation.
+    pub peer: u8,
+    /// Number of messages sent to the peer.
+    pub messages_out: usize,
+    /// Number of messages received from the peer.
+    pub messages_in: usize,
+    /// Bytes sent to the peer.
+    pub bytes_out: usize,
+    /// Bytes received from the peer.
+    pub bytes_in: usize,
+    /// Average time between a sent message and the peer's next message, in milliseconds.
+    pub avg_reply_latency_ms: Option<u64>,
+    /// Share of sent messages that could not be delivered, between 0 and 1.
+    pub delivery_failure_rate: f64,
+}
+
+/// Chat history of this node.
+#[derive(Debug)]
+pub struct History {
+    entries: Mutex<Vec<HistoryEntry>>,
+    timeseries: Arc<TimeSeries>,
+}
+
+impl History {
+    /// Creates an empty history recording chat throughput into `timeseries`.
+    #[must_use]
+    pub fn new(timeseries: Arc<TimeSeries>) -> Self {
+        History {
+            entries: Mutex::new(vec![]),
+            timeseries,
+        }
+    }
+
+    /// Records a message sent to `peer`.
+    pub fn record_outgoing(&self, peer: u8, bytes: usize, failed: bool) {
+        if !failed {
+            self.timeseries.record(Metric::Throughput, bytes as f64);
+        }
+        self.lock().push(HistoryEntry {
+            peer,
+            direction: Direction::Outgoing,
+            at: unix_millis(),
+            bytes,
+            failed,
+        });
+    }
+
+    /// Records a message received from `peer` at `at` (Unix time in milliseconds).
+    pub fn record_incoming(&self, peer: u8, bytes: usize, at: u64) {
+        self.timeseries.record(Metric::Throughput, bytes as f64);
+        self.lock().push(HistoryEntry {
+            peer,
+            direction: Direction::Incoming,
+            at,
+            bytes,
+            failed: false,
+        });
+    }
+
+    /// Replaces every received message with the chat messages among
+    /// `messages`, keeping sent messages untouched.
+    ///
+    /// Returns the number of conversations afterwards.
+    pub fn rebuild_incoming(&self, messages: &[StoredMessage]) -> usize {
+        let incoming = messages
+            .iter()
+            .filter(|stored| stored.envelope.is_chat_message())
+            .filter_map(|stored| {
+                let envelope = &stored.envelope;
+                stored
+                    .peer
+                    .or_else(|| envelope.sender())
+                    .map(|peer| HistoryEntry {
+                        peer,
+                        direction: Direction::Incoming,
+                        at: envelope.received_at,
+                        bytes: envelope.body_len(),
+                        failed: false,
+                    })
+            });
+
+        let mut entries = self.lock();
+        entries.retain(|entry| entry.direction == Direction::Outgoing);
+        entries.extend(incoming);
+        entries.sort_by_key(|entry| entry.at);
+
+        let peers: BTreeSet<u8> = entries.iter().map(|entry| entry.peer).collect();
+        peers.len()
+    }
+
+    /// Moves the recorded messages of peer `old` to peer `new`.
+    pub fn merge_peer(&self, old: u8, new: u8) {
+        for entry in self.lock().iter_mut().filter(|entry| entry.peer == old) {
+            entry.peer = new;
+        }
+    }
+
+    /// Returns every recorded message, oldest first.
+    #[must_use]
+    pub fn entries(&self) -> Vec<HistoryEntry> {
+        self.lock().clone()
+    }
+
+    /// Computes the statistics of the conversation with `peer`.
+    #[must_use]
+    pub fn conversation_stats(&self, peer: u8) -> ConversationStats {
+        let entries = self.lock();
+        let mut stats = ConversationStats {
+            peer,
+            ..ConversationStats::default()
+        };
+        let mut failed: u32 = 0;
+        // Time of the first message we sent that the peer hasn't answered yet
+        let mut awaiting_reply_since = None;
+        let mut reply_latencies = vec![];
+
+        for entry in entries.iter().filter(|entry| entry.peer == peer) {
+            match entry.direction {
+                Direction::Outgoing => {
+                    stats.messages_out += 1;
+                    stats.bytes_out += entry.bytes;
+                    if entry.failed {
+                        failed += 1;
+                    } else {
+                        awaiting_reply_since.get_or_insert(entry.at);
+                    }
+                }
+                Direction::Incoming => {
+                    stats.messages_in += 1;
+                    stats.bytes_in += entry.bytes;
+                    if let Some(sent_at) = awaiting_reply_since.take() {
+                        reply_latencies.push(entry.at.saturating_sub(sent_at));
+                    }
+                }
+            }
+        }
+
+        if !reply_latencies.is_empty() {
+            stats.avg_reply_latency_ms =
+                Some(reply_latencies.iter().sum::<u64>() / reply_latencies.len() as u64);
+        }
+        if stats.messages_out > 0 {
+            stats.delivery_failure_rate = f64::from(failed) / stats.messages_out as f64;
+        }
+        stats
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<HistoryEntry>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
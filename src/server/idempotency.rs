@@ -0,0 +1,210 @@
+//! Idempotency keys for `/send` and `/register`.
+//!
+//! A UI retrying after a timeout can't tell whether the first attempt reached
+//! the backend, and sending it again would enqueue a duplicate command. A
+//! request carrying an `Idempotency-Key` header is therefore handled once:
+//! the [`handle`] middleware keeps its response for `ServerOptions::idempotency_ttl`
+//! and answers repeated requests with the same key from there, marked with
+//! `Idempotent-Replayed: true`. A repeat arriving while the first request is
+//! still being handled gets 409 (Conflict). Server errors and 429s aren't kept,
+//! so the request can be retried under the same key. Keys are per user, so
+//! different users can't replay each other's responses.
+
+use actix_web::body::{self, EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::web::Bytes;
+use actix_web::{Error, HttpMessage, HttpResponse, web};
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use super::auth::AuthIdentity;
+
+/// Header carrying the idempotency key.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+/// Header marking a response answered from a previous request.
+const REPLAYED_HEADER: &str = "idempotent-replayed";
+/// Routes whose `POST` requests may carry a key.
+const ROUTES: &[&str] = &["/send", "/register"];
+/// Longest key accepted.
+const MAX_KEY_LEN: usize = 255;
+
+/// A response kept for replaying.
+#[derive(Debug, Clone)]
+struct StoredResponse {
+    status: StatusCode,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    body: Bytes,
+}
+
+impl StoredResponse {
+    fn to_response(&self, replayed: bool) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status);
+        for (name, value) in &self.headers {
+            response.append_header((name.clone(), value.clone()));
+        }
+        if replayed {
+            response.insert_header((REPLAYED_HEADER, "true"));
+        }
+        response.body(self.body.clone())
+    }
+}
+
+#[derive(Debug)]
+enum Entry {
+    InFlight,
+    Done(Instant, StoredResponse),
+}
+
+/// What to do with a request carrying a key.
+enum Begin {
+    /// The key is new; handle the request.
+    First,
+    /// The request with the key is still being handled.
+    InFlight,
+    /// The request with the key was handled already.
+    Replay(StoredResponse),
+}
+
+/// (user, path, key)
+type Key = (String, String, String);
+
+/// Responses of requests with an idempotency key.
+#[derive(Debug)]
+pub struct IdempotencyKeys {
+    ttl: Duration,
+    entries: Mutex<HashMap<Key, Entry>>,
+}
+
+impl IdempotencyKeys {
+    /// Keeps responses for `ttl`.
+    #[must_use]
+    pub fn new(ttl: Duration) -> Self {
+        IdempotencyKeys {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn begin(&self, key: &Key) -> Begin {
+        let mut entries = self.lock();
+        entries.retain(|_, entry| match entry {
+            Entry::InFlight => true,
+            Entry::Done(at, _) => at.elapsed() < self.ttl,
+        });
+        match entries.get(key) {
+            Some(Entry::InFlight) => Begin::InFlight,
+            Some(Entry::Done(_, response)) => Begin::Replay(response.clone()),
+            None => {
+                entries.insert(key.clone(), Entry::InFlight);
+                Begin::First
+            }
+        }
+    }
+
+    fn finish(&self, key: Key, response: Option<StoredResponse>) {
+        let mut entries = self.lock();
+        match response {
+            Some(response) => {
+                entries.insert(key, Entry::Done(Instant::now(), response));
+            }
+            None => {
+                entries.remove(&key);
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<Key, Entry>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Middleware handling every request with a given idempotency key only once.
+///
+/// # Errors
+/// Passes on the errors of the wrapped service.
+pub async fn handle<B: MessageBody + 'static>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let keys = req.app_data::<web::Data<IdempotencyKeys>>().cloned();
+    let value = req.headers().get(IDEMPOTENCY_KEY_HEADER).cloned();
+    let (Some(keys), Some(value)) = (keys, value) else {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    };
+    if req.method() != Method::POST || !ROUTES.contains(&req.path()) {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    }
+    let Some(key) = value
+        .to_str()
+        .ok()
+        .filter(|key| !key.is_empty() && key.len() <= MAX_KEY_LEN)
+    else {
+        let response = HttpResponse::BadRequest().json(format!(
+            "The idempotency key must be 1 to {MAX_KEY_LEN} visible ASCII characters"
+        ));
+        return Ok(req.into_response(response).map_into_right_body());
+    };
+    let user = req
+        .extensions()
+        .get::<AuthIdentity>()
+        .map(|identity| identity.user.clone())
+        .unwrap_or_default();
+    let key = (user, req.path().to_string(), key.to_string());
+
+    match keys.begin(&key) {
+        Begin::First => {}
+        Begin::InFlight => {
+            let response = HttpResponse::Conflict()
+                .json("A request with this idempotency key is still being handled");
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+        Begin::Replay(stored) => {
+            return Ok(req
+                .into_response(stored.to_response(true))
+                .map_into_right_body());
+        }
+    }
+
+    let response = match next.call(req).await {
+        Ok(response) => response,
+        Err(e) => {
+            keys.finish(key, None);
+            return Err(e);
+        }
+    };
+    let status = response.status();
+    if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+        keys.finish(key, None);
+        return Ok(response.map_into_left_body());
+    }
+
+    let (req, response) = response.into_parts();
+    let (head, body) = response.into_parts();
+    let Ok(body) = body::to_bytes(body).await else {
+        keys.finish(key, None);
+        let response = HttpResponse::InternalServerError().json("Failed to read the response");
+        return Ok(ServiceResponse::new(req, response).map_into_right_body());
+    };
+    let stored = StoredResponse {
+        status,
+        headers: head
+            .headers()
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect(),
+        body,
+    };
+    let response = stored.to_response(false);
+    keys.finish(key, Some(stored));
+    Ok(ServiceResponse::new(req, response).map_into_right_body())
+}
@@ -0,0 +1,213 @@
+//! Persistent identity of the local node.
+//!
+//! The identity (secret key, alias and contacts) is stored as JSON in the
+//! node's data directory, so restarting a node with the same id restores it
+//! instead of appearing as a brand-new peer.
+//!
+//! Contacts make up the address book: a label and the servers the peer is
+//! preferably reached through, so the UI can show names instead of node ids.
+//! They also carry the public key of the peer once keys were exchanged,
+//! which decides the [`KeyStatus`] of the conversation with it. The key is
+//! only recorded: messages are sent as plaintext whatever the status. A
+//! conversation can require a key; messages to that peer are then refused
+//! while none is recorded.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use super::{unix_millis, write_json_atomic};
+
+/// Whether a key of a peer was recorded. Messages aren't encrypted with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyStatus {
+    /// No key of the peer is recorded.
+    Missing,
+    /// The peer's key is recorded, but wasn't verified.
+    Recorded,
+    /// The peer's key is recorded and the user verified it.
+    Verified,
+}
+
+/// A known peer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Contact {
+    /// Human readable name of the peer.
+    pub label: String,
+    /// Hex encoded public key of the peer, once keys were exchanged.
+    #[serde(default)]
+    pub public_key: Option<String>,
+    /// Whether the user verified the peer's key, e.g. by comparing it in person.
+    #[serde(default)]
+    pub key_verified: bool,
+    /// Whether messages to the peer are refused while no key of it is recorded.
+    #[serde(default)]
+    pub require_key: bool,
+    /// Servers to reach the peer through, most preferred first.
+    #[serde(default)]
+    pub preferred_servers: Vec<u8>,
+}
+
+impl Contact {
+    /// Contact `label` without exchanged keys.
+    #[must_use]
+    pub fn new(label: String) -> Self {
+        Contact {
+            label,
+            public_key: None,
+            key_verified: false,
+            require_key: false,
+            preferred_servers: vec![],
+        }
+    }
+
+    /// Takes over the exchanged key of `other` and whether it was verified,
+    /// unless this contact has a key of its own.
+    pub fn adopt_key(&mut self, other: &Contact) {
+        if self.public_key.is_none() {
+            self.public_key.clone_from(&other.public_key);
+            self.key_verified = other.key_verified;
+        }
+    }
+
+    /// Whether the two contacts hold different keys, so they can't be merged.
+    #[must_use]
+    pub fn conflicts_with(&self, other: &Contact) -> bool {
+        matches!((&self.public_key, &other.public_key), (Some(a), Some(b)) if a != b)
+    }
+
+    /// Whether a key of the peer is recorded and verified.
+    #[must_use]
+    pub fn key_status(&self) -> KeyStatus {
+        match (&self.public_key, self.key_verified) {
+            (None, _) => KeyStatus::Missing,
+            (Some(_), false) => KeyStatus::Recorded,
+            (Some(_), true) => KeyStatus::Verified,
+        }
+    }
+
+    /// Whether a message to the peer would have to be refused: a key is
+    /// required, but none is recorded.
+    #[must_use]
+    pub fn lacks_required_key(&self) -> bool {
+        self.require_key && self.key_status() == KeyStatus::Missing
+    }
+}
+
+/// Everything that makes up the node's identity.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Identity {
+    /// The local node.
+    pub node_id: u8,
+    /// Unix time in milliseconds when the identity was generated.
+    pub created_at: u64,
+    /// Display name chosen by the user.
+    pub alias: Option<String>,
+    /// Hex encoded secret key, generated once.
+    pub secret_key: String,
+    /// Known peers by node id.
+    pub contacts: BTreeMap<u8, Contact>,
+}
+
+impl Identity {
+    fn generate(node_id: u8) -> Self {
+        let key: [u8; 32] = rand::random();
+        Identity {
+            node_id,
+            created_at: unix_millis(),
+            alias: None,
+            secret_key: key.iter().fold(String::new(), |mut hex, byte| {
+                let _ = write!(hex, "{byte:02x}");
+                hex
+            }),
+            contacts: BTreeMap::new(),
+        }
+    }
+
+    /// Short, non-secret identifier of the key, for telling identities apart.
+    #[must_use]
+    pub fn key_id(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.secret_key.hash(&mut hasher);
+        format!("{:016x}", hasher.finish())
+    }
+}
+
+/// The identity together with the file it is persisted in.
+#[derive(Debug)]
+pub struct IdentityStore {
+    path: PathBuf,
+    identity: Mutex<Identity>,
+}
+
+impl IdentityStore {
+    /// Loads the identity from `path`, generating and saving a new one for
+    /// `node_id` if the file doesn't exist.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read, parsed or written.
+    pub fn load_or_create(path: &Path, node_id: u8) -> io::Result<Self> {
+        let identity = match fs::read(path) {
+            Ok(json) => serde_json::from_slice(&json).map_err(io::Error::other)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let identity = Identity::generate(node_id);
+                write_json_atomic(path, &identity)?;
+                identity
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(IdentityStore {
+            path: path.to_path_buf(),
+            identity: Mutex::new(identity),
+        })
+    }
+
+    /// Returns a copy of the identity.
+    #[must_use]
+    pub fn get(&self) -> Identity {
+        self.lock().clone()
+    }
+
+    /// The label of every contact by node id, for showing nicknames next to node ids.
+    #[must_use]
+    pub fn nicknames(&self) -> BTreeMap<u8, String> {
+        self.lock()
+            .contacts
+            .iter()
+            .map(|(id, contact)| (*id, contact.label.clone()))
+            .collect()
+    }
+
+    /// Whether messages to `peer` have to be refused, see [`Contact::lacks_required_key`].
+    #[must_use]
+    pub fn lacks_required_key(&self, peer: u8) -> bool {
+        self.lock()
+            .contacts
+            .get(&peer)
+            .is_some_and(Contact::lacks_required_key)
+    }
+
+    /// Changes the identity with `f` and persists the result. The change is
+    /// discarded if it can't be persisted.
+    ///
+    /// # Errors
+    /// Returns an error if the identity can't be written.
+    pub fn update<R>(&self, f: impl FnOnce(&mut Identity) -> R) -> io::Result<R> {
+        let mut identity = self.lock();
+        let mut updated = identity.clone();
+        let result = f(&mut updated);
+        write_json_atomic(&self.path, &updated)?;
+        *identity = updated;
+        Ok(result)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Identity> {
+        self.identity.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,524 @@
+//! Local buffer for messages delivered by the backend.
+//!
+//! The backend hands out everything it received from servers in batches
+//! (`UnreadMessagesFromServer`). Some endpoints need to wait for one specific
+//! reply (e.g. `/register` waiting for the server's confirmation), so batches
+//! are unpacked into [`Envelope`]s and parked in the [`Inbox`]. Handlers take
+//! the entries they are interested in and leave the rest for `/messages`.
+//! Besides the handlers, the ingester started by [`spawn_ingester`] keeps
+//! fetching batches, so messages are stored as soon as they arrive.
+
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use messages::Message;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::away::AwayMode;
+use super::chat_error::is_error_reply;
+use super::clock::PeerClocks;
+use super::dedup::Deduplicator;
+use super::delivery::DeliveryTracker;
+use super::directory::Directory;
+use super::dispatcher::{DispatchError, Dispatcher};
+use super::history::History;
+use super::receipts;
+use super::storage::{MessageStore, StoredMessage};
+use super::typing;
+use super::unix_millis;
+
+/// Most messages buffered for the UI; beyond that, the oldest are dropped from
+/// the buffer. They stay in the [`MessageStore`], unread. The dispatcher holds
+/// at most as many for the next requester.
+pub(crate) const PENDING_CAPACITY: usize = 10_000;
+/// How long a single wait for backend messages lasts while waiting for a reply.
+const REPLY_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Spawns the ingester, which asks the backend for unread messages every
+/// `interval`, so they are stored and reach the UI's long polls even while no
+/// request is waiting for them. Its requests go ahead of other traffic in the
+/// [`Dispatcher`]. Stops once `shutdown` is disconnected.
+pub fn spawn_ingester(
+    inbox: Arc<Inbox>,
+    dispatcher: Dispatcher,
+    interval: Duration,
+    shutdown: Receiver<()>,
+) {
+    thread::spawn(move || {
+        loop {
+            inbox.refill(&dispatcher, REPLY_POLL_INTERVAL);
+            if let Err(RecvTimeoutError::Disconnected) = shutdown.recv_timeout(interval) {
+                return;
+            }
+        }
+    });
+}
+
+/// A single message received from the backend.
+///
+/// The payload is kept as JSON so the frontend does not depend on the exact
+/// shape of the backend's message type; the fields the frontend cares about
+/// are extracted once on arrival.
+#[derive(Debug, Clone, Serialize)]
+pub struct Envelope {
+    /// Id of the message in the [`MessageStore`], once stored.
+    #[serde(skip)]
+    pub id: Option<u64>,
+    /// Node that sent the message, if present in the payload.
+    pub source: Option<u8>,
+    /// Session the message belongs to, if present in the payload.
+    pub session_id: Option<u64>,
+    /// Unix time in milliseconds when the frontend received the message.
+    pub received_at: u64,
+    /// The message as returned by the backend.
+    pub payload: Value,
+    /// Data attached to the message by the frontend itself, e.g. by plugins and
+    /// scripts, keyed by name. Stored with the message; see [`MessageStore::set_metadata`].
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, Value>,
+}
+
+impl Envelope {
+    /// Wraps a backend message into an envelope.
+    pub fn new<T: Serialize>(message: &T) -> Self {
+        let payload = serde_json::to_value(message).unwrap_or(Value::Null);
+        let source = payload
+            .get("source")
+            .and_then(Value::as_u64)
+            .and_then(|id| u8::try_from(id).ok());
+        let session_id = payload.get("session_id").and_then(Value::as_u64);
+        Envelope {
+            id: None,
+            source,
+            session_id,
+            received_at: unix_millis(),
+            payload,
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the variant path of the message content,
+    /// e.g. `["Response", "ChatResponse", "MessageFrom"]`.
+    #[must_use]
+    pub fn kind(&self) -> Vec<&str> {
+        self.unwrap_content().0
+    }
+
+    /// Client that wrote the message.
+    ///
+    /// For chat messages forwarded by a server this is the original author,
+    /// otherwise the node that sent the message.
+    #[must_use]
+    pub fn sender(&self) -> Option<u8> {
+        self.unwrap_content()
+            .1
+            .and_then(|inner| inner.get("from"))
+            .and_then(Value::as_u64)
+            .and_then(|id| u8::try_from(id).ok())
+            .or(self.source)
+    }
+
+    /// Clients listed in a `ClientList` reply, or `None` for other messages.
+    #[must_use]
+    pub fn client_list(&self) -> Option<Vec<u8>> {
+        let (kind, inner) = self.unwrap_content();
+        if kind.last() != Some(&"ClientList") {
+            return None;
+        }
+        inner?
+            .as_array()?
+            .iter()
+            .map(|id| id.as_u64().and_then(|id| u8::try_from(id).ok()))
+            .collect()
+    }
+
+    /// Text of the message body, if it is text.
+    #[must_use]
+    pub fn body_text(&self) -> Option<&str> {
+        self.unwrap_content()
+            .1
+            .and_then(|inner| inner.get("message"))
+            .and_then(Value::as_str)
+    }
+
+    /// Send time stated by the author, in Unix milliseconds by the author's
+    /// clock, if the message carries one.
+    #[must_use]
+    pub fn sent_at(&self) -> Option<u64> {
+        self.unwrap_content()
+            .1
+            .and_then(|inner| inner.get("sent_at"))
+            .and_then(Value::as_u64)
+    }
+
+    /// Size of the message body in bytes.
+    #[must_use]
+    pub fn body_len(&self) -> usize {
+        match self
+            .unwrap_content()
+            .1
+            .and_then(|inner| inner.get("message"))
+        {
+            Some(Value::String(text)) => text.len(),
+            Some(Value::Array(bytes)) => bytes.len(),
+            _ => self.payload.to_string().len(),
+        }
+    }
+
+    /// Walks the externally tagged enums of the message content, returning
+    /// the variant names and the innermost value.
+    pub(crate) fn unwrap_content(&self) -> (Vec<&str>, Option<&Value>) {
+        unwrap_content(&self.payload)
+    }
+
+    /// Whether the message is an error reported by the remote node, see
+    /// [`ChatError`](super::chat_error::ChatError).
+    #[must_use]
+    pub fn is_error(&self) -> bool {
+        is_error_reply(&self.kind())
+    }
+
+    /// Whether the message is a chat message forwarded from another client.
+    #[must_use]
+    pub fn is_chat_message(&self) -> bool {
+        self.kind().last() == Some(&"MessageFrom")
+    }
+}
+
+/// Walks the externally tagged enums of the content of the message `payload`,
+/// returning the variant names and the innermost value.
+pub(crate) fn unwrap_content(payload: &Value) -> (Vec<&str>, Option<&Value>) {
+    let mut path = vec![];
+    let mut current = payload.get("content");
+    while let Some(Value::Object(map)) = current {
+        let Some((name, inner)) = map.iter().next().filter(|_| map.len() == 1) else {
+            break;
+        };
+        path.push(name.as_str());
+        current = Some(inner);
+    }
+    if let Some(Value::String(name)) = current {
+        path.push(name.as_str());
+    }
+    (path, current)
+}
+
+/// Messages fetched from the backend but not yet handed to the UI.
+#[derive(Debug)]
+pub struct Inbox {
+    pending: Mutex<VecDeque<Envelope>>,
+    history: Arc<History>,
+    deliveries: Arc<DeliveryTracker>,
+    directory: Arc<Directory>,
+    away: Arc<AwayMode>,
+    clocks: Arc<PeerClocks>,
+    store: Arc<MessageStore>,
+    dedup: Deduplicator,
+    // Session IDs of the requests waiting for a reply, per destination, oldest first
+    requests: Mutex<BTreeMap<u8, VecDeque<u64>>>,
+}
+
+impl Inbox {
+    /// Creates an empty inbox keeping every received message in `store`,
+    /// recording chat messages into `history`, settling sent messages in
+    /// `deliveries` as their replies arrive, learning client lists into
+    /// `directory`, letting `away` auto-reply to chat messages, sampling
+    /// peer clocks into `clocks` and dropping redelivered messages with `dedup`.
+    #[must_use]
+    pub fn new(
+        store: Arc<MessageStore>,
+        history: Arc<History>,
+        deliveries: Arc<DeliveryTracker>,
+        directory: Arc<Directory>,
+        away: Arc<AwayMode>,
+        clocks: Arc<PeerClocks>,
+        dedup: Deduplicator,
+    ) -> Self {
+        Inbox {
+            pending: Mutex::new(VecDeque::new()),
+            history,
+            deliveries,
+            directory,
+            away,
+            clocks,
+            store,
+            dedup,
+            requests: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Asks the backend for unread messages and buffers whatever arrives
+    /// within `timeout`.
+    ///
+    /// Returns `false` if the backend can't be reached.
+    pub fn refill(&self, dispatcher: &Dispatcher, timeout: Duration) -> bool {
+        match dispatcher.unread_messages(timeout) {
+            Ok(envelopes) => {
+                // Typing notices only raise events, see `typing`
+                let (notices, envelopes): (Vec<_>, Vec<_>) =
+                    envelopes.into_iter().partition(typing::is_notice);
+                self.directory.observe_typing(&notices);
+                // So do read receipts, see `receipts`
+                let (receipts, mut envelopes): (Vec<_>, Vec<_>) =
+                    envelopes.into_iter().partition(receipts::is_receipt);
+                for receipt in &receipts {
+                    self.deliveries.observe_receipt(receipt);
+                }
+                self.directory.observe_peers(&receipts);
+                envelopes.retain(|envelope| {
+                    let duplicate = self.dedup.is_duplicate(envelope);
+                    if duplicate {
+                        tracing::debug!(
+                            source = envelope.source,
+                            session_id = envelope.session_id,
+                            "Dropped a redelivered message"
+                        );
+                    }
+                    !duplicate
+                });
+                for envelope in &mut envelopes {
+                    // A storage failure must not keep the message from the UI
+                    envelope.id = self.store.append(envelope).ok();
+                    dispatcher
+                        .metrics()
+                        .record_ingestion(unix_millis().saturating_sub(envelope.received_at));
+                    self.deliveries.observe(envelope);
+                    if !envelope.is_chat_message() {
+                        self.directory.observe(envelope);
+                    } else if let Some(peer) = envelope.sender() {
+                        self.history.record_incoming(
+                            peer,
+                            envelope.body_len(),
+                            envelope.received_at,
+                        );
+                        self.clocks.observe(envelope);
+                    }
+                }
+                self.directory.observe_peers(&envelopes);
+                self.away.respond(&envelopes);
+                self.push(envelopes);
+                true
+            }
+            // Serve what is buffered rather than nothing
+            Err(DispatchError::Timeout | DispatchError::Busy | DispatchError::Unavailable(_)) => {
+                true
+            }
+            Err(DispatchError::Disconnected) => false,
+        }
+    }
+
+    /// Sends `message` and waits up to `timeout` for the destination's reply,
+    /// which is taken out of the buffer. The reply is the message from the
+    /// destination in the same session. A server that doesn't echo session IDs
+    /// answers in order, so then the reply is its first non-chat message not in
+    /// the session of another request, once every earlier request to it was
+    /// answered.
+    ///
+    /// # Errors
+    /// Returns [`DispatchError::Timeout`] if no reply arrives in time,
+    /// [`DispatchError::Busy`] if the backend's command channel is full,
+    /// [`DispatchError::Unavailable`] while the backend keeps failing and
+    /// [`DispatchError::Disconnected`] if the backend can't be reached.
+    pub fn request(
+        &self,
+        dispatcher: &Dispatcher,
+        message: Message,
+        timeout: Duration,
+    ) -> Result<Envelope, DispatchError> {
+        let destination = message.destination;
+        let session_id = message.session_id;
+        let _span = tracing::info_span!(
+            "backend_request",
+            node_id = message.source,
+            session_id,
+            destination
+        )
+        .entered();
+        self.lock_requests()
+            .entry(destination)
+            .or_default()
+            .push_back(session_id);
+        let reply = self.await_reply(dispatcher, message, timeout);
+        let mut requests = self.lock_requests();
+        if let Some(sessions) = requests.get_mut(&destination) {
+            sessions.retain(|waiting| *waiting != session_id);
+            if sessions.is_empty() {
+                requests.remove(&destination);
+            }
+        }
+        reply
+    }
+
+    fn await_reply(
+        &self,
+        dispatcher: &Dispatcher,
+        message: Message,
+        timeout: Duration,
+    ) -> Result<Envelope, DispatchError> {
+        let destination = message.destination;
+        let session_id = message.session_id;
+        dispatcher.send_messages(vec![message])?;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            // Anything but the reply (e.g. forwarded chat messages) stays in
+            // the inbox for `/messages`
+            if let Some(reply) = self
+                .take_first(|envelope| {
+                    envelope.source == Some(destination) && envelope.session_id == Some(session_id)
+                })
+                .or_else(|| self.take_unmatched_reply(destination, session_id))
+            {
+                return Ok(reply);
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(DispatchError::Timeout);
+            }
+            if !self.refill(dispatcher, remaining.min(REPLY_POLL_INTERVAL)) {
+                return Err(DispatchError::Disconnected);
+            }
+        }
+    }
+
+    /// Takes the first non-chat message from `destination` that isn't in the
+    /// session of another request, if the request in session `session_id` is
+    /// the oldest one to `destination` still waiting.
+    fn take_unmatched_reply(&self, destination: u8, session_id: u64) -> Option<Envelope> {
+        let waiting = self.lock_requests().get(&destination).cloned()?;
+        if waiting.front() != Some(&session_id) {
+            return None;
+        }
+        self.take_first(|envelope| {
+            envelope.source == Some(destination)
+                && !envelope.is_chat_message()
+                && envelope
+                    .session_id
+                    .is_none_or(|session_id| !waiting.contains(&session_id))
+        })
+    }
+
+    /// Appends envelopes to the buffer, dropping the oldest buffered ones
+    /// beyond its capacity.
+    pub fn push(&self, envelopes: impl IntoIterator<Item = Envelope>) {
+        let mut pending = self.lock();
+        pending.extend(envelopes);
+        let excess = pending.len().saturating_sub(PENDING_CAPACITY);
+        if excess > 0 {
+            pending.drain(..excess);
+            tracing::warn!("Dropped {excess} unread messages from the full inbox buffer");
+        }
+    }
+
+    /// Removes and returns the first buffered envelope matching `predicate`.
+    pub fn take_first(&self, predicate: impl Fn(&Envelope) -> bool) -> Option<Envelope> {
+        let mut pending = self.lock();
+        let index = pending.iter().position(predicate)?;
+        pending.remove(index)
+    }
+
+    /// Removes and returns every buffered envelope.
+    pub fn take_all(&self) -> Vec<Envelope> {
+        self.lock().drain(..).collect()
+    }
+
+    /// Removes and returns every buffered envelope matching `predicate`,
+    /// leaving the others buffered.
+    pub fn take_matching(&self, predicate: impl Fn(&Envelope) -> bool) -> Vec<Envelope> {
+        let mut pending = self.lock();
+        let (taken, kept): (VecDeque<_>, _) =
+            pending.drain(..).partition(|envelope| predicate(envelope));
+        *pending = kept;
+        taken.into()
+    }
+
+    /// Removes and returns every buffered envelope matching `predicate`
+    /// and marks them as read.
+    pub fn take_read(&self, predicate: impl Fn(&Envelope) -> bool) -> Vec<Envelope> {
+        let taken = self.take_matching(predicate);
+        self.mark_read(
+            &taken
+                .iter()
+                .filter_map(|envelope| envelope.id)
+                .collect::<Vec<_>>(),
+        );
+        taken
+    }
+
+    /// Returns copies of the buffered envelopes matching `predicate`,
+    /// leaving them buffered.
+    pub fn peek_matching(&self, predicate: impl Fn(&Envelope) -> bool) -> Vec<Envelope> {
+        self.lock()
+            .iter()
+            .filter(|envelope| predicate(envelope))
+            .cloned()
+            .collect()
+    }
+
+    /// Marks the messages with store ids `ids` as read: they are removed
+    /// from the buffer and their read time is stored.
+    ///
+    /// Returns the removed messages.
+    pub fn ack(&self, ids: &[u64]) -> Vec<Envelope> {
+        self.take_read(|envelope| envelope.id.is_some_and(|id| ids.contains(&id)))
+    }
+
+    /// Records the read time of the given messages in the store.
+    pub fn mark_read(&self, ids: &[u64]) {
+        if let Err(e) = self.store.mark_read(ids) {
+            tracing::error!("Failed to store read state: {e}");
+        }
+    }
+
+    /// Replaces the buffered chat messages with the unread ones among
+    /// `messages`, the stored messages outside the trash, so the unread state
+    /// follows the read flags of the store again. Other buffered messages stay,
+    /// as do chat messages that failed to be stored.
+    ///
+    /// Returns the number of unread chat messages now buffered.
+    pub fn rebuild_unread(&self, messages: &[StoredMessage]) -> usize {
+        let unread: Vec<_> = messages
+            .iter()
+            .filter(|stored| stored.read_at.is_none() && stored.envelope.is_chat_message())
+            .map(|stored| Envelope {
+                id: Some(stored.id),
+                ..stored.envelope.clone()
+            })
+            .collect();
+
+        let mut pending = self.lock();
+        pending.retain(|envelope| !envelope.is_chat_message() || envelope.id.is_none());
+        pending.extend(unread);
+        pending
+            .make_contiguous()
+            .sort_by_key(|envelope| envelope.received_at);
+        pending
+            .iter()
+            .filter(|envelope| envelope.is_chat_message())
+            .count()
+    }
+
+    /// Number of buffered chat messages per sender.
+    #[must_use]
+    pub fn unread_counts(&self) -> BTreeMap<u8, usize> {
+        let mut counts = BTreeMap::new();
+        let pending = self.lock();
+        let chat_messages = pending.iter().filter(|envelope| envelope.is_chat_message());
+        for sender in chat_messages.filter_map(Envelope::sender) {
+            *counts.entry(sender).or_default() += 1;
+        }
+        counts
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<Envelope>> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock_requests(&self) -> std::sync::MutexGuard<'_, BTreeMap<u8, VecDeque<u64>>> {
+        self.requests.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
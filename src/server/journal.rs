@@ -0,0 +1,168 @@
+//! Write-ahead journal of outbox mutations.
+//!
+//! Every change to the state of a sent message is appended to a JSON-lines
+//! file and flushed to disk before the change takes effect, so the outbox
+//! can be reconstructed after a crash or power loss. A torn last line (the
+//! write that was interrupted) is ignored on recovery.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use super::delivery::OutgoingMessage;
+use super::unix_millis;
+
+/// A journaled outbox mutation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum JournalOp {
+    /// A message was accepted for sending.
+    Enqueue {
+        /// Message id.
+        id: u64,
+        /// The message to send.
+        message: OutgoingMessage,
+        /// Position of the message in its conversation, 0 in records journaled
+        /// before messages were numbered.
+        #[serde(default)]
+        seq: u64,
+    },
+    /// No route to the message's server is known; it waits for one.
+    Hold {
+        /// Message id.
+        id: u64,
+    },
+    /// A route to the held message's server was found.
+    Release {
+        /// Message id.
+        id: u64,
+    },
+    /// The message is about to be handed to the backend.
+    Attempt {
+        /// Message id.
+        id: u64,
+    },
+    /// The send failed and the message will be sent again.
+    Retry {
+        /// Message id.
+        id: u64,
+        /// Why the send failed.
+        reason: String,
+        /// The server's answer, if it rejected the message.
+        reply: Option<Value>,
+    },
+    /// The server acknowledged the message.
+    Ack {
+        /// Message id.
+        id: u64,
+        /// The server's answer.
+        reply: Value,
+    },
+    /// The recipient wrote back after the server acknowledged the message.
+    Deliver {
+        /// Message id.
+        id: u64,
+    },
+    /// The recipient sent a read receipt for the message.
+    Read {
+        /// Message id.
+        id: u64,
+    },
+    /// The message could not be delivered.
+    Fail {
+        /// Message id.
+        id: u64,
+        /// The server's answer, if it rejected the message.
+        reply: Option<Value>,
+    },
+}
+
+/// A journal line.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalRecord {
+    /// Increasing record number.
+    pub seq: u64,
+    /// Unix time in milliseconds when the record was written.
+    pub at: u64,
+    /// The mutation.
+    #[serde(flatten)]
+    pub op: JournalOp,
+}
+
+#[derive(Debug)]
+struct Writer {
+    file: File,
+    next_seq: u64,
+}
+
+/// Append-only, fsynced journal file.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    writer: Mutex<Writer>,
+}
+
+impl Journal {
+    /// Opens (or creates) the journal at `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be opened or read.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let next_seq = read_records(path)?
+            .last()
+            .map_or(1, |record| record.seq + 1);
+        Ok(Journal {
+            path: path.to_path_buf(),
+            writer: Mutex::new(Writer { file, next_seq }),
+        })
+    }
+
+    /// Appends `op` and waits until it is on disk.
+    ///
+    /// # Errors
+    /// Returns an error if the record can't be written or synced.
+    pub fn append(&self, op: JournalOp) -> io::Result<()> {
+        let mut writer = self.lock();
+        let record = JournalRecord {
+            seq: writer.next_seq,
+            at: unix_millis(),
+            op,
+        };
+        let mut line = serde_json::to_vec(&record).map_err(io::Error::other)?;
+        line.push(b'\n');
+        writer.file.write_all(&line)?;
+        writer.file.sync_data()?;
+        writer.next_seq += 1;
+        Ok(())
+    }
+
+    /// Returns every record in the journal, oldest first.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read.
+    pub fn records(&self) -> io::Result<Vec<JournalRecord>> {
+        // Hold the writer so no record is half written while reading
+        let _writer = self.lock();
+        read_records(&self.path)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Writer> {
+        self.writer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn read_records(path: &Path) -> io::Result<Vec<JournalRecord>> {
+    let mut records = vec![];
+    for line in BufReader::new(File::open(path)?).lines() {
+        match serde_json::from_str(&line?) {
+            Ok(record) => records.push(record),
+            // Interrupted write; nothing after it was acknowledged
+            Err(_) => break,
+        }
+    }
+    Ok(records)
+}
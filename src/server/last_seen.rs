@@ -0,0 +1,91 @@
+//! When each peer was last seen.
+//!
+//! A peer is seen when it writes to this node or shows up in the client list
+//! of a server. The [`Directory`](super::directory::Directory) keeps the
+//! [`LastSeen`] times and passes every batch of received messages to
+//! [`LastSeen::observe`]. The times are stored as JSON in the node's data
+//! directory, so `/contacts` can tell how long ago a contact was around even
+//! after a restart.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use super::inbox::Envelope;
+use super::write_json_atomic;
+
+/// Unix time in milliseconds each peer was last seen, together with the file it
+/// is persisted in.
+#[derive(Debug)]
+pub struct LastSeen {
+    path: PathBuf,
+    peers: Mutex<BTreeMap<u8, u64>>,
+}
+
+impl LastSeen {
+    /// Loads the times from `path`, starting empty if the file doesn't exist.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read or parsed.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let peers = match fs::read(path) {
+            Ok(json) => serde_json::from_slice(&json).map_err(io::Error::other)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(LastSeen {
+            path: path.to_path_buf(),
+            peers: Mutex::new(peers),
+        })
+    }
+
+    /// Records the authors of the chat messages and the clients listed in the
+    /// `ClientList` replies among `envelopes` as seen when they arrived, and
+    /// persists the times if any changed.
+    pub fn observe(&self, envelopes: &[Envelope]) {
+        let mut peers = self.lock();
+        let mut changed = false;
+        for envelope in envelopes {
+            let seen = if envelope.is_chat_message() {
+                envelope.sender().into_iter().collect()
+            } else {
+                envelope.client_list().unwrap_or_default()
+            };
+            for peer in seen {
+                let last = peers.entry(peer).or_default();
+                if *last < envelope.received_at {
+                    *last = envelope.received_at;
+                    changed = true;
+                }
+            }
+        }
+        if changed && let Err(e) = write_json_atomic(&self.path, &*peers) {
+            tracing::warn!("Failed to save when peers were last seen: {e}");
+        }
+    }
+
+    /// Unix time in milliseconds when `peer` was last seen, if ever.
+    #[must_use]
+    pub fn get(&self, peer: u8) -> Option<u64> {
+        self.lock().get(&peer).copied()
+    }
+
+    /// Moves what is known about peer `old` to peer `new`, keeping the later time.
+    pub fn merge_peer(&self, old: u8, new: u8) {
+        let mut peers = self.lock();
+        let Some(seen) = peers.remove(&old) else {
+            return;
+        };
+        let last = peers.entry(new).or_default();
+        *last = (*last).max(seen);
+        if let Err(e) = write_json_atomic(&self.path, &*peers) {
+            tracing::warn!("Failed to save when peers were last seen: {e}");
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<u8, u64>> {
+        self.peers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,136 @@
+//! Limits on how many requests to a route may be handled at the same time.
+//!
+//! All handlers talk to the same backend command loop, so a UI firing many
+//! parallel requests can swamp it. Each [`RouteLimit`] caps the requests in
+//! flight on one route; requests beyond the cap either wait for a free slot
+//! or are turned away with 429 (Too Many Requests), see [`Overflow`]. The
+//! limits can be replaced while the server runs; requests holding a slot
+//! keep it until they are done.
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ResourceDef, ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::rt::time::timeout;
+use actix_web::{Error, HttpResponse, web};
+use serde::Serialize;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+use super::routed_path;
+
+/// What happens to a request arriving while its route is at its limit.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Overflow {
+    /// Answer with 429 right away.
+    Reject,
+    /// Wait up to the given time for a free slot, then answer with 429.
+    Queue(Duration),
+}
+
+/// Concurrency limit of a single route.
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteLimit {
+    /// Route pattern as declared on the handler, e.g. `/send/{id}/status`.
+    pub route: String,
+    /// Maximum number of requests handled at the same time.
+    pub max_in_flight: usize,
+    /// What to do with requests beyond `max_in_flight`.
+    pub overflow: Overflow,
+}
+
+impl RouteLimit {
+    /// Limits `route` to `max_in_flight` concurrent requests.
+    #[must_use]
+    pub fn new(route: &str, max_in_flight: usize, overflow: Overflow) -> Self {
+        RouteLimit {
+            route: route.to_string(),
+            max_in_flight,
+            overflow,
+        }
+    }
+}
+
+type Routes = Vec<(ResourceDef, Arc<Semaphore>, Overflow)>;
+
+/// The configured limits together with their free slots.
+#[derive(Debug)]
+pub struct ConcurrencyLimits {
+    routes: RwLock<Routes>,
+}
+
+impl ConcurrencyLimits {
+    /// Creates empty slots for every limit in `limits`.
+    #[must_use]
+    pub fn new(limits: &[RouteLimit]) -> Self {
+        ConcurrencyLimits {
+            routes: RwLock::new(routes(limits)),
+        }
+    }
+
+    /// Switches to `limits`, with empty slots.
+    pub fn replace(&self, limits: &[RouteLimit]) {
+        *self.routes.write().unwrap_or_else(PoisonError::into_inner) = routes(limits);
+    }
+
+    fn find(&self, path: &str) -> Option<(Arc<Semaphore>, Overflow)> {
+        self.routes
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .find(|(resource, _, _)| resource.is_match(path))
+            .map(|(_, slots, overflow)| (slots.clone(), *overflow))
+    }
+}
+
+fn routes(limits: &[RouteLimit]) -> Routes {
+    limits
+        .iter()
+        .map(|limit| {
+            (
+                ResourceDef::new(limit.route.as_str()),
+                Arc::new(Semaphore::new(limit.max_in_flight)),
+                limit.overflow,
+            )
+        })
+        .collect()
+}
+
+/// Middleware holding each request to a limited route until a slot is free.
+///
+/// # Errors
+/// Passes on the errors of the wrapped service.
+pub async fn handle<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let Some((slots, overflow)) = req
+        .app_data::<web::Data<ConcurrencyLimits>>()
+        .and_then(|limits| limits.find(routed_path(&req)))
+    else {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    };
+
+    let permit = match overflow {
+        Overflow::Reject => slots.try_acquire_owned().ok(),
+        Overflow::Queue(wait) => timeout(wait, slots.acquire_owned())
+            .await
+            .ok()
+            .and_then(Result::ok),
+    };
+    let Some(_permit) = permit else {
+        let response = HttpResponse::TooManyRequests()
+            .insert_header((header::RETRY_AFTER, 1))
+            .json("Too many requests to this route, try again later");
+        return Ok(req.into_response(response).map_into_right_body());
+    };
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
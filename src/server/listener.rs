@@ -0,0 +1,104 @@
+//! The socket the API is served on.
+//!
+//! By default the server listens on TCP. With `ServerOptions::unix_socket`
+//! it listens on a Unix domain socket instead, so many nodes on one host
+//! never compete for ports; a `{node_id}` in the path is replaced with the
+//! node's ID, letting nodes share one configuration. A socket file left
+//! behind by a previous run is replaced. The socket is bound before the rest
+//! of the server starts, so the diagnostics can tell the actual address. If it
+//! can't be, the [`io::Error`] carries a [`BindError`], which the library API
+//! reports as `Error::Bind`.
+
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+
+use super::ServerOptions;
+
+/// A bound socket, not yet accepting connections.
+#[derive(Debug)]
+pub enum Listener {
+    /// TCP socket on `ServerOptions::bind_address`.
+    Tcp(TcpListener, SocketAddr),
+    /// Unix domain socket at the given path.
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+/// A socket that couldn't be bound, carried by the [`io::Error`] of [`Listener::bind`].
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to bind {address}: {source}")]
+pub struct BindError {
+    /// The address, or `unix:` and the path of the socket.
+    pub address: String,
+    /// Why it couldn't be bound.
+    pub source: io::Error,
+}
+
+impl BindError {
+    fn wrap(address: String) -> impl FnOnce(io::Error) -> io::Error {
+        move |source| io::Error::new(source.kind(), BindError { address, source })
+    }
+}
+
+impl Listener {
+    /// Binds the socket of node `node_id`: the Unix socket if configured,
+    /// else TCP `port` on `ServerOptions::bind_address`.
+    ///
+    /// # Errors
+    /// Returns an error carrying a [`BindError`] if the socket can't be bound, or
+    /// if a Unix socket is configured on a system without them.
+    pub fn bind(options: &ServerOptions, node_id: u8, port: u16) -> io::Result<Self> {
+        let Some(path) = options.unix_socket_path(node_id) else {
+            let requested = SocketAddr::new(options.bind_address, port);
+            let bound = TcpListener::bind(requested).and_then(|listener| {
+                let address = listener.local_addr()?;
+                Ok(Listener::Tcp(listener, address))
+            });
+            return bound.map_err(BindError::wrap(requested.to_string()));
+        };
+        bind_unix(&path).map_err(BindError::wrap(format!("unix:{}", path.display())))
+    }
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> io::Result<Listener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    Ok(Listener::Unix(listener, path.to_path_buf()))
+}
+
+#[cfg(not(unix))]
+fn bind_unix(path: &Path) -> io::Result<Listener> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "Unix domain sockets aren't supported here: {}",
+            path.display()
+        ),
+    ))
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listener::Tcp(_, address) => write!(f, "{address}"),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
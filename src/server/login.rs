@@ -0,0 +1,35 @@
+//! Built-in login page served at `/login` when an auth provider is configured.
+//!
+//! The form posts the user name and password to `/login` as a regular form
+//! submission; on success the browser follows the redirect to `/` with the
+//! session cookie set, otherwise the page is shown again with the error.
+//! Browsers opening a protected page without a session are redirected here
+//! by the [`auth`](super::auth) middleware.
+
+use std::fmt::Write;
+
+/// Renders the login page of node `node_id`, telling about `error` if any.
+#[must_use]
+pub fn page(node_id: u8, error: Option<&str>) -> String {
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Log in to node {node_id}</title>\n</head>\n<body>\n\
+         <h1>Node {node_id}</h1>\n"
+    );
+    if let Some(error) = error {
+        let _ = writeln!(html, "<p role=\"alert\">{error}</p>");
+    }
+    html.push_str(FORM);
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Form posting the credentials to `/login`.
+const FORM: &str = r#"<form method="post" action="/login">
+  <label>User <input name="user" autocomplete="username" required autofocus></label>
+  <label>Password <input name="password" type="password" autocomplete="current-password" required></label>
+  <button>Log in</button>
+</form>
+"#;
@@ -0,0 +1,130 @@
+//! On-disk cache of media fetched from media servers.
+//!
+//! Fetching media means sending requests through the drone network, so every
+//! media is fetched once and kept in the `media` subdirectory of the node's
+//! data directory. `/media/{server_id}/{media_id}` serves it from there,
+//! which also gives the browser `Range` requests for seeking in large media.
+//! The cache counts against the media quota of the [`DiskQuotas`]. Media are
+//! kept in a [`BlobStore`], so the same content under several IDs or from
+//! several servers is stored once.
+
+use serde_json::Value;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::blobs::{BlobStore, StorageSavings};
+use super::content;
+use super::dispatcher::DispatchError;
+use super::inbox::Envelope;
+use super::quota::{DiskQuotas, QuotaExceeded, StorageArea};
+
+/// Reasons a media can't be provided.
+#[derive(Debug)]
+pub enum FetchError {
+    /// The request failed or timed out.
+    Backend(DispatchError),
+    /// The server answered with an error or something else than media.
+    Rejected(Value),
+    /// The media could not be written to the cache.
+    Storage(io::Error),
+    /// The media doesn't fit into the media quota.
+    Quota(QuotaExceeded),
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::Backend(e) => write!(f, "{e}"),
+            FetchError::Rejected(_) => write!(f, "Server did not send the media"),
+            FetchError::Storage(e) => write!(f, "Failed to cache the media: {e}"),
+            FetchError::Quota(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Media fetched so far, stored once per content.
+#[derive(Debug)]
+pub struct MediaCache {
+    blobs: BlobStore,
+    quotas: Arc<DiskQuotas>,
+}
+
+impl MediaCache {
+    /// Opens the cache in `dir`, creating the directory if needed, with its
+    /// index next to it. New media are only stored if they fit into the media
+    /// quota of `quotas`.
+    ///
+    /// # Errors
+    /// Returns an error if the directory can't be created or the index can't be read.
+    pub fn open(dir: &Path, quotas: Arc<DiskQuotas>) -> io::Result<Self> {
+        let blobs = BlobStore::open(dir, Some(dir.with_extension("json")))?;
+        // Media cached before deduplication are named after their ID
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if let Some(name) = path.file_name().and_then(|name| name.to_str())
+                && name.contains('-')
+                && !name.ends_with(".tmp")
+            {
+                blobs.put_file(name, &path)?;
+            }
+        }
+        Ok(MediaCache { blobs, quotas })
+    }
+
+    /// Path of media `media_id` of server `server_id`, if it is cached.
+    #[must_use]
+    pub fn cached(&self, server_id: u8, media_id: u64) -> Option<PathBuf> {
+        self.blobs.get(&reference(server_id, media_id))
+    }
+
+    /// How much space storing every content once saves.
+    #[must_use]
+    pub fn savings(&self) -> StorageSavings {
+        self.blobs.savings()
+    }
+
+    /// Path of media `media_id` of server `server_id`, calling `fetch` to
+    /// request it from the server if it isn't cached yet.
+    ///
+    /// # Errors
+    /// Returns a [`FetchError`] if the media can't be fetched or stored.
+    pub fn get_or_fetch(
+        &self,
+        server_id: u8,
+        media_id: u64,
+        fetch: impl FnOnce() -> Result<Envelope, DispatchError>,
+    ) -> Result<PathBuf, FetchError> {
+        if let Some(path) = self.cached(server_id, media_id) {
+            return Ok(path);
+        }
+        let reply = fetch().map_err(FetchError::Backend)?;
+        let Some(bytes) = content::media(&reply).filter(|_| !reply.is_error()) else {
+            return Err(FetchError::Rejected(reply.payload));
+        };
+
+        self.quotas
+            .reserve(StorageArea::Media, bytes.len() as u64)
+            .map_err(FetchError::Quota)?;
+        self.blobs
+            .put(&reference(server_id, media_id), &bytes)
+            .map_err(FetchError::Storage)
+    }
+
+    /// MIME type of the cached media at `path`, guessed from its first bytes.
+    #[must_use]
+    pub fn content_type(path: &Path) -> &'static str {
+        let mut head = [0; 16];
+        let len = fs::File::open(path)
+            .and_then(|mut file| file.read(&mut head))
+            .unwrap_or(0);
+        content::mime_type(&head[..len])
+    }
+}
+
+/// Reference of media `media_id` of server `server_id` in the blob store.
+fn reference(server_id: u8, media_id: u64) -> String {
+    format!("{server_id}-{media_id}")
+}
@@ -0,0 +1,181 @@
+//! Shared handling of `OPTIONS` and `HEAD` requests for every route.
+//!
+//! Handlers only answer the method they are declared with, so on their own
+//! `OPTIONS` and `HEAD` requests end up as 404s. The [`handle`] middleware
+//! fills the gap from [`ROUTES`]:
+//! - `OPTIONS` is answered with the allowed methods in an `Allow` header,
+//!   plus the `Access-Control-Allow-*` headers of a CORS preflight when the
+//!   request is one. Which origins may make cross-origin requests is
+//!   decided by the [`cors`](super::cors) middleware.
+//! - `HEAD` runs the route's `GET` handler; the HTTP layer drops the body but
+//!   keeps the headers, including `Content-Length`.
+//! - Any other method a known path doesn't support gets 405 (Method Not Allowed).
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ResourceDef, ServiceRequest, ServiceResponse};
+use actix_web::http::{Method, header};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpResponse};
+use std::sync::LazyLock;
+
+use super::routed_path;
+
+/// Every route and the method it is declared with. Keep in sync with the
+/// handlers registered in [`start_server`](super::start_server).
+pub(crate) const ROUTES: &[(&str, &str)] = &[
+    ("/", "GET"),
+    ("/static/{path:.*}", "GET"),
+    ("/admin/reload_assets", "POST"),
+    ("/admin/storage", "GET"),
+    ("/admin/config/reload", "POST"),
+    ("/admin/backend/restart", "POST"),
+    ("/flood", "GET"),
+    ("/flood/start", "POST"),
+    ("/flood/result/{id}", "GET"),
+    ("/servers", "GET"),
+    ("/topology", "GET"),
+    ("/register", "POST"),
+    ("/registrations", "GET"),
+    ("/send", "POST"),
+    ("/send/broadcast", "POST"),
+    ("/send/{id}/status", "GET"),
+    ("/status/{session_id}/timeline", "GET"),
+    ("/deadletter", "GET"),
+    ("/deadletter/{id}/retry", "POST"),
+    ("/outbox", "GET"),
+    ("/outbox/{id}", "GET"),
+    ("/clients", "POST"),
+    ("/typing", "POST"),
+    ("/presence/{server_id}", "GET"),
+    ("/content/{server_id}/files", "GET"),
+    ("/content/{server_id}/files/{file_id}", "GET"),
+    ("/content/{server_id}/upload", "POST"),
+    ("/content/uploads/{id}", "GET"),
+    ("/media/{server_id}/{media_id}", "GET"),
+    ("/messages", "GET"),
+    ("/messages/ack", "POST"),
+    ("/messages/count", "GET"),
+    ("/messages/longpoll", "GET"),
+    ("/messages/history", "GET"),
+    ("/inbox/priority", "GET"),
+    ("/inbox/priority/rules", "GET"),
+    ("/inbox/priority/rules", "POST"),
+    ("/inbox/priority/rules/{id}", "DELETE"),
+    ("/messages/{id}", "DELETE"),
+    ("/conversations", "GET"),
+    ("/conversations/{peer}", "DELETE"),
+    ("/conversations/{peer}/messages", "GET"),
+    ("/conversations/{peer}/key-requirement", "PUT"),
+    ("/contacts", "GET"),
+    ("/contacts/{peer}", "GET"),
+    ("/contacts/{peer}", "PUT"),
+    ("/contacts/{peer}", "DELETE"),
+    ("/contacts/{peer}/key", "PUT"),
+    ("/conversations/{peer}/stats", "GET"),
+    ("/peers/{id}/clock", "GET"),
+    ("/stats/drones", "GET"),
+    ("/events", "GET"),
+    ("/metrics", "GET"),
+    ("/stats/backend", "GET"),
+    ("/stats/disk", "GET"),
+    ("/stats/timeseries", "GET"),
+    ("/stats/export.csv", "GET"),
+    ("/export", "GET"),
+    ("/admin/reindex", "POST"),
+    ("/admin/journal", "GET"),
+    ("/trash", "GET"),
+    ("/trash/{id}/restore", "POST"),
+    ("/identity", "GET"),
+    ("/identity", "PUT"),
+    ("/contacts/{old}/merge/{new}", "POST"),
+    ("/config.json", "GET"),
+    ("/api/changes", "GET"),
+    ("/debug/env", "GET"),
+    ("/health", "GET"),
+    ("/ready", "GET"),
+    ("/status", "GET"),
+    ("/login", "GET"),
+    ("/login", "POST"),
+    ("/logout", "POST"),
+    ("/public/topology", "GET"),
+    ("/public/stats", "GET"),
+    ("/settings/away", "GET"),
+    ("/settings/away", "PUT"),
+];
+
+/// How long browsers may cache a preflight answer, in seconds.
+const PREFLIGHT_MAX_AGE_SECS: u32 = 600;
+
+static RESOURCES: LazyLock<Vec<(ResourceDef, &'static str)>> = LazyLock::new(|| {
+    ROUTES
+        .iter()
+        .map(|(pattern, method)| (ResourceDef::new(*pattern), *method))
+        .collect()
+});
+
+/// Methods allowed on `path`, or nothing if no route matches it.
+fn allowed_methods(path: &str) -> Vec<&'static str> {
+    let mut methods: Vec<_> = RESOURCES
+        .iter()
+        .filter(|(resource, _)| resource.is_match(path))
+        .map(|(_, method)| *method)
+        .collect();
+    if methods.is_empty() {
+        return methods;
+    }
+    if methods.contains(&"GET") {
+        methods.push("HEAD");
+    }
+    methods.push("OPTIONS");
+    methods.sort_unstable();
+    methods.dedup();
+    methods
+}
+
+/// Middleware answering `OPTIONS`, serving `HEAD` and rejecting unsupported methods.
+///
+/// # Errors
+/// Passes on the errors of the wrapped service.
+pub async fn handle<B: MessageBody>(
+    mut req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let allowed = allowed_methods(routed_path(&req));
+    if allowed.is_empty() {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    }
+    let allow = allowed.join(", ");
+
+    if req.method() == Method::OPTIONS {
+        let mut response = HttpResponse::NoContent();
+        response.insert_header((header::ALLOW, allow.clone()));
+        if req
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+        {
+            response.insert_header((header::ACCESS_CONTROL_ALLOW_METHODS, allow));
+            if let Some(requested) = req.headers().get(header::ACCESS_CONTROL_REQUEST_HEADERS) {
+                response.insert_header((header::ACCESS_CONTROL_ALLOW_HEADERS, requested.clone()));
+            }
+            response.insert_header((header::ACCESS_CONTROL_MAX_AGE, PREFLIGHT_MAX_AGE_SECS));
+        }
+        return Ok(req.into_response(response.finish()).map_into_right_body());
+    }
+
+    if !allowed.contains(&req.method().as_str()) {
+        let response = HttpResponse::MethodNotAllowed()
+            .insert_header((header::ALLOW, allow))
+            .json("Method not allowed");
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+    if req.method() == Method::HEAD {
+        req.head_mut().method = Method::GET;
+    }
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
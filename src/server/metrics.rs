@@ -0,0 +1,270 @@
+//! Counters of the node's activity, served in Prometheus text format.
+//!
+//! The [`Dispatcher`](super::dispatcher::Dispatcher) counts what goes to and
+//! comes from the backend, the [`handle`] middleware counts HTTP requests per
+//! route and measures their latency. The lengths of the channels to the
+//! backend are reported as gauges, so a backend falling behind shows up as
+//! messages piling up. `/metrics` renders everything for a Prometheus scraper.
+
+use actix_web::Error;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::web;
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the HTTP latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Requests to one route with one method and status.
+#[derive(Debug, Default, Clone)]
+struct HttpStats {
+    count: u64,
+    duration_sum: f64,
+    // Requests per latency bucket, not cumulative
+    buckets: [u64; LATENCY_BUCKETS.len()],
+}
+
+/// A channel whose length is reported as a gauge.
+struct ChannelGauge {
+    name: &'static str,
+    len: Box<dyn Fn() -> usize + Send + Sync>,
+}
+
+impl fmt::Debug for ChannelGauge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChannelGauge")
+            .field("name", &self.name)
+            .field("len", &(self.len)())
+            .finish()
+    }
+}
+
+/// Counters of the node's activity.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    commands_sent: AtomicU64,
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+    floods: AtomicU64,
+    backend_errors: AtomicU64,
+    ingested: AtomicU64,
+    ingestion_lag_ms_sum: AtomicU64,
+    ingestion_lag_ms_last: AtomicU64,
+    // Keyed by (route pattern, method, status)
+    http: Mutex<BTreeMap<(String, String, u16), HttpStats>>,
+    channels: Mutex<Vec<ChannelGauge>>,
+}
+
+impl Metrics {
+    /// Counts a command sent to the backend.
+    pub fn record_command(&self) {
+        self.commands_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts chat messages handed to the backend.
+    pub fn record_messages_sent(&self, count: usize) {
+        self.messages_sent
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    /// Counts messages received from the backend.
+    pub fn record_messages_received(&self, count: usize) {
+        self.messages_received
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    /// Counts a flood.
+    pub fn record_flood(&self) {
+        self.floods.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a backend request that failed or timed out.
+    pub fn record_backend_error(&self) {
+        self.backend_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that a message was stored `lag_ms` milliseconds after the backend delivered it.
+    pub fn record_ingestion(&self, lag_ms: u64) {
+        self.ingested.fetch_add(1, Ordering::Relaxed);
+        self.ingestion_lag_ms_sum
+            .fetch_add(lag_ms, Ordering::Relaxed);
+        self.ingestion_lag_ms_last.store(lag_ms, Ordering::Relaxed);
+    }
+
+    /// Reports the length of channel `name`, as returned by `len`, as a gauge.
+    /// A channel watched twice under the same name replaces the first one.
+    pub fn watch_channel(
+        &self,
+        name: &'static str,
+        len: impl Fn() -> usize + Send + Sync + 'static,
+    ) {
+        let mut channels = self.channels.lock().unwrap_or_else(PoisonError::into_inner);
+        channels.retain(|channel| channel.name != name);
+        channels.push(ChannelGauge {
+            name,
+            len: Box::new(len),
+        });
+    }
+
+    /// Number of items waiting in every watched channel, by name.
+    #[must_use]
+    pub fn channel_depths(&self) -> Vec<(&'static str, usize)> {
+        self.channels
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|channel| (channel.name, (channel.len)()))
+            .collect()
+    }
+
+    /// Counts an HTTP request to `route` and its latency.
+    pub fn record_http(&self, route: &str, method: &str, status: u16, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let mut http = self.lock();
+        let stats = http
+            .entry((route.to_string(), method.to_string(), status))
+            .or_default();
+        stats.count += 1;
+        stats.duration_sum += seconds;
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|le| seconds <= *le) {
+            stats.buckets[bucket] += 1;
+        }
+    }
+
+    /// Renders every counter in Prometheus text format.
+    #[must_use]
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, help, counter) in [
+            (
+                "frontend_commands_sent_total",
+                "Commands sent to the backend.",
+                &self.commands_sent,
+            ),
+            (
+                "frontend_messages_sent_total",
+                "Chat messages handed to the backend.",
+                &self.messages_sent,
+            ),
+            (
+                "frontend_messages_received_total",
+                "Messages received from the backend.",
+                &self.messages_received,
+            ),
+            ("frontend_floods_total", "Floods started.", &self.floods),
+            (
+                "frontend_backend_errors_total",
+                "Backend requests that failed or timed out.",
+                &self.backend_errors,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} counter");
+            let _ = writeln!(out, "{name} {}", counter.load(Ordering::Relaxed));
+        }
+
+        out.push_str(
+            "# HELP frontend_channel_depth Items waiting in a channel to or from the backend.\n",
+        );
+        out.push_str("# TYPE frontend_channel_depth gauge\n");
+        for (channel, depth) in self.channel_depths() {
+            let _ = writeln!(
+                out,
+                "frontend_channel_depth{{channel=\"{channel}\"}} {depth}"
+            );
+        }
+
+        let http = self.lock().clone();
+        out.push_str("# HELP frontend_http_requests_total HTTP requests handled.\n");
+        out.push_str("# TYPE frontend_http_requests_total counter\n");
+        for ((route, method, status), stats) in &http {
+            let _ = writeln!(
+                out,
+                "frontend_http_requests_total{{route=\"{route}\",method=\"{method}\",status=\"{status}\"}} {}",
+                stats.count
+            );
+        }
+
+        // The histogram doesn't tell statuses apart
+        let mut by_route: BTreeMap<(&str, &str), HttpStats> = BTreeMap::new();
+        for ((route, method, _), stats) in &http {
+            let merged = by_route.entry((route, method)).or_default();
+            merged.count += stats.count;
+            merged.duration_sum += stats.duration_sum;
+            for (merged, bucket) in merged.buckets.iter_mut().zip(stats.buckets) {
+                *merged += bucket;
+            }
+        }
+        out.push_str(
+            "# HELP frontend_http_request_duration_seconds Time taken to handle HTTP requests.\n",
+        );
+        out.push_str("# TYPE frontend_http_request_duration_seconds histogram\n");
+        for ((route, method), stats) in by_route {
+            let labels = format!("route=\"{route}\",method=\"{method}\"");
+            let mut cumulative = 0;
+            for (le, bucket) in LATENCY_BUCKETS.iter().zip(stats.buckets) {
+                cumulative += bucket;
+                let _ = writeln!(
+                    out,
+                    "frontend_http_request_duration_seconds_bucket{{{labels},le=\"{le}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "frontend_http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}",
+                stats.count
+            );
+            let _ = writeln!(
+                out,
+                "frontend_http_request_duration_seconds_sum{{{labels}}} {}",
+                stats.duration_sum
+            );
+            let _ = writeln!(
+                out,
+                "frontend_http_request_duration_seconds_count{{{labels}}} {}",
+                stats.count
+            );
+        }
+        out
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<(String, String, u16), HttpStats>> {
+        self.http.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Middleware counting every request with its latency, labeled with the
+/// matched route pattern so path parameters don't create new series.
+///
+/// # Errors
+/// Passes on the errors of the wrapped service.
+pub async fn handle<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<B>, Error> {
+    let started = Instant::now();
+    let metrics = req.app_data::<web::Data<Metrics>>().cloned();
+    let method = req.method().to_string();
+    let response = next.call(req).await?;
+
+    if let Some(metrics) = metrics {
+        let route = response
+            .request()
+            .match_pattern()
+            .unwrap_or_else(|| "unmatched".to_string());
+        metrics.record_http(
+            &route,
+            &method,
+            response.status().as_u16(),
+            started.elapsed(),
+        );
+    }
+    Ok(response)
+}
@@ -1,63 +1,1048 @@
+/// Public module `access` logging every request.
+pub mod access;
+/// Public module `assets` serving a reloadable snapshot of the static files.
+pub mod assets;
+/// Public module `auth` deciding who may use the API.
+pub mod auth;
+/// Public module `away` auto-replying while the user is away.
+pub mod away;
+/// Public module `blobs` storing files once per content.
+pub mod blobs;
+/// Public module `breaker` failing fast while the backend keeps failing.
+pub mod breaker;
+/// Public module `changes` describing how the API evolved.
+pub mod changes;
+/// Public module `chat_error` mapping error replies of servers to HTTP statuses.
+pub mod chat_error;
+/// Public module `clock` estimating the clock offsets of peers.
+pub mod clock;
+/// Public module `config` reading the server settings from a TOML file.
+pub mod config;
+/// Public module `content` speaking the content subset of the protocol.
+pub mod content;
+/// Public module `cookies` building cookies that respect the proxy setup.
+pub mod cookies;
+/// Public module `cors` allowing configured origins to call the API.
+pub mod cors;
+/// Public module `deadletter` keeping failed messages for sending them again.
+pub mod deadletter;
+/// Public module `dedup` dropping messages the backend delivers twice.
+pub mod dedup;
+/// Public module `delivery` tracking whether sent messages were acknowledged.
+pub mod delivery;
+/// Public module `demo` generating a network for running without a backend.
+pub mod demo;
+/// Public module `diagnostics` describing the environment at startup.
+pub mod diagnostics;
+/// Public module `directory` keeping the known servers and their clients.
+pub mod directory;
+/// Public module `dispatcher` routing backend replies to their requests.
+pub mod dispatcher;
 /// Public module `endpoints` containing HTTP handlers for various API routes.
 pub mod endpoints;
+/// Public module `events` logging notable events for the UI.
+pub mod events;
+/// Public module `export` rendering recorded data and the chat history for export.
+pub mod export;
+/// Public module `fallback` rendering the page served when the web UI is missing.
+pub mod fallback;
+/// Public module `flood` discovering the network by flooding.
+pub mod flood;
+/// Public module `history` recording chat traffic per conversation.
+pub mod history;
+/// Public module `idempotency` answering repeated requests with the same key once.
+pub mod idempotency;
+/// Public module `identity` persisting the node's keys, alias and contacts.
+pub mod identity;
+/// Public module `inbox` buffering messages received from the backend.
+pub mod inbox;
+/// Public module `journal` making outbox changes crash-safe.
+pub mod journal;
+/// Public module `last_seen` remembering when each peer was last seen.
+pub mod last_seen;
+/// Public module `limits` capping concurrent requests per route.
+pub mod limits;
+/// Public module `listener` binding the TCP or Unix socket the API is served on.
+pub mod listener;
+/// Public module `login` rendering the built-in login page.
+pub mod login;
+/// Public module `media` caching media fetched from media servers.
+pub mod media;
+/// Public module `methods` handling `OPTIONS` and `HEAD` for every route.
+pub mod methods;
+/// Public module `metrics` counting activity for Prometheus.
+pub mod metrics;
+/// Public module `outbox` sending messages in the background and retrying them.
+pub mod outbox;
+/// Public module `presence` keeping the client lists of servers fresh.
+pub mod presence;
+/// Public module `priority` marking incoming messages as important.
+pub mod priority;
+/// Public module `probe` measuring how responsive the backend is.
+pub mod probe;
+/// Public module `quota` limiting the disk space taken by the node's data.
+pub mod quota;
+/// Public module `receipts` exchanging read receipts with peers.
+pub mod receipts;
+/// Public module `registration` registering with communication servers.
+pub mod registration;
+/// Public module `report` summarizing a run on shutdown.
+pub mod report;
+/// Public module `request_id` giving every request an ID.
+pub mod request_id;
+/// Public module `servers` probing the discovered servers for their type.
+pub mod servers;
+/// Public module `session` allocating session IDs for outgoing messages.
+pub mod session;
+/// Public module `settings` changing settings while the node runs.
+pub mod settings;
+/// Public module `stats` tracking the health of remote nodes.
+pub mod stats;
+/// Public module `storage` persisting every received message in SQLite.
+pub mod storage;
+/// Public module `timeline` reconstructing the lifecycle of sent messages.
+pub mod timeline;
+/// Public module `timeseries` keeping downsampled metric history.
+pub mod timeseries;
+/// Public module `tls` loading the certificate to serve HTTPS with.
+pub mod tls;
+/// Public module `topology` describing the known network.
+pub mod topology;
+/// Public module `trace` running every request in a tracing span.
+pub mod trace;
+/// Public module `typing` relaying typing notifications between peers.
+pub mod typing;
+/// Public module `upload` uploading files to content servers.
+pub mod upload;
+/// Public module `validation` checking request payloads before they reach the backend.
+pub mod validation;
 
 use actix_web::App;
 use actix_web::HttpServer;
+use actix_web::dev::ServerHandle;
+use actix_web::middleware::{Condition, from_fn};
 use actix_web::web;
 use ap_client_backend_v2::backend::Command;
 use ap_client_backend_v2::backend::ListOfDiscoveredEdgeNodes;
 use ap_client_backend_v2::backend::UnreadMessagesFromServer;
-use crossbeam_channel::{Receiver, Sender};
+use assets::Assets;
+use auth::{AuthProvider, Sessions};
+use away::AwayMode;
+use blobs::BlobStore;
+use breaker::CircuitBreaker;
+use clock::PeerClocks;
+use cors::CorsOptions;
+use crossbeam_channel::{Receiver, Sender, bounded, unbounded};
+use deadletter::DeadLetterQueue;
+use dedup::Deduplicator;
+use delivery::DeliveryTracker;
+use demo::DemoNetwork;
+use diagnostics::{ChannelInfo, Diagnostics, STATIC_DIR};
+use directory::Directory;
+use dispatcher::Dispatcher;
+use endpoints::ack_messages;
+use endpoints::add_priority_rule;
+use endpoints::api_changes;
+use endpoints::backend_status;
+use endpoints::broadcast_message;
+use endpoints::client_config;
 use endpoints::clients;
+use endpoints::contact_presence;
+use endpoints::content_file;
+use endpoints::content_files;
+use endpoints::conversation_messages;
+use endpoints::conversation_stats;
+use endpoints::conversations;
+use endpoints::dead_letters;
+use endpoints::debug_env;
+use endpoints::delete_contact;
+use endpoints::delete_conversation;
+use endpoints::delete_message;
+use endpoints::delete_priority_rule;
+use endpoints::delivery_timeline;
+use endpoints::discovered_servers;
+use endpoints::disk_usage;
+use endpoints::drone_stats;
+use endpoints::export_chat;
 use endpoints::flood_network;
+use endpoints::flood_result;
+use endpoints::get_away;
+use endpoints::get_contact;
+use endpoints::get_events;
+use endpoints::get_identity;
 use endpoints::get_messages;
+use endpoints::health;
 use endpoints::index;
+use endpoints::list_contacts;
+use endpoints::list_priority_rules;
+use endpoints::login;
+use endpoints::login_page;
+use endpoints::logout;
+use endpoints::long_poll_messages;
+use endpoints::merge_contact;
+use endpoints::message_history;
+use endpoints::network_topology;
+use endpoints::node_status;
+use endpoints::outbox_journal;
+use endpoints::outbox_message;
+use endpoints::outbox_progress;
+use endpoints::peer_clock;
+use endpoints::priority_inbox;
+use endpoints::prometheus_metrics;
+use endpoints::public_stats;
+use endpoints::put_contact;
+use endpoints::ready;
 use endpoints::register;
+use endpoints::registrations;
+use endpoints::reindex;
+use endpoints::reload_assets;
+use endpoints::reload_config;
+use endpoints::restart_backend;
+use endpoints::restore_from_trash;
+use endpoints::retry_dead_letter;
 use endpoints::send_message;
+use endpoints::send_status;
+use endpoints::set_away;
+use endpoints::set_contact_key;
+use endpoints::set_key_requirement;
+use endpoints::start_flood;
+use endpoints::static_file;
+use endpoints::stats_export_csv;
+use endpoints::stats_timeseries;
+use endpoints::storage_savings;
+use endpoints::stream_media;
+use endpoints::trash;
+use endpoints::typing_notice;
+use endpoints::unread_count;
+use endpoints::update_identity;
+use endpoints::upload_file;
+use endpoints::upload_progress;
+use events::EventLog;
+use flood::{FloodCache, FloodJobs};
+use history::History;
+use idempotency::IdempotencyKeys;
+use identity::IdentityStore;
+use inbox::Inbox;
+use journal::Journal;
+use last_seen::LastSeen;
+use limits::{ConcurrencyLimits, Overflow, RouteLimit};
+use listener::Listener;
+use media::MediaCache;
+use outbox::RetryPolicy;
+use priority::PriorityRules;
+use probe::BackendProbe;
+use quota::{DiskQuota, DiskQuotas, Eviction, StorageArea};
+use registration::{Recorders, Registrar};
+use report::ShutdownReport;
+use serde::Serialize;
+use servers::ServerDirectory;
+use session::SessionIds;
+use settings::LiveOptions;
+use stats::NetworkStats;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use storage::MessageStore;
+use timeseries::TimeSeries;
+use tls::TlsOptions;
+use typing::TypingRelay;
+use upload::Uploads;
+
+/// Tunable settings of the HTTP server.
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerOptions {
+    /// How long `/register` waits for the target server to confirm the registration.
+    pub register_timeout: Duration,
+    /// Whether to register with every server found by a flood that this node
+    /// isn't registered with yet.
+    pub auto_register: bool,
+    /// Whether to send peers read receipts for their messages once the UI
+    /// acknowledged them via `/messages/ack`, see [`receipts`].
+    pub read_receipts: bool,
+    /// How long each content request (a file list, file, media or upload chunk) and
+    /// each probe of a server's type waits for the server's answer.
+    pub content_timeout: Duration,
+    /// Size of the chunks files are uploaded to content servers in, in bytes.
+    pub upload_chunk_size: usize,
+    /// How long the response to a `/send` or `/register` with an `Idempotency-Key`
+    /// is kept to answer retries with the same key.
+    pub idempotency_ttl: Duration,
+    /// Largest JSON request body accepted, in bytes; larger ones get HTTP 413.
+    pub json_limit: usize,
+    /// Longest chat message accepted by `/send` and `/send/broadcast`, in bytes.
+    pub max_message_len: usize,
+    /// How often the discovered nodes are polled while a flood is running.
+    pub flood_poll_interval: Duration,
+    /// Upper bound on how long a flood may take.
+    pub flood_timeout: Duration,
+    /// How long `/flood` answers with the last result instead of flooding again.
+    pub flood_cache_ttl: Duration,
+    /// How often to flood in the background to keep the cached result fresh, if at all.
+    pub flood_refresh_interval: Option<Duration>,
+    /// How long the type a server reported is cached before it is probed again.
+    pub server_probe_ttl: Duration,
+    /// How many servers are probed for their type at the same time.
+    pub server_probe_parallelism: usize,
+    /// How often unread messages are fetched from the backend in the background.
+    pub ingest_interval: Duration,
+    /// How often the client lists of the servers asked about via `/presence/{server_id}`
+    /// are refreshed, see [`presence`].
+    pub presence_interval: Duration,
+    /// How long a received chat message is remembered to drop it if it is
+    /// delivered again, see [`dedup`].
+    pub dedup_window: Duration,
+    /// How often the backend is pinged to measure its responsiveness.
+    pub backend_ping_interval: Duration,
+    /// How long a ping waits for the backend before it counts as failed.
+    pub backend_ping_timeout: Duration,
+    /// How many backend requests in a row may time out or find the backend gone
+    /// before requests fail right away, see [`breaker`].
+    pub breaker_failures: u32,
+    /// How long requests fail right away once the backend kept failing.
+    pub breaker_cooldown: Duration,
+    /// How often the outbox hands a message to the backend before it gives up and
+    /// files the message as a dead letter, see [`outbox`].
+    pub outbox_max_attempts: u32,
+    /// How long the outbox waits before the first retry; the wait doubles with
+    /// every further attempt.
+    pub outbox_backoff: Duration,
+    /// The longest the outbox waits between two attempts.
+    pub outbox_max_backoff: Duration,
+    /// How long the outbox waits for the server's answer to a sent message
+    /// before it sends the message again.
+    pub outbox_ack_timeout: Duration,
+    /// Directory holding persistent data (messages, outbox journal, identity, media,
+    /// transfers); each node uses its own `<node id>` subdirectory.
+    pub data_dir: PathBuf,
+    /// Directory the web UI is served from.
+    pub static_dir: PathBuf,
+    /// How long deleted messages stay in the trash before they are purged.
+    pub trash_retention: Duration,
+    /// Where to write the JSON shutdown report, if anywhere.
+    pub shutdown_report: Option<PathBuf>,
+    /// How long requests in flight may take to finish once `SIGINT` or `SIGTERM`
+    /// arrives, and how long [`Client::run`](crate::Client::run) then waits for
+    /// the backend thread to exit.
+    pub shutdown_timeout: Duration,
+    /// Port to listen on; without one, [`DEFAULT_BASE_PORT`] plus the node ID.
+    /// With 0, the system picks a free port, announced in the log and to
+    /// `on_listening`. See [`ServerOptions::listen_port`].
+    pub port: Option<u16>,
+    /// Unix domain socket to listen on instead of TCP, if any; `{node_id}` in the
+    /// path is replaced with the node ID. See [`listener`].
+    pub unix_socket: Option<PathBuf>,
+    /// Address to listen on; `0.0.0.0` exposes the node beyond localhost.
+    /// Defaults to the [`BIND_ADDRESS_ENV`] environment variable, else `127.0.0.1`.
+    pub bind_address: IpAddr,
+    /// Certificate and key to serve HTTPS with; plain HTTP without. See [`tls`].
+    pub tls: Option<TlsOptions>,
+    /// Whether clients reach the node through an HTTPS-terminating proxy.
+    /// Cookies are then marked `Secure` and generated URLs use `https`.
+    pub behind_tls_proxy: bool,
+    /// Whether to log every request (method, path, status, duration and remote
+    /// address) under the `access` target.
+    pub access_log: bool,
+    /// Which spans and events to log, in the syntax of `RUST_LOG`, overriding it;
+    /// can be changed while the node runs, see [`settings`].
+    pub log_filter: Option<String>,
+    /// Whether to count HTTP requests and serve `/metrics`.
+    pub metrics: bool,
+    /// Which other origins may call the API from a browser; by default none.
+    pub cors: CorsOptions,
+    /// Whether to serve the read-only viewer below `/public`: the topology and
+    /// aggregate statistics, without login and without message content.
+    pub public_viewer: bool,
+    /// Caps on concurrent requests per route; routes without one are unlimited.
+    pub route_limits: Vec<RouteLimit>,
+    /// Caps on the disk space of the areas of the data directory; areas without
+    /// one are unlimited.
+    pub disk_quotas: Vec<DiskQuota>,
+    /// Who may use the API; without a provider, everyone may. See [`auth`].
+    #[serde(skip)]
+    pub auth_provider: Option<Arc<dyn AuthProvider>>,
+    /// How long a session started via `/login` lasts without being used.
+    pub session_idle_timeout: Duration,
+    /// How long a session started via `/login` lasts at most, however often it is used.
+    pub session_lifetime: Duration,
+    /// The configuration file these settings were read from, if any; reloads
+    /// read it again, see [`settings`].
+    pub config_file: Option<PathBuf>,
+    /// Called once the server is bound, with the address it listens on.
+    #[serde(skip)]
+    pub on_listening: Option<ListenCallback>,
+    /// Called once the server runs, with a handle to stop it.
+    #[serde(skip)]
+    pub on_started: Option<StartedCallback>,
+}
+
+/// Callback learning the address the server listens on, e.g. the port the
+/// system assigned for a `ServerOptions::port` of 0.
+#[derive(Clone)]
+pub struct ListenCallback(pub Arc<dyn Fn(SocketAddr) + Send + Sync>);
+
+impl fmt::Debug for ListenCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ListenCallback")
+    }
+}
+
+/// Callback receiving the handle of the running server, e.g. to stop it from
+/// another thread.
+#[derive(Clone)]
+pub struct StartedCallback(pub Arc<dyn Fn(ServerHandle) + Send + Sync>);
+
+impl fmt::Debug for StartedCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StartedCallback")
+    }
+}
+
+impl ServerOptions {
+    /// Directory holding the persistent data of node `node_id`.
+    #[must_use]
+    pub fn node_data_dir(&self, node_id: u8) -> PathBuf {
+        self.data_dir.join(node_id.to_string())
+    }
+
+    /// Port node `node_id` listens on: `port` if set, else [`DEFAULT_BASE_PORT`]
+    /// plus the node ID.
+    #[must_use]
+    pub fn listen_port(&self, node_id: u8) -> u16 {
+        self.port.unwrap_or(DEFAULT_BASE_PORT + u16::from(node_id))
+    }
+
+    /// Unix domain socket node `node_id` listens on, if it doesn't use TCP.
+    #[must_use]
+    pub fn unix_socket_path(&self, node_id: u8) -> Option<PathBuf> {
+        self.unix_socket.as_ref().map(|path| {
+            PathBuf::from(
+                path.to_string_lossy()
+                    .replace("{node_id}", &node_id.to_string()),
+            )
+        })
+    }
+
+    /// Whether clients reach the node over HTTPS, served by itself or by a proxy.
+    #[must_use]
+    pub fn serves_https(&self) -> bool {
+        self.tls.is_some() || self.behind_tls_proxy
+    }
+}
+
+/// Bytes in a mebibyte.
+const MIB: u64 = 1024 * 1024;
+
+/// Port of node 0 when no `ServerOptions::port` is set; node `n` uses this plus `n`.
+pub const DEFAULT_BASE_PORT: u16 = 8000;
+
+/// Environment variable holding the default `ServerOptions::bind_address`.
+pub const BIND_ADDRESS_ENV: &str = "FRONTEND_BIND_ADDRESS";
+
+/// The address in [`BIND_ADDRESS_ENV`], or `127.0.0.1` if it is unset or invalid.
+fn default_bind_address() -> IpAddr {
+    let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    let Ok(address) = std::env::var(BIND_ADDRESS_ENV) else {
+        return localhost;
+    };
+    address.trim().parse().unwrap_or_else(|e| {
+        tracing::warn!("Ignoring {BIND_ADDRESS_ENV}={address}: {e}");
+        localhost
+    })
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        ServerOptions {
+            register_timeout: Duration::from_secs(5),
+            auto_register: false,
+            read_receipts: false,
+            content_timeout: Duration::from_secs(5),
+            upload_chunk_size: 16 * 1024,
+            idempotency_ttl: Duration::from_secs(60 * 60),
+            json_limit: 64 * 1024,
+            max_message_len: 4 * 1024,
+            flood_poll_interval: Duration::from_millis(200),
+            flood_timeout: Duration::from_secs(5),
+            flood_cache_ttl: Duration::from_secs(30),
+            flood_refresh_interval: None,
+            server_probe_ttl: Duration::from_secs(300),
+            server_probe_parallelism: 4,
+            ingest_interval: Duration::from_millis(200),
+            dedup_window: Duration::from_secs(60),
+            presence_interval: Duration::from_secs(30),
+            backend_ping_interval: Duration::from_secs(5),
+            backend_ping_timeout: Duration::from_secs(2),
+            breaker_failures: 5,
+            breaker_cooldown: Duration::from_secs(10),
+            outbox_max_attempts: 5,
+            outbox_backoff: Duration::from_secs(1),
+            outbox_max_backoff: Duration::from_secs(60),
+            outbox_ack_timeout: Duration::from_secs(30),
+            data_dir: PathBuf::from("data"),
+            static_dir: PathBuf::from(STATIC_DIR),
+            trash_retention: Duration::from_secs(7 * 24 * 60 * 60),
+            shutdown_report: None,
+            shutdown_timeout: Duration::from_secs(10),
+            port: None,
+            unix_socket: None,
+            bind_address: default_bind_address(),
+            tls: None,
+            behind_tls_proxy: false,
+            access_log: true,
+            log_filter: None,
+            metrics: true,
+            cors: CorsOptions::default(),
+            public_viewer: false,
+            route_limits: vec![
+                // A flood takes over the backend; later ones wait for the running one
+                RouteLimit::new("/flood", 1, Overflow::Queue(Duration::from_secs(10))),
+                RouteLimit::new("/send", 16, Overflow::Reject),
+                RouteLimit::new("/send/broadcast", 1, Overflow::Reject),
+            ],
+            disk_quotas: vec![
+                DiskQuota::new(StorageArea::Messages, 512 * MIB, Eviction::Oldest),
+                DiskQuota::new(StorageArea::Media, 1024 * MIB, Eviction::Oldest),
+                // Transfers in progress can't be thrown away; new ones wait for room
+                DiskQuota::new(StorageArea::Staging, 256 * MIB, Eviction::Never),
+            ],
+            auth_provider: None,
+            session_idle_timeout: Duration::from_secs(30 * 60),
+            session_lifetime: Duration::from_secs(12 * 60 * 60),
+            config_file: None,
+            on_listening: None,
+            on_started: None,
+        }
+    }
+}
+
+/// Current Unix time in milliseconds.
+pub(crate) fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| {
+            u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX)
+        })
+}
+
+/// Path of `req` as the router matches it, with percent-encoded characters
+/// decoded, so e.g. `/%61dmin/restart` is seen as `/admin/restart`.
+/// Middleware deciding by route must use this rather than the raw
+/// [`ServiceRequest::path`](actix_web::dev::ServiceRequest::path).
+pub(crate) fn routed_path(req: &actix_web::dev::ServiceRequest) -> &str {
+    req.match_info().as_str()
+}
+
+/// Writes `value` as JSON to a temporary file and renames it over `path`,
+/// so a crash never leaves a half-written file behind.
+pub(crate) fn write_json_atomic<T: serde::Serialize>(
+    path: &Path,
+    value: &T,
+) -> std::io::Result<()> {
+    let json = serde_json::to_vec_pretty(value).map_err(std::io::Error::other)?;
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, json)?;
+    std::fs::rename(tmp, path)
+}
 
 /// Starts the Actix Web HTTP server for the client API.
 ///
 /// The server exposes endpoints for:
-/// - Registering nodes
-/// - Sending messages
-/// - Retrieving messages
-/// - Discovering nearby nodes
-/// - Viewing connected clients
+/// - Registering nodes, optionally with every server a flood discovers
+/// - Sending messages, also to every known client, through an outbox retrying them,
+///   tracking their delivery and reconstructing their lifecycle, and sending failed
+///   ones again
+/// - Telling peers that the user is typing, and raising events when they are
+/// - Optionally, sending read receipts for acknowledged messages, and showing which sent messages
+///   were read
+/// - Retrieving messages, surfacing important ones, browsing the message history and
+///   exporting the chat history
+/// - Discovering nearby nodes and the types of the servers, and describing the known network
+/// - Viewing connected clients and which contacts are online, and the files of content
+///   servers, streaming their media and uploading files to them
+/// - Deleting messages and conversations into a restorable trash
+/// - Viewing and changing the node's persistent identity, keeping an address book of
+///   contacts and merging renumbered ones
+/// - Auto-replying while the user is away
+/// - Per-conversation statistics, key status and peer clock offsets
+/// - Network health statistics and events, Prometheus metrics, the backend's responsiveness and the disk
+///   usage against its quotas and the space saved by storing files once per content
+/// - Optionally, a read-only public viewer
+/// - Describing the supported API features and the environment of the node, health and readiness checks,
+///   and a summary of the node's status
+/// - Reloading the settings that can change at runtime, also on `SIGHUP`, see [`settings`]
+/// - Restarting a wedged backend, if whatever runs it installed a [`probe::RestartHook`]
+/// - Optionally, logging in through a pluggable [`AuthProvider`]
+/// - Optionally, cross-origin access for the origins in `ServerOptions::cors`
+/// - Optionally, an access log of every request
 ///
 /// # Arguments
 /// * `command_send_channel` - Channel used to send backend commands.
-/// * `port` - The base port number. The server will bind to `port + 8000`.
+/// * `port` - The port to listen on, on `ServerOptions::bind_address`, with TLS if
+///   `ServerOptions::tls` is set. See [`ServerOptions::listen_port`] for the usual choice.
 /// * `node_id` - Unique identifier for the local node.
 /// * `flood_recv_channel` - Channel for receiving lists of discovered edge nodes.
 /// * `unread_msg_recv_channel` - Channel for receiving unread messages from the backend.
+/// * `probe` - Where the backend's responsiveness is recorded, shared with whatever
+///   supervises the backend thread to record its restarts.
+/// * `options` - Timeouts and other tunables, see [`ServerOptions`].
+///
+/// Both receive channels are handed to a [`Dispatcher`], which becomes their only reader.
+///
+/// The server shuts down on `SIGINT` or `SIGTERM`, giving requests in flight up to
+/// `ServerOptions::shutdown_timeout` to finish. A [`ShutdownReport`] is then logged
+/// and, if `ServerOptions::shutdown_report` is set, written to that path. The background
+/// loops stop with the server, and the dispatcher thread gets up to the same timeout to exit.
 ///
 /// # Returns
 /// An [`std::io::Result`] which is `Ok(())` if the server started successfully.
 ///
 /// # Errors
-/// Returns an [`std::io::Error`] if binding to the port or starting the server fails.
+/// Returns an [`std::io::Error`] if the message database can't be opened,
+/// or if binding to the port or starting the server fails.
 pub async fn start_server(
     command_send_channel: Sender<Command>,
     port: u16,
     node_id: u8,
     flood_recv_channel: Receiver<ListOfDiscoveredEdgeNodes>,
     unread_msg_recv_channel: Receiver<UnreadMessagesFromServer>,
+    probe: Arc<BackendProbe>,
+    options: ServerOptions,
+) -> std::io::Result<()> {
+    let channels = vec![
+        ChannelInfo::new("commands", command_send_channel.capacity()),
+        ChannelInfo::new("flood results", flood_recv_channel.capacity()),
+        ChannelInfo::new("unread messages", unread_msg_recv_channel.capacity()),
+    ];
+    let dispatcher = Dispatcher::spawn(
+        command_send_channel.clone(),
+        flood_recv_channel,
+        unread_msg_recv_channel,
+        probe.subscribe_restarts(),
+        CircuitBreaker::new(&options),
+    );
+    let shutdown_timeout = options.shutdown_timeout;
+    let result = serve(
+        command_send_channel,
+        port,
+        node_id,
+        dispatcher.clone(),
+        channels,
+        probe,
+        options,
+    )
+    .await;
+    join_dispatcher(dispatcher, shutdown_timeout).await;
+    result
+}
+
+/// Starts the HTTP server like [`start_server`], but on a generated [`DemoNetwork`]
+/// instead of the backend, so the web UI can be developed and shown without any
+/// drone network. Data is kept in a `demo` subdirectory of `ServerOptions::data_dir`,
+/// apart from that of real runs.
+///
+/// # Errors
+/// Same as [`start_server`].
+pub async fn start_demo_server(
+    port: u16,
+    node_id: u8,
+    mut options: ServerOptions,
 ) -> std::io::Result<()> {
-    let port = port + 8000;
-    HttpServer::new(move || {
+    let (command_send_channel, command_recv_channel) = unbounded::<Command>();
+    let dispatcher = Dispatcher::spawn_demo(
+        command_recv_channel,
+        DemoNetwork::generate(node_id),
+        CircuitBreaker::new(&options),
+    );
+    let shutdown_timeout = options.shutdown_timeout;
+    options.data_dir = options.data_dir.join("demo");
+    let channels = vec![ChannelInfo::new(
+        "commands",
+        command_send_channel.capacity(),
+    )];
+    let result = serve(
+        command_send_channel,
+        port,
+        node_id,
+        dispatcher.clone(),
+        channels,
+        Arc::default(),
+        options,
+    )
+    .await;
+    join_dispatcher(dispatcher, shutdown_timeout).await;
+    result
+}
+
+/// Waits up to `timeout` for the dispatcher thread to exit after [`serve`] returned,
+/// which stopped the background loops holding on to it.
+async fn join_dispatcher(dispatcher: Dispatcher, timeout: Duration) {
+    let joined = web::block(move || dispatcher.join(timeout)).await;
+    if !matches!(joined, Ok(true)) {
+        tracing::warn!("The dispatcher did not stop within {timeout:?}");
+    }
+}
+
+/// Serves the client API on `port`, talking to the backend through `dispatcher`.
+/// Logs the [`Diagnostics`] of the environment first; `channels` describes the
+/// channels to the backend for them.
+async fn serve(
+    command_send_channel: Sender<Command>,
+    port: u16,
+    node_id: u8,
+    dispatcher: Dispatcher,
+    channels: Vec<ChannelInfo>,
+    probe: Arc<BackendProbe>,
+    options: ServerOptions,
+) -> std::io::Result<()> {
+    let started_at = unix_millis();
+    // Dropped when this returns, which stops the background loops
+    let (_stop, shutdown) = bounded::<()>(0);
+    if let Some(filter) = &options.log_filter {
+        trace::set_filter(filter)?;
+    }
+    let timeseries = Arc::new(TimeSeries::default());
+    let events = Arc::new(EventLog::default());
+    let history = Arc::new(History::new(timeseries.clone()));
+    let data_dir = options.node_data_dir(node_id);
+    // Bound first, so the diagnostics know the port even if the system picked it
+    let listener = Listener::bind(&options, node_id, port)?;
+    // Report the environment even if the data directory turns out to be unusable
+    let data_dir_created = std::fs::create_dir_all(&data_dir);
+    let diagnostics = web::Data::new(Diagnostics::collect(
+        node_id,
+        listener.to_string(),
+        &data_dir,
+        channels,
+        &options,
+    ));
+    tracing::info!("{}", diagnostics.banner());
+    data_dir_created?;
+    let tls_config = options.tls.as_ref().map(TlsOptions::load).transpose()?;
+    let dead_letters = Arc::new(DeadLetterQueue::load(&data_dir.join("deadletter.json"))?);
+    let deliveries = Arc::new(DeliveryTracker::recover(
+        Journal::open(&data_dir.join("outbox.journal"))?,
+        dead_letters.clone(),
+        RetryPolicy::new(&options),
+    )?);
+    let identity = web::Data::new(IdentityStore::load_or_create(
+        &data_dir.join("identity.json"),
+        node_id,
+    )?);
+    let priority_rules =
+        web::Data::new(PriorityRules::load(&data_dir.join("priority_rules.json"))?);
+    let store = Arc::new(
+        MessageStore::open(&data_dir.join("messages.sqlite")).map_err(std::io::Error::other)?,
+    );
+    // Restore the conversations of previous runs
+    history.rebuild_incoming(&store.all().map_err(std::io::Error::other)?);
+    storage::spawn_janitor(store.clone(), options.trash_retention, shutdown.clone());
+    // Sends what was left in the outbox before a restart, too
+    let outbox = Arc::new(outbox::spawn(
+        deliveries.clone(),
+        dispatcher.clone(),
+        history.clone(),
+        node_id,
+    ));
+    let directory = Arc::new(Directory::new(
+        LastSeen::load(&data_dir.join("last_seen.json"))?,
+        TypingRelay::new(events.clone()),
+    ));
+    let network_stats = Arc::new(NetworkStats::new(events.clone(), timeseries.clone()));
+    let clocks = Arc::new(PeerClocks::new(network_stats.clone()));
+    let session_ids = Arc::new(SessionIds::new(node_id));
+    let away = Arc::new(AwayMode::load(
+        &data_dir.join("away.json"),
+        node_id,
+        session_ids.clone(),
+        outbox.clone(),
+    )?);
+    let inbox = Arc::new(Inbox::new(
+        store.clone(),
+        history.clone(),
+        deliveries.clone(),
+        directory.clone(),
+        away.clone(),
+        clocks.clone(),
+        Deduplicator::new(options.dedup_window),
+    ));
+    inbox::spawn_ingester(
+        inbox.clone(),
+        dispatcher.clone(),
+        options.ingest_interval,
+        shutdown.clone(),
+    );
+    let limits = Arc::new(ConcurrencyLimits::new(&options.route_limits));
+    let live = Arc::new(LiveOptions::new(options.clone(), limits.clone()));
+    settings::spawn_reload_on_hangup(live.clone());
+    let registrar = Arc::new(Registrar::new(
+        node_id,
+        live.clone(),
+        dispatcher.clone(),
+        inbox.clone(),
+        session_ids.clone(),
+        Recorders {
+            network_stats: network_stats.clone(),
+            directory: directory.clone(),
+            outbox: outbox.clone(),
+        },
+    ));
+    let flood_jobs = web::Data::new(FloodJobs::default());
+    let flood_cache = Arc::new(FloodCache::new(
+        options.auto_register.then(|| registrar.clone()),
+        outbox.clone(),
+    ));
+    flood::spawn_refresher(
+        command_send_channel.clone(),
+        dispatcher.clone(),
+        live.clone(),
+        flood_cache.clone(),
+        shutdown.clone(),
+    );
+    let presence = web::Data::new(presence::spawn(
+        dispatcher.clone(),
+        session_ids.clone(),
+        node_id,
+        options.presence_interval,
+    ));
+    let receipts = web::Data::new(receipts::spawn(
+        dispatcher.clone(),
+        session_ids.clone(),
+        node_id,
+        options.read_receipts,
+    ));
+    let server_directory = web::Data::new(ServerDirectory::new(
+        node_id,
+        live.clone(),
+        dispatcher.clone(),
+        inbox.clone(),
+        session_ids.clone(),
+        network_stats.clone(),
+    ));
+    probe::spawn_prober(
+        probe.clone(),
+        dispatcher.clone(),
+        live.clone(),
+        timeseries.clone(),
+        shutdown.clone(),
+    );
+    let staging_dir = data_dir.join("staging");
+    std::fs::create_dir_all(&staging_dir)?;
+    let quotas = Arc::new(DiskQuotas::new(
+        &options.disk_quotas,
+        store.clone(),
+        &data_dir.join("media"),
+        &staging_dir,
+        events.clone(),
+    ));
+    quota::spawn_enforcer(quotas.clone(), shutdown.clone());
+    let uploads = Arc::new(Uploads::new(
+        node_id,
+        live.clone(),
+        BlobStore::open(&staging_dir, None)?,
+        dispatcher.clone(),
+        inbox.clone(),
+        session_ids.clone(),
+        network_stats.clone(),
+    ));
+    let media_cache = web::Data::new(MediaCache::open(&data_dir.join("media"), quotas.clone())?);
+    let assets = web::Data::new(Assets::load(&options.static_dir, node_id));
+    let sessions = Arc::new(Sessions::new(
+        options.session_idle_timeout,
+        options.session_lifetime,
+    ));
+    auth::spawn_janitor(sessions.clone(), shutdown.clone());
+    let idempotency_keys = web::Data::new(IdempotencyKeys::new(options.idempotency_ttl));
+    // The server closure takes ownership; keep what the report needs
+    let report_sources = (history.clone(), deliveries.clone(), network_stats.clone());
+    let shutdown_report = options.shutdown_report.clone();
+    let shutdown_timeout = options.shutdown_timeout.as_secs();
+    let on_listening = options.on_listening.clone();
+    let on_started = options.on_started.clone();
+
+    let server = HttpServer::new(move || {
         App::new()
             .service(clients)
+            .service(typing_notice)
+            .service(contact_presence)
+            .service(content_files)
+            .service(content_file)
+            .service(upload_file)
+            .service(upload_progress)
+            .service(stream_media)
             .service(register)
+            .service(registrations)
+            .service(broadcast_message)
             .service(send_message)
+            .service(send_status)
+            .service(delivery_timeline)
+            .service(dead_letters)
+            .service(retry_dead_letter)
+            .service(outbox_progress)
+            .service(outbox_message)
             .service(get_messages)
+            .service(message_history)
+            .service(long_poll_messages)
+            .service(unread_count)
+            .service(ack_messages)
+            .service(priority_inbox)
+            .service(list_priority_rules)
+            .service(add_priority_rule)
+            .service(delete_priority_rule)
             .service(flood_network)
+            .service(start_flood)
+            .service(flood_result)
+            .service(network_topology)
+            .service(discovered_servers)
+            .service(conversations)
+            .service(conversation_messages)
+            .service(set_key_requirement)
+            .service(list_contacts)
+            .service(get_contact)
+            .service(put_contact)
+            .service(delete_contact)
+            .service(set_contact_key)
+            .service(conversation_stats)
+            .service(peer_clock)
+            .service(drone_stats)
+            .service(get_events)
+            .service(prometheus_metrics)
+            .service(backend_status)
+            .service(disk_usage)
+            .service(stats_timeseries)
+            .service(stats_export_csv)
+            .service(export_chat)
+            .service(reindex)
+            .service(reload_assets)
+            .service(reload_config)
+            .service(restart_backend)
+            .service(storage_savings)
+            .service(static_file)
+            .service(outbox_journal)
+            .service(delete_message)
+            .service(delete_conversation)
+            .service(trash)
+            .service(get_identity)
+            .service(update_identity)
+            .service(merge_contact)
+            .service(client_config)
+            .service(api_changes)
+            .service(debug_env)
+            .service(health)
+            .service(ready)
+            .service(node_status)
+            .service(login_page)
+            .service(login)
+            .service(logout)
+            .service(get_away)
+            .service(set_away)
+            .service(restore_from_trash)
             .route("/", web::get().to(index))
+            .configure(|cfg| {
+                if options.public_viewer {
+                    cfg.service(
+                        web::scope("/public")
+                            .service(network_topology)
+                            .service(public_stats),
+                    );
+                }
+            })
+            .wrap(from_fn(limits::handle))
+            .wrap(from_fn(idempotency::handle))
+            .wrap(from_fn(auth::handle))
+            .wrap(from_fn(methods::handle))
+            .wrap(from_fn(cors::handle))
+            .wrap(Condition::new(options.metrics, from_fn(metrics::handle)))
+            .wrap(from_fn(access::handle))
+            .wrap(from_fn(trace::handle))
+            .wrap(from_fn(request_id::handle))
             .app_data(web::Data::new(command_send_channel.clone()))
-            .app_data(web::Data::new(flood_recv_channel.clone()))
-            .app_data(web::Data::new(unread_msg_recv_channel.clone()))
+            .app_data(web::Data::new(dispatcher.clone()))
             .app_data(web::Data::new(node_id))
-    })
-    .bind(("127.0.0.1", port))?
-    .run()
-    .await
+            .app_data(web::Data::from(live.clone()))
+            .app_data(validation::json_config(options.json_limit))
+            .app_data(web::Data::from(inbox.clone()))
+            .app_data(web::Data::from(registrar.clone()))
+            .app_data(web::Data::from(history.clone()))
+            .app_data(web::Data::from(deliveries.clone()))
+            .app_data(web::Data::from(dead_letters.clone()))
+            .app_data(web::Data::from(outbox.clone()))
+            .app_data(web::Data::from(directory.clone()))
+            .app_data(presence.clone())
+            .app_data(receipts.clone())
+            .app_data(web::Data::from(store.clone()))
+            .app_data(web::Data::from(network_stats.clone()))
+            .app_data(web::Data::from(session_ids.clone()))
+            .app_data(web::Data::from(away.clone()))
+            .app_data(web::Data::from(clocks.clone()))
+            .app_data(identity.clone())
+            .app_data(priority_rules.clone())
+            .app_data(web::Data::from(limits.clone()))
+            .app_data(diagnostics.clone())
+            .app_data(assets.clone())
+            .app_data(media_cache.clone())
+            .app_data(web::Data::from(quotas.clone()))
+            .app_data(web::Data::from(probe.clone()))
+            .app_data(server_directory.clone())
+            .app_data(web::Data::from(dispatcher.metrics().clone()))
+            .app_data(web::Data::from(uploads.clone()))
+            .app_data(web::Data::from(sessions.clone()))
+            .app_data(idempotency_keys.clone())
+            .app_data(flood_jobs.clone())
+            .app_data(web::Data::from(flood_cache.clone()))
+            .app_data(web::Data::from(events.clone()))
+            .app_data(web::Data::from(timeseries.clone()))
+    });
+    // On SIGINT or SIGTERM, stop accepting and let running requests finish
+    let server = server.shutdown_timeout(shutdown_timeout);
+    let address = listener.to_string();
+    let server = match (listener, tls_config) {
+        (Listener::Tcp(listener, local_addr), tls_config) => {
+            let server = match tls_config {
+                Some(config) => server.listen_rustls_0_23(listener, config)?,
+                None => server.listen(listener)?,
+            };
+            tracing::info!(
+                node_id,
+                address = %local_addr,
+                port = local_addr.port(),
+                "Listening on {local_addr}"
+            );
+            if let Some(ListenCallback(callback)) = &on_listening {
+                callback(local_addr);
+            }
+            server
+        }
+        #[cfg(unix)]
+        (Listener::Unix(..), Some(_)) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "TLS is not supported on a Unix domain socket",
+            ));
+        }
+        #[cfg(unix)]
+        (Listener::Unix(listener, _), None) => {
+            let server = server.listen_uds(listener)?;
+            tracing::info!(node_id, address = %address, "Listening on {address}");
+            server
+        }
+    };
+    let server = server.run();
+    if let Some(StartedCallback(callback)) = &on_started {
+        callback(server.handle());
+    }
+    let result = server.await;
+
+    let (history, deliveries, network_stats) = report_sources;
+    let report =
+        ShutdownReport::collect(node_id, started_at, &history, &deliveries, &network_stats);
+    tracing::info!("{}", report.summary());
+    if let Some(path) = &shutdown_report
+        && let Err(e) = report.write(path)
+    {
+        tracing::error!("Failed to write shutdown report to {}: {e}", path.display());
+    }
+
+    result
 }
@@ -0,0 +1,210 @@
+//! Outbox handing sent messages to the backend in the background.
+//!
+//! `/send` and the other senders only [`Outbox::enqueue`] their messages: each
+//! is journaled by the [`DeliveryTracker`] and the worker started by [`spawn`]
+//! is woken. The worker hands every due message to the backend in one batch.
+//! A message whose send failed, whose server rejected it or didn't answer
+//! within `ServerOptions::outbox_ack_timeout` is due again after a backoff:
+//! `ServerOptions::outbox_backoff` before the first retry, twice as long before
+//! each further one, up to `ServerOptions::outbox_max_backoff`. After
+//! `ServerOptions::outbox_max_attempts` attempts the message fails and becomes
+//! a dead letter. While the circuit breaker is open, the worker holds the
+//! messages back instead of using up their attempts.
+//!
+//! Messages to a server no route is known to are put in with [`Outbox::hold`]
+//! instead: they wait without using up attempts until [`Outbox::release`] is called for
+//! their server, after a flood found it or it confirmed a registration.
+//!
+//! Messages to the same client through the same server go out in the order they
+//! were enqueued: one that is held or waiting for a retry holds back the later ones.
+//!
+//! The progress of a message shows in `/send/{id}/status` and `/outbox/{id}`,
+//! that of every message in `/outbox`.
+
+use crossbeam_channel::{RecvTimeoutError, Sender, bounded};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use super::ServerOptions;
+use super::delivery::{DeliveryTracker, OutgoingMessage};
+use super::dispatcher::{DispatchError, Dispatcher};
+use super::history::History;
+use super::unix_millis;
+
+/// The longest the worker sleeps before it looks for due messages again.
+const IDLE_WAIT: Duration = Duration::from_secs(1);
+
+/// When the outbox sends a message again, and how often at most.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// How often a message is handed to the backend before it fails.
+    pub max_attempts: u32,
+    /// How long to wait before the first retry.
+    pub backoff: Duration,
+    /// The longest wait between two attempts.
+    pub max_backoff: Duration,
+    /// How long to wait for the server's answer before sending again.
+    pub ack_timeout: Duration,
+}
+
+impl RetryPolicy {
+    /// The policy set by the `outbox_*` fields of `options`.
+    #[must_use]
+    pub fn new(options: &ServerOptions) -> Self {
+        RetryPolicy {
+            max_attempts: options.outbox_max_attempts.max(1),
+            backoff: options.outbox_backoff,
+            max_backoff: options.outbox_max_backoff,
+            ack_timeout: options.outbox_ack_timeout,
+        }
+    }
+
+    /// How long to wait before the next attempt once `attempts` attempts failed.
+    #[must_use]
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2_u32.saturating_pow(attempts.saturating_sub(1));
+        self.backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+
+    /// Unix time in milliseconds of the next attempt after `attempts` attempts,
+    /// the last of which failed at `at`.
+    pub(crate) fn retry_at(&self, at: u64, attempts: u32) -> u64 {
+        at.saturating_add(millis(self.backoff(attempts)))
+    }
+
+    /// Unix time in milliseconds by which the server must have answered a
+    /// message handed to the backend at `at`.
+    pub(crate) fn answer_by(&self, at: u64) -> u64 {
+        at.saturating_add(millis(self.ack_timeout))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(&ServerOptions::default())
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Result of enqueueing one message: its message ID, or why it wasn't accepted.
+pub type EnqueueResult = Result<u64, &'static str>;
+
+/// Where messages are put for the worker to send, see the [module docs](self).
+#[derive(Debug)]
+pub struct Outbox {
+    deliveries: Arc<DeliveryTracker>,
+    wake: Sender<()>,
+}
+
+impl Outbox {
+    /// Journals the `outgoing` messages and wakes the worker to send them.
+    /// Returns a result per message, in order.
+    #[must_use]
+    pub fn enqueue(&self, outgoing: Vec<OutgoingMessage>) -> Vec<(OutgoingMessage, EnqueueResult)> {
+        let results = self.track(outgoing, false);
+        self.wake();
+        results
+    }
+
+    /// Journals the `outgoing` messages, to be sent once their server is released.
+    /// Returns a result per message, in order.
+    #[must_use]
+    pub fn hold(&self, outgoing: Vec<OutgoingMessage>) -> Vec<(OutgoingMessage, EnqueueResult)> {
+        self.track(outgoing, true)
+    }
+
+    /// Sends the held messages to any of `servers`, now that a route to them is known.
+    pub fn release(&self, servers: &[u8]) {
+        let released = self.deliveries.release(servers);
+        if released > 0 {
+            tracing::info!("Found a route for {released} held messages");
+            self.wake();
+        }
+    }
+
+    fn track(
+        &self,
+        outgoing: Vec<OutgoingMessage>,
+        held: bool,
+    ) -> Vec<(OutgoingMessage, EnqueueResult)> {
+        outgoing
+            .into_iter()
+            .map(|outgoing| {
+                let result = self
+                    .deliveries
+                    .track(&outgoing, held)
+                    .map_err(|_| "Failed to journal the message");
+                (outgoing, result)
+            })
+            .collect()
+    }
+
+    fn wake(&self) {
+        // A full channel means the worker is woken already
+        let _ = self.wake.try_send(());
+    }
+}
+
+/// Spawns the worker sending the messages tracked by `deliveries` as node
+/// `node_id`, starting with those recovered from the journal, and returns the
+/// outbox feeding it. The worker stops once the outbox is dropped.
+pub fn spawn(
+    deliveries: Arc<DeliveryTracker>,
+    dispatcher: Dispatcher,
+    history: Arc<History>,
+    node_id: u8,
+) -> Outbox {
+    let (wake, woken) = bounded(1);
+    let tracker = deliveries.clone();
+    thread::spawn(move || {
+        loop {
+            let wait = match dispatcher.check_breaker() {
+                Err(DispatchError::Unavailable(left)) => left.min(IDLE_WAIT),
+                _ if dispatcher.is_busy() => IDLE_WAIT,
+                _ => {
+                    send_due(&tracker, &dispatcher, &history, node_id);
+                    tracker.next_due().map_or(IDLE_WAIT, |at| {
+                        Duration::from_millis(at.saturating_sub(unix_millis())).min(IDLE_WAIT)
+                    })
+                }
+            };
+            if let Err(RecvTimeoutError::Disconnected) = woken.recv_timeout(wait) {
+                return;
+            }
+        }
+    });
+    Outbox { deliveries, wake }
+}
+
+/// Hands every due message to the backend in one batch and schedules a retry
+/// for each of them if that fails.
+fn send_due(deliveries: &DeliveryTracker, dispatcher: &Dispatcher, history: &History, node_id: u8) {
+    let mut batch = vec![];
+    let mut attempted = vec![];
+    for (id, outgoing, attempts) in deliveries.due(unix_millis()) {
+        // Journal the attempt before handing the message over
+        if deliveries.mark_attempted(id).is_err() {
+            deliveries.fail(id, "Failed to journal the message");
+            continue;
+        }
+        batch.push(outgoing.to_message(node_id));
+        attempted.push((id, outgoing, attempts == 0));
+    }
+    if batch.is_empty() {
+        return;
+    }
+
+    let sent = dispatcher.send_messages(batch);
+    for (id, outgoing, first) in attempted {
+        if first {
+            history.record_outgoing(outgoing.client_id, outgoing.message.len(), sent.is_err());
+        }
+        if let Err(e) = &sent {
+            deliveries.retry(id, &e.to_string());
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Online status of contacts, from the client lists of servers.
+//!
+//! A client is online while it is registered with a server, which the server
+//! tells in its `ClientList` reply. Once `/presence/{server_id}` asked about a
+//! server, the worker started by [`spawn`] requests its client list every
+//! `ServerOptions::presence_interval`. The replies reach the
+//! [`Directory`](super::directory::Directory) like those to `/clients`, which
+//! also records when each list arrived, so the UI can tell how fresh the
+//! status is.
+
+use crossbeam_channel::{RecvTimeoutError, Sender, bounded};
+use messages::{ChatRequest, Message, MessageType, RequestType};
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use super::dispatcher::Dispatcher;
+use super::session::SessionIds;
+
+/// The servers whose client lists are kept fresh.
+#[derive(Debug)]
+pub struct Presence {
+    watched: Arc<Mutex<BTreeSet<u8>>>,
+    interval: Duration,
+    wake: Sender<()>,
+}
+
+impl Presence {
+    /// Starts refreshing the client list of `server_id`, right away if it
+    /// wasn't refreshed before.
+    pub fn watch(&self, server_id: u8) {
+        if lock(&self.watched).insert(server_id) {
+            // A full channel means the worker is woken already
+            let _ = self.wake.try_send(());
+        }
+    }
+
+    /// How often the client lists are refreshed.
+    #[must_use]
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+}
+
+/// Spawns the worker requesting the client lists of the watched servers as node
+/// `node_id` every `interval`, and returns the presence feeding it. The worker
+/// stops once the presence is dropped.
+pub fn spawn(
+    dispatcher: Dispatcher,
+    session_ids: Arc<SessionIds>,
+    node_id: u8,
+    interval: Duration,
+) -> Presence {
+    let (wake, woken) = bounded(1);
+    let watched = Arc::new(Mutex::new(BTreeSet::new()));
+    let servers = watched.clone();
+    thread::spawn(move || {
+        loop {
+            if let Err(RecvTimeoutError::Disconnected) = woken.recv_timeout(interval) {
+                return;
+            }
+            let requests: Vec<_> = lock(&servers)
+                .iter()
+                .map(|server_id| Message {
+                    source: node_id,
+                    destination: *server_id,
+                    session_id: session_ids.next(),
+                    content: MessageType::Request(RequestType::ChatRequest(
+                        ChatRequest::ClientList,
+                    )),
+                })
+                .collect();
+            if requests.is_empty() || dispatcher.is_busy() {
+                continue;
+            }
+            if let Err(e) = dispatcher.send_messages(requests) {
+                tracing::debug!("Failed to refresh the client lists: {e}");
+            }
+        }
+    });
+    Presence {
+        watched,
+        interval,
+        wake,
+    }
+}
+
+fn lock(watched: &Mutex<BTreeSet<u8>>) -> MutexGuard<'_, BTreeSet<u8>> {
+    watched.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,153 @@
+//! User-defined rules marking incoming messages as important.
+//!
+//! A rule names a sender, a keyword or both; a chat message matching every
+//! part of any rule is important. Important messages are listed by
+//! `/inbox/priority` and are meant to be notified about even while
+//! notifications are muted. The rules are stored as JSON in the node's data
+//! directory.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use super::inbox::Envelope;
+use super::write_json_atomic;
+
+/// Criteria of a rule, as given by the user.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleCriteria {
+    /// Messages written by this node.
+    pub from: Option<u8>,
+    /// Messages containing this text, ignoring case.
+    pub keyword: Option<String>,
+}
+
+/// A stored rule.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PriorityRule {
+    /// ID to delete the rule with.
+    pub id: u64,
+    /// What the rule matches.
+    #[serde(flatten)]
+    pub criteria: RuleCriteria,
+}
+
+impl PriorityRule {
+    fn matches(&self, envelope: &Envelope) -> bool {
+        self.criteria
+            .from
+            .is_none_or(|from| envelope.sender() == Some(from))
+            && self.criteria.keyword.as_ref().is_none_or(|keyword| {
+                envelope
+                    .body_text()
+                    .is_some_and(|text| text.to_lowercase().contains(&keyword.to_lowercase()))
+            })
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Rules {
+    next_id: u64,
+    rules: Vec<PriorityRule>,
+}
+
+/// The rules together with the file they are persisted in.
+#[derive(Debug)]
+pub struct PriorityRules {
+    path: PathBuf,
+    rules: Mutex<Rules>,
+}
+
+impl PriorityRules {
+    /// Loads the rules from `path`, starting without rules if the file doesn't exist.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read or parsed.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let rules = match fs::read(path) {
+            Ok(json) => serde_json::from_slice(&json).map_err(io::Error::other)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Rules::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(PriorityRules {
+            path: path.to_path_buf(),
+            rules: Mutex::new(rules),
+        })
+    }
+
+    /// Returns every rule, oldest first.
+    #[must_use]
+    pub fn list(&self) -> Vec<PriorityRule> {
+        self.lock().rules.clone()
+    }
+
+    /// Adds a rule matching `criteria` and persists it.
+    ///
+    /// # Errors
+    /// Returns an error if the rules can't be written.
+    pub fn add(&self, criteria: RuleCriteria) -> io::Result<PriorityRule> {
+        let mut rules = self.lock();
+        let rule = PriorityRule {
+            id: rules.next_id + 1,
+            criteria,
+        };
+        rules.next_id += 1;
+        rules.rules.push(rule.clone());
+        if let Err(e) = write_json_atomic(&self.path, &*rules) {
+            rules.rules.pop();
+            rules.next_id -= 1;
+            return Err(e);
+        }
+        Ok(rule)
+    }
+
+    /// Removes rule `id`. Returns `false` if there is no such rule.
+    ///
+    /// # Errors
+    /// Returns an error if the rules can't be written.
+    pub fn remove(&self, id: u64) -> io::Result<bool> {
+        let mut rules = self.lock();
+        let Some(index) = rules.rules.iter().position(|rule| rule.id == id) else {
+            return Ok(false);
+        };
+        let rule = rules.rules.remove(index);
+        if let Err(e) = write_json_atomic(&self.path, &*rules) {
+            rules.rules.insert(index, rule);
+            return Err(e);
+        }
+        Ok(true)
+    }
+
+    /// Points the rules for sender `old` to sender `new` and persists them.
+    ///
+    /// # Errors
+    /// Returns an error if the rules can't be written.
+    pub fn merge_peer(&self, old: u8, new: u8) -> io::Result<()> {
+        let mut rules = self.lock();
+        let mut merged = rules.rules.clone();
+        for rule in merged
+            .iter_mut()
+            .filter(|rule| rule.criteria.from == Some(old))
+        {
+            rule.criteria.from = Some(new);
+        }
+        let previous = std::mem::replace(&mut rules.rules, merged);
+        if let Err(e) = write_json_atomic(&self.path, &*rules) {
+            rules.rules = previous;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Whether `envelope` is a chat message matching any rule.
+    #[must_use]
+    pub fn is_important(&self, envelope: &Envelope) -> bool {
+        envelope.is_chat_message() && self.lock().rules.iter().any(|rule| rule.matches(envelope))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Rules> {
+        self.rules.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
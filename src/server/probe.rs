@@ -0,0 +1,176 @@
+//! Periodic probe of the backend's responsiveness.
+//!
+//! Every request of the frontend ends up in the backend's command loop, so a
+//! stalled loop stalls everything. The probe pings it through the
+//! [`Dispatcher`] at a fixed interval and keeps the latest round trip time as
+//! a gauge, together with how many pings in a row failed. Health checks and
+//! anything else that must not wait on a stuck backend read [`BackendProbe::status`]
+//! instead of sending requests of their own. The supervisor of the backend thread
+//! records here when it had to restart a panicked backend, and installs a
+//! [`RestartHook`] for restarting it on request. Every restart is passed on to
+//! the subscribers of [`BackendProbe::subscribe_restarts`], so the dispatcher
+//! stops waiting for answers the old backend will never give.
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, unbounded};
+use serde::Serialize;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use super::dispatcher::{DispatchError, Dispatcher};
+use super::settings::LiveOptions;
+use super::timeseries::{Metric, TimeSeries};
+use super::unix_millis;
+
+/// Number of failed pings in a row after which the backend counts as unresponsive.
+const FAILURES_UNTIL_UNRESPONSIVE: u32 = 3;
+
+/// What the probe knows about the backend.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ProbeStatus {
+    /// Round trip time of the last successful ping, in milliseconds.
+    pub latency_ms: Option<u64>,
+    /// Unix time in milliseconds of the last successful ping.
+    pub last_ok_at: Option<u64>,
+    /// Pings that failed since the last successful one.
+    pub consecutive_failures: u32,
+    /// Why the last ping failed, if it did.
+    pub last_error: Option<String>,
+    /// Whether the backend answered recently.
+    pub responsive: bool,
+    /// How often the backend was restarted after a panic.
+    pub restarts: u32,
+    /// The latest restart, if any.
+    pub last_restart: Option<BackendRestart>,
+}
+
+/// A restart of the backend after it panicked.
+#[derive(Debug, Clone, Serialize)]
+pub struct BackendRestart {
+    /// Unix time in milliseconds of the restart.
+    pub at: u64,
+    /// What the backend panicked with.
+    pub reason: String,
+}
+
+/// Replaces the backend with a new one, returning why it couldn't.
+pub struct RestartHook(pub Box<dyn Fn() -> Result<(), String> + Send + Sync>);
+
+impl fmt::Debug for RestartHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RestartHook")
+    }
+}
+
+/// Latest results of the pings to the backend.
+#[derive(Debug, Default)]
+pub struct BackendProbe {
+    status: Mutex<ProbeStatus>,
+    restart: OnceLock<RestartHook>,
+    restarts: Mutex<Vec<Sender<()>>>,
+}
+
+impl BackendProbe {
+    /// What the probe knows about the backend right now.
+    #[must_use]
+    pub fn status(&self) -> ProbeStatus {
+        self.lock().clone()
+    }
+
+    /// Whether the backend answered recently. Unresponsive before the first ping.
+    #[must_use]
+    pub fn responsive(&self) -> bool {
+        self.lock().responsive
+    }
+
+    /// Records the result of a ping.
+    pub fn record(&self, result: Result<Duration, DispatchError>) {
+        let mut status = self.lock();
+        match result {
+            Ok(latency) => {
+                status.latency_ms = Some(u64::try_from(latency.as_millis()).unwrap_or(u64::MAX));
+                status.last_ok_at = Some(unix_millis());
+                status.consecutive_failures = 0;
+                status.last_error = None;
+                status.responsive = true;
+            }
+            Err(e) => {
+                status.consecutive_failures += 1;
+                status.last_error = Some(e.to_string());
+                if status.consecutive_failures >= FAILURES_UNTIL_UNRESPONSIVE {
+                    status.responsive = false;
+                }
+            }
+        }
+    }
+
+    /// Records that the backend is being restarted, because it panicked with
+    /// `reason` or on request, and tells the subscribers.
+    pub fn record_restart(&self, reason: String) {
+        {
+            let mut status = self.lock();
+            status.restarts += 1;
+            status.last_restart = Some(BackendRestart {
+                at: unix_millis(),
+                reason,
+            });
+        }
+        self.restarts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|subscriber| subscriber.send(()).is_ok());
+    }
+
+    /// Channel receiving a message for every restart recorded from now on.
+    pub fn subscribe_restarts(&self) -> Receiver<()> {
+        let (subscriber, restarts) = unbounded();
+        self.restarts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(subscriber);
+        restarts
+    }
+
+    /// Lets [`BackendProbe::restart`] restart the backend through `hook`. Only the
+    /// first hook is kept.
+    pub fn set_restart(&self, hook: RestartHook) {
+        let _ = self.restart.set(hook);
+    }
+
+    /// Restarts the backend through the installed hook; `None` if there is none,
+    /// as with the generated network of demo mode.
+    pub fn restart(&self) -> Option<Result<(), String>> {
+        self.restart.get().map(|RestartHook(hook)| hook())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ProbeStatus> {
+        self.status.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Spawns the prober, which pings the backend every `ServerOptions::backend_ping_interval`,
+/// waiting up to the current `ServerOptions::backend_ping_timeout` for each answer, and
+/// records the round trip times in `probe` and `timeseries`. Stops once `shutdown` is
+/// disconnected.
+pub fn spawn_prober(
+    probe: Arc<BackendProbe>,
+    dispatcher: Dispatcher,
+    options: Arc<LiveOptions>,
+    timeseries: Arc<TimeSeries>,
+    shutdown: Receiver<()>,
+) {
+    let interval = options.current().backend_ping_interval;
+    thread::spawn(move || {
+        loop {
+            let result = dispatcher.ping(options.current().backend_ping_timeout);
+            if let Ok(latency) = result {
+                timeseries.record(Metric::BackendLatency, latency.as_secs_f64() * 1000.0);
+            }
+            probe.record(result);
+            if let Err(RecvTimeoutError::Disconnected) = shutdown.recv_timeout(interval) {
+                return;
+            }
+        }
+    });
+}
@@ -0,0 +1,319 @@
+//! Limits on how much disk space the node's data may take.
+//!
+//! Lab machines run many nodes side by side, so every growing part of the
+//! data directory has its own [`DiskQuota`]: the message database, the media
+//! cache and the staging area of file transfers. When an area is over its
+//! quota, the oldest data is evicted if the quota allows it (see [`Eviction`]);
+//! otherwise writes that don't fit are refused with a [`QuotaExceeded`],
+//! which the handlers turn into 507 (Insufficient Storage).
+//!
+//! Only read or trashed messages are ever evicted from the database; unread
+//! messages are kept even beyond the quota.
+
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use super::events::{EventKind, EventLog};
+use super::storage::MessageStore;
+
+/// How often the enforcer brings every area back within its quota.
+const ENFORCE_INTERVAL: Duration = Duration::from_secs(60);
+/// How many messages are evicted from the database at a time.
+const MESSAGE_EVICTION_BATCH: usize = 100;
+
+/// A part of the data directory with its own quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageArea {
+    /// The message database.
+    Messages,
+    /// Media cached from media servers.
+    Media,
+    /// Files being transferred, e.g. uploads waiting to be sent.
+    Staging,
+}
+
+impl StorageArea {
+    /// Every area.
+    pub const ALL: [StorageArea; 3] = [
+        StorageArea::Messages,
+        StorageArea::Media,
+        StorageArea::Staging,
+    ];
+}
+
+/// What happens when an area is over its quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Eviction {
+    /// Remove the oldest data until the area fits again.
+    Oldest,
+    /// Keep everything and refuse new data.
+    Never,
+}
+
+/// Quota of a single area.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiskQuota {
+    /// The limited area.
+    pub area: StorageArea,
+    /// Most bytes the area may take.
+    pub max_bytes: u64,
+    /// What to do when the area is full.
+    pub eviction: Eviction,
+}
+
+impl DiskQuota {
+    /// Limits `area` to `max_bytes`.
+    #[must_use]
+    pub fn new(area: StorageArea, max_bytes: u64, eviction: Eviction) -> Self {
+        DiskQuota {
+            area,
+            max_bytes,
+            eviction,
+        }
+    }
+}
+
+/// A write refused because it doesn't fit into the quota of its area.
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaExceeded {
+    /// The full area.
+    pub area: StorageArea,
+    /// Bytes the write needed.
+    pub requested_bytes: u64,
+    /// Bytes the area takes, after evicting what could be evicted.
+    pub used_bytes: u64,
+    /// The quota of the area.
+    pub max_bytes: u64,
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes don't fit into the {:?} quota ({} of {} bytes used)",
+            self.requested_bytes, self.area, self.used_bytes, self.max_bytes
+        )
+    }
+}
+
+/// Disk usage of an area, served by `/stats/disk`.
+#[derive(Debug, Clone, Serialize)]
+pub struct AreaUsage {
+    /// The area.
+    pub area: StorageArea,
+    /// Bytes the area takes.
+    pub used_bytes: u64,
+    /// The quota of the area; `None` if it is unlimited.
+    pub max_bytes: Option<u64>,
+    /// Bytes evicted from the area since the start.
+    pub evicted_bytes: u64,
+    /// Writes refused since the start.
+    pub rejected_writes: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Counters {
+    evicted_bytes: u64,
+    rejected_writes: u64,
+}
+
+/// The configured quotas together with the areas they limit.
+#[derive(Debug)]
+pub struct DiskQuotas {
+    quotas: Vec<DiskQuota>,
+    store: Arc<MessageStore>,
+    media_dir: PathBuf,
+    staging_dir: PathBuf,
+    events: Arc<EventLog>,
+    counters: Mutex<HashMap<StorageArea, Counters>>,
+}
+
+impl DiskQuotas {
+    /// Applies `quotas` to `store` and the files below `media_dir` and `staging_dir`.
+    #[must_use]
+    pub fn new(
+        quotas: &[DiskQuota],
+        store: Arc<MessageStore>,
+        media_dir: &Path,
+        staging_dir: &Path,
+        events: Arc<EventLog>,
+    ) -> Self {
+        DiskQuotas {
+            quotas: quotas.to_vec(),
+            store,
+            media_dir: media_dir.to_path_buf(),
+            staging_dir: staging_dir.to_path_buf(),
+            events,
+            counters: Mutex::default(),
+        }
+    }
+
+    /// Makes room for `bytes` more in `area`, evicting old data if its quota allows.
+    ///
+    /// # Errors
+    /// Returns [`QuotaExceeded`] if the bytes don't fit even after evicting.
+    pub fn reserve(&self, area: StorageArea, bytes: u64) -> Result<(), QuotaExceeded> {
+        let Some(quota) = self.quota(area) else {
+            return Ok(());
+        };
+        let mut used_bytes = self.usage(area);
+        if used_bytes.saturating_add(bytes) > quota.max_bytes && quota.eviction == Eviction::Oldest
+        {
+            used_bytes = self.evict(area, quota.max_bytes.saturating_sub(bytes));
+        }
+        if used_bytes.saturating_add(bytes) <= quota.max_bytes {
+            return Ok(());
+        }
+        self.lock().entry(area).or_default().rejected_writes += 1;
+        Err(QuotaExceeded {
+            area,
+            requested_bytes: bytes,
+            used_bytes,
+            max_bytes: quota.max_bytes,
+        })
+    }
+
+    /// Evicts old data from every area that is over a quota allowing it.
+    pub fn enforce(&self) {
+        for quota in &self.quotas {
+            if quota.eviction == Eviction::Oldest && self.usage(quota.area) > quota.max_bytes {
+                self.evict(quota.area, quota.max_bytes);
+            }
+        }
+    }
+
+    /// Disk usage of every area.
+    #[must_use]
+    pub fn report(&self) -> Vec<AreaUsage> {
+        let counters = self.lock().clone();
+        StorageArea::ALL
+            .into_iter()
+            .map(|area| {
+                let counters = counters.get(&area).copied().unwrap_or_default();
+                AreaUsage {
+                    area,
+                    used_bytes: self.usage(area),
+                    max_bytes: self.quota(area).map(|quota| quota.max_bytes),
+                    evicted_bytes: counters.evicted_bytes,
+                    rejected_writes: counters.rejected_writes,
+                }
+            })
+            .collect()
+    }
+
+    /// Bytes `area` takes.
+    #[must_use]
+    pub fn usage(&self, area: StorageArea) -> u64 {
+        match area {
+            StorageArea::Messages => self.store.used_bytes().unwrap_or(0),
+            StorageArea::Media => dir_size(&self.media_dir),
+            StorageArea::Staging => dir_size(&self.staging_dir),
+        }
+    }
+
+    /// Directory of the staging area.
+    #[must_use]
+    pub fn staging_dir(&self) -> &Path {
+        &self.staging_dir
+    }
+
+    fn quota(&self, area: StorageArea) -> Option<&DiskQuota> {
+        self.quotas.iter().find(|quota| quota.area == area)
+    }
+
+    /// Evicts the oldest data of `area` until it takes at most `target` bytes
+    /// or nothing is left to evict. Returns the bytes it takes afterwards.
+    fn evict(&self, area: StorageArea, target: u64) -> u64 {
+        let before = self.usage(area);
+        let after = match area {
+            StorageArea::Messages => {
+                let mut used = before;
+                while used > target {
+                    match self.store.evict_oldest(MESSAGE_EVICTION_BATCH) {
+                        Ok(0) | Err(_) => break,
+                        Ok(_) => used = self.usage(area),
+                    }
+                }
+                used
+            }
+            StorageArea::Media => evict_files(&self.media_dir, before, target),
+            StorageArea::Staging => evict_files(&self.staging_dir, before, target),
+        };
+
+        let freed_bytes = before.saturating_sub(after);
+        if freed_bytes > 0 {
+            self.lock().entry(area).or_default().evicted_bytes += freed_bytes;
+            self.events
+                .push(EventKind::QuotaEviction { area, freed_bytes });
+            tracing::info!(
+                "Evicted {freed_bytes} bytes from the {area:?} area to stay within its quota"
+            );
+        }
+        after
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<StorageArea, Counters>> {
+        self.counters.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Spawns the enforcer, which periodically evicts old data from areas over
+/// their quota, as the message database grows without going through [`DiskQuotas::reserve`].
+/// Stops once `shutdown` is disconnected.
+pub fn spawn_enforcer(quotas: Arc<DiskQuotas>, shutdown: Receiver<()>) {
+    thread::spawn(move || {
+        loop {
+            if let Err(RecvTimeoutError::Disconnected) = shutdown.recv_timeout(ENFORCE_INTERVAL) {
+                return;
+            }
+            quotas.enforce();
+        }
+    });
+}
+
+/// Files directly in `dir` with their size and modification time.
+fn files(dir: &Path) -> io::Result<Vec<(PathBuf, u64, SystemTime)>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            files.push((entry.path(), metadata.len(), modified));
+        }
+    }
+    Ok(files)
+}
+
+/// Total size of the files directly in `dir`.
+fn dir_size(dir: &Path) -> u64 {
+    files(dir).map_or(0, |files| files.iter().map(|(_, len, _)| len).sum())
+}
+
+/// Removes the least recently written files of `dir`, which takes `used`
+/// bytes, until it takes at most `target`. Returns the bytes it takes afterwards.
+fn evict_files(dir: &Path, mut used: u64, target: u64) -> u64 {
+    let Ok(mut files) = files(dir) else {
+        return used;
+    };
+    files.sort_by_key(|(_, _, modified)| *modified);
+    for (path, len, _) in files {
+        if used <= target {
+            break;
+        }
+        if fs::remove_file(&path).is_ok() {
+            used = used.saturating_sub(len);
+        }
+    }
+    used
+}
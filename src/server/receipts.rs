@@ -0,0 +1,153 @@
+//! Read receipts exchanged between peers.
+//!
+//! Like [typing notices](super::typing), read receipts are chat messages
+//! following a convention on top of `ChatRequest::SendMessage`: the text
+//! `\u{1}READ <session IDs>\u{1}`, with the session IDs separated by spaces,
+//! tells the recipient that the messages it sent in those sessions were read.
+//! Servers forward a chat message in the session it was sent in, so the
+//! author's [`DeliveryTracker`](super::delivery::DeliveryTracker) can find its
+//! messages by their session IDs and mark them read.
+//!
+//! With `ServerOptions::read_receipts` on, the messages the UI acknowledges
+//! with `/messages/ack` are passed to [`ReadReceipts::send`], which hands one
+//! receipt per author and server to the worker started by [`spawn`]. Taking
+//! messages with `/messages` sends no receipts, so read-only clients don't act
+//! on the user's behalf. Like typing notices, receipts bypass the outbox: a
+//! lost one only leaves the messages delivered. Received receipts never reach
+//! the inbox.
+
+use crossbeam_channel::{Sender, bounded};
+use messages::{ChatRequest, Message, MessageType, RequestType};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::thread;
+
+use super::dispatcher::Dispatcher;
+use super::inbox::Envelope;
+use super::session::SessionIds;
+
+/// Start of the text of a read receipt, followed by the session IDs.
+const RECEIPT_PREFIX: &str = "\u{1}READ ";
+/// End of the text of a read receipt.
+const RECEIPT_SUFFIX: &str = "\u{1}";
+/// How many batches of receipts may wait for the worker before more are dropped.
+const QUEUE_CAPACITY: usize = 64;
+
+/// Builds the read receipt node `node_id` sends to `client_id` through
+/// `server_id` for the messages it sent in the sessions `read`.
+#[must_use]
+pub fn receipt(
+    node_id: u8,
+    server_id: u8,
+    client_id: u8,
+    session_id: u64,
+    read: &[u64],
+) -> Message {
+    let sessions: Vec<_> = read.iter().map(u64::to_string).collect();
+    Message {
+        source: node_id,
+        destination: server_id,
+        session_id,
+        content: MessageType::Request(RequestType::ChatRequest(ChatRequest::SendMessage {
+            from: node_id,
+            to: client_id,
+            message: format!("{RECEIPT_PREFIX}{}{RECEIPT_SUFFIX}", sessions.join(" ")),
+        })),
+    }
+}
+
+/// Session IDs of the messages read receipt `envelope` reports as read, or
+/// `None` if it isn't a read receipt.
+#[must_use]
+pub fn read_sessions(envelope: &Envelope) -> Option<Vec<u64>> {
+    if !envelope.is_chat_message() {
+        return None;
+    }
+    envelope
+        .body_text()?
+        .strip_prefix(RECEIPT_PREFIX)?
+        .strip_suffix(RECEIPT_SUFFIX)?
+        .split(' ')
+        .map(|session_id| session_id.parse().ok())
+        .collect()
+}
+
+/// Whether `envelope` is a read receipt rather than a message to show.
+#[must_use]
+pub fn is_receipt(envelope: &Envelope) -> bool {
+    read_sessions(envelope).is_some()
+}
+
+/// Where read messages are reported to their authors, see the [module docs](self).
+#[derive(Debug)]
+pub struct ReadReceipts {
+    enabled: bool,
+    node_id: u8,
+    session_ids: Arc<SessionIds>,
+    queue: Sender<Vec<Message>>,
+}
+
+impl ReadReceipts {
+    /// Sends the authors of the chat messages among `read` a receipt for them,
+    /// one per author and server, if read receipts are enabled.
+    pub fn send(&self, read: &[Envelope]) {
+        if !self.enabled {
+            return;
+        }
+        let mut sessions: BTreeMap<(u8, u8), Vec<u64>> = BTreeMap::new();
+        for envelope in read.iter().filter(|envelope| envelope.is_chat_message()) {
+            if let (Some(server_id), Some(client_id), Some(session_id)) =
+                (envelope.source, envelope.sender(), envelope.session_id)
+                && client_id != self.node_id
+            {
+                sessions
+                    .entry((server_id, client_id))
+                    .or_default()
+                    .push(session_id);
+            }
+        }
+        if sessions.is_empty() {
+            return;
+        }
+        let receipts = sessions
+            .into_iter()
+            .map(|((server_id, client_id), read)| {
+                receipt(
+                    self.node_id,
+                    server_id,
+                    client_id,
+                    self.session_ids.next(),
+                    &read,
+                )
+            })
+            .collect();
+        if self.queue.try_send(receipts).is_err() {
+            tracing::debug!("Dropped read receipts while the backend is backed up");
+        }
+    }
+}
+
+/// Spawns the worker sending the read receipts of node `node_id`, and returns
+/// the receipts feeding it, sending none unless `enabled`. The worker stops
+/// once the receipts are dropped.
+pub fn spawn(
+    dispatcher: Dispatcher,
+    session_ids: Arc<SessionIds>,
+    node_id: u8,
+    enabled: bool,
+) -> ReadReceipts {
+    let (queue, queued) = bounded(QUEUE_CAPACITY);
+    thread::spawn(move || {
+        for receipts in queued {
+            if let Err(e) = dispatcher.send_messages(receipts) {
+                tracing::debug!("Failed to send read receipts: {e}");
+            }
+        }
+    });
+    ReadReceipts {
+        enabled,
+        node_id,
+        session_ids,
+        queue,
+    }
+}
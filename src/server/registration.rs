@@ -0,0 +1,228 @@
+//! Registration with communication servers.
+//!
+//! `/register` registers with one server and waits for its confirmation.
+//! With `ServerOptions::auto_register` set, every flood result is also handed
+//! to [`Registrar::register_discovered`], which registers in the background
+//! with each discovered server this node isn't registered with yet. The
+//! outcome of every registration is kept for `/registrations`, and a confirmed
+//! one releases the messages the outbox held for the server.
+
+use messages::{ChatRequest, Message, MessageType, RequestType};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::directory::Directory;
+use super::dispatcher::{DispatchError, Dispatcher};
+use super::inbox::Inbox;
+use super::outbox::Outbox;
+use super::session::SessionIds;
+use super::settings::LiveOptions;
+use super::stats::NetworkStats;
+use super::unix_millis;
+
+/// How a registration attempt ended.
+#[derive(Debug)]
+pub enum RegisterOutcome {
+    /// The server confirmed; carries its reply.
+    Confirmed(Value),
+    /// The server answered with an error; carries its reply.
+    Rejected(Value),
+    /// No answer arrived in time.
+    TimedOut,
+    /// The backend could not be reached.
+    Failed,
+    /// The command channel to the backend was full.
+    Busy,
+    /// The backend kept failing; carries how long until it is tried again.
+    Unavailable(Duration),
+}
+
+/// State of the latest registration with a server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegistrationState {
+    /// Waiting for the server's answer.
+    Pending,
+    /// The server confirmed.
+    Registered,
+    /// The server answered with an error.
+    Rejected,
+    /// The server did not answer in time.
+    TimedOut,
+    /// The backend could not be reached.
+    Failed,
+}
+
+/// The latest registration with a server.
+#[derive(Debug, Clone, Serialize)]
+pub struct Registration {
+    /// Where the registration stands.
+    pub state: RegistrationState,
+    /// Whether it was started after a flood rather than via `/register`.
+    pub automatic: bool,
+    /// Unix time in milliseconds of the last state change.
+    pub updated_at: u64,
+}
+
+/// Where the [`Registrar`] records the answers of servers.
+#[derive(Debug, Clone)]
+pub struct Recorders {
+    /// Records how fast each server answered, and which didn't.
+    pub network_stats: Arc<NetworkStats>,
+    /// Learns the servers that confirmed.
+    pub directory: Arc<Directory>,
+    /// Sends the messages held for the servers that confirmed.
+    pub outbox: Arc<Outbox>,
+}
+
+/// Registers this node with servers and remembers how that went.
+#[derive(Debug)]
+pub struct Registrar {
+    node_id: u8,
+    options: Arc<LiveOptions>,
+    dispatcher: Dispatcher,
+    inbox: Arc<Inbox>,
+    session_ids: Arc<SessionIds>,
+    recorders: Recorders,
+    registrations: Mutex<BTreeMap<u8, Registration>>,
+}
+
+impl Registrar {
+    /// Creates a registrar sending requests as node `node_id` and waiting up to
+    /// the current `ServerOptions::register_timeout` for each confirmation.
+    /// Answers are recorded in `recorders`.
+    #[must_use]
+    pub fn new(
+        node_id: u8,
+        options: Arc<LiveOptions>,
+        dispatcher: Dispatcher,
+        inbox: Arc<Inbox>,
+        session_ids: Arc<SessionIds>,
+        recorders: Recorders,
+    ) -> Self {
+        Registrar {
+            node_id,
+            options,
+            dispatcher,
+            inbox,
+            session_ids,
+            recorders,
+            registrations: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Sends a `Register` request to `server_id` and blocks until the server
+    /// answers or the timeout passes.
+    pub fn register(&self, server_id: u8, automatic: bool) -> RegisterOutcome {
+        self.set_state(server_id, RegistrationState::Pending, automatic);
+        let outcome = self.send_and_wait(server_id);
+        let state = match &outcome {
+            RegisterOutcome::Confirmed(_) => {
+                self.recorders.directory.add_server(server_id);
+                self.recorders.outbox.release(&[server_id]);
+                RegistrationState::Registered
+            }
+            RegisterOutcome::Rejected(_) => RegistrationState::Rejected,
+            RegisterOutcome::TimedOut => RegistrationState::TimedOut,
+            RegisterOutcome::Failed | RegisterOutcome::Busy | RegisterOutcome::Unavailable(_) => {
+                RegistrationState::Failed
+            }
+        };
+        self.set_state(server_id, state, automatic);
+        outcome
+    }
+
+    /// Registers in the background with each of `servers` that this node
+    /// isn't registered with and isn't already registering with.
+    pub fn register_discovered(self: &Arc<Self>, servers: &[u8]) {
+        let registered = self.recorders.directory.servers();
+        let new: Vec<u8> = {
+            let registrations = self.lock();
+            servers
+                .iter()
+                .copied()
+                .filter(|server_id| {
+                    !registered.contains(server_id)
+                        && registrations.get(server_id).is_none_or(|registration| {
+                            registration.state != RegistrationState::Pending
+                        })
+                })
+                .collect()
+        };
+        if new.is_empty() {
+            return;
+        }
+        for server_id in &new {
+            self.set_state(*server_id, RegistrationState::Pending, true);
+        }
+
+        let this = self.clone();
+        thread::spawn(move || {
+            for server_id in new {
+                if let RegisterOutcome::Failed
+                | RegisterOutcome::Busy
+                | RegisterOutcome::Unavailable(_) = this.register(server_id, true)
+                {
+                    tracing::warn!("Failed to auto-register with server {server_id}");
+                }
+            }
+        });
+    }
+
+    /// The latest registration with every server, by server id.
+    #[must_use]
+    pub fn registrations(&self) -> BTreeMap<u8, Registration> {
+        self.lock().clone()
+    }
+
+    fn send_and_wait(&self, server_id: u8) -> RegisterOutcome {
+        let msg = Message {
+            source: self.node_id,
+            destination: server_id,
+            session_id: self.session_ids.next(),
+            content: MessageType::Request(RequestType::ChatRequest(ChatRequest::Register)),
+        };
+        let started = Instant::now();
+        let timeout = self.options.current().register_timeout;
+        match self.inbox.request(&self.dispatcher, msg, timeout) {
+            Ok(reply) => {
+                self.recorders
+                    .network_stats
+                    .record_answer(server_id, started.elapsed());
+                if reply.is_error() {
+                    RegisterOutcome::Rejected(reply.payload)
+                } else {
+                    RegisterOutcome::Confirmed(reply.payload)
+                }
+            }
+            Err(DispatchError::Timeout) => {
+                self.recorders.network_stats.record_drop(server_id);
+                RegisterOutcome::TimedOut
+            }
+            Err(DispatchError::Disconnected) => RegisterOutcome::Failed,
+            Err(DispatchError::Busy) => RegisterOutcome::Busy,
+            Err(DispatchError::Unavailable(left)) => RegisterOutcome::Unavailable(left),
+        }
+    }
+
+    fn set_state(&self, server_id: u8, state: RegistrationState, automatic: bool) {
+        self.lock().insert(
+            server_id,
+            Registration {
+                state,
+                automatic,
+                updated_at: unix_millis(),
+            },
+        );
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<u8, Registration>> {
+        self.registrations
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,123 @@
+//! Machine-readable summary of a node's run, written on shutdown.
+
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::delivery::{DeliveryState, DeliveryTracker};
+use super::history::{Direction, History};
+use super::stats::NetworkStats;
+use super::unix_millis;
+
+/// Delivery states of the messages sent during the run.
+#[derive(Debug, Default, Serialize)]
+pub struct OutboxSummary {
+    /// Messages still waiting for the server's answer.
+    pub pending: usize,
+    /// Messages acknowledged by their server, but not known to be delivered.
+    pub acknowledged: usize,
+    /// Messages whose recipient wrote back after the server acknowledged them.
+    pub delivered: usize,
+    /// Messages the recipient sent a read receipt for.
+    pub read: usize,
+    /// Messages that could not be delivered.
+    pub failed: usize,
+}
+
+/// Summary of a node's run.
+#[derive(Debug, Serialize)]
+pub struct ShutdownReport {
+    /// The local node.
+    pub node_id: u8,
+    /// Unix time in milliseconds when the server started.
+    pub started_at: u64,
+    /// Unix time in milliseconds when the server stopped.
+    pub stopped_at: u64,
+    /// Seconds between start and stop.
+    pub uptime_secs: u64,
+    /// Chat messages sent.
+    pub messages_sent: usize,
+    /// Chat messages received.
+    pub messages_received: usize,
+    /// Chat messages that could not be handed to the backend.
+    pub send_failures: usize,
+    /// Requests to other nodes that never got an answer.
+    pub requests_dropped: u64,
+    /// Final state of the sent messages.
+    pub outbox: OutboxSummary,
+}
+
+impl ShutdownReport {
+    /// Collects the report of a run that started at `started_at`.
+    #[must_use]
+    pub fn collect(
+        node_id: u8,
+        started_at: u64,
+        history: &History,
+        deliveries: &DeliveryTracker,
+        network_stats: &NetworkStats,
+    ) -> Self {
+        let stopped_at = unix_millis();
+        let entries = history.entries();
+        let mut outbox = OutboxSummary::default();
+        for status in deliveries.sent_since(started_at) {
+            match status.state {
+                DeliveryState::Queued | DeliveryState::Sent => outbox.pending += 1,
+                DeliveryState::Acknowledged => outbox.acknowledged += 1,
+                DeliveryState::Delivered => outbox.delivered += 1,
+                DeliveryState::Read => outbox.read += 1,
+                DeliveryState::Failed => outbox.failed += 1,
+            }
+        }
+
+        ShutdownReport {
+            node_id,
+            started_at,
+            stopped_at,
+            uptime_secs: stopped_at.saturating_sub(started_at) / 1000,
+            messages_sent: entries
+                .iter()
+                .filter(|entry| entry.direction == Direction::Outgoing && entry.at >= started_at)
+                .count(),
+            messages_received: entries
+                .iter()
+                .filter(|entry| entry.direction == Direction::Incoming && entry.at >= started_at)
+                .count(),
+            send_failures: entries
+                .iter()
+                .filter(|entry| entry.failed && entry.at >= started_at)
+                .count(),
+            requests_dropped: network_stats
+                .with_nodes(|nodes| nodes.values().map(|stats| stats.dropped).sum()),
+            outbox,
+        }
+    }
+
+    /// One-line human readable summary.
+    #[must_use]
+    pub fn summary(&self) -> String {
+        format!(
+            "node {} ran {}s: {} sent ({} failed), {} received, {} requests dropped, outbox {} pending / {} acknowledged / {} delivered / {} failed",
+            self.node_id,
+            self.uptime_secs,
+            self.messages_sent,
+            self.send_failures,
+            self.messages_received,
+            self.requests_dropped,
+            self.outbox.pending,
+            self.outbox.acknowledged,
+            self.outbox.delivered,
+            self.outbox.failed,
+        )
+    }
+
+    /// Writes the report as JSON to `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be written.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_vec_pretty(self).map_err(io::Error::other)?;
+        fs::write(path, json)
+    }
+}
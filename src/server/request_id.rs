@@ -0,0 +1,57 @@
+//! IDs of HTTP requests.
+//!
+//! The [`handle`] middleware gives every request an ID, returned in the
+//! `X-Request-Id` response header. A request arriving with a usable
+//! `X-Request-Id` (e.g. set by a proxy) keeps it. The ID is a field of the
+//! request's tracing span, which the [`Dispatcher`](super::dispatcher::Dispatcher)
+//! uses as the parent of the spans of the commands it hands to the backend,
+//! so a failure the user sees can be followed through the channel logs by the
+//! ID in the response.
+
+use actix_web::Error;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use std::fmt;
+
+/// Header carrying the request ID.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+/// Longest request ID accepted from a client.
+const MAX_LEN: usize = 64;
+
+/// ID of the request being handled, stored in the request extensions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Middleware assigning every request an ID and returning it in the response.
+///
+/// # Errors
+/// Passes on the errors of the wrapped service.
+pub async fn handle<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<B>, Error> {
+    let id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_LEN)
+        .filter(|id| id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+        .map_or_else(|| format!("{:016x}", rand::random::<u64>()), str::to_string);
+    req.extensions_mut().insert(RequestId(id.clone()));
+
+    let mut response = next.call(req).await?;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+    }
+    Ok(response)
+}
@@ -0,0 +1,203 @@
+//! Types of the discovered servers.
+//!
+//! A flood only tells servers apart from clients; whether a server is a chat,
+//! text or media server has to be asked. Asking every server in turn takes
+//! the network's full latency per server, so [`ServerDirectory`] probes the
+//! servers concurrently, at most `ServerOptions::server_probe_parallelism` at
+//! a time, and caches every answer. Each entry expires on its own: a known
+//! type after `ServerOptions::server_probe_ttl`, a failed probe sooner, so an
+//! unreachable server is asked again without probing all the others.
+
+use messages::{Message, MessageType, RequestType};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::dispatcher::{DispatchError, Dispatcher};
+use super::inbox::{Envelope, Inbox};
+use super::session::SessionIds;
+use super::settings::LiveOptions;
+use super::stats::NetworkStats;
+use super::unix_millis;
+
+/// How long a failed probe is cached, at most.
+const FAILED_PROBE_TTL: Duration = Duration::from_secs(10);
+
+/// What is known about a server.
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerInfo {
+    /// Server ID.
+    pub id: u8,
+    /// Type the server reported, e.g. `Chat`, `Text` or `Media`.
+    pub server_type: Option<String>,
+    /// Round trip time of the probe in milliseconds, if the server answered.
+    pub latency_ms: Option<u64>,
+    /// Why the probe failed, if it did.
+    pub error: Option<String>,
+    /// Unix time in milliseconds of the probe.
+    pub probed_at: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    info: ServerInfo,
+    expires_at: Instant,
+}
+
+/// Cached types of the discovered servers.
+#[derive(Debug)]
+pub struct ServerDirectory {
+    node_id: u8,
+    ttl: Duration,
+    parallelism: usize,
+    options: Arc<LiveOptions>,
+    dispatcher: Dispatcher,
+    inbox: Arc<Inbox>,
+    session_ids: Arc<SessionIds>,
+    network_stats: Arc<NetworkStats>,
+    entries: Mutex<HashMap<u8, Entry>>,
+}
+
+impl ServerDirectory {
+    /// Probes from node `node_id`, at most `ServerOptions::server_probe_parallelism`
+    /// servers at a time, waiting up to the current `ServerOptions::content_timeout`
+    /// for each answer and keeping known types for `ServerOptions::server_probe_ttl`.
+    #[must_use]
+    pub fn new(
+        node_id: u8,
+        options: Arc<LiveOptions>,
+        dispatcher: Dispatcher,
+        inbox: Arc<Inbox>,
+        session_ids: Arc<SessionIds>,
+        network_stats: Arc<NetworkStats>,
+    ) -> Self {
+        let current = options.current();
+        ServerDirectory {
+            node_id,
+            ttl: current.server_probe_ttl,
+            parallelism: current.server_probe_parallelism.max(1),
+            options,
+            dispatcher,
+            inbox,
+            session_ids,
+            network_stats,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Returns what is known about each of `servers`, in the same order,
+    /// probing those without a fresh cache entry concurrently first.
+    pub fn lookup(&self, servers: &[u8]) -> Vec<ServerInfo> {
+        let now = Instant::now();
+        let stale: Vec<u8> = {
+            let entries = self.lock();
+            servers
+                .iter()
+                .copied()
+                .filter(|id| entries.get(id).is_none_or(|entry| entry.expires_at <= now))
+                .collect()
+        };
+        self.probe_all(&stale);
+
+        let entries = self.lock();
+        servers
+            .iter()
+            .filter_map(|id| entries.get(id).map(|entry| entry.info.clone()))
+            .collect()
+    }
+
+    /// Type server `server_id` reported, if it is cached.
+    #[must_use]
+    pub fn server_type(&self, server_id: u8) -> Option<String> {
+        self.lock()
+            .get(&server_id)
+            .and_then(|entry| entry.info.server_type.clone())
+    }
+
+    /// Probes `servers` with at most `parallelism` probes in flight and caches the answers.
+    fn probe_all(&self, servers: &[u8]) {
+        let queue = Mutex::new(servers.iter().copied());
+        thread::scope(|scope| {
+            for _ in 0..self.parallelism.min(servers.len()) {
+                scope.spawn(|| {
+                    loop {
+                        let next = queue.lock().unwrap_or_else(PoisonError::into_inner).next();
+                        let Some(server_id) = next else { break };
+                        let entry = self.probe(server_id);
+                        self.lock().insert(server_id, entry);
+                    }
+                });
+            }
+        });
+    }
+
+    fn probe(&self, server_id: u8) -> Entry {
+        let request = server_type_request(self.node_id, server_id, self.session_ids.next());
+        let started = Instant::now();
+        let timeout = self.options.current().content_timeout;
+        let reply = self.inbox.request(&self.dispatcher, request, timeout);
+        let latency = started.elapsed();
+        let mut info = ServerInfo {
+            id: server_id,
+            server_type: None,
+            latency_ms: None,
+            error: None,
+            probed_at: unix_millis(),
+        };
+        match reply {
+            Ok(reply) => {
+                self.network_stats.record_answer(server_id, latency);
+                info.latency_ms = Some(u64::try_from(latency.as_millis()).unwrap_or(u64::MAX));
+                match reported_type(&reply) {
+                    Some(server_type) => info.server_type = Some(server_type),
+                    None => info.error = Some(format!("Unexpected answer: {}", reply.payload)),
+                }
+            }
+            Err(e) => {
+                if e == DispatchError::Timeout {
+                    self.network_stats.record_drop(server_id);
+                }
+                info.error = Some(e.to_string());
+            }
+        }
+        let ttl = if info.server_type.is_some() {
+            self.ttl
+        } else {
+            self.ttl.min(FAILED_PROBE_TTL)
+        };
+        Entry {
+            info,
+            expires_at: Instant::now() + ttl,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<u8, Entry>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Builds the request asking server `server_id` for its type.
+fn server_type_request(node_id: u8, server_id: u8, session_id: u64) -> Message {
+    Message {
+        source: node_id,
+        destination: server_id,
+        session_id,
+        content: MessageType::Request(RequestType::DiscoveryRequest(())),
+    }
+}
+
+/// Type stated in a `DiscoveryResponse`, or `None` for other messages.
+fn reported_type(reply: &Envelope) -> Option<String> {
+    let (kind, inner) = reply.unwrap_content();
+    if kind.get(1) != Some(&"DiscoveryResponse") {
+        return None;
+    }
+    // A type carrying data is an object keyed by the type's name
+    match inner {
+        Some(Value::String(server_type)) => Some(server_type.clone()),
+        _ => kind.get(2).map(|server_type| (*server_type).to_string()),
+    }
+}
@@ -0,0 +1,30 @@
+//! Allocation of session IDs for outgoing messages.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::unix_millis;
+
+/// Hands out unique session IDs for messages built by the frontend.
+///
+/// The lowest byte of every ID is the local node id, so IDs never collide
+/// with those of other nodes. The remaining bits start at the current time
+/// and count up, so a restarted node doesn't reuse IDs of its previous run.
+#[derive(Debug)]
+pub struct SessionIds {
+    next: AtomicU64,
+}
+
+impl SessionIds {
+    /// Creates an allocator for node `node_id`.
+    #[must_use]
+    pub fn new(node_id: u8) -> Self {
+        SessionIds {
+            next: AtomicU64::new((unix_millis() << 8) | u64::from(node_id)),
+        }
+    }
+
+    /// Returns a fresh session ID.
+    pub fn next(&self) -> u64 {
+        self.next.fetch_add(1 << 8, Ordering::Relaxed)
+    }
+}
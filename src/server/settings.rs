@@ -0,0 +1,138 @@
+//! Settings that can change while the node runs.
+//!
+//! The timeouts, the flood cache TTL, the route limits and the log filter can
+//! be tuned without a restart, which would drop the messages in flight. On
+//! `SIGHUP` or `POST /admin/config/reload`, [`LiveOptions::reload`] reads the
+//! configuration again, from `ServerOptions::config_file` or else as
+//! [`config::load_from_env`] does, and swaps in the [`Tunables`] it holds.
+//! Everything else, like the address or the data directory, keeps the value
+//! the server started with. Handlers and background tasks read the settings
+//! through [`LiveOptions::current`] each time, so they see a reload at once;
+//! requests already running finish with the settings they started with.
+
+use serde::Serialize;
+use std::io;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+
+use super::ServerOptions;
+use super::config;
+use super::limits::{ConcurrencyLimits, RouteLimit};
+use super::trace;
+
+/// The settings [`LiveOptions::reload`] changes.
+#[derive(Debug, Clone, Serialize)]
+pub struct Tunables {
+    /// See `ServerOptions::register_timeout`.
+    pub register_timeout: Duration,
+    /// See `ServerOptions::content_timeout`.
+    pub content_timeout: Duration,
+    /// See `ServerOptions::backend_ping_timeout`.
+    pub backend_ping_timeout: Duration,
+    /// See `ServerOptions::flood_timeout`.
+    pub flood_timeout: Duration,
+    /// See `ServerOptions::flood_cache_ttl`.
+    pub flood_cache_ttl: Duration,
+    /// See `ServerOptions::route_limits`.
+    pub route_limits: Vec<RouteLimit>,
+    /// See `ServerOptions::log_filter`.
+    pub log_filter: Option<String>,
+}
+
+impl Tunables {
+    fn of(options: &ServerOptions) -> Self {
+        Tunables {
+            register_timeout: options.register_timeout,
+            content_timeout: options.content_timeout,
+            backend_ping_timeout: options.backend_ping_timeout,
+            flood_timeout: options.flood_timeout,
+            flood_cache_ttl: options.flood_cache_ttl,
+            route_limits: options.route_limits.clone(),
+            log_filter: options.log_filter.clone(),
+        }
+    }
+
+    fn apply(self, options: &mut ServerOptions) {
+        options.register_timeout = self.register_timeout;
+        options.content_timeout = self.content_timeout;
+        options.backend_ping_timeout = self.backend_ping_timeout;
+        options.flood_timeout = self.flood_timeout;
+        options.flood_cache_ttl = self.flood_cache_ttl;
+        options.route_limits = self.route_limits;
+        options.log_filter = self.log_filter;
+    }
+}
+
+/// The settings of the running server.
+#[derive(Debug)]
+pub struct LiveOptions {
+    current: RwLock<Arc<ServerOptions>>,
+    limits: Arc<ConcurrencyLimits>,
+}
+
+impl LiveOptions {
+    /// Starts out with `options`; reloads resize the slots of `limits`.
+    #[must_use]
+    pub fn new(options: ServerOptions, limits: Arc<ConcurrencyLimits>) -> Self {
+        LiveOptions {
+            current: RwLock::new(Arc::new(options)),
+            limits,
+        }
+    }
+
+    /// The settings as of now.
+    #[must_use]
+    pub fn current(&self) -> Arc<ServerOptions> {
+        self.current
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Reads the configuration again and applies its [`Tunables`], returning them.
+    ///
+    /// # Errors
+    /// Returns an error, keeping the current settings, if the configuration
+    /// can't be loaded or its log filter is invalid.
+    pub fn reload(&self) -> io::Result<Tunables> {
+        let current = self.current();
+        let loaded = match &current.config_file {
+            Some(path) => config::load(path)?,
+            None => config::load_from_env()?,
+        };
+        let tunables = Tunables::of(&loaded);
+        if let Some(filter) = &tunables.log_filter {
+            trace::set_filter(filter)?;
+        }
+        self.limits.replace(&tunables.route_limits);
+        let mut options = (*current).clone();
+        tunables.clone().apply(&mut options);
+        *self.current.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(options);
+        tracing::info!(?tunables, "Reloaded the configuration");
+        Ok(tunables)
+    }
+}
+
+/// Spawns a task reloading `live` whenever the process receives `SIGHUP`.
+/// Does nothing on systems without signals.
+pub fn spawn_reload_on_hangup(live: Arc<LiveOptions>) {
+    #[cfg(unix)]
+    actix_web::rt::spawn(async move {
+        use actix_web::rt::signal::unix::{SignalKind, signal};
+
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(e) => {
+                tracing::warn!("Can't reload the configuration on SIGHUP: {e}");
+                return;
+            }
+        };
+        while hangups.recv().await.is_some() {
+            if let Err(e) = live.reload() {
+                tracing::error!("Failed to reload the configuration: {e}");
+            }
+        }
+    });
+    #[cfg(not(unix))]
+    let _ = live;
+}
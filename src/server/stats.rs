@@ -0,0 +1,161 @@
+//! Network health statistics per remote node.
+//!
+//! The client only sees the end-to-end outcome of its requests, not what
+//! happens to individual packets inside the drone network. Every request
+//! that expects an answer is therefore recorded against its destination:
+//! either with the measured round trip time or as dropped when no answer
+//! arrived. Nodes whose recent behavior deviates from their own baseline
+//! are flagged and an [`EventKind::Anomaly`] is raised.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use super::events::{EventKind, EventLog};
+use super::timeseries::{Metric, TimeSeries};
+
+/// Smoothing factor of the long-term latency average.
+const BASELINE_ALPHA: f64 = 0.05;
+/// Smoothing factor of the short-term latency average.
+const RECENT_ALPHA: f64 = 0.3;
+/// Number of latest outcomes considered for the recent drop rate.
+const RECENT_WINDOW: usize = 20;
+/// Samples needed before a node's baseline is trusted.
+const MIN_SAMPLES: u64 = 10;
+/// Recent latency above this multiple of the baseline is a regression.
+const LATENCY_REGRESSION_FACTOR: f64 = 2.0;
+/// Recent drop rate this much above the long-term rate is a spike.
+const DROP_SPIKE_DELTA: f64 = 0.3;
+
+/// Kind of abnormal behavior detected on a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Anomaly {
+    /// Recent round trips are much slower than usual.
+    LatencyRegression,
+    /// Many more requests are lost than usual.
+    DropSpike,
+}
+
+/// Health statistics of a single node.
+#[derive(Debug, Default, Serialize)]
+pub struct NodeStats {
+    /// Requests answered by the node.
+    pub answered: u64,
+    /// Requests that never got an answer.
+    pub dropped: u64,
+    /// Long-term average round trip time in milliseconds.
+    pub baseline_latency_ms: Option<f64>,
+    /// Short-term average round trip time in milliseconds.
+    pub recent_latency_ms: Option<f64>,
+    /// Share of dropped requests among the latest ones.
+    pub recent_drop_rate: f64,
+    /// Currently detected anomalies.
+    pub anomalies: Vec<Anomaly>,
+    #[serde(skip)]
+    recent: VecDeque<bool>,
+}
+
+impl NodeStats {
+    fn record(&mut self, latency: Option<Duration>) {
+        if let Some(latency) = latency {
+            let millis = latency.as_secs_f64() * 1000.0;
+            self.answered += 1;
+            self.baseline_latency_ms = Some(ewma(self.baseline_latency_ms, millis, BASELINE_ALPHA));
+            self.recent_latency_ms = Some(ewma(self.recent_latency_ms, millis, RECENT_ALPHA));
+        } else {
+            self.dropped += 1;
+        }
+
+        if self.recent.len() == RECENT_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(latency.is_none());
+        let recent_drops = self.recent.iter().filter(|dropped| **dropped).count();
+        self.recent_drop_rate = recent_drops as f64 / self.recent.len() as f64;
+    }
+
+    fn detect(&self) -> Vec<Anomaly> {
+        let total = self.answered + self.dropped;
+        if total < MIN_SAMPLES {
+            return vec![];
+        }
+
+        let mut anomalies = vec![];
+        if let (Some(baseline), Some(recent)) = (self.baseline_latency_ms, self.recent_latency_ms) {
+            if recent > baseline * LATENCY_REGRESSION_FACTOR {
+                anomalies.push(Anomaly::LatencyRegression);
+            }
+        }
+        let drop_rate = self.dropped as f64 / total as f64;
+        if self.recent_drop_rate - drop_rate >= DROP_SPIKE_DELTA {
+            anomalies.push(Anomaly::DropSpike);
+        }
+        anomalies
+    }
+}
+
+fn ewma(average: Option<f64>, sample: f64, alpha: f64) -> f64 {
+    average.map_or(sample, |average| average + alpha * (sample - average))
+}
+
+/// Health statistics of every node this client talked to.
+#[derive(Debug)]
+pub struct NetworkStats {
+    nodes: Mutex<BTreeMap<u8, NodeStats>>,
+    events: Arc<EventLog>,
+    timeseries: Arc<TimeSeries>,
+}
+
+impl NetworkStats {
+    /// Creates empty statistics raising anomaly events into `events` and
+    /// recording latency and drops into `timeseries`.
+    #[must_use]
+    pub fn new(events: Arc<EventLog>, timeseries: Arc<TimeSeries>) -> Self {
+        NetworkStats {
+            nodes: Mutex::new(BTreeMap::new()),
+            events,
+            timeseries,
+        }
+    }
+
+    /// Records a request to `node` answered after `latency`.
+    pub fn record_answer(&self, node: u8, latency: Duration) {
+        self.timeseries
+            .record(Metric::Latency, latency.as_secs_f64() * 1000.0);
+        self.record(node, Some(latency));
+    }
+
+    /// Records a request to `node` that never got an answer.
+    pub fn record_drop(&self, node: u8) {
+        self.timeseries.record(Metric::Drops, 1.0);
+        self.record(node, None);
+    }
+
+    /// Runs `f` on the statistics of all nodes, ordered by node id.
+    pub fn with_nodes<R>(&self, f: impl FnOnce(&BTreeMap<u8, NodeStats>) -> R) -> R {
+        f(&self.lock())
+    }
+
+    fn record(&self, node: u8, latency: Option<Duration>) {
+        let mut nodes = self.lock();
+        let stats = nodes.entry(node).or_default();
+        stats.record(latency);
+
+        let anomalies = stats.detect();
+        for anomaly in &anomalies {
+            if !stats.anomalies.contains(anomaly) {
+                self.events.push(EventKind::Anomaly {
+                    node,
+                    anomaly: *anomaly,
+                });
+            }
+        }
+        stats.anomalies = anomalies;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<u8, NodeStats>> {
+        self.nodes.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,450 @@
+//! Persistent store of every message received from the backend.
+//!
+//! Messages are written to a local SQLite database as they arrive, so they
+//! survive restarts and can be served again after `/messages` handed them out.
+//! Derived data such as the conversation [`History`](super::history::History)
+//! can always be rebuilt from this store by replaying it.
+//!
+//! Every message carries a free-form metadata map (see [`Envelope::metadata`]),
+//! stored as one JSON column, so new kinds of metadata need no migration.
+//!
+//! Deleting a message only moves it to the trash; trashed messages can be
+//! restored until [`MessageStore::purge_trash`] removes them for good.
+
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use rusqlite::{Connection, OptionalExtension, Row, params};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use super::inbox::Envelope;
+use super::unix_millis;
+
+/// Schema migrations; the database's `user_version` is the number of applied ones.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS messages (
+        id          INTEGER PRIMARY KEY AUTOINCREMENT,
+        received_at INTEGER NOT NULL,
+        source      INTEGER,
+        session_id  INTEGER,
+        payload     TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS messages_source ON messages (source, received_at);",
+    "ALTER TABLE messages ADD COLUMN peer INTEGER;
+    ALTER TABLE messages ADD COLUMN deleted_at INTEGER;
+    CREATE INDEX messages_peer ON messages (peer, received_at);",
+    "ALTER TABLE messages ADD COLUMN read_at INTEGER;",
+    "ALTER TABLE messages ADD COLUMN metadata TEXT;",
+];
+
+/// How often the janitor looks for expired trash.
+const JANITOR_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+const COLUMNS: &str =
+    "id, received_at, source, session_id, payload, deleted_at, read_at, peer, metadata";
+
+/// A received message together with its store id.
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredMessage {
+    /// Increasing id assigned by the store.
+    pub id: u64,
+    /// Unix time in milliseconds when the message was moved to the trash.
+    pub deleted_at: Option<u64>,
+    /// Unix time in milliseconds when the message was marked as read.
+    pub read_at: Option<u64>,
+    /// Conversation a chat message belongs to; its author unless the peer was merged.
+    pub peer: Option<u8>,
+    /// The message as received.
+    #[serde(flatten)]
+    pub envelope: Envelope,
+}
+
+/// Restricts which messages are returned.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+pub struct MessageFilter {
+    /// Only messages written by this node (see [`Envelope::sender`]).
+    pub from: Option<u8>,
+    /// Only messages received at or after this Unix time in milliseconds.
+    pub since: Option<u64>,
+    /// Only messages received at or before this Unix time in milliseconds.
+    pub until: Option<u64>,
+}
+
+impl MessageFilter {
+    /// Whether `envelope` passes the filter.
+    #[must_use]
+    pub fn matches(&self, envelope: &Envelope) -> bool {
+        self.from.is_none_or(|from| envelope.sender() == Some(from))
+            && self.since.is_none_or(|since| envelope.received_at >= since)
+            && self.until.is_none_or(|until| envelope.received_at <= until)
+    }
+}
+
+/// SQL condition implementing [`MessageFilter`], bound to parameters `?1` to `?3`.
+const FILTER_CLAUSE: &str = "deleted_at IS NULL
+    AND (?1 IS NULL OR peer = ?1 OR (peer IS NULL AND source = ?1))
+    AND (?2 IS NULL OR received_at >= ?2)
+    AND (?3 IS NULL OR received_at <= ?3)";
+
+/// SQLite store of received messages.
+#[derive(Debug)]
+pub struct MessageStore {
+    conn: Mutex<Connection>,
+}
+
+impl MessageStore {
+    /// Opens (or creates) the store at `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the database can't be opened or initialized.
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        Self::init(Connection::open(path)?)
+    }
+
+    /// Opens a store that only lives in memory.
+    ///
+    /// # Errors
+    /// Returns an error if the database can't be initialized.
+    pub fn open_in_memory() -> rusqlite::Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> rusqlite::Result<Self> {
+        let applied: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+            conn.execute_batch(migration)?;
+            conn.pragma_update(None, "user_version", version + 1)?;
+        }
+        Ok(MessageStore {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Appends a received message and returns its id.
+    ///
+    /// # Errors
+    /// Returns an error if the message can't be written.
+    pub fn append(&self, envelope: &Envelope) -> rusqlite::Result<u64> {
+        let peer = envelope
+            .is_chat_message()
+            .then(|| envelope.sender())
+            .flatten();
+        let conn = self.lock();
+        conn.execute(
+            "INSERT INTO messages (received_at, source, session_id, payload, peer, metadata)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                to_sql_int(envelope.received_at),
+                envelope.source,
+                envelope.session_id.map(to_sql_int),
+                envelope.payload.to_string(),
+                peer,
+                metadata_column(&envelope.metadata),
+            ],
+        )?;
+        Ok(from_sql_int(conn.last_insert_rowid()))
+    }
+
+    /// Returns every stored message that is not in the trash, oldest first.
+    ///
+    /// # Errors
+    /// Returns an error if the messages can't be read.
+    pub fn all(&self) -> rusqlite::Result<Vec<StoredMessage>> {
+        self.select("WHERE deleted_at IS NULL ORDER BY id", [])
+    }
+
+    /// Returns every stored message in session `session_id`, trashed ones included,
+    /// oldest first.
+    ///
+    /// # Errors
+    /// Returns an error if the messages can't be read.
+    pub fn in_session(&self, session_id: u64) -> rusqlite::Result<Vec<StoredMessage>> {
+        self.select(
+            "WHERE session_id = ?1 ORDER BY id",
+            [to_sql_int(session_id)],
+        )
+    }
+
+    /// Returns up to `limit` messages outside the trash passing `filter`,
+    /// newest first, skipping the `offset` newest ones.
+    ///
+    /// # Errors
+    /// Returns an error if the messages can't be read.
+    pub fn page(
+        &self,
+        filter: &MessageFilter,
+        limit: u32,
+        offset: u32,
+    ) -> rusqlite::Result<Vec<StoredMessage>> {
+        self.select(
+            &format!("WHERE {FILTER_CLAUSE} ORDER BY id DESC LIMIT ?4 OFFSET ?5"),
+            params![
+                filter.from,
+                filter.since.map(to_sql_int),
+                filter.until.map(to_sql_int),
+                limit,
+                offset
+            ],
+        )
+    }
+
+    /// Returns the number of messages outside the trash passing `filter`.
+    ///
+    /// # Errors
+    /// Returns an error if the messages can't be counted.
+    pub fn count(&self, filter: &MessageFilter) -> rusqlite::Result<u64> {
+        self.lock()
+            .query_row(
+                &format!("SELECT COUNT(*) FROM messages WHERE {FILTER_CLAUSE}"),
+                params![
+                    filter.from,
+                    filter.since.map(to_sql_int),
+                    filter.until.map(to_sql_int)
+                ],
+                |row| row.get(0),
+            )
+            .map(from_sql_int)
+    }
+
+    /// Returns the stored message with id `id`, even if it is in the trash.
+    ///
+    /// # Errors
+    /// Returns an error if the message can't be read.
+    pub fn get(&self, id: u64) -> rusqlite::Result<Option<StoredMessage>> {
+        self.lock()
+            .query_row(
+                &format!("SELECT {COLUMNS} FROM messages WHERE id = ?1"),
+                [to_sql_int(id)],
+                read_message,
+            )
+            .optional()
+    }
+
+    /// Sets metadata `key` of message `id` to `value`, or removes it if `value` is
+    /// `None`. Returns `false` if there is no such message.
+    ///
+    /// # Errors
+    /// Returns an error if the message can't be read or updated.
+    pub fn set_metadata(&self, id: u64, key: &str, value: Option<Value>) -> rusqlite::Result<bool> {
+        let mut conn = self.lock();
+        let tx = conn.transaction()?;
+        let Some(metadata) = tx
+            .query_row(
+                "SELECT metadata FROM messages WHERE id = ?1",
+                [to_sql_int(id)],
+                |row| row.get::<_, Option<String>>(0),
+            )
+            .optional()?
+        else {
+            return Ok(false);
+        };
+        let mut metadata = parse_metadata(metadata);
+        match value {
+            Some(value) => metadata.insert(key.to_string(), value),
+            None => metadata.remove(key),
+        };
+        tx.execute(
+            "UPDATE messages SET metadata = ?1 WHERE id = ?2",
+            params![metadata_column(&metadata), to_sql_int(id)],
+        )?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Marks the messages with ids `ids` as read, unless they already are.
+    ///
+    /// # Errors
+    /// Returns an error if the messages can't be updated.
+    pub fn mark_read(&self, ids: &[u64]) -> rusqlite::Result<()> {
+        let mut conn = self.lock();
+        let tx = conn.transaction()?;
+        {
+            let mut stmt =
+                tx.prepare("UPDATE messages SET read_at = ?1 WHERE id = ?2 AND read_at IS NULL")?;
+            let now = to_sql_int(unix_millis());
+            for id in ids {
+                stmt.execute([now, to_sql_int(*id)])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// Moves message `id` to the trash. Returns `false` if there is no such
+    /// message outside the trash.
+    ///
+    /// # Errors
+    /// Returns an error if the message can't be updated.
+    pub fn trash(&self, id: u64) -> rusqlite::Result<bool> {
+        let changed = self.lock().execute(
+            "UPDATE messages SET deleted_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+            [to_sql_int(unix_millis()), to_sql_int(id)],
+        )?;
+        Ok(changed > 0)
+    }
+
+    /// Moves every chat message received from `peer` to the trash and
+    /// returns how many were moved.
+    ///
+    /// # Errors
+    /// Returns an error if the messages can't be updated.
+    pub fn trash_conversation(&self, peer: u8) -> rusqlite::Result<usize> {
+        self.lock().execute(
+            "UPDATE messages SET deleted_at = ?1 WHERE peer = ?2 AND deleted_at IS NULL",
+            params![to_sql_int(unix_millis()), peer],
+        )
+    }
+
+    /// Moves every chat message of the conversation with `old` to the
+    /// conversation with `new` and returns how many were moved.
+    ///
+    /// # Errors
+    /// Returns an error if the messages can't be updated.
+    pub fn reassign_peer(&self, old: u8, new: u8) -> rusqlite::Result<usize> {
+        self.lock()
+            .execute("UPDATE messages SET peer = ?1 WHERE peer = ?2", [new, old])
+    }
+
+    /// Returns every message in the trash, most recently deleted first.
+    ///
+    /// # Errors
+    /// Returns an error if the messages can't be read.
+    pub fn trashed(&self) -> rusqlite::Result<Vec<StoredMessage>> {
+        self.select(
+            "WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC, id",
+            [],
+        )
+    }
+
+    /// Takes message `id` out of the trash. Returns `false` if it isn't in the trash.
+    ///
+    /// # Errors
+    /// Returns an error if the message can't be updated.
+    pub fn restore(&self, id: u64) -> rusqlite::Result<bool> {
+        let changed = self.lock().execute(
+            "UPDATE messages SET deleted_at = NULL WHERE id = ?1 AND deleted_at IS NOT NULL",
+            [to_sql_int(id)],
+        )?;
+        Ok(changed > 0)
+    }
+
+    /// Permanently removes messages trashed before `before` (Unix time in
+    /// milliseconds) and returns how many were removed.
+    ///
+    /// # Errors
+    /// Returns an error if the messages can't be deleted.
+    pub fn purge_trash(&self, before: u64) -> rusqlite::Result<usize> {
+        self.lock().execute(
+            "DELETE FROM messages WHERE deleted_at < ?1",
+            [to_sql_int(before)],
+        )
+    }
+
+    /// Permanently removes up to `count` of the oldest messages that are in the
+    /// trash or already read, trashed ones first, and returns how many were removed.
+    /// Unread messages are never removed.
+    ///
+    /// # Errors
+    /// Returns an error if the messages can't be deleted.
+    pub fn evict_oldest(&self, count: usize) -> rusqlite::Result<usize> {
+        self.lock().execute(
+            "DELETE FROM messages WHERE id IN (
+                SELECT id FROM messages
+                WHERE deleted_at IS NOT NULL OR read_at IS NOT NULL
+                ORDER BY deleted_at IS NULL, received_at
+                LIMIT ?1
+            )",
+            [i64::try_from(count).unwrap_or(i64::MAX)],
+        )
+    }
+
+    /// Bytes of the database holding data; pages freed by deletions don't count.
+    ///
+    /// # Errors
+    /// Returns an error if the size can't be queried.
+    pub fn used_bytes(&self) -> rusqlite::Result<u64> {
+        self.lock()
+            .query_row(
+                "SELECT (page_count - freelist_count) * page_size
+                FROM pragma_page_count(), pragma_freelist_count(), pragma_page_size()",
+                [],
+                |row| row.get(0),
+            )
+            .map(from_sql_int)
+    }
+
+    fn select(
+        &self,
+        clause: &str,
+        params: impl rusqlite::Params,
+    ) -> rusqlite::Result<Vec<StoredMessage>> {
+        let conn = self.lock();
+        let mut stmt = conn.prepare(&format!("SELECT {COLUMNS} FROM messages {clause}"))?;
+        stmt.query_map(params, read_message)?.collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Spawns the retention janitor, which permanently removes messages that
+/// have been in the trash for longer than `retention`, until `shutdown` is disconnected.
+pub fn spawn_janitor(store: Arc<MessageStore>, retention: Duration, shutdown: Receiver<()>) {
+    let retention_ms = u64::try_from(retention.as_millis()).unwrap_or(u64::MAX);
+    thread::spawn(move || {
+        loop {
+            if let Err(RecvTimeoutError::Disconnected) = shutdown.recv_timeout(JANITOR_INTERVAL) {
+                return;
+            }
+            // Retried on the next round if the database is busy
+            let _ = store.purge_trash(unix_millis().saturating_sub(retention_ms));
+        }
+    });
+}
+
+/// Maps a row selected as [`COLUMNS`].
+fn read_message(row: &Row<'_>) -> rusqlite::Result<StoredMessage> {
+    let payload: String = row.get(4)?;
+    Ok(StoredMessage {
+        id: from_sql_int(row.get(0)?),
+        deleted_at: row.get::<_, Option<i64>>(5)?.map(from_sql_int),
+        read_at: row.get::<_, Option<i64>>(6)?.map(from_sql_int),
+        peer: row.get(7)?,
+        envelope: Envelope {
+            id: Some(from_sql_int(row.get(0)?)),
+            source: row.get(2)?,
+            session_id: row.get::<_, Option<i64>>(3)?.map(from_sql_int),
+            received_at: from_sql_int(row.get(1)?),
+            payload: serde_json::from_str(&payload).unwrap_or(Value::Null),
+            metadata: parse_metadata(row.get(8)?),
+        },
+    })
+}
+
+/// Metadata as stored in the `metadata` column; `NULL` when there is none.
+fn metadata_column(metadata: &BTreeMap<String, Value>) -> Option<String> {
+    if metadata.is_empty() {
+        return None;
+    }
+    serde_json::to_string(metadata).ok()
+}
+
+/// Reads the `metadata` column; unreadable metadata counts as none.
+fn parse_metadata(column: Option<String>) -> BTreeMap<String, Value> {
+    column
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+/// SQLite integers are signed; ids and timestamps are stored bit-for-bit.
+fn to_sql_int(value: u64) -> i64 {
+    i64::from_ne_bytes(value.to_ne_bytes())
+}
+
+fn from_sql_int(value: i64) -> u64 {
+    u64::from_ne_bytes(value.to_ne_bytes())
+}
@@ -0,0 +1,208 @@
+//! Lifecycle of a sent message, reconstructed for debugging.
+//!
+//! "My message never arrived" has many possible causes, so the timeline of a
+//! session puts everything the node knows about it in order: the outbox
+//! journal (accepted, held until a route was found, handed to the backend,
+//! retried, acknowledged, delivered or failed), the replies stored under the session ID and the
+//! anomalies raised for the server and client while the message was underway.
+//! Fragmenting, drops inside the drone network and route changes happen in
+//! the backend and only show up through those anomalies.
+
+use serde::Serialize;
+use serde_json::Value;
+
+use super::delivery::DeliveryState;
+use super::events::{Event, EventKind};
+use super::journal::{JournalOp, JournalRecord};
+use super::storage::StoredMessage;
+
+/// A step in the life of a message.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum TimelineStep {
+    /// `/send` accepted the message.
+    Accepted {
+        /// Message ID.
+        id: u64,
+        /// Server the message is sent through.
+        server_id: u8,
+        /// Client the message is addressed to.
+        client_id: u8,
+    },
+    /// No route to the server was known; the message was held.
+    AwaitingRoute,
+    /// A flood or registration found a route to the server; the held message was released.
+    RouteFound,
+    /// The message was handed to the backend.
+    HandedToBackend {
+        /// 1 for the first attempt, higher when the outbox sent it again.
+        attempt: u32,
+    },
+    /// An attempt failed and the outbox is going to send the message again.
+    Retrying {
+        /// Why the attempt failed.
+        reason: String,
+        /// The server's answer, if it rejected the message.
+        reply: Option<Value>,
+    },
+    /// The server acknowledged the message.
+    Acknowledged {
+        /// The server's answer.
+        reply: Value,
+    },
+    /// The recipient wrote back after the server acknowledged the message.
+    Delivered,
+    /// The recipient sent a read receipt for the message.
+    Read,
+    /// The message could not be sent or the server rejected it.
+    Failed {
+        /// The server's answer, if it rejected the message.
+        reply: Option<Value>,
+    },
+    /// A message with the same session ID arrived.
+    ReplyReceived {
+        /// ID of the stored message.
+        message_id: u64,
+        /// Node that sent it.
+        source: Option<u8>,
+    },
+    /// Something went wrong with the server or client while the message was underway.
+    Event {
+        /// The logged event.
+        event: Event,
+    },
+}
+
+/// A timestamped step.
+#[derive(Debug, Clone, Serialize)]
+pub struct TimelineEntry {
+    /// Unix time in milliseconds.
+    pub at: u64,
+    /// What happened.
+    #[serde(flatten)]
+    pub step: TimelineStep,
+}
+
+/// Everything known about the message sent in a session, in order.
+#[derive(Debug, Clone, Serialize)]
+pub struct Timeline {
+    /// The session.
+    pub session_id: u64,
+    /// Where the message stands now.
+    pub state: DeliveryState,
+    /// The steps, oldest first.
+    pub entries: Vec<TimelineEntry>,
+}
+
+/// Reconstructs the timeline of the message sent in session `session_id` from
+/// the outbox journal `records`, the stored `replies` in that session and the
+/// logged `events`. Returns `None` if no message was sent in the session.
+#[must_use]
+pub fn reconstruct(
+    session_id: u64,
+    records: &[JournalRecord],
+    replies: &[StoredMessage],
+    events: &[Event],
+    now: u64,
+) -> Option<Timeline> {
+    let (id, accepted_at, server_id, client_id) =
+        records.iter().find_map(|record| match &record.op {
+            JournalOp::Enqueue { id, message, .. } if message.session_id == session_id => {
+                Some((*id, record.at, message.server_id, message.client_id))
+            }
+            _ => None,
+        })?;
+
+    let mut state = DeliveryState::Queued;
+    let mut settled_at = None;
+    let mut attempts = 0;
+    let mut entries = vec![];
+    for record in records {
+        let step = match &record.op {
+            JournalOp::Enqueue { id: enqueued, .. } if *enqueued == id => TimelineStep::Accepted {
+                id,
+                server_id,
+                client_id,
+            },
+            JournalOp::Hold { id: held } if *held == id => TimelineStep::AwaitingRoute,
+            JournalOp::Release { id: released } if *released == id => TimelineStep::RouteFound,
+            JournalOp::Attempt { id: attempted } if *attempted == id => {
+                state = DeliveryState::Sent;
+                attempts += 1;
+                TimelineStep::HandedToBackend { attempt: attempts }
+            }
+            JournalOp::Retry {
+                id: retried,
+                reason,
+                reply,
+            } if *retried == id => {
+                state = DeliveryState::Queued;
+                TimelineStep::Retrying {
+                    reason: reason.clone(),
+                    reply: reply.clone(),
+                }
+            }
+            JournalOp::Ack { id: acked, reply } if *acked == id => {
+                state = DeliveryState::Acknowledged;
+                settled_at = Some(record.at);
+                TimelineStep::Acknowledged {
+                    reply: reply.clone(),
+                }
+            }
+            JournalOp::Deliver { id: delivered } if *delivered == id => {
+                state = DeliveryState::Delivered;
+                TimelineStep::Delivered
+            }
+            JournalOp::Read { id: read } if *read == id => {
+                state = DeliveryState::Read;
+                TimelineStep::Read
+            }
+            JournalOp::Fail { id: failed, reply } if *failed == id => {
+                state = DeliveryState::Failed;
+                settled_at = Some(record.at);
+                TimelineStep::Failed {
+                    reply: reply.clone(),
+                }
+            }
+            _ => continue,
+        };
+        entries.push(TimelineEntry {
+            at: record.at,
+            step,
+        });
+    }
+
+    entries.extend(replies.iter().map(|reply| TimelineEntry {
+        at: reply.envelope.received_at,
+        step: TimelineStep::ReplyReceived {
+            message_id: reply.id,
+            source: reply.envelope.source,
+        },
+    }));
+
+    // Only what happened to the nodes involved while the message was underway
+    let until = settled_at.unwrap_or(now);
+    entries.extend(
+        events
+            .iter()
+            .filter(|event| (accepted_at..=until).contains(&event.at))
+            .filter(|event| match &event.kind {
+                EventKind::Anomaly { node, .. } => *node == server_id || *node == client_id,
+                _ => false,
+            })
+            .map(|event| TimelineEntry {
+                at: event.at,
+                step: TimelineStep::Event {
+                    event: event.clone(),
+                },
+            }),
+    );
+
+    // Stable, so journal order wins for steps in the same millisecond
+    entries.sort_by_key(|entry| entry.at);
+    Some(Timeline {
+        session_id,
+        state,
+        entries,
+    })
+}
@@ -0,0 +1,180 @@
+//! Downsampled time series of key network metrics.
+//!
+//! Samples are aggregated into fixed-width buckets. Once a series holds
+//! [`MAX_BUCKETS`] buckets, neighboring buckets are merged and the bucket
+//! width doubles, so a whole session fits into bounded memory with
+//! progressively coarser resolution.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use super::unix_millis;
+
+/// Width of the buckets of a fresh series.
+const INITIAL_RESOLUTION_MS: u64 = 1000;
+/// Bucket count at which a series is downsampled.
+const MAX_BUCKETS: usize = 720;
+
+/// Recorded metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// Round trip time of answered requests, in milliseconds.
+    Latency,
+    /// Requests that never got an answer.
+    Drops,
+    /// Chat bytes sent and received.
+    Throughput,
+    /// Round trip time of backend pings, in milliseconds.
+    BackendLatency,
+}
+
+impl Metric {
+    /// Every recorded metric.
+    pub const ALL: [Metric; 4] = [
+        Metric::Latency,
+        Metric::Drops,
+        Metric::Throughput,
+        Metric::BackendLatency,
+    ];
+
+    /// Name of the metric as used in the API.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Metric::Latency => "latency",
+            Metric::Drops => "drops",
+            Metric::Throughput => "throughput",
+            Metric::BackendLatency => "backend_latency",
+        }
+    }
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Metric::ALL
+            .into_iter()
+            .find(|metric| metric.name() == name)
+            .ok_or_else(|| format!("Unknown metric `{name}`"))
+    }
+}
+
+/// Aggregate of the samples recorded within one bucket.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Point {
+    /// Unix time in milliseconds at which the bucket starts.
+    pub at: u64,
+    /// Width of the bucket in milliseconds.
+    pub width: u64,
+    /// Number of samples.
+    pub count: u64,
+    /// Sum of the samples.
+    pub sum: f64,
+    /// Smallest sample.
+    pub min: f64,
+    /// Largest sample.
+    pub max: f64,
+}
+
+impl Point {
+    fn merge(&mut self, other: &Point) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+}
+
+#[derive(Debug)]
+struct Series {
+    resolution: u64,
+    points: Vec<Point>,
+}
+
+impl Default for Series {
+    fn default() -> Self {
+        Series {
+            resolution: INITIAL_RESOLUTION_MS,
+            points: vec![],
+        }
+    }
+}
+
+impl Series {
+    fn record(&mut self, at: u64, value: f64) {
+        let sample = Point {
+            at: at - at % self.resolution,
+            width: self.resolution,
+            count: 1,
+            sum: value,
+            min: value,
+            max: value,
+        };
+        match self.points.last_mut() {
+            Some(last) if last.at == sample.at => last.merge(&sample),
+            _ => self.points.push(sample),
+        }
+        if self.points.len() > MAX_BUCKETS {
+            self.downsample();
+        }
+    }
+
+    fn downsample(&mut self) {
+        self.resolution *= 2;
+        let mut merged: Vec<Point> = Vec::with_capacity(self.points.len() / 2 + 1);
+        for point in &self.points {
+            let at = point.at - point.at % self.resolution;
+            match merged.last_mut() {
+                Some(last) if last.at == at => last.merge(point),
+                _ => merged.push(Point {
+                    at,
+                    width: self.resolution,
+                    ..*point
+                }),
+            }
+        }
+        self.points = merged;
+    }
+}
+
+/// Time series of every [`Metric`].
+#[derive(Debug, Default)]
+pub struct TimeSeries {
+    series: Mutex<HashMap<Metric, Series>>,
+}
+
+impl TimeSeries {
+    /// Records a sample of `metric` taken now.
+    pub fn record(&self, metric: Metric, value: f64) {
+        self.lock()
+            .entry(metric)
+            .or_default()
+            .record(unix_millis(), value);
+    }
+
+    /// Returns the buckets of `metric`, limited to the last `range_ms`
+    /// milliseconds if given.
+    #[must_use]
+    pub fn query(&self, metric: Metric, range_ms: Option<u64>) -> Vec<Point> {
+        let since = range_ms.map_or(0, |range| unix_millis().saturating_sub(range));
+        self.lock()
+            .get(&metric)
+            .map(|series| {
+                series
+                    .points
+                    .iter()
+                    .filter(|point| point.at + point.width > since)
+                    .copied()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<Metric, Series>> {
+        self.series.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
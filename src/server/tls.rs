@@ -0,0 +1,61 @@
+//! Serving the API over HTTPS.
+//!
+//! With `ServerOptions::tls` set, the server binds with rustls instead of
+//! plain HTTP, so the node can be exposed beyond localhost without a proxy
+//! in front of it. The certificate chain and the private key are read from
+//! PEM files once at startup; a missing or unusable file stops the server
+//! from starting rather than silently falling back to plaintext.
+
+use rustls::ServerConfig;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+/// Where to find the certificate and key to serve HTTPS with.
+#[derive(Debug, Clone, Serialize)]
+pub struct TlsOptions {
+    /// PEM file with the certificate chain, the server's certificate first.
+    pub cert_path: PathBuf,
+    /// PEM file with the private key of the certificate (PKCS#8, PKCS#1 or SEC1).
+    pub key_path: PathBuf,
+}
+
+impl TlsOptions {
+    /// Reads the certificate and key into a rustls server configuration.
+    ///
+    /// # Errors
+    /// Returns an error if a file can't be read, holds no certificate or key,
+    /// or the key doesn't match the certificate.
+    pub fn load(&self) -> io::Result<ServerConfig> {
+        let certs = read_certs(&self.cert_path)?;
+        let key = read_key(&self.key_path)?;
+        ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+fn read_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<io::Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("No certificate in {}", path.display()),
+        ));
+    }
+    Ok(certs)
+}
+
+fn read_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    rustls_pemfile::private_key(&mut reader)?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("No private key in {}", path.display()),
+        )
+    })
+}
@@ -0,0 +1,115 @@
+//! The network as far as the frontend knows it, for drawing it.
+//!
+//! The backend only reports the edge nodes (clients and servers) found by the
+//! last flood, not the drones or links between them. The edges are therefore
+//! the links the frontend learned itself: the servers this node registered
+//! with and the clients each server listed.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use wg_2024::packet::NodeType;
+
+use super::directory::Directory;
+use super::flood::EdgeNode;
+
+/// Type of a node in the [`Topology`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeKind {
+    /// A client, including this node.
+    Client,
+    /// A drone forwarding packets.
+    Drone,
+    /// A chat server.
+    Server,
+}
+
+impl From<&NodeType> for NodeKind {
+    fn from(node_type: &NodeType) -> Self {
+        match node_type {
+            NodeType::Client => NodeKind::Client,
+            NodeType::Drone => NodeKind::Drone,
+            NodeType::Server => NodeKind::Server,
+        }
+    }
+}
+
+/// A node of the [`Topology`].
+#[derive(Debug, Clone, Serialize)]
+pub struct TopologyNode {
+    /// Node ID.
+    pub id: u8,
+    /// Node type.
+    #[serde(rename = "type")]
+    pub kind: NodeKind,
+}
+
+/// How the ends of an [`Edge`] are known to be linked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeKind {
+    /// This node is registered with the server.
+    Registered,
+    /// The server listed the client as connected.
+    Connected,
+}
+
+/// A link between two nodes of the [`Topology`].
+#[derive(Debug, Clone, Serialize)]
+pub struct Edge {
+    /// Client end of the link.
+    pub from: u8,
+    /// Server end of the link.
+    pub to: u8,
+    /// How the link is known.
+    pub kind: EdgeKind,
+}
+
+/// Known nodes and links.
+#[derive(Debug, Clone, Serialize)]
+pub struct Topology {
+    /// Every known node, ordered by ID.
+    pub nodes: Vec<TopologyNode>,
+    /// Every known link.
+    pub edges: Vec<Edge>,
+}
+
+impl Topology {
+    /// Combines the nodes of the last flood with the links in `directory`,
+    /// as seen from node `node_id`.
+    #[must_use]
+    pub fn build(node_id: u8, edge_nodes: &[EdgeNode], directory: &Directory) -> Self {
+        let mut nodes = BTreeMap::from([(node_id, NodeKind::Client)]);
+        for (id, node_type) in edge_nodes {
+            nodes.insert(*id, node_type.into());
+        }
+
+        let mut edges = vec![];
+        for server_id in directory.servers() {
+            nodes.insert(server_id, NodeKind::Server);
+            edges.push(Edge {
+                from: node_id,
+                to: server_id,
+                kind: EdgeKind::Registered,
+            });
+            for client_id in directory.clients(server_id) {
+                nodes.entry(client_id).or_insert(NodeKind::Client);
+                if client_id != node_id {
+                    edges.push(Edge {
+                        from: client_id,
+                        to: server_id,
+                        kind: EdgeKind::Connected,
+                    });
+                }
+            }
+        }
+
+        Topology {
+            nodes: nodes
+                .into_iter()
+                .map(|(id, kind)| TopologyNode { id, kind })
+                .collect(),
+            edges,
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! Tracing of requests through the server.
+//!
+//! The [`handle`] middleware opens a span for every HTTP request carrying the
+//! node ID, request ID (see [`request_id`](super::request_id)), method and path, and records the matched route and the status
+//! once the handler is done. Everything logged while the request is handled, down to
+//! the requests it sends to servers (see [`Inbox::request`]), ends up in that
+//! span. The dispatcher adds spans for the commands it hands to the backend
+//! and an event for every message the backend delivers, with node and session
+//! IDs as fields, so a message can be followed by its session ID.
+//!
+//! Which spans and events are printed is controlled by `RUST_LOG`, see
+//! [`init_subscriber`], or by `ServerOptions::log_filter`, which can be changed
+//! while the node runs, see [`set_filter`].
+//!
+//! [`Inbox::request`]: super::inbox::Inbox::request
+
+use actix_web::Error;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::web;
+use std::io;
+use std::sync::OnceLock;
+use tracing::Instrument;
+use tracing::field::Empty;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::Formatter;
+use tracing_subscriber::reload::Handle;
+
+use super::request_id::RequestId;
+
+/// Installs a subscriber printing spans and events as filtered by `RUST_LOG`,
+/// `info` by default. Records of the `log` crate, e.g. from the backend, are
+/// printed as well. Does nothing if a subscriber is installed already, as when
+/// several nodes run in the same process.
+pub fn init_subscriber() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_filter_reloading();
+    let handle = builder.reload_handle();
+    if builder.try_init().is_ok() {
+        let _ = FILTER.set(handle);
+    }
+}
+
+/// Swaps the filter of the subscriber installed by [`init_subscriber`].
+static FILTER: OnceLock<Handle<EnvFilter, Formatter>> = OnceLock::new();
+
+/// Replaces the filter of the subscriber installed by [`init_subscriber`] with
+/// `directives`, in the syntax of `RUST_LOG`. Does nothing if another
+/// subscriber was installed.
+///
+/// # Errors
+/// Returns an error if `directives` are invalid.
+pub fn set_filter(directives: &str) -> io::Result<()> {
+    let filter = EnvFilter::try_new(directives).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid log filter {directives}: {e}"),
+        )
+    })?;
+    if let Some(handle) = FILTER.get() {
+        handle.reload(filter).map_err(io::Error::other)?;
+    }
+    Ok(())
+}
+
+/// Middleware running every request in a `request` span.
+///
+/// # Errors
+/// Passes on the errors of the wrapped service.
+pub async fn handle<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<B>, Error> {
+    let node_id = req.app_data::<web::Data<u8>>().map(|node_id| **node_id);
+    let request_id = req.extensions().get::<RequestId>().cloned();
+    let span = tracing::info_span!(
+        "request",
+        node_id,
+        request_id = request_id.as_ref().map(tracing::field::display),
+        method = %req.method(),
+        path = req.path(),
+        route = Empty,
+        status = Empty,
+    );
+    let response = next.call(req).instrument(span.clone()).await?;
+    // Routes are only matched inside the app, after this middleware
+    if let Some(route) = response.request().match_pattern() {
+        span.record("route", route);
+    }
+    span.record("status", response.status().as_u16());
+    tracing::debug!(parent: &span, "Request handled");
+    Ok(response)
+}
@@ -0,0 +1,84 @@
+//! Typing indicators relayed between peers.
+//!
+//! The protocol has no message telling a peer that someone is typing, so this
+//! node uses a convention on top of `ChatRequest::SendMessage`: a chat message
+//! whose text is exactly [`TYPING_NOTICE`], a control character framed tag like
+//! the CTCP messages of IRC, means its author is typing. `/typing` sends one
+//! without going through the outbox, as a lost notice doesn't matter.
+//!
+//! Received notices never reach the inbox, the history or the away mode. The
+//! [`TypingRelay`] raises an [`EventKind::Typing`] for the author instead, at
+//! most once per [`EVENT_INTERVAL_MS`], so the UI polling `/events` can show
+//! that the peer is typing without the notices crowding out the other events.
+
+use messages::{ChatRequest, Message, MessageType, RequestType};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use super::events::{EventKind, EventLog};
+use super::inbox::Envelope;
+
+/// Text of a chat message telling the recipient that its author is typing.
+pub const TYPING_NOTICE: &str = "\u{1}TYPING\u{1}";
+
+/// How long after an event for a peer further notices of it raise none, in milliseconds.
+pub const EVENT_INTERVAL_MS: u64 = 3000;
+
+/// Builds the typing notice node `node_id` sends to `client_id` through `server_id`.
+#[must_use]
+pub fn notice(node_id: u8, server_id: u8, client_id: u8, session_id: u64) -> Message {
+    Message {
+        source: node_id,
+        destination: server_id,
+        session_id,
+        content: MessageType::Request(RequestType::ChatRequest(ChatRequest::SendMessage {
+            from: node_id,
+            to: client_id,
+            message: TYPING_NOTICE.to_string(),
+        })),
+    }
+}
+
+/// Whether `envelope` is a typing notice rather than a message to show.
+#[must_use]
+pub fn is_notice(envelope: &Envelope) -> bool {
+    envelope.is_chat_message() && envelope.body_text() == Some(TYPING_NOTICE)
+}
+
+/// Turns received typing notices into events, see the [module docs](self).
+#[derive(Debug)]
+pub struct TypingRelay {
+    events: Arc<EventLog>,
+    // When the last event was raised, per peer
+    raised: Mutex<BTreeMap<u8, u64>>,
+}
+
+impl TypingRelay {
+    /// A relay raising its events in `events`.
+    #[must_use]
+    pub fn new(events: Arc<EventLog>) -> Self {
+        TypingRelay {
+            events,
+            raised: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Raises an event for the author of typing notice `envelope`, unless one
+    /// was raised for it within [`EVENT_INTERVAL_MS`].
+    pub fn observe(&self, envelope: &Envelope) {
+        let Some(peer) = envelope.sender() else {
+            return;
+        };
+        let mut raised = self.lock();
+        let last = raised.entry(peer).or_default();
+        if envelope.received_at < last.saturating_add(EVENT_INTERVAL_MS) {
+            return;
+        }
+        *last = envelope.received_at;
+        self.events.push(EventKind::Typing { peer });
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<u8, u64>> {
+        self.raised.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,333 @@
+//! Uploads of files to content servers.
+//!
+//! An uploaded file is first staged in the data directory's staging area,
+//! which counts against its quota. [`Uploads`] then sends it to the content
+//! server in the background, one chunk per request, and waits for each chunk
+//! to be acknowledged before sending the next one. The progress of every chunk
+//! can be followed at `/content/uploads/{id}`.
+//!
+//! Staged files are moved into a [`BlobStore`] while they are sent, so the
+//! same file uploaded to many servers at once is staged only once.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Instant;
+
+use super::blobs::{BlobStore, StorageSavings};
+use super::content;
+use super::dispatcher::{DispatchError, Dispatcher};
+use super::inbox::Inbox;
+use super::quota::{DiskQuotas, QuotaExceeded, StorageArea};
+use super::session::SessionIds;
+use super::settings::LiveOptions;
+use super::stats::NetworkStats;
+
+/// Number of uploads whose progress is kept.
+const MAX_UPLOADS: usize = 32;
+
+/// Where a chunk stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkState {
+    /// Not sent yet.
+    Pending,
+    /// Sent, waiting for the server's acknowledgement.
+    Sent,
+    /// Acknowledged by the server.
+    Acknowledged,
+    /// Rejected by the server or not acknowledged in time.
+    Failed,
+}
+
+/// Where an upload stands.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub enum UploadState {
+    /// Chunks are being sent.
+    Running,
+    /// Every chunk was acknowledged.
+    Done {
+        /// ID of the file on the server, if it reported one.
+        file_id: Option<u64>,
+    },
+    /// A chunk failed; the remaining ones were not sent.
+    Failed {
+        /// What went wrong.
+        error: String,
+    },
+}
+
+/// Progress of an upload.
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadProgress {
+    /// Upload ID.
+    pub id: u64,
+    /// The content server the file goes to.
+    pub server_id: u8,
+    /// File name.
+    pub name: String,
+    /// File size in bytes.
+    pub bytes: u64,
+    /// Number of acknowledged chunks.
+    pub acknowledged: usize,
+    /// State of every chunk, in order.
+    pub chunks: Vec<ChunkState>,
+    /// State of the upload as a whole.
+    #[serde(flatten)]
+    pub state: UploadState,
+}
+
+/// Reasons a file can't be staged.
+#[derive(Debug)]
+pub enum StageError {
+    /// The file doesn't fit into the staging quota.
+    Quota(QuotaExceeded),
+    /// The file could not be written.
+    Io(io::Error),
+}
+
+impl fmt::Display for StageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StageError::Quota(e) => write!(f, "{e}"),
+            StageError::Io(e) => write!(f, "Failed to stage the file: {e}"),
+        }
+    }
+}
+
+/// A file being written to the staging area. Removed again when dropped
+/// before [`StagedFile::finish`] is called.
+#[derive(Debug)]
+pub struct StagedFile {
+    path: Option<PathBuf>,
+    file: fs::File,
+    quotas: Arc<DiskQuotas>,
+}
+
+impl StagedFile {
+    /// Creates an empty file in the staging area of `quotas`.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be created.
+    pub fn create(quotas: Arc<DiskQuotas>) -> io::Result<Self> {
+        let path = quotas
+            .staging_dir()
+            .join(format!("upload-{:016x}.part", rand::random::<u64>()));
+        Ok(StagedFile {
+            file: fs::File::create(&path)?,
+            path: Some(path),
+            quotas,
+        })
+    }
+
+    /// Appends `bytes` to the file if they fit into the staging quota.
+    ///
+    /// # Errors
+    /// Returns a [`StageError`] if they don't fit or can't be written.
+    pub fn append(&mut self, bytes: &[u8]) -> Result<(), StageError> {
+        self.quotas
+            .reserve(StorageArea::Staging, bytes.len() as u64)
+            .map_err(StageError::Quota)?;
+        self.file.write_all(bytes).map_err(StageError::Io)
+    }
+
+    /// Keeps the file and returns its path.
+    #[must_use]
+    pub fn finish(mut self) -> PathBuf {
+        self.path.take().unwrap_or_default()
+    }
+}
+
+impl Drop for StagedFile {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Jobs {
+    next_id: u64,
+    by_id: BTreeMap<u64, UploadProgress>,
+}
+
+/// Uploads running in the background and the progress of the latest ones.
+#[derive(Debug)]
+pub struct Uploads {
+    node_id: u8,
+    chunk_size: usize,
+    options: Arc<LiveOptions>,
+    blobs: BlobStore,
+    dispatcher: Dispatcher,
+    inbox: Arc<Inbox>,
+    session_ids: Arc<SessionIds>,
+    network_stats: Arc<NetworkStats>,
+    jobs: Mutex<Jobs>,
+}
+
+impl Uploads {
+    /// Uploads from node `node_id` in chunks of `ServerOptions::upload_chunk_size`
+    /// bytes, waiting up to the current `ServerOptions::content_timeout` for each
+    /// chunk to be acknowledged. Staged files are kept in `blobs` while they are sent.
+    #[must_use]
+    pub fn new(
+        node_id: u8,
+        options: Arc<LiveOptions>,
+        blobs: BlobStore,
+        dispatcher: Dispatcher,
+        inbox: Arc<Inbox>,
+        session_ids: Arc<SessionIds>,
+        network_stats: Arc<NetworkStats>,
+    ) -> Self {
+        Uploads {
+            node_id,
+            chunk_size: options.current().upload_chunk_size.max(1),
+            options,
+            blobs,
+            dispatcher,
+            inbox,
+            session_ids,
+            network_stats,
+            jobs: Mutex::default(),
+        }
+    }
+
+    /// Starts sending the file staged at `staged` to server `server_id` as `name`
+    /// and returns the upload ID. The staged file is removed once the upload ends.
+    pub fn start(self: &Arc<Self>, server_id: u8, name: String, staged: PathBuf) -> u64 {
+        let bytes = fs::metadata(&staged).map_or(0, |metadata| metadata.len());
+        let chunks = usize::try_from(bytes.div_ceil(self.chunk_size as u64))
+            .unwrap_or(usize::MAX)
+            .max(1);
+
+        let mut jobs = self.lock();
+        jobs.next_id += 1;
+        let id = jobs.next_id;
+        jobs.by_id.insert(
+            id,
+            UploadProgress {
+                id,
+                server_id,
+                name: name.clone(),
+                bytes,
+                acknowledged: 0,
+                chunks: vec![ChunkState::Pending; chunks],
+                state: UploadState::Running,
+            },
+        );
+        while jobs.by_id.len() > MAX_UPLOADS {
+            jobs.by_id.pop_first();
+        }
+        drop(jobs);
+
+        let this = self.clone();
+        thread::spawn(move || {
+            let reference = format!("upload-{id}");
+            let state = match this.blobs.put_file(&reference, &staged) {
+                Ok(blob) => {
+                    let state = this.send(id, server_id, &name, &blob);
+                    this.blobs.release(&reference);
+                    state
+                }
+                Err(_) => {
+                    let state = this.send(id, server_id, &name, &staged);
+                    let _ = fs::remove_file(&staged);
+                    state
+                }
+            };
+            this.update(id, |progress| progress.state = state);
+        });
+        id
+    }
+
+    /// How much space staging every file once saves.
+    #[must_use]
+    pub fn savings(&self) -> StorageSavings {
+        self.blobs.savings()
+    }
+
+    /// Returns the progress of upload `id`, unless it is unknown or too old.
+    #[must_use]
+    pub fn get(&self, id: u64) -> Option<UploadProgress> {
+        self.lock().by_id.get(&id).cloned()
+    }
+
+    /// Sends the chunks of the staged file one after another.
+    fn send(&self, id: u64, server_id: u8, name: &str, staged: &Path) -> UploadState {
+        let data = match fs::read(staged) {
+            Ok(data) => data,
+            Err(e) => {
+                return UploadState::Failed {
+                    error: format!("Failed to read the staged file: {e}"),
+                };
+            }
+        };
+        let chunks: Vec<&[u8]> = if data.is_empty() {
+            vec![&[]]
+        } else {
+            data.chunks(self.chunk_size).collect()
+        };
+        let total = chunks.len() as u64;
+
+        let mut file_id = None;
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            self.set_chunk(id, index, ChunkState::Sent);
+            let request = content::upload_chunk(
+                self.node_id,
+                server_id,
+                self.session_ids.next(),
+                name,
+                (index as u64, total),
+                chunk.to_vec(),
+            );
+            let started = Instant::now();
+            let timeout = self.options.current().content_timeout;
+            let reply = self.inbox.request(&self.dispatcher, request, timeout);
+            let error = match reply {
+                Ok(reply) if !reply.is_error() => {
+                    self.network_stats
+                        .record_answer(server_id, started.elapsed());
+                    file_id = content::uploaded_file_id(&reply).or(file_id);
+                    self.set_chunk(id, index, ChunkState::Acknowledged);
+                    self.update(id, |progress| progress.acknowledged += 1);
+                    continue;
+                }
+                Ok(reply) => format!("Server rejected chunk {index}: {}", reply.payload),
+                Err(e) => {
+                    if e == DispatchError::Timeout {
+                        self.network_stats.record_drop(server_id);
+                    }
+                    format!("Chunk {index}: {e}")
+                }
+            };
+            self.set_chunk(id, index, ChunkState::Failed);
+            return UploadState::Failed { error };
+        }
+        UploadState::Done { file_id }
+    }
+
+    fn set_chunk(&self, id: u64, index: usize, state: ChunkState) {
+        self.update(id, |progress| {
+            if let Some(chunk) = progress.chunks.get_mut(index) {
+                *chunk = state;
+            }
+        });
+    }
+
+    fn update(&self, id: u64, f: impl FnOnce(&mut UploadProgress)) {
+        if let Some(progress) = self.lock().by_id.get_mut(&id) {
+            f(progress);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Jobs> {
+        self.jobs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,111 @@
+//! Validation of request bodies, above all of `/send` and `/register`.
+//!
+//! JSON bodies larger than `ServerOptions::json_limit` are refused with HTTP 413
+//! before they are parsed, see [`json_config`], and so are chat messages
+//! longer than `ServerOptions::max_message_len`: the backend would split
+//! them into a flood of fragments on the drone network.
+//!
+//! Payloads are checked before anything goes to the backend: node IDs must
+//! fit the protocol's `u8` and must not be this node, servers must have been
+//! discovered by a flood or registered with, and messages must not be empty.
+//! As long as no server is known at all, e.g. right after startup, server IDs
+//! are taken as they are. Every failed check is reported under the name of
+//! its field and answered with HTTP 400.
+
+use actix_web::error::{InternalError, JsonPayloadError};
+use actix_web::{HttpResponse, web};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The failed checks of a payload, by field.
+#[derive(Debug, Default, Serialize)]
+pub struct ValidationErrors {
+    errors: BTreeMap<&'static str, String>,
+}
+
+impl ValidationErrors {
+    /// Reports that `field` is invalid because of `reason`.
+    pub fn add(&mut self, field: &'static str, reason: impl Into<String>) {
+        self.errors.entry(field).or_insert_with(|| reason.into());
+    }
+
+    /// Checks that `field` holds a node ID other than this node `node_id`.
+    pub fn node_id(&mut self, field: &'static str, value: i64, node_id: u8) -> Option<u8> {
+        let Ok(id) = u8::try_from(value) else {
+            self.add(field, format!("{value} is not a node ID (0-{})", u8::MAX));
+            return None;
+        };
+        if id == node_id {
+            self.add(field, "Must not be this node");
+            return None;
+        }
+        Some(id)
+    }
+
+    /// Like [`ValidationErrors::node_id`], and checks that the ID is among the
+    /// `known` servers, unless none is known.
+    pub fn server_id(
+        &mut self,
+        field: &'static str,
+        value: i64,
+        node_id: u8,
+        known: &BTreeSet<u8>,
+    ) -> Option<u8> {
+        let id = self.node_id(field, value, node_id)?;
+        if !known.is_empty() && !known.contains(&id) {
+            self.add(field, format!("Server {id} was never discovered"));
+            return None;
+        }
+        Some(id)
+    }
+
+    /// Checks that the text in `field` isn't empty or only whitespace.
+    pub fn non_empty(&mut self, field: &'static str, text: &str) {
+        if text.trim().is_empty() {
+            self.add(field, "Must not be empty");
+        }
+    }
+
+    /// Whether every check passed.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// The failed checks as an HTTP 400 response.
+    #[must_use]
+    pub fn response(&self) -> HttpResponse {
+        HttpResponse::BadRequest().json(self)
+    }
+}
+
+/// JSON extractor settings refusing bodies larger than `limit` bytes with
+/// HTTP 413 and the limit; other malformed bodies get the usual HTTP 400.
+#[must_use]
+pub fn json_config(limit: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(limit)
+        .error_handler(|e, _| match e {
+            JsonPayloadError::Overflow { limit }
+            | JsonPayloadError::OverflowKnownLength { limit, .. } => {
+                let response = HttpResponse::PayloadTooLarge()
+                    .json(format!("The body exceeds the limit of {limit} bytes"));
+                InternalError::from_response(e, response).into()
+            }
+            e => e.into(),
+        })
+}
+
+/// Refuses a chat `message` longer than `max_len` bytes with HTTP 413.
+///
+/// # Errors
+/// Returns the response to answer with if the message is too long.
+pub fn check_message_len(message: &str, max_len: usize) -> Result<(), HttpResponse> {
+    if message.len() > max_len {
+        return Err(HttpResponse::PayloadTooLarge().json(format!(
+            "The message is {} bytes long, at most {max_len} are allowed",
+            message.len()
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,236 @@
+//! Supervision of the backend thread.
+//!
+//! The backend runs on a thread of its own. If it panics, the [`Supervisor`]
+//! makes a new one after [`RESTART_DELAY`] and records the restart in the
+//! [`BackendProbe`], which has the dispatcher fail the requests the old backend
+//! never answered, so requests don't silently go nowhere; `/admin/backend/restart`
+//! replaces a wedged backend the same way. A stuck thread can't be stopped, so
+//! every backend gets channels of its own, fed through [`Switch`]es: making a new
+//! one moves what is still queued over and cuts the old one off, leaving it to
+//! exit once it notices. A backend that exits on its own, rather than
+//! because the client stops, is reported as [`Error::Disconnected`].
+
+use ap_client_backend_v2::backend::Service;
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, bounded, select};
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::channel;
+use crate::error::{Error, Result};
+use crate::server::probe::BackendProbe;
+
+/// How long the supervisor waits before restarting a panicked backend, so a
+/// backend panicking right away doesn't spin.
+pub(crate) const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Forwards what arrives on a channel to the channel of the current backend.
+pub(crate) struct Switch<T> {
+    current: Arc<Mutex<(Sender<T>, Receiver<T>)>>,
+    capacity: Option<usize>,
+}
+
+impl<T: Send + 'static> Switch<T> {
+    /// Forwards from `source` until `stop` is closed or `source` hangs up; the
+    /// channel of the current backend is closed then as well. The channels of the
+    /// backends hold up to `capacity` items, if given.
+    pub(crate) fn new(source: Receiver<T>, stop: Receiver<()>, capacity: Option<usize>) -> Self {
+        let current = Arc::new(Mutex::new(channel(capacity)));
+        let target = current.clone();
+        thread::spawn(move || {
+            loop {
+                select! {
+                    recv(source) -> item => {
+                        let Ok(item) = item else { break };
+                        // Waiting for room must not block `replace`
+                        let send = lock(&target).0.clone();
+                        let _ = send.send(item);
+                    }
+                    recv(stop) -> _ => break,
+                }
+            }
+            // Dropping the last sender tells the backend there is nothing more
+            *lock(&target) = channel(capacity);
+        });
+        Switch { current, capacity }
+    }
+
+    /// A channel of its own for a new backend, with what is still queued for the
+    /// previous one.
+    pub(crate) fn replace(&self) -> Receiver<T> {
+        // Holds whatever the previous one, of the same capacity, still holds
+        let (send, recv) = channel(self.capacity);
+        let mut current = lock(&self.current);
+        for item in current.1.try_iter() {
+            let _ = send.send(item);
+        }
+        *current = (send, recv.clone());
+        recv
+    }
+}
+
+/// Keeps a backend made by `F` running.
+pub(crate) struct Supervisor<F> {
+    node_id: u8,
+    make: Mutex<F>,
+    probe: Arc<BackendProbe>,
+    // Bumped for every backend made; a thread whose backend was replaced exits
+    generation: AtomicU64,
+    stopping: AtomicBool,
+    // Whether the current backend returned before the client was stopping
+    exited_early: AtomicBool,
+    // The current backend thread and the channel closed when it exits
+    thread: Mutex<Option<(JoinHandle<()>, Receiver<()>)>>,
+}
+
+impl<F> Supervisor<F>
+where
+    F: FnMut() -> Result<Service> + Send + 'static,
+{
+    /// Runs a backend made by `make` for node `node_id`, recording restarts in `probe`.
+    ///
+    /// # Errors
+    /// Returns an error if the backend can't be made.
+    pub(crate) fn start(node_id: u8, make: F, probe: Arc<BackendProbe>) -> Result<Arc<Self>> {
+        let supervisor = Arc::new(Supervisor {
+            node_id,
+            make: Mutex::new(make),
+            probe,
+            generation: AtomicU64::new(0),
+            stopping: AtomicBool::new(false),
+            exited_early: AtomicBool::new(false),
+            thread: Mutex::new(None),
+        });
+        supervisor.replace()?;
+        Ok(supervisor)
+    }
+
+    /// Replaces the backend with a new one, e.g. because it is wedged.
+    ///
+    /// # Errors
+    /// Returns an error, keeping the current backend, if the new one can't be made.
+    pub(crate) fn restart(self: &Arc<Self>) -> Result<()> {
+        self.replace()?;
+        tracing::warn!(node_id = self.node_id, "Restarted the backend on request");
+        self.probe
+            .record_restart("Restarted on request".to_string());
+        Ok(())
+    }
+
+    /// Tells the supervisor that the backend is about to be told to exit, so its
+    /// exit counts neither as a disconnect nor as a reason to restart it.
+    pub(crate) fn prepare_stop(&self) {
+        self.stopping.store(true, Ordering::Relaxed);
+    }
+
+    /// Waits up to `timeout` for the backend to exit, once its channels are closed.
+    ///
+    /// # Errors
+    /// Returns [`Error::Shutdown`] if the backend didn't exit in time or its thread
+    /// panicked, or [`Error::Disconnected`] if it had exited on its own before.
+    pub(crate) fn stop(&self, timeout: Duration) -> Result<()> {
+        self.prepare_stop();
+        let node_id = self.node_id;
+        let Some((thread, exited)) = lock(&self.thread).take() else {
+            return Ok(());
+        };
+        tracing::info!(node_id, "Waiting for the backend to exit");
+        // A panic drops the sender as well, so both end the wait
+        match exited.recv_timeout(timeout) {
+            Err(RecvTimeoutError::Timeout) => {
+                tracing::warn!(
+                    node_id,
+                    "The backend didn't exit within {timeout:?}; leaving it running"
+                );
+                return Err(Error::Shutdown(format!(
+                    "The backend didn't exit within {timeout:?}"
+                )));
+            }
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => {
+                if thread.join().is_err() {
+                    tracing::error!(node_id, "The backend thread panicked");
+                    return Err(Error::Shutdown("The backend thread panicked".to_string()));
+                }
+            }
+        }
+        if self.exited_early.load(Ordering::Relaxed) {
+            return Err(Error::Disconnected);
+        }
+        Ok(())
+    }
+
+    /// Makes a new backend and runs it on a thread of its own.
+    fn replace(self: &Arc<Self>) -> Result<()> {
+        let backend = (lock(&self.make))()?;
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        self.exited_early.store(false, Ordering::Relaxed);
+        let (exited_send, exited_recv) = bounded::<()>(1);
+        let supervisor = self.clone();
+        let thread = thread::spawn(move || {
+            supervisor.supervise(generation, backend);
+            let _ = exited_send.send(());
+        });
+        if lock(&self.thread).replace((thread, exited_recv)).is_some() {
+            tracing::info!(
+                node_id = self.node_id,
+                "Left the previous backend to exit on its own"
+            );
+        }
+        Ok(())
+    }
+
+    /// Runs `backend` until it returns, making a new one whenever it panics.
+    fn supervise(&self, generation: u64, mut backend: Service) {
+        let node_id = self.node_id;
+        let current = || {
+            !self.stopping.load(Ordering::Relaxed)
+                && self.generation.load(Ordering::SeqCst) == generation
+        };
+        loop {
+            let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| backend.run())) else {
+                if current() {
+                    tracing::error!(node_id, "The backend exited while the server still runs");
+                    self.exited_early.store(true, Ordering::Relaxed);
+                }
+                return;
+            };
+            if !current() {
+                return;
+            }
+            let reason = panic_message(payload.as_ref());
+            tracing::error!(
+                node_id,
+                "The backend panicked: {reason}; restarting it in {RESTART_DELAY:?}"
+            );
+            self.probe.record_restart(reason);
+            thread::sleep(RESTART_DELAY);
+            if !current() {
+                return;
+            }
+            backend = match (lock(&self.make))() {
+                Ok(backend) => backend,
+                Err(e) => {
+                    tracing::error!(node_id, "Failed to restart the backend: {e}");
+                    return;
+                }
+            };
+            tracing::info!(node_id, "Restarted the backend");
+        }
+    }
+}
+
+/// The message a panic was raised with.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(ToString::to_string)
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}